@@ -83,6 +83,12 @@ impl Iter {
     pub fn retry_count(&self) -> u32 {
         self.retry_count
     }
+
+    /// Start over from the minimum duration
+    pub fn reset(&mut self) {
+        self.retry_count = 0;
+        self.last_exponent = 0;
+    }
 }
 
 impl iter::Iterator for Iter {
@@ -397,6 +397,7 @@ async fn handle_conn(
                     debug_log(&mut stream, "gathering members").await;
 
                     let values = {
+                        let now = Timestamp::from(agent.clock().new_timestamp());
                         let members = agent.members().read();
                         members
                             .states
@@ -408,6 +409,9 @@ async fn handle_conn(
                                     "id": actor_id,
                                     "state": state,
                                     "rtts": rtts,
+                                    "last_sync_secs_ago": state
+                                        .since_last_sync(now)
+                                        .map(|ago| ago.as_secs_f64()),
                                 })
                             })
                             .collect::<Vec<_>>()
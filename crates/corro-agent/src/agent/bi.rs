@@ -79,6 +79,8 @@ pub fn spawn_bipayload_handler(
                                                             trace_ctx,
                                                         },
                                                     cluster_id,
+                                                    schema_hash,
+                                                    schema_broadcasts,
                                                 } => {
                                                     trace!(
                                                         "framed read buffer len: {}",
@@ -87,8 +89,15 @@ pub fn spawn_bipayload_handler(
 
                                                     // println!("got sync state: {state:?}");
                                                     if let Err(e) = serve_sync(
-                                                        &agent, &bookie, actor_id, trace_ctx,
-                                                        cluster_id, framed, tx,
+                                                        &agent,
+                                                        &bookie,
+                                                        actor_id,
+                                                        trace_ctx,
+                                                        schema_hash,
+                                                        schema_broadcasts,
+                                                        cluster_id,
+                                                        framed,
+                                                        tx,
                                                     )
                                                     .await
                                                     {
@@ -1,23 +1,41 @@
-use crate::agent::RANDOM_NODES_CHOICES;
-use corro_types::{agent::SplitPool, config::DEFAULT_GOSSIP_PORT};
+use corro_types::{
+    agent::SplitPool,
+    config::{parse_scoped_socket_addr, BootstrapEntry, DnsConfig, DnsRecordType, GossipConfig},
+};
 
 use hickory_resolver::{
+    config::{NameServerConfigGroup, ResolverConfig, ResolverOpts},
     error::ResolveErrorKind,
     proto::rr::{RData, RecordType},
+    system_conf::read_system_conf,
+    TokioAsyncResolver,
 };
 use rand::{rngs::StdRng, seq::IteratorRandom, SeedableRng};
-use std::{collections::HashSet, net::SocketAddr};
+use std::{
+    collections::HashSet,
+    net::{Ipv6Addr, SocketAddr, SocketAddrV6},
+    time::Duration,
+};
 use tokio::task::block_in_place;
 use tracing::{debug, error, warn};
 
 /// Apply the user-provided set of bootstrap nodes
+///
+/// At most `bootstrap_fanout` nodes are picked at random, falling back
+/// to up to `bootstrap_fallback_limit` known members if none of the
+/// bootstrap nodes could be resolved. Successfully resolved addresses
+/// are stored in `resolved` for [`refresh_bootstrap`] to diff against.
 pub async fn generate_bootstrap(
-    bootstrap: &[String],
+    gossip: &GossipConfig,
     our_addr: SocketAddr,
     pool: &SplitPool,
+    resolved: &mut HashSet<SocketAddr>,
 ) -> eyre::Result<Vec<SocketAddr>> {
-    let mut addrs = match resolve_bootstrap(bootstrap, our_addr).await {
-        Ok(addrs) => addrs,
+    let mut addrs = match resolve_bootstrap(&gossip.bootstrap, &gossip.dns, our_addr).await {
+        Ok(addrs) => {
+            resolved.clone_from(&addrs);
+            addrs
+        }
         Err(e) => {
             warn!("could not resolve bootstraps, falling back to in-db nodes: {e}");
             HashSet::new()
@@ -29,12 +47,14 @@ pub async fn generate_bootstrap(
         let conn = pool.read().await?;
         addrs = block_in_place(|| {
             let mut prepped =
-                conn.prepare("SELECT address FROM __corro_members ORDER BY RANDOM() LIMIT 5")?;
-            let node_addrs = prepped.query_map([], |row| row.get::<_, String>(0))?;
+                conn.prepare("SELECT address FROM __corro_members ORDER BY RANDOM() LIMIT ?")?;
+            let node_addrs = prepped.query_map([gossip.bootstrap_fallback_limit.get()], |row| {
+                row.get::<_, String>(0)
+            })?;
             Ok::<_, rusqlite::Error>(
                 node_addrs
                     .flatten()
-                    .flat_map(|addr| addr.parse())
+                    .flat_map(|addr| parse_scoped_socket_addr(&addr))
                     .filter(|addr| match (our_addr, addr) {
                         (SocketAddr::V6(our_ip), SocketAddr::V6(ip)) if our_ip != *ip => true,
                         (SocketAddr::V4(our_ip), SocketAddr::V4(ip)) if our_ip != *ip => true,
@@ -52,99 +72,316 @@ pub async fn generate_bootstrap(
 
     Ok(addrs
         .into_iter()
-        .choose_multiple(&mut rng, RANDOM_NODES_CHOICES))
+        .choose_multiple(&mut rng, gossip.bootstrap_fanout.get()))
+}
+
+/// Resolve the bootstrap nodes again and only return addresses that
+/// were not part of the `previous` resolution, which gets replaced by
+/// the current one.
+pub async fn refresh_bootstrap(
+    gossip: &GossipConfig,
+    our_addr: SocketAddr,
+    previous: &mut HashSet<SocketAddr>,
+) -> eyre::Result<Vec<SocketAddr>> {
+    let addrs = resolve_bootstrap(&gossip.bootstrap, &gossip.dns, our_addr).await?;
+
+    let new_addrs = addrs.difference(previous).copied().collect();
+    *previous = addrs;
+
+    Ok(new_addrs)
+}
+
+/// Apply the configured overrides on top of a resolver's options
+fn resolver_opts(dns: &DnsConfig, mut opts: ResolverOpts) -> ResolverOpts {
+    if let Some(timeout_ms) = dns.timeout_ms {
+        opts.timeout = Duration::from_millis(timeout_ms);
+    }
+    if let Some(attempts) = dns.attempts {
+        opts.attempts = attempts;
+    }
+    if !dns.cache {
+        opts.cache_size = 0;
+    }
+    opts
+}
+
+fn record_type(dns: &DnsConfig, our_addr: SocketAddr) -> RecordType {
+    match dns.record_type {
+        DnsRecordType::Auto if our_addr.is_ipv6() => RecordType::AAAA,
+        DnsRecordType::Auto | DnsRecordType::A => RecordType::A,
+        DnsRecordType::Aaaa => RecordType::AAAA,
+    }
+}
+
+/// DNS records can't carry a zone id, link-local addresses are only usable
+/// through the interface we're bound to
+fn scoped_like(addr: SocketAddrV6, our_addr: SocketAddr) -> SocketAddr {
+    match our_addr {
+        SocketAddr::V6(ours) if is_link_local(addr.ip()) && addr.scope_id() == 0 => SocketAddr::V6(
+            SocketAddrV6::new(*addr.ip(), addr.port(), 0, ours.scope_id()),
+        ),
+        _ => SocketAddr::V6(addr),
+    }
+}
+
+/// `fe80::/10`, `Ipv6Addr::is_unicast_link_local` isn't stable yet
+fn is_link_local(ip: &Ipv6Addr) -> bool {
+    ip.segments()[0] & 0xffc0 == 0xfe80
 }
 
 async fn resolve_bootstrap(
     bootstrap: &[String],
+    dns: &DnsConfig,
     our_addr: SocketAddr,
 ) -> eyre::Result<HashSet<SocketAddr>> {
-    use hickory_resolver::config::{NameServerConfigGroup, ResolverConfig, ResolverOpts};
-    use hickory_resolver::{AsyncResolver, TokioAsyncResolver};
-
     let mut addrs = HashSet::new();
 
     if bootstrap.is_empty() {
         return Ok(addrs);
     }
 
-    let system_resolver = AsyncResolver::tokio_from_system_conf()?;
-
-    for s in bootstrap {
-        if let Ok(addr) = s.parse() {
-            addrs.insert(addr);
-        } else {
-            debug!("attempting to resolve {s}");
-            let mut host_port_dns_server = s.split('@');
-            let mut host_port = host_port_dns_server.next().unwrap().split(':');
-            let mut resolver = None;
-            if let Some(dns_server) = host_port_dns_server.next() {
-                debug!("attempting to use resolver: {dns_server}");
-                let (ip, port) = if let Ok(addr) = dns_server.parse::<SocketAddr>() {
-                    (addr.ip(), addr.port())
-                } else {
-                    (dns_server.parse()?, 53)
-                };
-                resolver = Some(TokioAsyncResolver::tokio(
-                    ResolverConfig::from_parts(
-                        None,
-                        vec![],
-                        NameServerConfigGroup::from_ips_clear(&[ip], port, true),
-                    ),
-                    ResolverOpts::default(),
-                ));
-                debug!("using resolver: {dns_server}");
+    let (config, opts) = if dns.use_system_conf {
+        read_system_conf()?
+    } else {
+        (ResolverConfig::default(), ResolverOpts::default())
+    };
+    let default_resolver = TokioAsyncResolver::tokio(config, resolver_opts(dns, opts));
+    let record_type = record_type(dns, our_addr);
+
+    for entry in bootstrap {
+        let (hostname, port, dns_server) = match entry.parse::<BootstrapEntry>() {
+            Ok(BootstrapEntry::Addr(addr)) => {
+                addrs.insert(addr);
+                continue;
             }
-            if let Some(hostname) = host_port.next() {
-                debug!("Resolving '{hostname}' to an IP");
-                match resolver
-                    .as_ref()
-                    .unwrap_or(&system_resolver)
-                    .lookup(
-                        hostname,
-                        if our_addr.is_ipv6() {
-                            RecordType::AAAA
-                        } else {
-                            RecordType::A
-                        },
-                    )
-                    .await
-                {
-                    Ok(response) => {
-                        debug!("Successfully resolved things: {response:?}");
-                        let port: u16 = host_port
-                            .next()
-                            .and_then(|p| p.parse().ok())
-                            .unwrap_or(DEFAULT_GOSSIP_PORT);
-                        for addr in response.iter().filter_map(|rdata| match rdata {
-                            RData::A(ip) => Some(SocketAddr::from((ip.0, port))),
-                            RData::AAAA(ip) => Some(SocketAddr::from((ip.0, port))),
-                            _ => None,
-                        }) {
-                            match (our_addr, addr) {
-                                (SocketAddr::V4(our_ip), SocketAddr::V4(ip)) if our_ip != ip => {}
-                                (SocketAddr::V6(our_ip), SocketAddr::V6(ip)) if our_ip != ip => {}
-                                _ => {
-                                    debug!("ignore node with addr: {addr}");
-                                    continue;
-                                }
-                            }
-                            addrs.insert(addr);
-                        }
+            Ok(BootstrapEntry::Host {
+                hostname,
+                port,
+                resolver,
+            }) => (hostname, port, resolver),
+            Err(e) => {
+                // should've been caught by `Config::validate`
+                warn!("skipping invalid bootstrap entry '{entry}': {e}");
+                continue;
+            }
+        };
+
+        debug!("attempting to resolve {entry}");
+        let resolver = dns_server.map(|dns_server| {
+            debug!("using resolver: {dns_server}");
+            TokioAsyncResolver::tokio(
+                ResolverConfig::from_parts(
+                    None,
+                    vec![],
+                    NameServerConfigGroup::from_ips_clear(
+                        &[dns_server.ip()],
+                        dns_server.port(),
+                        true,
+                    ),
+                ),
+                resolver_opts(dns, ResolverOpts::default()),
+            )
+        });
+
+        debug!("Resolving '{hostname}' to an IP");
+        match resolver
+            .as_ref()
+            .unwrap_or(&default_resolver)
+            .lookup(hostname.as_str(), record_type)
+            .await
+        {
+            Ok(response) => {
+                debug!("Successfully resolved things: {response:?}");
+                for addr in response.iter().filter_map(|rdata| match rdata {
+                    RData::A(ip) => Some(SocketAddr::from((ip.0, port))),
+                    RData::AAAA(ip) => {
+                        Some(scoped_like(SocketAddrV6::new(ip.0, port, 0, 0), our_addr))
                     }
-                    Err(e) => match e.kind() {
-                        ResolveErrorKind::NoRecordsFound { .. } => {
-                            // do nothing, that might be fine!
-                        }
-                        _ => {
-                            error!("could not resolve '{hostname}': {e}");
-                            return Err(e.into());
-                        }
-                    },
+                    _ => None,
+                }) {
+                    // the record type may be forced, only skip ourselves
+                    if addr == our_addr {
+                        debug!("ignore node with addr: {addr}");
+                        continue;
+                    }
+                    addrs.insert(addr);
                 }
             }
+            Err(e) => match e.kind() {
+                ResolveErrorKind::NoRecordsFound { .. } => {
+                    // do nothing, that might be fine!
+                }
+                _ => {
+                    error!("could not resolve '{hostname}': {e}");
+                    return Err(e.into());
+                }
+            },
         }
     }
 
     Ok(addrs)
 }
+
+#[cfg(test)]
+mod tests {
+    use corro_types::config::BootstrapEntryError;
+
+    use super::*;
+
+    #[test]
+    fn test_dns_config() {
+        let v4: SocketAddr = "127.0.0.1:8787".parse().unwrap();
+        let v6: SocketAddr = "[::1]:8787".parse().unwrap();
+
+        let mut dns = DnsConfig::default();
+        assert_eq!(record_type(&dns, v4), RecordType::A);
+        assert_eq!(record_type(&dns, v6), RecordType::AAAA);
+
+        // dual-stack nodes listening on IPv6 may still want A records
+        dns.record_type = DnsRecordType::A;
+        assert_eq!(record_type(&dns, v6), RecordType::A);
+        dns.record_type = DnsRecordType::Aaaa;
+        assert_eq!(record_type(&dns, v4), RecordType::AAAA);
+
+        let defaults = ResolverOpts::default();
+        let opts = resolver_opts(&dns, ResolverOpts::default());
+        assert_eq!(opts.timeout, defaults.timeout);
+        assert_eq!(opts.attempts, defaults.attempts);
+        assert_eq!(opts.cache_size, defaults.cache_size);
+
+        dns.timeout_ms = Some(200);
+        dns.attempts = Some(5);
+        dns.cache = false;
+        let opts = resolver_opts(&dns, ResolverOpts::default());
+        assert_eq!(opts.timeout, Duration::from_millis(200));
+        assert_eq!(opts.attempts, 5);
+        assert_eq!(opts.cache_size, 0);
+    }
+
+    // interface names differ across platforms, `lo` is linux's loopback
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_scoped_ipv6_bootstrap() -> eyre::Result<()> {
+        let our_addr: SocketAddr = "[fe80::1%1]:8787".parse()?;
+        let dns = DnsConfig {
+            use_system_conf: false,
+            ..Default::default()
+        };
+
+        let resolved = resolve_bootstrap(
+            &[
+                "[fe80::2%lo]:8787".into(),
+                "fe80::3%1".into(),
+                "[::1]:8787".into(),
+            ],
+            &dns,
+            our_addr,
+        )
+        .await?;
+
+        assert_eq!(resolved.len(), 3);
+        assert!(resolved.contains(&SocketAddr::V6(SocketAddrV6::new(
+            "fe80::3".parse()?,
+            4001,
+            0,
+            1
+        ))));
+        assert!(resolved.contains(&"[::1]:8787".parse()?));
+        // the loopback interface's index, whatever it is
+        assert!(resolved.iter().any(|addr| matches!(
+            addr,
+            SocketAddr::V6(addr) if addr.ip().segments() == [0xfe80, 0, 0, 0, 0, 0, 0, 2]
+                && addr.port() == 8787
+                && addr.scope_id() != 0
+        )));
+
+        // as stored in `__corro_members` and read back when falling back
+        for addr in resolved {
+            assert_eq!(parse_scoped_socket_addr(&addr.to_string())?, addr);
+        }
+
+        assert!(matches!(
+            "[fe80::2%nope0]:8787".parse::<BootstrapEntry>(),
+            Err(BootstrapEntryError::InvalidScope(_))
+        ));
+
+        // link-local answers get the zone id we're bound with
+        assert_eq!(
+            scoped_like(SocketAddrV6::new("fe80::4".parse()?, 8787, 0, 0), our_addr),
+            SocketAddr::V6(SocketAddrV6::new("fe80::4".parse()?, 8787, 0, 1))
+        );
+        assert_eq!(
+            scoped_like(SocketAddrV6::new("fdaa::4".parse()?, 8787, 0, 0), our_addr),
+            SocketAddr::V6(SocketAddrV6::new("fdaa::4".parse()?, 8787, 0, 0))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_bootstrap_entries() {
+        let parse = |s: &str| s.parse::<BootstrapEntry>();
+
+        assert_eq!(
+            parse("127.0.0.1:8787").unwrap(),
+            BootstrapEntry::Addr("127.0.0.1:8787".parse().unwrap())
+        );
+        assert_eq!(
+            parse("[::1]:8787").unwrap(),
+            BootstrapEntry::Addr("[::1]:8787".parse().unwrap())
+        );
+        assert_eq!(
+            parse("10.0.0.1").unwrap(),
+            BootstrapEntry::Addr("10.0.0.1:4001".parse().unwrap())
+        );
+        assert_eq!(
+            parse("corrosion.internal").unwrap(),
+            BootstrapEntry::Host {
+                hostname: "corrosion.internal".into(),
+                port: 4001,
+                resolver: None,
+            }
+        );
+
+        let entry = parse("corrosion.internal:8787@[fdaa::3]").unwrap();
+        assert_eq!(
+            entry,
+            BootstrapEntry::Host {
+                hostname: "corrosion.internal".into(),
+                port: 8787,
+                resolver: Some("[fdaa::3]:53".parse().unwrap()),
+            }
+        );
+        assert_eq!(entry.to_string(), "corrosion.internal:8787@[fdaa::3]:53");
+
+        assert!(matches!(
+            parse("corrosion.internal:8787@10.0.0.1:5353").unwrap(),
+            BootstrapEntry::Host {
+                resolver: Some(addr),
+                ..
+            } if addr == "10.0.0.1:5353".parse().unwrap()
+        ));
+
+        assert!(matches!(parse(""), Err(BootstrapEntryError::Empty)));
+        assert!(matches!(
+            parse("host::8787"),
+            Err(BootstrapEntryError::InvalidPort(_))
+        ));
+        assert!(matches!(
+            parse("host:99999"),
+            Err(BootstrapEntryError::InvalidPort(_))
+        ));
+        assert!(matches!(
+            parse("host:8787@dns.local"),
+            Err(BootstrapEntryError::InvalidResolver(_))
+        ));
+        assert!(matches!(
+            parse("host@10.0.0.1@10.0.0.2"),
+            Err(BootstrapEntryError::MultipleResolvers)
+        ));
+        assert!(matches!(
+            parse("bad host:8787"),
+            Err(BootstrapEntryError::InvalidHostname(_))
+        ));
+    }
+}
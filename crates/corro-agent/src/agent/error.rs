@@ -1,10 +1,12 @@
 use crate::{api::peer::SyncError, transport::TransportError};
 use corro_types::{
+    actor::ActorId,
     agent::ChangeError,
     sqlite::SqlitePoolError,
     sync::{SyncMessageDecodeError, SyncMessageEncodeError},
 };
 use hyper::StatusCode;
+use std::time::Duration;
 use tokio::time::error::Elapsed;
 
 #[derive(Debug, thiserror::Error)]
@@ -28,6 +30,10 @@ pub enum SyncClientError {
 
     #[error(transparent)]
     Sync(#[from] SyncError),
+    #[error("no member to sync with")]
+    NoCandidate,
+    #[error("member {0} is not a sync candidate")]
+    NotCandidate(ActorId),
 }
 
 impl SyncClientError {
@@ -52,6 +58,10 @@ pub enum SyncRecvError {
     ExpectedClockMessage,
     #[error("timed out waiting for sync message")]
     TimedOut(#[from] Elapsed),
+    #[error("peer stalled, nothing received for {0:?}")]
+    Stalled(Duration),
+    #[error("sync was cancelled")]
+    Cancelled,
     #[error("changes channel is closed")]
     ChangesChannelClosed,
     #[error("requests channel is closed")]
@@ -2,23 +2,34 @@
 //!
 //! This module is _big_ and maybe should be split up further.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::ops::RangeInclusive;
 
 use std::{
     cmp,
     collections::VecDeque,
-    net::SocketAddr,
+    net::{IpAddr, SocketAddr},
+    num::NonZeroU32,
+    sync::Arc,
     time::{Duration, Instant},
 };
 
 use crate::{
     agent::{
-        bi, bootstrap, uni,
-        util::{log_at_pow_10, process_multiple_changes},
-        SyncClientError, ANNOUNCE_INTERVAL,
+        bi, bootstrap,
+        shutdown::{ShutdownStage, ShutdownStages},
+        uni,
+        util::{
+            check_disk_full, jittered_period, log_at_pow_10, process_multiple_changes,
+            save_swim_identity,
+        },
+        SyncClientError, ANNOUNCE_INTERVAL, BOOTSTRAP_REFRESH_INTERVAL,
+        SUSPECT_AFTER_SEND_FAILURES,
+    },
+    api::{
+        peer::{parallel_sync, parallel_sync_from},
+        public::execute_schema,
     },
-    api::peer::parallel_sync,
     transport::Transport,
 };
 use camino::Utf8Path;
@@ -26,10 +37,13 @@ use corro_types::{
     actor::{Actor, ActorId},
     agent::{get_last_cleared_ts, Agent, Bookie, SplitPool},
     base::CrsqlSeq,
-    broadcast::{BroadcastInput, BroadcastV1, ChangeSource, ChangeV1, Changeset, FocaInput},
-    channel::CorroReceiver,
+    broadcast::{
+        BroadcastInput, BroadcastV1, ChangeSource, ChangeV1, Changeset, FocaInput, SchemaChangeV1,
+    },
+    channel::{CorroReceiver, CorroSender},
+    config::SyncScoring,
     members::MemberAddedResult,
-    sync::generate_sync,
+    sync::{generate_sync, SyncStateV1},
 };
 
 use bytes::Bytes;
@@ -41,6 +55,7 @@ use foca::Notification;
 use indexmap::map::Entry;
 use indexmap::IndexMap;
 use metrics::{counter, gauge, histogram};
+use parking_lot::Mutex;
 use rand::{prelude::IteratorRandom, rngs::StdRng, SeedableRng};
 use rangemap::RangeInclusiveSet;
 use spawn::spawn_counted;
@@ -58,13 +73,13 @@ use tripwire::{Outcome, PreemptibleFutureExt, TimeoutFutureExt, Tripwire};
 pub fn spawn_gossipserver_handler(
     agent: &Agent,
     bookie: &Bookie,
-    tripwire: &Tripwire,
+    stages: &ShutdownStages,
     gossip_server_endpoint: quinn::Endpoint,
 ) {
-    spawn_counted({
+    spawn_counted(stages.track(ShutdownStage::Gossip, {
         let agent = agent.clone();
         let bookie = bookie.clone();
-        let mut tripwire = tripwire.clone();
+        let mut tripwire = stages.tripwire(ShutdownStage::Gossip);
         async move {
             loop {
                 let connecting = match gossip_server_endpoint
@@ -89,7 +104,7 @@ pub fn spawn_gossipserver_handler(
                 .await;
             gossip_server_endpoint.close(0u32.into(), b"shutting down");
         }
-    });
+    }));
     info!("gossipserver_handler is done");
 }
 
@@ -124,10 +139,13 @@ pub fn spawn_incoming_connection_handlers(
         // Spawn handler tasks for this connection
         spawn_foca_handler(&agent, &tripwire, &conn);
         uni::spawn_unipayload_handler(
+            &agent,
+            &bookie,
             &tripwire,
             &conn,
-            agent.cluster_id(),
             agent.tx_changes().clone(),
+            agent.limits().gossip.clone(),
+            agent.config().gossip.max_broadcast_frame_len,
         );
         bi::spawn_bipayload_handler(&agent, &bookie, &tripwire, &conn);
     });
@@ -194,7 +212,9 @@ pub fn spawn_foca_handler(agent: &Agent, tripwire: &Tripwire, conn: &quinn::Conn
 /// beginning, get a full picture of the cluster, then stop spamming
 /// everyone.
 ///
-///
+/// In between full announcements, bootstrap nodes are re-resolved every
+/// [`BOOTSTRAP_REFRESH_INTERVAL`] and we only announce ourselves to
+/// addresses that newly appeared.
 pub fn spawn_swim_announcer(agent: &Agent, gossip_addr: SocketAddr, mut tripwire: Tripwire) {
     tokio::spawn({
         let agent = agent.clone();
@@ -205,51 +225,102 @@ pub fn spawn_swim_announcer(agent: &Agent, gossip_addr: SocketAddr, mut tripwire
             let timer = tokio::time::sleep(Duration::new(0, 0));
             tokio::pin!(timer);
 
+            let refresh_every = jittered_period(&agent, BOOTSTRAP_REFRESH_INTERVAL);
+            let mut refresh = tokio::time::interval_at(
+                tokio::time::Instant::now() + refresh_every,
+                refresh_every,
+            );
+            refresh.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+            // addresses from the last bootstrap resolution
+            let mut resolved = HashSet::new();
+
             loop {
-                tokio::select! {
+                let full = tokio::select! {
                     _ = &mut tripwire => {
                         break;
                     }
-                    _ = timer.as_mut() => {}
-                }
+                    _ = timer.as_mut() => true,
+                    _ = refresh.tick() => false,
+                };
 
-                match bootstrap::generate_bootstrap(
-                    agent.config().gossip.bootstrap.as_slice(),
-                    gossip_addr,
-                    agent.pool(),
-                )
-                .await
-                {
+                let res = if full {
+                    bootstrap::generate_bootstrap(
+                        &agent.config().gossip,
+                        gossip_addr,
+                        agent.pool(),
+                        &mut resolved,
+                    )
+                    .await
+                } else {
+                    bootstrap::refresh_bootstrap(&agent.config().gossip, gossip_addr, &mut resolved)
+                        .await
+                };
+
+                match res {
                     Ok(addrs) => {
-                        for addr in addrs.iter() {
-                            debug!("Bootstrapping w/ {addr}");
-                            if let Err(e) = agent
-                                .tx_foca()
-                                .send(FocaInput::Announce((*addr).into()))
-                                .await
-                            {
-                                error!("could not send foca Announce message: {e}");
-                            } else {
-                                debug!("successfully sent announce message");
-                            }
+                        if !full && !addrs.is_empty() {
+                            info!("found {} new bootstrap addresses, announcing", addrs.len());
                         }
+                        send_announces(&agent, &addrs).await;
                     }
                     Err(e) => {
                         error!("could not find nodes to announce ourselves to: {e}");
                     }
                 }
 
-                let dur = boff.next().unwrap_or(ANNOUNCE_INTERVAL);
-                timer.as_mut().reset(tokio::time::Instant::now() + dur);
+                if full {
+                    let dur = boff.next().unwrap_or(ANNOUNCE_INTERVAL);
+                    timer.as_mut().reset(tokio::time::Instant::now() + dur);
+                }
             }
         }
     });
 }
 
+/// Announce ourselves to bootstrap addresses and known members right away,
+/// outside of the announcer's schedule. Returns the addresses announced to.
+pub async fn announce_now(agent: &Agent, gossip_addr: SocketAddr) -> eyre::Result<Vec<SocketAddr>> {
+    let addrs = bootstrap::generate_bootstrap(
+        &agent.config().gossip,
+        gossip_addr,
+        agent.pool(),
+        &mut HashSet::new(),
+    )
+    .await?;
+
+    Ok(send_announces(agent, &addrs).await)
+}
+
+/// Queue a SWIM announce to each of `addrs`, returning the ones queued
+async fn send_announces(agent: &Agent, addrs: &[SocketAddr]) -> Vec<SocketAddr> {
+    let mut announced = Vec::with_capacity(addrs.len());
+    for addr in addrs.iter() {
+        debug!("Bootstrapping w/ {addr}");
+        if let Err(e) = agent
+            .tx_foca()
+            .send(FocaInput::Announce((*addr).into()))
+            .await
+        {
+            error!("could not send foca Announce message: {e}");
+        } else {
+            debug!("successfully sent announce message");
+            announced.push(*addr);
+        }
+    }
+    announced
+}
+
+/// Consecutive gossip send failures per actor, shared with
+/// `handle_notifications` to forget members that went down
+pub type SendFailures = Arc<Mutex<HashMap<ActorId, u32>>>;
+
 /// A central dispatcher for SWIM cluster management messages
 // TODO: we may be able to inline this code where it is needed
 pub async fn handle_gossip_to_send(
     transport: Transport,
+    tx_foca: CorroSender<FocaInput>,
+    send_failures: SendFailures,
     mut swim_to_send_rx: CorroReceiver<(Actor, Bytes)>,
 ) {
     // TODO: use tripwire and drain messages to send when that happens...
@@ -260,14 +331,39 @@ pub async fn handle_gossip_to_send(
         let actor_id = actor.id();
 
         let transport = transport.clone();
+        let tx_foca = tx_foca.clone();
+        let send_failures = send_failures.clone();
 
         let len = data.len();
         spawn_counted(
             async move {
                 if let Err(e) = transport.send_datagram(addr, data).await {
-                    error!("could not write datagram {addr}: {e}");
+                    if e.is_closed_by_peer() {
+                        warn!("could not write datagram {addr}, peer closed the connection: {e}");
+                    } else {
+                        error!("could not write datagram {addr}: {e}");
+                    }
+                    counter!("corro.gossip.send.failures", "actor_id" => actor_id.to_string())
+                        .increment(1);
+
+                    let failures = {
+                        let mut send_failures = send_failures.lock();
+                        let failures = send_failures.entry(actor_id).or_default();
+                        *failures += 1;
+                        *failures
+                    };
+
+                    if failures == SUSPECT_AFTER_SEND_FAILURES {
+                        warn!(%actor_id, %addr, "failed to send gossip {failures} times in a row, marking member as suspect");
+                        if let Err(e) = tx_foca.send(FocaInput::Suspect(actor)).await {
+                            // the SWIM runtime is gone once shutdown leaves the cluster
+                            debug!("could not send foca suspect input: {e}");
+                        }
+                    }
                     return;
                 }
+                send_failures.lock().remove(&actor_id);
+
                 counter!("corro.peer.datagram.sent.total", "actor_id" => actor_id.to_string())
                     .increment(1);
                 counter!("corro.peer.datagram.bytes.sent.total").increment(len as u64);
@@ -282,8 +378,27 @@ pub async fn handle_gossip_to_send(
 pub async fn handle_notifications(
     agent: Agent,
     mut notification_rx: CorroReceiver<Notification<Actor>>,
+    send_failures: SendFailures,
 ) {
-    while let Some(notification) = notification_rx.recv().await {
+    // cluster size updates are debounced, members can come and go a lot
+    let mut cluster_size_interval = tokio::time::interval(Duration::from_millis(
+        agent.config().gossip.cluster_size_debounce_ms.get(),
+    ));
+    cluster_size_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut last_cluster_size = None;
+
+    loop {
+        let notification = tokio::select! {
+            notification = notification_rx.recv() => match notification {
+                Some(notification) => notification,
+                None => break,
+            },
+            _ = cluster_size_interval.tick() => {
+                update_cluster_size(&agent, &mut last_cluster_size).await;
+                continue;
+            }
+        };
+
         trace!("handle notification");
         match notification {
             Notification::MemberUp(actor) => {
@@ -310,20 +425,10 @@ pub async fn handle_notifications(
                             }
                         };
 
-                        let members_len = {
-                            let mut members = agent.members().write();
-                            members.update_last_empty(&actor.id(), last_cleared_ts);
-                            members.states.len() as u32
-                        };
-
-                        // actually added a member
-                        // notify of new cluster size
-                        if let Ok(size) = members_len.try_into() {
-                            if let Err(e) = agent.tx_foca().send(FocaInput::ClusterSize(size)).await
-                            {
-                                error!("could not send new foca cluster size: {e}");
-                            }
-                        }
+                        agent
+                            .members()
+                            .write()
+                            .update_last_empty(&actor.id(), last_cleared_ts);
                     }
                     MemberAddedResult::Updated => {
                         debug!("Member Updated {actor:?}");
@@ -352,22 +457,16 @@ pub async fn handle_notifications(
             Notification::MemberDown(actor) => {
                 let removed = { agent.members().write().remove_member(&actor) };
                 info!("Member Down {actor:?} (removed: {removed})");
+                send_failures.lock().remove(&actor.id());
                 if removed {
                     debug!("Member Down {actor:?}");
                     counter!("corro.gossip.member.removed", "id" => actor.id().0.to_string(), "addr" => actor.addr().to_string()).increment(1);
-                    // actually removed a member
-                    // notify of new cluster size
-                    let member_len = { agent.members().read().states.len() as u32 };
-                    if let Ok(size) = member_len.try_into() {
-                        if let Err(e) = agent.tx_foca().send(FocaInput::ClusterSize(size)).await {
-                            error!("could not send new foca cluster size: {e}");
-                        }
-                    }
                 }
                 counter!("corro.swim.notification", "type" => "memberdown").increment(1);
             }
             Notification::Active => {
                 info!("Current node is considered ACTIVE");
+                agent.set_joined();
                 counter!("corro.swim.notification", "type" => "active").increment(1);
             }
             Notification::Idle => {
@@ -381,12 +480,32 @@ pub async fn handle_notifications(
             }
             Notification::Rejoin(id) => {
                 info!("Rejoined the cluster with id: {id:?}");
+                save_swim_identity(&agent, &id).await;
                 counter!("corro.swim.notification", "type" => "rejoin").increment(1);
             }
         }
     }
 }
 
+/// Notify foca of the current cluster size, if it changed since the last update
+async fn update_cluster_size(agent: &Agent, last_cluster_size: &mut Option<NonZeroU32>) {
+    let members_len = { agent.members().read().states.len() as u32 };
+    gauge!("corro.cluster.size").set(members_len as f64);
+
+    let Some(size) = NonZeroU32::new(members_len) else {
+        return;
+    };
+    if *last_cluster_size == Some(size) {
+        return;
+    }
+
+    if let Err(e) = agent.tx_foca().send(FocaInput::ClusterSize(size)).await {
+        error!("could not send new foca cluster size: {e}");
+        return;
+    }
+    *last_cluster_size = Some(size);
+}
+
 /// We keep a write-ahead-log, which under write-pressure can grow to
 /// multiple gigabytes and needs periodic truncation.  We don't want
 /// to schedule this task too often since it locks the whole DB.
@@ -478,38 +597,60 @@ async fn vacuum_db(pool: &SplitPool, lim: u64) -> eyre::Result<()> {
 pub fn spawn_handle_db_maintenance(agent: &Agent) {
     let mut wal_path = agent.config().db.path.clone();
     let wal_threshold = agent.config().perf.wal_threshold_gb as u64;
+    let wal_truncate = agent.config().db.wal_truncate;
     wal_path.set_extension(format!("{}-wal", wal_path.extension().unwrap_or_default()));
 
+    if wal_truncate {
+        info!("WAL will be truncated when it grows over {wal_threshold}GB");
+    } else {
+        info!("WAL truncation disabled, relying on SQLite's automatic checkpoints");
+    }
+
     let pool = agent.pool().clone();
 
+    let initial_delay = jittered_period(agent, Duration::from_secs(60));
+    let vacuum_every = jittered_period(agent, Duration::from_secs(60 * 5));
+
     tokio::spawn(async move {
         let truncate_wal_threshold: u64 = wal_threshold * 1024 * 1024 * 1024;
 
         // try to initially truncate the WAL
-        match wal_checkpoint_over_threshold(wal_path.as_path(), &pool, truncate_wal_threshold).await
-        {
-            Ok(truncated) if truncated => {
-                info!("initially truncated WAL");
-            }
-            Err(e) => {
-                error!("could not initially truncate WAL: {e}");
+        if wal_truncate {
+            match wal_checkpoint_over_threshold(wal_path.as_path(), &pool, truncate_wal_threshold)
+                .await
+            {
+                Ok(truncated) if truncated => {
+                    info!("initially truncated WAL");
+                }
+                Err(e) => {
+                    error!("could not initially truncate WAL: {e}");
+                }
+                _ => {}
             }
-            _ => {}
         }
 
         // large sleep right at the start to give node time to sync
-        sleep(Duration::from_secs(60)).await;
+        sleep(initial_delay).await;
 
-        let mut vacuum_interval = tokio::time::interval(Duration::from_secs(60 * 5));
+        let mut vacuum_interval = tokio::time::interval(vacuum_every);
 
         const MAX_DB_FREE_PAGES: u64 = 10000;
 
         loop {
             vacuum_interval.tick().await;
+            if pool.is_closed() {
+                debug!("database pools are closed, stopping db maintenance");
+                break;
+            }
+
             if let Err(e) = vacuum_db(&pool, MAX_DB_FREE_PAGES).await {
                 error!("could not check freelist and vacuum: {e}");
             }
 
+            if !wal_truncate {
+                continue;
+            }
+
             if let Err(e) =
                 wal_checkpoint_over_threshold(wal_path.as_path(), &pool, truncate_wal_threshold)
                     .await
@@ -533,6 +674,41 @@ async fn wal_checkpoint_over_threshold(
     Ok(should_truncate)
 }
 
+/// Apply a schema change broadcast by another node, the same way it was
+/// applied there. Merging a schema never removes or changes tables and
+/// columns, those migrations are refused.
+///
+/// The change is passed on to other nodes only if it changed anything
+/// here, so it stops spreading once every node has it. It skips ahead of
+/// queued broadcasts since those changes may depend on the new schema.
+pub async fn handle_schema_change(agent: &Agent, change: SchemaChangeV1) {
+    if change.actor_id == agent.actor_id() {
+        return;
+    }
+
+    match execute_schema(agent, change.statements.clone(), false).await {
+        Ok(diff) if diff.is_empty() => {
+            trace!(actor_id = %change.actor_id, "already had broadcast schema");
+        }
+        Ok(diff) => {
+            info!(actor_id = %change.actor_id, "applied {} schema change(s) from peer", diff.len());
+            counter!("corro.schema.broadcast.applied").increment(1);
+            if let Err(_e) = agent
+                .tx_bcast()
+                .try_send(BroadcastInput::PriorityRebroadcast(BroadcastV1::Schema(
+                    change,
+                )))
+            {
+                debug!("broadcasts are full or done!");
+            }
+        }
+        Err(e) => {
+            warn!(actor_id = %change.actor_id, "could not apply schema from peer: {e}");
+            counter!("corro.schema.broadcast.rejected").increment(1);
+        }
+    }
+}
+
 /// Handle incoming emptyset received during syncs
 ///_
 #[allow(dead_code)]
@@ -771,10 +947,27 @@ pub async fn handle_changes(
     let mut seen: IndexMap<_, RangeInclusiveSet<CrsqlSeq>> = IndexMap::new();
 
     let mut drop_log_count: u64 = 0;
+
+    // once tripped, stop receiving and process what's queued, up to this long
+    let drain_timeout = Duration::from_secs(agent.config().perf.shutdown_drain_timeout_secs);
+    let drain_deadline = tokio::time::sleep(Duration::ZERO);
+    tokio::pin!(drain_deadline);
+    let mut tripped = false;
+
     // complicated loop to process changes efficiently w/ a max concurrency
     // and a minimum chunk size for bigger and faster SQLite transactions
     loop {
-        while (buf_cost >= max_changes_chunk || (!queue.is_empty() && join_set.is_empty()))
+        if tripped && queue.is_empty() && join_set.is_empty() {
+            info!("processed received changes before shutdown");
+            break;
+        }
+
+        // applying would only fail until space is freed, keep changes
+        // queued (dropping the oldest when full) until then
+        let disk_full = agent.is_disk_full();
+
+        while !disk_full
+            && (buf_cost >= max_changes_chunk || (!queue.is_empty() && join_set.is_empty()))
             && join_set.len() < MAX_CONCURRENT
         {
             // Process if we hit the chunk size OR if we have any items and available capacity
@@ -810,11 +1003,12 @@ pub async fn handle_changes(
                 debug!("processed multiple changes concurrently");
                 if let Some(Ok(Err(e))) = res {
                     error!("could not process multiple changes: {e}");
+                    check_disk_full(&agent, &e);
                 }
                 continue;
             },
 
-            maybe_change_src = rx_changes.recv() => match maybe_change_src {
+            maybe_change_src = rx_changes.recv(), if !tripped => match maybe_change_src {
                 Some((change, src)) => (change, src),
                 None => break,
             },
@@ -826,7 +1020,7 @@ pub async fn handle_changes(
                 gauge!("corro.agent.changesets.in_queue").set(queue.len() as f64);
                 gauge!("corro.agent.changes.processing.jobs").set(join_set.len() as f64);
 
-                if buf_cost < max_changes_chunk && !queue.is_empty() && join_set.len() < MAX_CONCURRENT {
+                if !disk_full && buf_cost < max_changes_chunk && !queue.is_empty() && join_set.len() < MAX_CONCURRENT {
                     // we can process this right away
                     debug!(%buf_cost, "spawning processing multiple changes from max wait interval");
                     let changes: Vec<_> = queue.drain(..).collect();
@@ -844,7 +1038,22 @@ pub async fn handle_changes(
                 continue
             },
 
-            _ = &mut tripwire => {
+            _ = &mut tripwire, if !tripped => {
+                if drain_timeout.is_zero() {
+                    break;
+                }
+                tripped = true;
+                drain_deadline
+                    .as_mut()
+                    .reset(tokio::time::Instant::now() + drain_timeout);
+                continue;
+            },
+
+            _ = &mut drain_deadline, if tripped => {
+                warn!(
+                    "could not process received changes before shutdown, dropping {} queued change(s)",
+                    queue.len()
+                );
                 break;
             }
         };
@@ -856,21 +1065,26 @@ pub async fn handle_changes(
             continue;
         }
 
-        if let Some(mut seqs) = change.seqs().cloned() {
-            let v = *change.versions().start();
-            if let Some(seen_seqs) = seen.get(&(change.actor_id, v)) {
-                if seqs.all(|seq| seen_seqs.contains(&seq)) {
+        // resynced changes are wanted even if we've seen or booked them
+        let resync = matches!(src, ChangeSource::Resync);
+
+        if !resync {
+            if let Some(mut seqs) = change.seqs().cloned() {
+                let v = *change.versions().start();
+                if let Some(seen_seqs) = seen.get(&(change.actor_id, v)) {
+                    if seqs.all(|seq| seen_seqs.contains(&seq)) {
+                        continue;
+                    }
+                }
+            } else {
+                // empty versions
+                if change
+                    .versions()
+                    .all(|v| seen.contains_key(&(change.actor_id, v)))
+                {
                     continue;
                 }
             }
-        } else {
-            // empty versions
-            if change
-                .versions()
-                .all(|v| seen.contains_key(&(change.actor_id, v)))
-            {
-                continue;
-            }
         }
 
         let recv_lag = change
@@ -889,7 +1103,7 @@ pub async fn handle_changes(
                 .cloned()
         };
 
-        if let Some(booked) = booked {
+        if let Some(booked) = booked.filter(|_| !resync) {
             if booked
                 .read("handle_change(contains?)", change.actor_id.as_simple())
                 .await
@@ -964,8 +1178,9 @@ pub async fn handle_sync(
     agent: &Agent,
     bookie: &Bookie,
     transport: &Transport,
-) -> Result<(), SyncClientError> {
+) -> Result<usize, SyncClientError> {
     let sync_state = generate_sync(bookie, agent.actor_id()).await;
+    agent.record_sync_needs(sync_state.need_len());
 
     for (actor_id, needed) in sync_state.need.iter() {
         gauge!("corro.sync.client.needed", "actor_id" => actor_id.to_string())
@@ -977,6 +1192,7 @@ pub async fn handle_sync(
 
     let chosen: Vec<(ActorId, SocketAddr)> = {
         let candidates = {
+            let now = Instant::now();
             let members = agent.members().read();
 
             members
@@ -993,13 +1209,35 @@ pub async fn handle_sync(
                         state.ring.unwrap_or(255),
                         state.addr,
                         state.last_sync_ts,
+                        state.sync_score(sync_state.need_len_for_actor(id)),
+                        state.in_sync_cooldown(now),
                     )
                 })
-                .collect::<Vec<(ActorId, u8, SocketAddr, Option<Timestamp>)>>()
+                .collect::<Vec<(ActorId, u8, SocketAddr, Option<Timestamp>, f64, bool)>>()
         };
 
         if candidates.is_empty() {
-            return Ok(());
+            if agent.config().gossip.bootstrap.is_empty() {
+                // nobody to join or sync with, we're alone
+                agent.set_joined();
+                agent.set_synced();
+            }
+            return Ok(0);
+        }
+
+        // skip peers we just failed to sync with
+        let total = candidates.len();
+        let candidates = candidates
+            .into_iter()
+            .filter(|(_, _, _, _, _, in_cooldown)| !in_cooldown)
+            .map(|(id, ring, addr, last_sync_ts, score, _)| (id, ring, addr, last_sync_ts, score))
+            .collect::<Vec<(ActorId, u8, SocketAddr, Option<Timestamp>, f64)>>();
+
+        gauge!("corro.sync.client.cooldown").set((total - candidates.len()) as f64);
+
+        if candidates.is_empty() {
+            debug!("all {total} sync candidates are cooling down after failed syncs");
+            return Ok(0);
         }
 
         debug!("found {} candidates to synchronize with", candidates.len());
@@ -1013,27 +1251,65 @@ pub async fn handle_sync(
             .into_iter()
             .choose_multiple(&mut rng, desired_count * 2);
 
+        let config = agent.config();
+        let sync_conf = &config.sync;
+        let scoring = sync_conf.scoring;
+
+        // the bind address is often unspecified, prefer the advertised one
+        let our_ip = agent.external_addr().unwrap_or(agent.gossip_addr()).ip();
+        let subnet_weight = if our_ip.is_unspecified() {
+            0.0
+        } else {
+            sync_conf.same_subnet_weight.max(0.0)
+        };
+        let in_our_subnet = |addr: &SocketAddr| {
+            subnet_weight > 0.0
+                && same_subnet(
+                    our_ip,
+                    addr.ip(),
+                    sync_conf.subnet_prefix_v4,
+                    sync_conf.subnet_prefix_v6,
+                )
+        };
+        let weight = |addr: &SocketAddr| {
+            if in_our_subnet(addr) {
+                1.0 + subnet_weight
+            } else {
+                1.0
+            }
+        };
+
         choices.sort_by(|a, b| {
-            // most missing actors first
-            sync_state
-                .need_len_for_actor(&b.0)
-                .cmp(&sync_state.need_len_for_actor(&a.0))
-                // if equal, look at last sync time
-                .then_with(|| a.3.cmp(&b.3))
-                // if equal, look at proximity (via `ring`)
-                .then_with(|| a.1.cmp(&b.1))
+            match scoring {
+                // most missing actors first
+                SyncScoring::NeedOnly => {
+                    let need_a = sync_state.need_len_for_actor(&a.0) as f64 * weight(&a.2);
+                    let need_b = sync_state.need_len_for_actor(&b.0) as f64 * weight(&b.2);
+                    need_b.total_cmp(&need_a)
+                }
+                // best need / latency / failures trade-off first
+                SyncScoring::NeedAndLatency => {
+                    (b.4 * weight(&b.2)).total_cmp(&(a.4 * weight(&a.2)))
+                }
+            }
+            // if equal, prefer peers in our subnet
+            .then_with(|| in_our_subnet(&b.2).cmp(&in_our_subnet(&a.2)))
+            // if equal, look at last sync time
+            .then_with(|| a.3.cmp(&b.3))
+            // if equal, look at proximity (via `ring`)
+            .then_with(|| a.1.cmp(&b.1))
         });
 
         choices.truncate(desired_count);
         choices
             .into_iter()
-            .map(|(actor_id, _, addr, _)| (actor_id, addr))
+            .map(|(actor_id, _, addr, _, _)| (actor_id, addr))
             .collect()
     };
 
     trace!("Sync set: {chosen:?}");
     if chosen.is_empty() {
-        return Ok(());
+        return Ok(0);
     }
 
     let mut last_cleared: HashMap<ActorId, Option<Timestamp>> = HashMap::new();
@@ -1048,18 +1324,74 @@ pub async fn handle_sync(
     }
 
     let start = Instant::now();
-    let n = match parallel_sync(agent, transport, chosen.clone(), sync_state, last_cleared).await {
+    let res = parallel_sync(agent, transport, chosen.clone(), sync_state, last_cleared).await;
+
+    // peers that shed our sync may have pointed us at a caught-up member
+    let alternates: Vec<(ActorId, SocketAddr)> = {
+        let mut members = agent.members().write();
+        let ids = chosen
+            .iter()
+            .filter_map(|(actor_id, _)| members.take_sync_alternate(actor_id))
+            .filter(|id| *id != agent.actor_id() && !chosen.iter().any(|(c, _)| c == id))
+            .collect::<HashSet<ActorId>>();
+        ids.into_iter()
+            .filter_map(|id| members.states.get(&id).map(|state| (id, state.addr)))
+            .collect()
+    };
+
+    let mut first_err = None;
+    let mut n = match res {
         Ok(n) => n,
-        Err(e) => {
+        Err(e) if alternates.is_empty() => {
             error!("failed to execute parallel sync: {e:?}");
             return Err(e.into());
         }
+        Err(e) => {
+            warn!("failed to execute parallel sync, trying suggested alternates: {e:?}");
+            first_err = Some(e);
+            0
+        }
     };
 
+    if !alternates.is_empty() {
+        counter!("corro.sync.client.redirected").increment(alternates.len() as u64);
+        debug!("redirected to sync with alternates: {alternates:?}");
+
+        let sync_state = generate_sync(bookie, agent.actor_id()).await;
+        let last_cleared = {
+            let members = agent.members().read();
+            alternates
+                .iter()
+                .map(|(actor_id, _)| {
+                    (
+                        *actor_id,
+                        members
+                            .states
+                            .get(actor_id)
+                            .and_then(|state| state.last_empty_ts),
+                    )
+                })
+                .collect()
+        };
+
+        match parallel_sync(agent, transport, alternates, sync_state, last_cleared).await {
+            Ok(alt_n) => n += alt_n,
+            Err(e) => match first_err {
+                Some(first) => {
+                    error!("failed to execute parallel sync with alternates: {e:?}");
+                    return Err(first.into());
+                }
+                None => warn!("failed to execute parallel sync with alternates: {e:?}"),
+            },
+        }
+    }
+
+    agent.set_synced();
+
     let elapsed = start.elapsed();
     if n > 0 {
         info!(
-            "synced {n} changes w/ {} in {}s @ {} changes/s",
+            "received {n} changes from {} in {}s @ {} changes/s",
             chosen
                 .clone()
                 .into_iter()
@@ -1070,13 +1402,98 @@ pub async fn handle_sync(
             n as f64 / elapsed.as_secs_f64()
         );
     }
-    Ok(())
+    Ok(n)
+}
+
+/// Whether two addresses share their first `prefix_v4` bits (IPv4) or
+/// `prefix_v6` bits (IPv6), addresses of different families never do
+fn same_subnet(a: IpAddr, b: IpAddr, prefix_v4: u8, prefix_v6: u8) -> bool {
+    match (a, b) {
+        (IpAddr::V4(a), IpAddr::V4(b)) => {
+            let mask = u32::MAX
+                .checked_shl(32 - u32::from(cmp::min(prefix_v4, 32)))
+                .unwrap_or(0);
+            u32::from(a) & mask == u32::from(b) & mask
+        }
+        (IpAddr::V6(a), IpAddr::V6(b)) => {
+            let mask = u128::MAX
+                .checked_shl(128 - u32::from(cmp::min(prefix_v6, 128)))
+                .unwrap_or(0);
+            u128::from(a) & mask == u128::from(b) & mask
+        }
+        _ => false,
+    }
+}
+
+/// Pull every change a peer has, as if we had nothing, and apply them
+/// through the regular sync path. Versions bookkeeping already knows
+/// about are still skipped when applying.
+///
+/// The peer is picked at random among sync candidates unless `peer` is
+/// given. Returns the peer and the number of changes pulled.
+pub async fn handle_resync(
+    agent: &Agent,
+    bookie: &Bookie,
+    transport: &Transport,
+    peer: Option<ActorId>,
+) -> Result<(ActorId, usize), SyncClientError> {
+    let (actor_id, addr) = {
+        let members = agent.members().read();
+        let mut candidates = members.states.iter().filter(|(id, state)| {
+            **id != agent.actor_id() && state.cluster_id == agent.cluster_id()
+        });
+
+        match peer {
+            Some(peer) => candidates
+                .find(|(id, _)| **id == peer)
+                .map(|(id, state)| (*id, state.addr))
+                .ok_or(SyncClientError::NotCandidate(peer))?,
+            None => candidates
+                .choose(&mut StdRng::from_entropy())
+                .map(|(id, state)| (*id, state.addr))
+                .ok_or(SyncClientError::NoCandidate)?,
+        }
+    };
+
+    let actors: HashSet<ActorId> = {
+        let known = bookie
+            .read::<&str, _>("handle_resync", None)
+            .await
+            .keys()
+            .copied()
+            .collect::<Vec<_>>();
+        let members = agent.members().read();
+        known
+            .into_iter()
+            .chain(members.states.keys().copied())
+            .collect()
+    };
+
+    info!(%actor_id, %addr, "re-syncing everything from {} actor(s)", actors.len());
+    counter!("corro.sync.client.resync").increment(1);
+
+    let sync_state = SyncStateV1::need_everything(agent.actor_id(), actors);
+
+    let start = Instant::now();
+    let n = parallel_sync_from(
+        agent,
+        transport,
+        vec![(actor_id, addr)],
+        sync_state,
+        HashMap::new(),
+        ChangeSource::Resync,
+    )
+    .await?;
+
+    info!(%actor_id, "re-synced, received {n} changes in {:?}", start.elapsed());
+
+    Ok((actor_id, n))
 }
 
 #[cfg(test)]
 mod tests {
     use crate::agent::setup;
-    use crate::api::public::api_v1_db_schema;
+    use crate::api::public::{api_v1_db_schema, SchemaParams};
 
     use super::*;
     use axum::{http::StatusCode, Extension, Json};
@@ -1090,6 +1507,23 @@ mod tests {
     use tokio::sync::Semaphore;
     use tokio::time::{timeout, Duration};
 
+    #[test]
+    fn test_same_subnet() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+
+        assert!(same_subnet(ip("10.0.1.5"), ip("10.0.1.200"), 24, 64));
+        assert!(!same_subnet(ip("10.0.1.5"), ip("10.0.2.5"), 24, 64));
+        assert!(same_subnet(ip("10.0.1.5"), ip("10.0.2.5"), 16, 64));
+        assert!(same_subnet(ip("10.0.1.5"), ip("192.168.0.1"), 0, 64));
+        assert!(!same_subnet(ip("10.0.1.5"), ip("10.0.1.6"), 32, 64));
+
+        assert!(same_subnet(ip("fd00:1::1"), ip("fd00:1::ffff"), 24, 64));
+        assert!(!same_subnet(ip("fd00:1::1"), ip("fd00:2::1"), 24, 64));
+
+        // families never match
+        assert!(!same_subnet(ip("10.0.1.5"), ip("::ffff:10.0.1.5"), 0, 0));
+    }
+
     #[test]
     fn ensure_truncate_works() -> eyre::Result<()> {
         let tmpdir = tempfile::tempdir()?;
@@ -1124,8 +1558,12 @@ mod tests {
 
         let (agent, agent_options) = setup(config, tripwire.clone()).await?;
 
-        let (status_code, _res) =
-            api_v1_db_schema(Extension(agent.clone()), Json(vec![TEST_SCHEMA.to_owned()])).await;
+        let (status_code, _res) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::extract::Query(SchemaParams::default()),
+            Json(vec![TEST_SCHEMA.to_owned()]),
+        )
+        .await;
         assert_eq!(status_code, StatusCode::OK);
 
         let other_actor = ActorId(uuid::Uuid::new_v4());
@@ -1169,6 +1607,7 @@ mod tests {
                             last_seq: CrsqlSeq(0),
                             ts: agent.clock().new_timestamp().into(),
                         },
+                        trace_id: None,
                     },
                     ChangeSource::Sync,
                 );
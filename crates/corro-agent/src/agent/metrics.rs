@@ -1,15 +1,66 @@
-use crate::transport::Transport;
-use corro_types::{actor::ActorId, agent::Agent};
+use crate::{
+    agent::{uni, util::jittered_period},
+    transport::Transport,
+};
+use corro_types::{actor::ActorId, agent::Agent, broadcast::Timestamp};
 use metrics::gauge;
-use std::time::Duration;
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder};
+use metrics_util::MetricKindMask;
+use std::{collections::BTreeMap, net::SocketAddr, time::Duration};
 use tokio::task::block_in_place;
 use tracing::error;
 
+/// Install a Prometheus recorder for the `metrics` facade, serving
+/// scrapes over HTTP on `addr`, every metric carrying `static_labels`
+pub fn setup_prometheus(
+    addr: SocketAddr,
+    static_labels: &BTreeMap<String, String>,
+) -> eyre::Result<()> {
+    prometheus_builder(static_labels)?
+        .with_http_listener(addr)
+        .install()?;
+    Ok(())
+}
+
+fn prometheus_builder(static_labels: &BTreeMap<String, String>) -> eyre::Result<PrometheusBuilder> {
+    let builder = static_labels
+        .iter()
+        .fold(PrometheusBuilder::new(), |builder, (key, value)| {
+            builder.add_global_label(key, value)
+        });
+
+    Ok(builder
+        .idle_timeout(MetricKindMask::GAUGE, Some(Duration::from_secs(120)))
+        .set_buckets_for_metric(
+            Matcher::Suffix("chunk_size".into()),
+            &[1.0, 10.0, 75.0, 250.0, 375.0, 500.0, 650.0],
+        )?
+        .set_buckets(&[
+            0.001, // 1ms
+            0.005, // 5ms
+            0.025, // 25ms
+            0.050, // 50ms
+            0.100, // 100ms
+            0.200, // 200ms
+            1.0,   // 1s
+            2.0,   // 2s
+            3.0,   // 3s
+            4.0,   // 4s
+            5.0,   // 5s
+            10.0,  // 10s :screaming:
+            30.0, 60.0,
+        ])?)
+}
+
 pub async fn metrics_loop(agent: Agent, transport: Transport) {
-    let mut metrics_interval = tokio::time::interval(Duration::from_secs(10));
+    let mut metrics_interval =
+        tokio::time::interval(jittered_period(&agent, Duration::from_secs(10)));
 
     loop {
         metrics_interval.tick().await;
+        if agent.pool().is_closed() {
+            break;
+        }
 
         block_in_place(|| collect_metrics(&agent, &transport));
     }
@@ -18,6 +69,19 @@ pub async fn metrics_loop(agent: Agent, transport: Transport) {
 pub fn collect_metrics(agent: &Agent, transport: &Transport) {
     agent.pool().emit_metrics();
     transport.emit_metrics();
+    uni::record_handler_in_flight(agent);
+    gauge!("corro.broadcast.handler.capacity").set(agent.limits().gossip_capacity as f64);
+
+    {
+        let now = Timestamp::from(agent.clock().new_timestamp());
+        let members = agent.members().read();
+        for (actor_id, state) in members.states.iter() {
+            if let Some(ago) = state.since_last_sync(now) {
+                gauge!("corro.sync.last_success.seconds_ago", "actor_id" => actor_id.to_string())
+                    .set(ago.as_secs_f64());
+            }
+        }
+    }
 
     let schema = agent.schema().read();
 
@@ -106,3 +170,36 @@ pub fn collect_metrics(agent: &Agent, transport: &Transport) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use metrics::counter;
+
+    use super::*;
+
+    #[test]
+    fn test_static_labels() -> eyre::Result<()> {
+        let static_labels: BTreeMap<String, String> = [
+            ("region".to_owned(), "ams".to_owned()),
+            ("env".to_owned(), "staging".to_owned()),
+        ]
+        .into();
+        let recorder = prometheus_builder(&static_labels)?.build_recorder();
+        let handle = recorder.handle();
+
+        metrics::with_local_recorder(&recorder, || {
+            counter!("corro.test.labels", "actor_id" => "abc").increment(1);
+        });
+
+        let rendered = handle.render();
+        let line = rendered
+            .lines()
+            .find(|line| line.starts_with("corro_test_labels{"))
+            .expect("counter was not rendered");
+        for label in [r#"actor_id="abc""#, r#"env="staging""#, r#"region="ams""#] {
+            assert!(line.contains(label), "missing {label} in: {line}");
+        }
+
+        Ok(())
+    }
+}
@@ -11,6 +11,7 @@ mod handlers;
 mod metrics;
 mod run_root;
 mod setup;
+pub mod shutdown;
 mod uni;
 pub mod util;
 
@@ -25,24 +26,29 @@ use uuid::Uuid;
 
 // Public exports
 pub use error::{SyncClientError, SyncRecvError};
+pub use handlers::{announce_now, handle_resync};
 pub use run_root::start_with_config;
 pub use setup::{setup, AgentOptions};
 pub use util::process_multiple_changes;
 pub use uni::spawn_unipayload_handler;
 
 pub const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(300);
+pub const BOOTSTRAP_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
 #[cfg(test)]
 pub const MAX_SYNC_BACKOFF: Duration = Duration::from_secs(2);
 #[cfg(not(test))]
 pub const MAX_SYNC_BACKOFF: Duration = Duration::from_secs(15);
-pub const RANDOM_NODES_CHOICES: usize = 10;
 
 pub const CHECK_EMPTIES_TO_INSERT_AFTER: Duration = Duration::from_secs(120);
 pub const TO_CLEAR_COUNT: usize = 1000;
+/// Consecutive gossip send failures after which a member is marked suspect
+pub const SUSPECT_AFTER_SEND_FAILURES: u32 = 5;
 
 pub type BcastCache = Arc<RwLock<HashMap<Uuid, Sender<(Bytes, QueryEventMeta)>>>>;
 
-#[derive(Clone)]
+/// Executor for hyper servers, spawning counted tasks so shutdown can
+/// wait for them
+#[derive(Clone, Default)]
 pub struct CountedExecutor;
 
 impl<F> hyper::rt::Executor<F> for CountedExecutor
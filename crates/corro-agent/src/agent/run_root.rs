@@ -1,11 +1,13 @@
 //! Start the root agent tasks
 
-use std::time::Instant;
+use std::{cmp, time::Instant};
 
 use crate::{
     agent::{
         handlers::{self, spawn_handle_db_maintenance},
-        metrics, setup, util, AgentOptions,
+        metrics, setup,
+        shutdown::{ShutdownStage, ShutdownStages},
+        util, AgentOptions,
     },
     broadcast::runtime_loop,
 };
@@ -14,12 +16,13 @@ use corro_types::{
     agent::{Agent, BookedVersions, Bookie},
     base::CrsqlSeq,
     channel::bounded,
-    config::{Config, PerfConfig},
+    config::{Config, PerfConfig, PrometheusConfig},
 };
 
+use ::metrics::histogram;
 use futures::{FutureExt, StreamExt, TryStreamExt};
 use spawn::spawn_counted;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 use tripwire::Tripwire;
 
 /// Start a new agent with an existing configuration
@@ -67,6 +70,9 @@ async fn run(agent: Agent, opts: AgentOptions, pconf: PerfConfig) -> eyre::Resul
         );
     }
 
+    // subsystems are torn down in order once the tripwire trips
+    let stages = ShutdownStages::spawn(&agent, tripwire.clone());
+
     let (to_send_tx, to_send_rx) = bounded(pconf.to_send_channel_len, "to_send");
     let (notifications_tx, notifications_rx) =
         bounded(pconf.notifications_channel_len, "notifications");
@@ -74,28 +80,42 @@ async fn run(agent: Agent, opts: AgentOptions, pconf: PerfConfig) -> eyre::Resul
     //// Start the main SWIM runtime loop
     runtime_loop(
         // here the agent already has the current cluster_id, we don't need to pass one
-        agent.actor(None),
+        util::swim_identity(&agent).await,
         agent.clone(),
         transport.clone(),
         rx_foca,
         rx_bcast,
         to_send_tx,
         notifications_tx,
-        tripwire.clone(),
+        &stages,
     );
 
     //// Update member connection RTTs
     handlers::spawn_rtt_handler(&agent, rtt_rx);
 
-    handlers::spawn_swim_announcer(&agent, gossip_addr, tripwire.clone());
+    // peers know us by our external address, if any, that's the one to
+    // filter out of bootstrap addresses
+    let advertised_addr = agent.external_addr().unwrap_or(gossip_addr);
+    if advertised_addr != gossip_addr {
+        info!("Advertising gossip address {advertised_addr} (bound to {gossip_addr})");
+    }
+    handlers::spawn_swim_announcer(&agent, advertised_addr, tripwire.clone());
 
     // Load existing cluster members into the SWIM runtime
     util::initialise_foca(&agent).await;
 
+    let bookie = Bookie::new_with_registry(Default::default(), lock_registry);
+    {
+        let mut w = bookie.write::<&str, _>("init", None).await;
+        w.insert(agent.actor_id(), agent.booked().clone());
+    }
+
     // Setup client http API
     util::setup_http_api_handler(
         &agent,
-        &tripwire,
+        &bookie,
+        &transport,
+        &stages,
         subs_bcast_cache,
         updates_bcast_cache,
         &subs_manager,
@@ -105,24 +125,31 @@ async fn run(agent: Agent, opts: AgentOptions, pconf: PerfConfig) -> eyre::Resul
 
     tokio::spawn(util::clear_buffered_meta_loop(agent.clone(), rx_clear_buf));
 
+    // embedders that didn't configure an exporter bring their own recorder
+    let telemetry = &agent.config().telemetry;
+    if let Some(PrometheusConfig { bind_addr }) = telemetry.prometheus {
+        match metrics::setup_prometheus(bind_addr, &telemetry.static_labels) {
+            Ok(()) => info!("Serving Prometheus metrics on {bind_addr}"),
+            Err(e) => warn!("could not setup prometheus exporter on {bind_addr}: {e}"),
+        }
+    }
+
     tokio::spawn(metrics::metrics_loop(agent.clone(), transport.clone()));
+    let send_failures = handlers::SendFailures::default();
     tokio::spawn(handlers::handle_gossip_to_send(
         transport.clone(),
+        agent.tx_foca().clone(),
+        send_failures.clone(),
         to_send_rx,
     ));
     tokio::spawn(handlers::handle_notifications(
         agent.clone(),
         notifications_rx,
+        send_failures,
     ));
 
     spawn_handle_db_maintenance(&agent);
 
-    let bookie = Bookie::new_with_registry(Default::default(), lock_registry);
-    {
-        let mut w = bookie.write::<&str, _>("init", None).await;
-        w.insert(agent.actor_id(), agent.booked().clone());
-    }
-
     let start = Instant::now();
     {
         let conn = agent.pool().read().await?;
@@ -135,6 +162,9 @@ async fn run(agent: Agent, opts: AgentOptions, pconf: PerfConfig) -> eyre::Resul
         drop(conn);
 
         let pool = agent.pool();
+        let batch_size = pconf.bookkeeping_load_batch_size;
+        let actors_count = actor_ids.len();
+        info!("Loading bookkeeping for {actors_count} actors");
 
         let mut buf = futures::stream::iter(
             actor_ids
@@ -148,7 +178,7 @@ async fn run(agent: Agent, opts: AgentOptions, pconf: PerfConfig) -> eyre::Resul
                             let conn = pool.read().await?;
 
                             tokio::task::block_in_place(|| {
-                                BookedVersions::from_conn(&conn, actor_id)
+                                BookedVersions::from_conn_in_batches(&conn, actor_id, batch_size)
                                     .map(|bv| (actor_id, bv))
                                     .map_err(eyre::Report::from)
                             })
@@ -157,9 +187,18 @@ async fn run(agent: Agent, opts: AgentOptions, pconf: PerfConfig) -> eyre::Resul
                     }
                 }),
         )
-        .buffer_unordered(4);
+        .buffer_unordered(cmp::max(pconf.bookkeeping_load_concurrency, 1));
 
+        let mut loaded = 0;
         while let Some((actor_id, bv)) = TryStreamExt::try_next(&mut buf).await? {
+            loaded += 1;
+            if loaded % 100 == 0 {
+                info!(
+                    "Loaded bookkeeping for {loaded}/{actors_count} actors in {:?}",
+                    start.elapsed()
+                );
+            }
+
             for (version, partial) in bv.partials.iter() {
                 let gaps_count = partial.seqs.gaps(&(CrsqlSeq(0)..=partial.last_seq)).count();
 
@@ -183,40 +222,67 @@ async fn run(agent: Agent, opts: AgentOptions, pconf: PerfConfig) -> eyre::Resul
     }
 
     info!("Bookkeeping fully loaded in {:?}", start.elapsed());
+    histogram!("corro.agent.bookkeeping.load.seconds").record(start.elapsed().as_secs_f64());
 
     spawn_counted(
-        util::sync_loop(
-            agent.clone(),
-            bookie.clone(),
-            transport.clone(),
-            tripwire.clone(),
-        )
-        .inspect(|_| info!("corrosion agent sync loop is done")),
+        stages
+            .track(
+                ShutdownStage::Accept,
+                util::sync_loop(
+                    agent.clone(),
+                    bookie.clone(),
+                    transport.clone(),
+                    tripwire.clone(),
+                ),
+            )
+            .inspect(|_| info!("corrosion agent sync loop is done")),
     );
 
     spawn_counted(
-        util::apply_fully_buffered_changes_loop(
-            agent.clone(),
-            bookie.clone(),
-            rx_apply,
-            tripwire.clone(),
-        )
-        .inspect(|_| info!("corrosion buffered changes loop is done")),
+        stages
+            .track(
+                ShutdownStage::Drain,
+                util::apply_fully_buffered_changes_loop(
+                    agent.clone(),
+                    bookie.clone(),
+                    rx_apply,
+                    stages.tripwire(ShutdownStage::Drain),
+                ),
+            )
+            .inspect(|_| info!("corrosion buffered changes loop is done")),
     );
 
     info!("Starting peer API on udp/{gossip_addr} (QUIC)");
 
     //// Start an incoming (corrosion) connection handler.  This
     //// future tree spawns additional message type sub-handlers
-    handlers::spawn_gossipserver_handler(&agent, &bookie, &tripwire, gossip_server_endpoint);
+    handlers::spawn_gossipserver_handler(&agent, &bookie, &stages, gossip_server_endpoint);
 
     spawn_counted(
-        handlers::handle_changes(agent.clone(), bookie.clone(), rx_changes, tripwire.clone())
+        stages
+            .track(
+                ShutdownStage::Drain,
+                handlers::handle_changes(
+                    agent.clone(),
+                    bookie.clone(),
+                    rx_changes,
+                    stages.tripwire(ShutdownStage::Drain),
+                ),
+            )
             .inspect(|_| info!("corrosion handle changes loop is done")),
     );
 
     spawn_counted(
-        handlers::handle_emptyset(agent.clone(), bookie.clone(), rx_emptyset, tripwire.clone())
+        stages
+            .track(
+                ShutdownStage::Drain,
+                handlers::handle_emptyset(
+                    agent.clone(),
+                    bookie.clone(),
+                    rx_emptyset,
+                    stages.tripwire(ShutdownStage::Drain),
+                ),
+            )
             .inspect(|_| info!("corrosion handle emptyset loop is done")),
     );
 
@@ -7,6 +7,7 @@ use indexmap::IndexMap;
 use metrics::counter;
 use parking_lot::RwLock;
 use rusqlite::{Connection, OptionalExtension};
+use socket2::{Domain, Protocol, Socket, Type};
 use std::{
     net::SocketAddr,
     ops::{DerefMut, RangeInclusive},
@@ -41,7 +42,7 @@ use corro_types::{
     base::Version,
     broadcast::{BroadcastInput, ChangeSource, ChangeV1, FocaInput},
     channel::{bounded, CorroReceiver},
-    config::Config,
+    config::{ActorIdPolicy, Config, ListenerConfig},
     members::Members,
     pubsub::{Matcher, SubsManager},
     schema::{init_schema, Schema},
@@ -67,10 +68,80 @@ pub struct AgentOptions {
     pub tripwire: Tripwire,
 }
 
+/// Reconcile the configured actor id with the site id persisted in the
+/// database, according to the configured policy
+fn reconcile_actor_id(
+    conn: &Connection,
+    configured: Option<ActorId>,
+    policy: ActorIdPolicy,
+) -> eyre::Result<ActorId> {
+    let persisted: ActorId = conn.query_row("SELECT crsql_site_id();", [], |row| row.get(0))?;
+
+    let configured = match configured {
+        Some(configured) if configured != persisted => configured,
+        _ => return Ok(persisted),
+    };
+
+    match policy {
+        ActorIdPolicy::Override => {
+            warn!(%persisted, %configured, "overriding persisted site id with configured actor id");
+            override_site_id(conn, configured)?;
+            Ok(configured)
+        }
+        ActorIdPolicy::AdoptPersisted => {
+            warn!(%persisted, %configured, "configured actor id differs from persisted site id, adopting persisted site id");
+            Ok(persisted)
+        }
+        ActorIdPolicy::FailOnMismatch => {
+            eyre::bail!(
+                "configured actor id {configured} does not match persisted site id {persisted}"
+            )
+        }
+    }
+}
+
+/// Replace the database's own site id, taking over the clock entries of the
+/// new site id if it was already known
+fn override_site_id(conn: &Connection, site_id: ActorId) -> rusqlite::Result<()> {
+    let tx = conn.unchecked_transaction()?;
+
+    let ordinal: Option<i64> = tx
+        .query_row(
+            "DELETE FROM crsql_site_id WHERE site_id = ? RETURNING ordinal",
+            [site_id],
+            |row| row.get(0),
+        )
+        .optional()?;
+
+    tx.execute(
+        "INSERT OR REPLACE INTO crsql_site_id (ordinal, site_id) VALUES (0, ?)",
+        [site_id],
+    )?;
+
+    if let Some(ordinal) = ordinal {
+        let tables: Vec<String> = tx
+            .prepare("SELECT name FROM sqlite_schema WHERE type = 'table' AND name LIKE '%__crsql_clock'")?
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for table in tables {
+            let n = tx.execute(
+                &format!("UPDATE \"{table}\" SET site_id = 0 WHERE site_id = ?"),
+                [ordinal],
+            )?;
+            debug!("updated {n} rows in {table}");
+        }
+    }
+
+    tx.commit()
+}
+
 /// Setup an agent runtime and state with a configuration
 pub async fn setup(conf: Config, tripwire: Tripwire) -> eyre::Result<(Agent, AgentOptions)> {
     debug!("setting up corrosion @ {}", conf.db.path);
 
+    conf.validate()?;
+
     if let Some(parent) = conf.db.path.parent() {
         tokio::fs::create_dir_all(parent).await?;
     }
@@ -84,16 +155,17 @@ pub async fn setup(conf: Config, tripwire: Tripwire) -> eyre::Result<(Agent, Age
         db_conn.execute_batch("PRAGMA auto_vacuum = INCREMENTAL")?;
 
         let conn = CrConn::init(db_conn)?;
-        conn.query_row("SELECT crsql_site_id();", [], |row| {
-            row.get::<_, ActorId>(0)
-        })
-    }?;
+        reconcile_actor_id(&conn, conf.actor_id, conf.actor_id_policy)?
+    };
 
     info!("Actor ID: {actor_id}");
 
     let write_sema = Arc::new(Semaphore::new(1));
 
-    let pool = SplitPool::create(&conf.db.path, write_sema.clone()).await?;
+    info!("Using synchronous = {}", conf.db.synchronous.as_str());
+    let pool =
+        SplitPool::create_with_synchronous(&conf.db.path, write_sema.clone(), conf.db.synchronous)
+            .await?;
 
     let clock = Arc::new(
         uhlc::HLCBuilder::default()
@@ -139,6 +211,20 @@ pub async fn setup(conf: Config, tripwire: Tripwire) -> eyre::Result<(Agent, Age
 
     info!("Cluster ID: {cluster_id}");
 
+    info!(
+        "Channel capacities: apply={}, changes={}, empties={}, to_send={}, notifications={}, schedule={}, clearbuf={}, bcast={}, foca={}, rtt={}",
+        conf.perf.apply_channel_len,
+        conf.perf.changes_channel_len,
+        conf.perf.empties_channel_len,
+        conf.perf.to_send_channel_len,
+        conf.perf.notifications_channel_len,
+        conf.perf.schedule_channel_len,
+        conf.perf.clearbuf_channel_len,
+        conf.perf.bcast_channel_len,
+        conf.perf.foca_channel_len,
+        conf.perf.rtt_channel_len,
+    );
+
     let (tx_apply, rx_apply) = bounded(conf.perf.apply_channel_len, "apply");
     let (tx_clear_buf, rx_clear_buf) = bounded(conf.perf.clearbuf_channel_len, "clear_buf");
 
@@ -149,19 +235,19 @@ pub async fn setup(conf: Config, tripwire: Tripwire) -> eyre::Result<(Agent, Age
 
     // RTT handling interacts with the tokio ReceiverStream and as
     // such needs a raw tokio channel
-    let (rtt_tx, rtt_rx) = tokio_channel(128);
+    let (rtt_tx, rtt_rx) = tokio_channel(conf.perf.rtt_channel_len);
 
     let transport = Transport::new(&conf.gossip, rtt_tx).await?;
 
     let mut api_listeners = Vec::with_capacity(conf.api.bind_addr.len());
     for addr in conf.api.bind_addr.iter() {
-        api_listeners.push(TcpListener::bind(addr).await?);
+        api_listeners.push(bind_api_listener(*addr, &conf.api.listener)?);
     }
     let api_addr = api_listeners.first().unwrap().local_addr()?;
 
     let (tx_bcast, rx_bcast) = bounded(conf.perf.bcast_channel_len, "bcast");
     let (tx_changes, rx_changes) = bounded(conf.perf.changes_channel_len, "changes");
-    let (tx_emptyset, rx_emptyset) = bounded(conf.perf.changes_channel_len, "emptyset");
+    let (tx_emptyset, rx_emptyset) = bounded(conf.perf.empties_channel_len, "emptyset");
     let (tx_foca, rx_foca) = bounded(conf.perf.foca_channel_len, "foca");
 
     let lock_registry = LockRegistry::default();
@@ -274,6 +360,25 @@ pub async fn setup(conf: Config, tripwire: Tripwire) -> eyre::Result<(Agent, Age
     Ok((agent, opts))
 }
 
+/// Bind an API listener with the configured socket options
+fn bind_api_listener(addr: SocketAddr, config: &ListenerConfig) -> eyre::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+
+    socket.set_reuse_address(config.reuse_addr)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(config.reuse_port)?;
+    #[cfg(not(unix))]
+    if config.reuse_port {
+        warn!("SO_REUSEPORT is not supported on this platform, ignoring");
+    }
+    socket.set_nonblocking(true)?;
+
+    socket.bind(&addr.into())?;
+    socket.listen(config.backlog.try_into().unwrap_or(i32::MAX))?;
+
+    Ok(TcpListener::from_std(socket.into())?)
+}
+
 /// Initialise subscription state and tasks
 ///
 /// 1. Get subscriptions state directory from config
@@ -333,3 +438,85 @@ async fn setup_spawn_subscriptions(
 
     Ok(Arc::new(TokioRwLock::new(subs_bcast_cache)))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn own_site_id(conn: &Connection) -> rusqlite::Result<ActorId> {
+        conn.query_row(
+            "SELECT site_id FROM crsql_site_id WHERE ordinal = 0",
+            [],
+            |row| row.get(0),
+        )
+    }
+
+    #[test]
+    fn reconcile_actor_id_policies() -> eyre::Result<()> {
+        let conn = CrConn::init(Connection::open_in_memory()?)?;
+        let persisted = own_site_id(&conn)?;
+        let configured = ActorId(uuid::Uuid::new_v4());
+
+        // nothing configured or matching, nothing to reconcile
+        for policy in [
+            ActorIdPolicy::Override,
+            ActorIdPolicy::AdoptPersisted,
+            ActorIdPolicy::FailOnMismatch,
+        ] {
+            assert_eq!(reconcile_actor_id(&conn, None, policy)?, persisted);
+            assert_eq!(
+                reconcile_actor_id(&conn, Some(persisted), policy)?,
+                persisted
+            );
+        }
+
+        assert!(
+            reconcile_actor_id(&conn, Some(configured), ActorIdPolicy::FailOnMismatch).is_err()
+        );
+        assert_eq!(own_site_id(&conn)?, persisted);
+
+        assert_eq!(
+            reconcile_actor_id(&conn, Some(configured), ActorIdPolicy::AdoptPersisted)?,
+            persisted
+        );
+        assert_eq!(own_site_id(&conn)?, persisted);
+
+        assert_eq!(
+            reconcile_actor_id(&conn, Some(configured), ActorIdPolicy::Override)?,
+            configured
+        );
+        assert_eq!(own_site_id(&conn)?, configured);
+
+        Ok(())
+    }
+
+    #[test]
+    fn override_site_id_takes_over_known_site() -> eyre::Result<()> {
+        let conn = CrConn::init(Connection::open_in_memory()?)?;
+        conn.execute_batch(
+            "CREATE TABLE foo (id INTEGER NOT NULL PRIMARY KEY, text TEXT);
+             SELECT crsql_as_crr('foo');",
+        )?;
+
+        let other = ActorId(uuid::Uuid::new_v4());
+        let ordinal: i64 = conn.query_row(
+            "INSERT INTO crsql_site_id (site_id) VALUES (?) RETURNING ordinal",
+            [other],
+            |row| row.get(0),
+        )?;
+        conn.execute("INSERT INTO foo VALUES (1, 'hello')", [])?;
+        conn.execute("UPDATE foo__crsql_clock SET site_id = ?", [ordinal])?;
+
+        override_site_id(&conn, other)?;
+
+        assert_eq!(own_site_id(&conn)?, other);
+        let remaining: i64 = conn.query_row(
+            "SELECT count(*) FROM foo__crsql_clock WHERE site_id != 0",
+            [],
+            |row| row.get(0),
+        )?;
+        assert_eq!(remaining, 0);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,237 @@
+//! Ordered shutdown of the agent's subsystems
+//!
+//! Each subsystem belongs to a stage, and each stage has its own tripwire.
+//! Once the root tripwire trips, stages are tripped one after the other,
+//! each one only after the tasks of the previous stage are done:
+//!
+//! 1. [`ShutdownStage::Accept`]: stop accepting new work from API clients,
+//!    syncs and new members. This stage uses the root tripwire.
+//! 2. [`ShutdownStage::Drain`]: process received changes, apply buffered
+//!    versions and send queued broadcasts
+//! 3. [`ShutdownStage::Gossip`]: leave the cluster and stop serving peers
+//! 4. [`ShutdownStage::Closed`]: close the database pools
+//!
+//! A stage that takes too long is not waited on any further, so one stuck
+//! task can't hold up the whole shutdown.
+
+use std::{cmp, future::Future, sync::Arc, time::Duration};
+
+use corro_types::agent::Agent;
+use spawn::spawn_counted;
+use tokio::sync::{mpsc, watch};
+use tracing::{info, warn};
+use tripwire::Tripwire;
+
+/// Leaving the cluster and closing the peer server each wait up to this long
+const GOSSIP_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Extra time given to a stage over its own tasks' deadlines
+const STAGE_GRACE: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, strum::IntoStaticStr)]
+#[strum(serialize_all = "snake_case")]
+pub enum ShutdownStage {
+    Running,
+    Accept,
+    Drain,
+    Gossip,
+    Closed,
+}
+
+/// Counts the running tasks of a stage
+#[derive(Clone)]
+struct StageTasks(Arc<watch::Sender<usize>>);
+
+impl StageTasks {
+    fn new() -> Self {
+        Self(Arc::new(watch::channel(0).0))
+    }
+
+    fn guard(&self) -> StageGuard {
+        self.0.send_modify(|count| *count += 1);
+        StageGuard(self.0.clone())
+    }
+
+    fn count(&self) -> usize {
+        *self.0.borrow()
+    }
+
+    async fn wait_done(&self) {
+        let mut rx = self.0.subscribe();
+        _ = rx.wait_for(|count| *count == 0).await;
+    }
+}
+
+/// Keeps its stage from being done while alive
+struct StageGuard(Arc<watch::Sender<usize>>);
+
+impl Drop for StageGuard {
+    fn drop(&mut self) {
+        self.0.send_modify(|count| *count -= 1);
+    }
+}
+
+/// Tripwires for each shutdown stage, tripped in order
+#[derive(Clone)]
+pub struct ShutdownStages {
+    accept: Tripwire,
+    drain: Tripwire,
+    gossip: Tripwire,
+    accept_tasks: StageTasks,
+    drain_tasks: StageTasks,
+    gossip_tasks: StageTasks,
+    stage: watch::Receiver<ShutdownStage>,
+}
+
+impl ShutdownStages {
+    /// Start walking through the stages once `tripwire` trips, the agent's
+    /// database pools are closed at the end
+    pub fn spawn(agent: &Agent, tripwire: Tripwire) -> Self {
+        let (drain, drain_worker, drain_tx) = Tripwire::new_simple();
+        let (gossip, gossip_worker, gossip_tx) = Tripwire::new_simple();
+        tokio::spawn(drain_worker);
+        tokio::spawn(gossip_worker);
+
+        let (stage_tx, stage_rx) = watch::channel(ShutdownStage::Running);
+
+        let stages = Self {
+            accept: tripwire.clone(),
+            drain,
+            gossip,
+            accept_tasks: StageTasks::new(),
+            drain_tasks: StageTasks::new(),
+            gossip_tasks: StageTasks::new(),
+            stage: stage_rx,
+        };
+
+        // draining tasks give up after the drain timeout on their own, and
+        // every stage gets at least as long as leaving the cluster takes
+        let drain_timeout = Duration::from_secs(agent.config().perf.shutdown_drain_timeout_secs);
+        let stage_timeout = cmp::max(drain_timeout, GOSSIP_SHUTDOWN_TIMEOUT) + STAGE_GRACE;
+
+        spawn_counted({
+            let stages = stages.clone();
+            let pool = agent.pool().clone();
+            async move {
+                tripwire.await;
+
+                stages
+                    .run_stage(&stage_tx, ShutdownStage::Accept, None, stage_timeout)
+                    .await;
+                stages
+                    .run_stage(
+                        &stage_tx,
+                        ShutdownStage::Drain,
+                        Some(drain_tx),
+                        stage_timeout,
+                    )
+                    .await;
+                stages
+                    .run_stage(
+                        &stage_tx,
+                        ShutdownStage::Gossip,
+                        Some(gossip_tx),
+                        stage_timeout,
+                    )
+                    .await;
+
+                pool.close();
+                stage_tx.send_replace(ShutdownStage::Closed);
+                info!("closed database pools, shutdown stages are done");
+            }
+        });
+
+        stages
+    }
+
+    /// Tripwire to watch for tasks of `stage`
+    pub fn tripwire(&self, stage: ShutdownStage) -> Tripwire {
+        match stage {
+            ShutdownStage::Drain => self.drain.clone(),
+            ShutdownStage::Gossip | ShutdownStage::Closed => self.gossip.clone(),
+            ShutdownStage::Running | ShutdownStage::Accept => self.accept.clone(),
+        }
+    }
+
+    /// Keep `stage` from being done until `fut` completes
+    pub fn track<F: Future>(
+        &self,
+        stage: ShutdownStage,
+        fut: F,
+    ) -> impl Future<Output = F::Output> {
+        let guard = self.tasks(stage).map(StageTasks::guard);
+        async move {
+            let _guard = guard;
+            fut.await
+        }
+    }
+
+    /// Watch the current shutdown stage
+    pub fn subscribe(&self) -> watch::Receiver<ShutdownStage> {
+        self.stage.clone()
+    }
+
+    fn tasks(&self, stage: ShutdownStage) -> Option<&StageTasks> {
+        match stage {
+            ShutdownStage::Accept => Some(&self.accept_tasks),
+            ShutdownStage::Drain => Some(&self.drain_tasks),
+            ShutdownStage::Gossip => Some(&self.gossip_tasks),
+            ShutdownStage::Running | ShutdownStage::Closed => None,
+        }
+    }
+
+    async fn run_stage(
+        &self,
+        stage_tx: &watch::Sender<ShutdownStage>,
+        stage: ShutdownStage,
+        trip_tx: Option<mpsc::Sender<()>>,
+        timeout: Duration,
+    ) {
+        let name: &'static str = stage.into();
+        info!("entering {name} shutdown stage");
+        stage_tx.send_replace(stage);
+        if let Some(trip_tx) = trip_tx {
+            _ = trip_tx.send(()).await;
+        }
+
+        let Some(tasks) = self.tasks(stage) else {
+            return;
+        };
+        if tokio::time::timeout(timeout, tasks.wait_done())
+            .await
+            .is_err()
+        {
+            warn!(
+                "{} task(s) still running after {timeout:?} in the {name} shutdown stage, moving on",
+                tasks.count()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_stage_tasks_wait_for_guards() {
+        let tasks = StageTasks::new();
+        tasks.wait_done().await;
+
+        let guard = tasks.guard();
+        let other = tasks.guard();
+        assert_eq!(tasks.count(), 2);
+
+        drop(guard);
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), tasks.wait_done())
+                .await
+                .is_err()
+        );
+
+        drop(other);
+        tokio::time::timeout(Duration::from_millis(50), tasks.wait_done())
+            .await
+            .unwrap();
+    }
+}
@@ -1,11 +1,16 @@
 use std::{
     collections::{BTreeMap, HashMap},
     net::SocketAddr,
+    num::NonZeroUsize,
     ops::{Deref, RangeInclusive},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
     time::{Duration, Instant},
 };
 
-use axum::Extension;
+use axum::{response::IntoResponse, Extension};
 use futures::{future, stream::FuturesUnordered, StreamExt, TryStreamExt};
 use hyper::StatusCode;
 use rand::{
@@ -24,10 +29,27 @@ use tripwire::Tripwire;
 use uuid::Uuid;
 
 use crate::{
-    agent::process_multiple_changes,
+    agent::{
+        handle_resync, process_multiple_changes,
+        util::{process_fully_buffered_changes, unapplied_changes, MAX_DECOMPRESSED_BODY_LEN},
+        SyncClientError,
+    },
     api::{
-        peer::parallel_sync,
-        public::{api_v1_db_schema, api_v1_transactions, TransactionParams},
+        peer::{parallel_sync, SyncError},
+        public::{
+            admin::{
+                api_v1_admin_announce, api_v1_admin_config, api_v1_admin_config_reload,
+                api_v1_admin_drain, api_v1_admin_member_probe, api_v1_admin_members_prune,
+                api_v1_admin_pause, api_v1_admin_resume, api_v1_admin_storage,
+                api_v1_admin_undrain, PruneMembersParams, StorageParams,
+            },
+            api_v1_db_schema, api_v1_health, api_v1_transactions,
+            changes::{
+                api_v1_changes, api_v1_changes_since, ChangesEvent, ChangesSinceParams,
+                VersionChanges,
+            },
+            SchemaParams, TransactionParams,
+        },
     },
     transport::Transport,
 };
@@ -35,20 +57,24 @@ use corro_tests::*;
 use corro_types::broadcast::Timestamp;
 use corro_types::change::Change;
 use corro_types::{
-    actor::ActorId,
-    agent::migrate,
+    actor::{Actor, ActorId},
+    agent::{migrate, MAX_CONCURRENT_SYNCS, REDUNDANT_MIN_CHANGES},
     api::{ExecResponse, ExecResult, Statement},
     base::{CrsqlDbVersion, CrsqlSeq, Version},
     broadcast::{ChangeSource, ChangeV1, Changeset},
     change::store_empty_changeset,
+    config::{
+        ApplyConfig, AuthzConfig, ColumnMergeConfig, Config, MergeStrategy, NodeRole, SyncConfig,
+    },
     sqlite::CrConn,
-    sync::generate_sync,
+    sync::{generate_sync, SyncRejectionV1, SyncStateV1},
 };
 use corro_types::{
     agent::Agent,
-    api::{ColumnName, TableName},
+    api::{ColumnName, SqliteValue, TableName},
     change::row_to_change,
     pubsub::pack_columns,
+    schema::{schema_hash, PkError},
 };
 
 #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
@@ -275,6 +301,85 @@ async fn insert_rows_and_gossip() -> eyre::Result<()> {
     Ok(())
 }
 
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn compressed_transactions_body() -> eyre::Result<()> {
+    _ = tracing_subscriber::fmt::try_init();
+    let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+    let ta1 = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+
+    let client = hyper::Client::builder().build_http::<hyper::Body>();
+
+    let req_body: Vec<Statement> = serde_json::from_value(json!([
+        [
+            "INSERT INTO tests (id,text) VALUES (?,?)",
+            [1, "hello gzip"]
+        ],
+        [
+            "INSERT INTO tests (id,text) VALUES (?,?)",
+            [2, "hello again"]
+        ],
+    ]))?;
+    let req_body = serde_json::to_vec(&req_body)?;
+
+    let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+    std::io::Write::write_all(&mut encoder, &req_body)?;
+    let compressed = encoder.finish()?;
+
+    let post = |encoding: &'static str, body: Vec<u8>| {
+        client.request(
+            hyper::Request::builder()
+                .method(hyper::Method::POST)
+                .uri(format!("http://{}/v1/transactions", ta1.agent.api_addr()))
+                .header(hyper::header::CONTENT_TYPE, "application/json")
+                .header(hyper::header::CONTENT_ENCODING, encoding)
+                .body(body.into())
+                .unwrap(),
+        )
+    };
+
+    let res = timeout(Duration::from_secs(5), post("gzip", compressed.clone())).await??;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let rows: Vec<(i64, String)> = ta1
+        .agent
+        .pool()
+        .read()
+        .await?
+        .prepare("SELECT id, text FROM tests ORDER BY id")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+    assert_eq!(
+        rows,
+        vec![
+            (1, "hello gzip".to_string()),
+            (2, "hello again".to_string())
+        ]
+    );
+
+    let res = timeout(Duration::from_secs(5), post("br", compressed)).await??;
+    assert_eq!(res.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+    // small bodies decompressing past the limit are rejected
+    let zeros = vec![0u8; MAX_DECOMPRESSED_BODY_LEN + 1];
+    let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+    std::io::Write::write_all(&mut encoder, &zeros)?;
+    let res = timeout(Duration::from_secs(5), post("gzip", encoder.finish()?)).await??;
+    assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    let res = timeout(
+        Duration::from_secs(5),
+        post("zstd", zstd::stream::encode_all(zeros.as_slice(), 0)?),
+    )
+    .await??;
+    assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    tripwire_tx.send(()).await.ok();
+    tripwire_worker.await;
+    wait_for_all_pending_handles().await;
+
+    Ok(())
+}
+
 #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
 async fn chill_test() -> eyre::Result<()> {
     configurable_stress_test(2, 1, 1).await
@@ -804,6 +909,7 @@ async fn test_clear_empty_versions() -> eyre::Result<()> {
     // setup the schema, for both nodes
     let (status_code, _body) = api_v1_db_schema(
         Extension(ta1.agent.clone()),
+        axum::extract::Query(SchemaParams::default()),
         axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
     )
     .await;
@@ -812,6 +918,7 @@ async fn test_clear_empty_versions() -> eyre::Result<()> {
 
     let (status_code, _body) = api_v1_db_schema(
         Extension(ta2.agent.clone()),
+        axum::extract::Query(SchemaParams::default()),
         axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
     )
     .await;
@@ -928,6 +1035,7 @@ async fn process_failed_changes() -> eyre::Result<()> {
     // setup the schema, for both nodes
     let (status_code, _body) = api_v1_db_schema(
         Extension(ta1.agent.clone()),
+        axum::extract::Query(SchemaParams::default()),
         axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
     )
     .await;
@@ -936,6 +1044,7 @@ async fn process_failed_changes() -> eyre::Result<()> {
     let ta2 = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
     let (status_code, _body) = api_v1_db_schema(
         Extension(ta2.agent.clone()),
+        axum::extract::Query(SchemaParams::default()),
         axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
     )
     .await;
@@ -992,6 +1101,7 @@ async fn process_failed_changes() -> eyre::Result<()> {
                 last_seq: CrsqlSeq(1),
                 ts: Default::default(),
             },
+            trace_id: None,
         },
         ChangeSource::Sync,
         Instant::now(),
@@ -1049,6 +1159,7 @@ async fn test_process_multiple_changes() -> eyre::Result<()> {
     // setup the schema, for both nodes
     let (status_code, _body) = api_v1_db_schema(
         Extension(ta1.agent.clone()),
+        axum::extract::Query(SchemaParams::default()),
         axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
     )
     .await;
@@ -1057,6 +1168,7 @@ async fn test_process_multiple_changes() -> eyre::Result<()> {
 
     let (status_code, _body) = api_v1_db_schema(
         Extension(ta2.agent.clone()),
+        axum::extract::Query(SchemaParams::default()),
         axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
     )
     .await;
@@ -1194,6 +1306,2529 @@ async fn test_process_multiple_changes() -> eyre::Result<()> {
     Ok(())
 }
 
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn agents_become_ready() -> eyre::Result<()> {
+    _ = tracing_subscriber::fmt::try_init();
+
+    let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+    let ta1 = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+
+    // alone, nobody to join or sync with
+    timeout(Duration::from_secs(10), ta1.agent.ready()).await?;
+    assert!(ta1.agent.is_ready());
+
+    let ta2 = launch_test_agent(
+        |conf| {
+            conf.bootstrap(vec![ta1.agent.gossip_addr().to_string()])
+                .build()
+        },
+        tripwire.clone(),
+    )
+    .await?;
+
+    timeout(Duration::from_secs(30), ta2.agent.ready()).await?;
+    assert!(ta2.agent.is_ready());
+
+    tripwire_tx.send(()).await.ok();
+    tripwire_worker.await;
+    wait_for_all_pending_handles().await;
+
+    Ok(())
+}
+
+/// Counts error-level events
+struct ErrorCounter(Arc<AtomicUsize>);
+
+impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for ErrorCounter {
+    fn on_event(
+        &self,
+        event: &tracing::Event<'_>,
+        _ctx: tracing_subscriber::layer::Context<'_, S>,
+    ) {
+        if *event.metadata().level() == tracing::Level::ERROR {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
+
+#[test]
+fn clean_shutdown_logs_no_errors() -> eyre::Result<()> {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    // the subscriber is scoped to this test's runtime threads, other tests
+    // install their own global one
+    let errors = Arc::new(AtomicUsize::new(0));
+    let dispatch = tracing::Dispatch::new(
+        tracing_subscriber::registry()
+            .with(ErrorCounter(errors.clone()))
+            .with(tracing_subscriber::fmt::layer().with_test_writer()),
+    );
+    let _guard = tracing::dispatcher::set_default(&dispatch);
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(2)
+        .enable_all()
+        .on_thread_start({
+            let dispatch = dispatch.clone();
+            move || std::mem::forget(tracing::dispatcher::set_default(&dispatch))
+        })
+        .build()?;
+
+    rt.block_on(async {
+        let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+        let ta1 = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+        let ta2 = launch_test_agent(
+            |conf| {
+                conf.bootstrap(vec![ta1.agent.gossip_addr().to_string()])
+                    .build()
+            },
+            tripwire.clone(),
+        )
+        .await?;
+
+        let (status_code, _) = api_v1_transactions(
+            Extension(ta1.agent.clone()),
+            axum::extract::Query(TransactionParams { timeout: None }),
+            axum::Json(vec![Statement::WithParams(
+                "INSERT INTO tests (id,text) VALUES (?,?)".into(),
+                vec![1.into(), "hello world 1".into()],
+            )]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+
+        // wait for the change to make it to the other node, so that
+        // shutdown happens with a cluster and broadcasts in flight
+        timeout(Duration::from_secs(10), async {
+            loop {
+                let count: i64 = ta2.agent.pool().read().await?.query_row(
+                    "SELECT COUNT(*) FROM tests",
+                    [],
+                    |row| row.get(0),
+                )?;
+                if count == 1 {
+                    return Ok::<_, eyre::Report>(());
+                }
+                sleep(Duration::from_millis(100)).await;
+            }
+        })
+        .await??;
+
+        tripwire_tx.send(()).await.ok();
+        tripwire_worker.await;
+        wait_for_all_pending_handles().await;
+
+        // closing the pools is the last stage
+        assert!(ta1.agent.pool().is_closed());
+        assert!(ta2.agent.pool().is_closed());
+
+        Ok::<_, eyre::Report>(())
+    })?;
+
+    assert_eq!(errors.load(Ordering::SeqCst), 0);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn inspect_version_changes() -> eyre::Result<()> {
+    _ = tracing_subscriber::fmt::try_init();
+
+    let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+    let ta1 = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+
+    let (status_code, _body) = api_v1_db_schema(
+        Extension(ta1.agent.clone()),
+        axum::extract::Query(SchemaParams::default()),
+        axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
+    )
+    .await;
+    assert_eq!(status_code, StatusCode::OK);
+
+    let (status_code, _body) = api_v1_transactions(
+        Extension(ta1.agent.clone()),
+        axum::extract::Query(TransactionParams::default()),
+        axum::Json(vec![Statement::WithParams(
+            "insert into tests (id,text) values (?,?)".into(),
+            vec![1i64.into(), "one".into()],
+        )]),
+    )
+    .await;
+    assert_eq!(status_code, StatusCode::OK);
+
+    let (status_code, body) = api_v1_changes(
+        Extension(ta1.agent.clone()),
+        axum::extract::Path((ta1.agent.actor_id(), Version(1))),
+    )
+    .await;
+    assert_eq!(status_code, StatusCode::OK);
+    let changes: VersionChanges = serde_json::from_value(body.0)?;
+    assert!(matches!(
+        changes,
+        VersionChanges::Current { changes, .. } if changes.len() == 1 && changes[0].cid.0 == "text"
+    ));
+
+    let (status_code, _body) = api_v1_changes(
+        Extension(ta1.agent.clone()),
+        axum::extract::Path((ta1.agent.actor_id(), Version(2))),
+    )
+    .await;
+    assert_eq!(status_code, StatusCode::NOT_FOUND);
+
+    // only the first of 3 sequences from another actor
+    let actor_id = ActorId(Uuid::new_v4());
+    let change = Change {
+        table: TableName("tests".into()),
+        pk: pack_columns(&vec![2i64.into()])?,
+        cid: ColumnName("text".into()),
+        val: "two".into(),
+        col_version: 1,
+        db_version: CrsqlDbVersion(1),
+        seq: CrsqlSeq(0),
+        site_id: actor_id.to_bytes(),
+        cl: 1,
+    };
+    process_multiple_changes(
+        ta1.agent.clone(),
+        ta1.bookie.clone(),
+        vec![(
+            ChangeV1 {
+                actor_id,
+                changeset: Changeset::Full {
+                    version: Version(1),
+                    changes: vec![change.clone()],
+                    seqs: CrsqlSeq(0)..=CrsqlSeq(0),
+                    last_seq: CrsqlSeq(2),
+                    ts: Default::default(),
+                },
+                trace_id: None,
+            },
+            ChangeSource::Sync,
+            Instant::now(),
+        )],
+        Duration::from_secs(60),
+    )
+    .await?;
+
+    let (status_code, body) = api_v1_changes(
+        Extension(ta1.agent.clone()),
+        axum::extract::Path((actor_id, Version(1))),
+    )
+    .await;
+    assert_eq!(status_code, StatusCode::OK);
+    match serde_json::from_value(body.0)? {
+        VersionChanges::Partial {
+            last_seq,
+            seqs,
+            gaps,
+            changes,
+            ..
+        } => {
+            assert_eq!(last_seq, CrsqlSeq(2));
+            assert_eq!(seqs, vec![CrsqlSeq(0)..=CrsqlSeq(0)]);
+            assert_eq!(gaps, vec![CrsqlSeq(1)..=CrsqlSeq(2)]);
+            assert_eq!(changes, vec![change]);
+        }
+        changes => panic!("expected a partial version, got {changes:?}"),
+    }
+
+    tripwire_tx.send(()).await.ok();
+    tripwire_worker.await;
+    wait_for_all_pending_handles().await;
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn failing_buffered_version_is_retried_then_quarantined() -> eyre::Result<()> {
+    _ = tracing_subscriber::fmt::try_init();
+
+    let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+    let ta1 = launch_test_agent(
+        |conf| {
+            conf.apply(ApplyConfig {
+                quarantine_after: 2,
+                ..Default::default()
+            })
+            .build()
+        },
+        tripwire.clone(),
+    )
+    .await?;
+
+    // a version in 2 chunks, for a table we don't have so it can't apply
+    let actor_id = ActorId(Uuid::new_v4());
+    let changes = (0i64..2)
+        .map(|seq| {
+            let change = Change {
+                table: TableName("dropped_table".into()),
+                pk: pack_columns(&vec![seq.into()])?,
+                cid: ColumnName("text".into()),
+                val: "gone".into(),
+                col_version: 1,
+                db_version: CrsqlDbVersion(1),
+                seq: CrsqlSeq(seq as u64),
+                site_id: actor_id.to_bytes(),
+                cl: 1,
+            };
+            Ok((
+                ChangeV1 {
+                    actor_id,
+                    changeset: Changeset::Full {
+                        version: Version(1),
+                        changes: vec![change],
+                        seqs: CrsqlSeq(seq as u64)..=CrsqlSeq(seq as u64),
+                        last_seq: CrsqlSeq(1),
+                        ts: Default::default(),
+                    },
+                    trace_id: None,
+                },
+                ChangeSource::Sync,
+                Instant::now(),
+            ))
+        })
+        .collect::<eyre::Result<Vec<_>>>()?;
+
+    process_multiple_changes(
+        ta1.agent.clone(),
+        ta1.bookie.clone(),
+        changes,
+        Duration::from_secs(60),
+    )
+    .await?;
+
+    // the first attempt fails, the retry after a backoff fails again
+    timeout(Duration::from_secs(10), async {
+        while !ta1
+            .agent
+            .quarantine()
+            .read()
+            .is_quarantined(actor_id, Version(1))
+        {
+            sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await?;
+
+    let quarantined = ta1.agent.quarantine().read().list();
+    assert_eq!(quarantined.len(), 1);
+    assert_eq!(quarantined[0].failures, 2);
+    assert!(!quarantined[0].last_error.is_empty());
+
+    // it stays buffered and isn't attempted anymore
+    sleep(Duration::from_secs(3)).await;
+    assert_eq!(ta1.agent.quarantine().read().list(), quarantined);
+    let buffered: i64 = ta1.agent.pool().read().await?.query_row(
+        "SELECT count(*) FROM __corro_buffered_changes WHERE site_id = ?",
+        [actor_id.as_bytes()],
+        |row| row.get(0),
+    )?;
+    assert_eq!(buffered, 2);
+
+    tripwire_tx.send(()).await.ok();
+    tripwire_worker.await;
+    wait_for_all_pending_handles().await;
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn verify_synced_changes_took_effect() -> eyre::Result<()> {
+    _ = tracing_subscriber::fmt::try_init();
+
+    let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+    let ta1 = launch_test_agent(
+        |conf| {
+            conf.sync(SyncConfig {
+                verify_sample_rate: 1.0,
+                ..Default::default()
+            })
+            .build()
+        },
+        tripwire.clone(),
+    )
+    .await?;
+
+    let actor_id = ActorId(Uuid::new_v4());
+    let change = Change {
+        table: TableName("tests".into()),
+        pk: pack_columns(&vec![1i64.into()])?,
+        cid: ColumnName("text".into()),
+        val: "one".into(),
+        col_version: 1,
+        db_version: CrsqlDbVersion(1),
+        seq: CrsqlSeq(0),
+        site_id: actor_id.to_bytes(),
+        cl: 1,
+    };
+    process_multiple_changes(
+        ta1.agent.clone(),
+        ta1.bookie.clone(),
+        vec![(
+            ChangeV1 {
+                actor_id,
+                changeset: Changeset::Full {
+                    version: Version(1),
+                    changes: vec![change.clone()],
+                    seqs: CrsqlSeq(0)..=CrsqlSeq(0),
+                    last_seq: CrsqlSeq(0),
+                    ts: Default::default(),
+                },
+                trace_id: None,
+            },
+            ChangeSource::Sync,
+            Instant::now(),
+        )],
+        Duration::from_secs(60),
+    )
+    .await?;
+
+    let conn = ta1.agent.pool().read().await?;
+    assert!(unapplied_changes(&conn, &[change.clone()])?.is_empty());
+
+    // a row that was never written
+    let missing = Change {
+        pk: pack_columns(&vec![2i64.into()])?,
+        ..change.clone()
+    };
+    // the same write, with a different value
+    let different = Change {
+        val: "uno".into(),
+        ..change.clone()
+    };
+    // a newer write that never made it
+    let newer = Change {
+        col_version: 2,
+        val: "eins".into(),
+        ..change.clone()
+    };
+    assert_eq!(
+        unapplied_changes(&conn, &[missing.clone(), different.clone(), newer.clone()])?,
+        vec![&missing, &different, &newer]
+    );
+
+    // an older write, superseded since, is fine
+    let older = Change {
+        col_version: 0,
+        val: "zero".into(),
+        ..change
+    };
+    assert!(unapplied_changes(&conn, &[older])?.is_empty());
+
+    drop(conn);
+
+    tripwire_tx.send(()).await.ok();
+    tripwire_worker.await;
+    wait_for_all_pending_handles().await;
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn composite_pk_round_trip() -> eyre::Result<()> {
+    _ = tracing_subscriber::fmt::try_init();
+
+    let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+    let ta1 = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+
+    // `wide` has a (id1, id2) primary key
+    for int in [1i64, 2] {
+        let (status_code, _body) = api_v1_transactions(
+            Extension(ta1.agent.clone()),
+            axum::extract::Query(TransactionParams::default()),
+            axum::Json(vec![Statement::WithParams(
+                "INSERT INTO wide (id1, id2, int) VALUES (?, ?, ?)
+                    ON CONFLICT (id1, id2) DO UPDATE SET int = excluded.int"
+                    .into(),
+                vec![vec![1u8, 2].into(), "two".into(), int.into()],
+            )]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+    }
+
+    let table = ta1
+        .agent
+        .schema()
+        .read()
+        .tables
+        .get("wide")
+        .cloned()
+        .expect("wide table is part of the test schema");
+
+    let conn = ta1.agent.pool().read().await?;
+    let pk: Vec<u8> = conn.query_row(
+        "SELECT pk FROM crsql_changes WHERE \"table\" = 'wide' AND cid = 'int'",
+        [],
+        |row| row.get(0),
+    )?;
+
+    let unpacked = table.unpack_pk(&pk)?;
+    assert_eq!(
+        unpacked
+            .iter()
+            .map(|(col, value)| (*col, value.to_owned()))
+            .collect::<Vec<_>>(),
+        vec![
+            ("id1", SqliteValue::from(vec![1u8, 2])),
+            ("id2", SqliteValue::from("two")),
+        ]
+    );
+
+    // values are found by name, whatever order they're given in
+    let values: HashMap<String, SqliteValue> = [
+        ("int".to_string(), 2i64.into()),
+        ("id2".to_string(), "two".into()),
+        ("id1".to_string(), vec![1u8, 2].into()),
+    ]
+    .into_iter()
+    .collect();
+    assert_eq!(table.pack_pk(&values)?, pk);
+
+    let int: i64 = conn.query_row(
+        "SELECT int FROM wide WHERE id1 = ? AND id2 = ?",
+        [&unpacked[0].1, &unpacked[1].1],
+        |row| row.get(0),
+    )?;
+    assert_eq!(int, 2);
+
+    let mut missing = values.clone();
+    missing.remove("id2");
+    assert!(matches!(
+        table.pack_pk(&missing),
+        Err(PkError::MissingColumn(col)) if col == "id2"
+    ));
+
+    drop(conn);
+
+    tripwire_tx.send(()).await.ok();
+    tripwire_worker.await;
+    wait_for_all_pending_handles().await;
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn tail_changes_since_db_version() -> eyre::Result<()> {
+    _ = tracing_subscriber::fmt::try_init();
+
+    let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+    let ta1 = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+
+    let (status_code, _body) = api_v1_db_schema(
+        Extension(ta1.agent.clone()),
+        axum::extract::Query(SchemaParams::default()),
+        axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
+    )
+    .await;
+    assert_eq!(status_code, StatusCode::OK);
+
+    for (id, text) in [(1i64, "one"), (2i64, "two")] {
+        let (status_code, _body) = api_v1_transactions(
+            Extension(ta1.agent.clone()),
+            axum::extract::Query(TransactionParams::default()),
+            axum::Json(vec![Statement::WithParams(
+                "insert into tests (id,text) values (?,?)".into(),
+                vec![id.into(), text.into()],
+            )]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+    }
+
+    let changes_since = |since_db_version| {
+        let agent = ta1.agent.clone();
+        async move {
+            let res = api_v1_changes_since(
+                Extension(agent),
+                axum::extract::Query(ChangesSinceParams {
+                    since_db_version,
+                    ..Default::default()
+                }),
+            )
+            .await
+            .into_response();
+            assert_eq!(res.status(), StatusCode::OK);
+            let body = hyper::body::to_bytes(res.into_body()).await?;
+            body.split(|b| *b == b'\n')
+                .filter(|line| !line.is_empty())
+                .map(serde_json::from_slice)
+                .collect::<Result<Vec<ChangesEvent>, _>>()
+                .map_err(eyre::Report::from)
+        }
+    };
+
+    let events = changes_since(None).await?;
+    assert_eq!(events.len(), 3);
+    assert!(matches!(&events[0], ChangesEvent::Change(change) if change.val == "one".into()));
+    assert!(matches!(&events[1], ChangesEvent::Change(change) if change.val == "two".into()));
+    assert!(matches!(
+        events[2],
+        ChangesEvent::EndOfChanges { db_version } if db_version == CrsqlDbVersion(2)
+    ));
+
+    // resuming from the first change only returns the second one
+    let events = changes_since(Some(CrsqlDbVersion(1))).await?;
+    assert_eq!(events.len(), 2);
+    assert!(matches!(&events[0], ChangesEvent::Change(change) if change.val == "two".into()));
+
+    // nothing new, only the end marker
+    let events = changes_since(Some(CrsqlDbVersion(2))).await?;
+    assert!(matches!(
+        events[..],
+        [ChangesEvent::EndOfChanges { db_version }] if db_version == CrsqlDbVersion(2)
+    ));
+
+    tripwire_tx.send(()).await.ok();
+    tripwire_worker.await;
+    wait_for_all_pending_handles().await;
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn tail_changes_filtered_by_table_and_column() -> eyre::Result<()> {
+    _ = tracing_subscriber::fmt::try_init();
+
+    let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+    let ta1 = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+
+    let (status_code, _body) = api_v1_transactions(
+        Extension(ta1.agent.clone()),
+        axum::extract::Query(TransactionParams::default()),
+        axum::Json(vec![
+            Statement::WithParams(
+                "insert into tests (id,text) values (?,?)".into(),
+                vec![1i64.into(), "one".into()],
+            ),
+            Statement::WithParams(
+                "insert into tests3 (id,text,text2,num) values (?,?,?,?)".into(),
+                vec![1i64.into(), "a".into(), "b".into(), 2i64.into()],
+            ),
+            Statement::WithParams(
+                "insert into tests3 (id,text,text2,num) values (?,?,?,?)".into(),
+                vec![2i64.into(), "c".into(), "d".into(), 3i64.into()],
+            ),
+            Statement::WithParams("delete from tests3 where id = ?".into(), vec![2i64.into()]),
+        ]),
+    )
+    .await;
+    assert_eq!(status_code, StatusCode::OK);
+
+    let changes = |tables: Option<&str>, columns: Option<&str>| {
+        let agent = ta1.agent.clone();
+        let params = ChangesSinceParams {
+            since_db_version: None,
+            tables: tables.map(String::from),
+            columns: columns.map(String::from),
+        };
+        async move {
+            let res = api_v1_changes_since(Extension(agent), axum::extract::Query(params))
+                .await
+                .into_response();
+            assert_eq!(res.status(), StatusCode::OK);
+            let body = hyper::body::to_bytes(res.into_body()).await?;
+            let events = body
+                .split(|b| *b == b'\n')
+                .filter(|line| !line.is_empty())
+                .map(serde_json::from_slice)
+                .collect::<Result<Vec<ChangesEvent>, _>>()?;
+
+            // filtering never drops the end marker
+            assert!(matches!(
+                events.last(),
+                Some(ChangesEvent::EndOfChanges { db_version }) if *db_version == CrsqlDbVersion(1)
+            ));
+
+            Ok::<_, eyre::Report>(
+                events
+                    .into_iter()
+                    .filter_map(|event| match event {
+                        ChangesEvent::Change(change) => {
+                            Some((change.table.to_string(), change.cid.to_string(), change.val))
+                        }
+                        ChangesEvent::EndOfChanges { .. } => None,
+                    })
+                    .collect::<Vec<_>>(),
+            )
+        }
+    };
+
+    let all = changes(None, None).await?;
+    assert!(all.iter().any(|(table, _, _)| table == "tests"));
+    assert!(all.iter().any(|(table, _, _)| table == "tests3"));
+
+    let tests3 = changes(Some("tests3"), None).await?;
+    assert!(!tests3.is_empty());
+    assert!(tests3.iter().all(|(table, _, _)| table == "tests3"));
+
+    // the deleted row only shows up through its sentinel
+    let text = changes(Some("tests3"), Some("text")).await?;
+    assert_eq!(
+        text.iter()
+            .filter(|(_, cid, _)| cid != "-1")
+            .collect::<Vec<_>>(),
+        vec![&(
+            "tests3".to_string(),
+            "text".to_string(),
+            SqliteValue::from("a")
+        )]
+    );
+    assert!(text.iter().any(|(_, cid, _)| cid == "-1"));
+
+    // columns apply across tables, and blanks are ignored
+    let cols = changes(None, Some(" text2, num ,")).await?;
+    let mut cids: Vec<_> = cols
+        .iter()
+        .filter(|(_, cid, _)| cid != "-1")
+        .map(|(table, cid, _)| (table.as_str(), cid.as_str()))
+        .collect();
+    cids.sort();
+    assert_eq!(cids, vec![("tests3", "num"), ("tests3", "text2")]);
+
+    let none = changes(Some("tests2"), None).await?;
+    assert!(none.is_empty());
+
+    tripwire_tx.send(()).await.ok();
+    tripwire_worker.await;
+    wait_for_all_pending_handles().await;
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn agent_advertises_external_addr() -> eyre::Result<()> {
+    _ = tracing_subscriber::fmt::try_init();
+
+    let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+    let external_addr: SocketAddr = "127.0.0.2:8787".parse()?;
+    let ta1 = launch_test_agent(
+        |conf| conf.external_addr(external_addr).build(),
+        tripwire.clone(),
+    )
+    .await?;
+
+    assert_ne!(ta1.agent.gossip_addr(), external_addr);
+    assert_eq!(ta1.agent.actor(None).addr(), external_addr);
+
+    tripwire_tx.send(()).await.ok();
+    tripwire_worker.await;
+    wait_for_all_pending_handles().await;
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn apply_hooks() -> eyre::Result<()> {
+    _ = tracing_subscriber::fmt::try_init();
+
+    let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+    let ta1 = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+    let ta2 = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+
+    let hook = |agent: &Agent| {
+        let (tx, rx) = mpsc::channel(16);
+        agent.register_apply_hook(16, move |applied| {
+            let tx = tx.clone();
+            async move {
+                _ = tx.send(applied).await;
+            }
+        });
+        rx
+    };
+    let mut ta1_rx = hook(&ta1.agent);
+    let mut ta2_rx = hook(&ta2.agent);
+
+    // local changes
+    insert_rows(ta1.agent.clone(), 1, 1).await;
+    let applied = timeout(Duration::from_secs(5), ta1_rx.recv())
+        .await?
+        .expect("apply hook channel closed");
+    assert_eq!(applied.db_version, CrsqlDbVersion(1));
+    assert!(applied
+        .changes
+        .iter()
+        .all(|change| change.table.as_str() == "tests3"
+            && change.site_id == ta1.agent.actor_id().to_bytes()));
+
+    // remote changes
+    let rows = get_rows(ta1.agent.clone(), vec![(Version(1)..=Version(1), None)]).await?;
+    process_multiple_changes(
+        ta2.agent.clone(),
+        ta2.bookie.clone(),
+        rows,
+        Duration::from_secs(60),
+    )
+    .await?;
+    let remote = timeout(Duration::from_secs(5), ta2_rx.recv())
+        .await?
+        .expect("apply hook channel closed");
+    assert_eq!(remote.changes.len(), applied.changes.len());
+    assert!(remote
+        .changes
+        .iter()
+        .all(|change| change.site_id == ta1.agent.actor_id().to_bytes()));
+
+    tripwire_tx.send(()).await.ok();
+    tripwire_worker.await;
+    wait_for_all_pending_handles().await;
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn lost_conflicts_are_logged() -> eyre::Result<()> {
+    _ = tracing_subscriber::fmt::try_init();
+
+    let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+    let ta1 = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+    let ta2 = launch_test_agent(
+        |conf| {
+            conf.apply(ApplyConfig {
+                log_lost_conflicts: true,
+                ..Default::default()
+            })
+            .build()
+        },
+        tripwire.clone(),
+    )
+    .await?;
+
+    // ta2 writes the row twice, so its columns have a higher col_version
+    insert_rows(ta2.agent.clone(), 1, 1).await;
+    let (status_code, _) = api_v1_transactions(
+        Extension(ta2.agent.clone()),
+        axum::extract::Query(TransactionParams { timeout: None }),
+        axum::Json(vec![Statement::Simple(
+            "UPDATE tests3 SET text = 'local' WHERE id = 1".into(),
+        )]),
+    )
+    .await;
+    assert_eq!(status_code, StatusCode::OK);
+
+    insert_rows(ta1.agent.clone(), 1, 1).await;
+
+    let rows = get_rows(ta1.agent.clone(), vec![(Version(1)..=Version(1), None)]).await?;
+
+    // changes are applied on this thread, so a local recorder sees the
+    // counter bumped next to every logged conflict
+    let recorder = metrics_exporter_prometheus::PrometheusBuilder::new().build_recorder();
+    let handle = recorder.handle();
+    {
+        let _guard = metrics::set_default_local_recorder(&recorder);
+        process_multiple_changes(
+            ta2.agent.clone(),
+            ta2.bookie.clone(),
+            rows,
+            Duration::from_secs(60),
+        )
+        .await?;
+    }
+
+    let rendered = handle.render();
+    let lost: u64 = rendered
+        .lines()
+        .find_map(|line| line.strip_prefix(r#"corro_changes_lost_conflict{table="tests3"} "#))
+        .expect("lost conflicts were not counted")
+        .parse()?;
+    assert!(lost > 0);
+
+    // every incoming change lost, the local value stays
+    let text: String = ta2.agent.pool().read().await?.query_row(
+        "SELECT text FROM tests3 WHERE id = 1",
+        [],
+        |row| row.get(0),
+    )?;
+    assert_eq!(text, "local");
+
+    tripwire_tx.send(()).await.ok();
+    tripwire_worker.await;
+    wait_for_all_pending_handles().await;
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn column_merge_max_keeps_greatest_value() -> eyre::Result<()> {
+    _ = tracing_subscriber::fmt::try_init();
+
+    let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+    let ta1 = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+    let ta2 = launch_test_agent(
+        |conf| {
+            conf.apply(ApplyConfig {
+                column_merge: vec![ColumnMergeConfig {
+                    table: "tests3".into(),
+                    column: "num".into(),
+                    strategy: MergeStrategy::Max,
+                }],
+                ..Default::default()
+            })
+            .build()
+        },
+        tripwire.clone(),
+    )
+    .await?;
+
+    let exec = |agent: Agent, sql: &'static str| async move {
+        let (status_code, _) = api_v1_transactions(
+            Extension(agent),
+            axum::extract::Query(TransactionParams { timeout: None }),
+            axum::Json(vec![Statement::Simple(sql.into())]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+    };
+
+    exec(
+        ta2.agent.clone(),
+        "INSERT INTO tests3 (id, num) VALUES (1, 10)",
+    )
+    .await;
+
+    // ta1's writes have a higher col_version and would normally win
+    exec(
+        ta1.agent.clone(),
+        "INSERT INTO tests3 (id, num) VALUES (1, 1)",
+    )
+    .await;
+    exec(ta1.agent.clone(), "UPDATE tests3 SET num = 5 WHERE id = 1").await;
+
+    let num = |agent: Agent| async move {
+        agent
+            .pool()
+            .read()
+            .await?
+            .query_row("SELECT num FROM tests3 WHERE id = 1", [], |row| {
+                row.get::<_, i64>(0)
+            })
+            .map_err(eyre::Report::from)
+    };
+
+    let rows = get_rows(ta1.agent.clone(), vec![(Version(1)..=Version(2), None)]).await?;
+    process_multiple_changes(
+        ta2.agent.clone(),
+        ta2.bookie.clone(),
+        rows,
+        Duration::from_secs(60),
+    )
+    .await?;
+    assert_eq!(num(ta2.agent.clone()).await?, 10);
+
+    // greater values still go through
+    exec(ta1.agent.clone(), "UPDATE tests3 SET num = 20 WHERE id = 1").await;
+    let rows = get_rows(ta1.agent.clone(), vec![(Version(3)..=Version(3), None)]).await?;
+    process_multiple_changes(
+        ta2.agent.clone(),
+        ta2.bookie.clone(),
+        rows,
+        Duration::from_secs(60),
+    )
+    .await?;
+    assert_eq!(num(ta2.agent.clone()).await?, 20);
+
+    tripwire_tx.send(()).await.ok();
+    tripwire_worker.await;
+    wait_for_all_pending_handles().await;
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn broadcasts_drain_on_shutdown() -> eyre::Result<()> {
+    _ = tracing_subscriber::fmt::try_init();
+
+    let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+    let (ta1_tripwire, ta1_tripwire_worker, ta1_tripwire_tx) = Tripwire::new_simple();
+    let ta1 = launch_test_agent(|conf| conf.build(), ta1_tripwire.clone()).await?;
+    let ta2 = launch_test_agent(
+        |conf| {
+            conf.bootstrap(vec![ta1.agent.gossip_addr().to_string()])
+                .build()
+        },
+        tripwire.clone(),
+    )
+    .await?;
+
+    // ta1 needs someone to broadcast to
+    timeout(Duration::from_secs(10), async {
+        while !ta1
+            .agent
+            .members()
+            .read()
+            .states
+            .contains_key(&ta2.agent.actor_id())
+        {
+            sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await?;
+
+    // shut ta1 down right after writing, its changes must still go out
+    insert_rows(ta1.agent.clone(), 1, 1).await;
+    ta1_tripwire_tx.send(()).await.ok();
+    ta1_tripwire_worker.await;
+
+    timeout(Duration::from_secs(10), async {
+        loop {
+            let count: i64 = ta2.agent.pool().read().await?.query_row(
+                "SELECT count(*) FROM tests3",
+                [],
+                |row| row.get(0),
+            )?;
+            if count == 1 {
+                return Ok::<_, eyre::Report>(());
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await??;
+
+    tripwire_tx.send(()).await.ok();
+    tripwire_worker.await;
+    wait_for_all_pending_handles().await;
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn schema_changes_are_broadcast() -> eyre::Result<()> {
+    _ = tracing_subscriber::fmt::try_init();
+
+    let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+    let ta1 = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+    let ta2 = launch_test_agent(
+        |conf| {
+            conf.bootstrap(vec![ta1.agent.gossip_addr().to_string()])
+                .build()
+        },
+        tripwire.clone(),
+    )
+    .await?;
+
+    // schema changes are only sent to members known to decode them, which
+    // ta1 learns by syncing with ta2
+    timeout(Duration::from_secs(20), async {
+        while !ta1
+            .agent
+            .members()
+            .read()
+            .get(&ta2.agent.actor_id())
+            .map_or(false, |state| state.schema_broadcasts)
+        {
+            sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await?;
+
+    let (status_code, _body) = api_v1_db_schema(
+        Extension(ta1.agent.clone()),
+        axum::extract::Query(SchemaParams::default()),
+        axum::Json(vec![r#"
+            CREATE TABLE IF NOT EXISTS tests (
+                id INTEGER NOT NULL PRIMARY KEY,
+                text TEXT NOT NULL DEFAULT "",
+                added TEXT
+            ) WITHOUT ROWID;
+        "#
+        .into()]),
+    )
+    .await;
+    assert_eq!(status_code, StatusCode::OK);
+
+    // ta2 never got the new schema via its files or the API
+    timeout(Duration::from_secs(10), async {
+        loop {
+            if ta2
+                .agent
+                .schema()
+                .read()
+                .tables
+                .get("tests")
+                .map(|table| table.columns.contains_key("added"))
+                .unwrap_or(false)
+            {
+                break;
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await?;
+
+    let count: i64 = ta2.agent.pool().read().await?.query_row(
+        "SELECT count(*) FROM pragma_table_info('tests') WHERE name = 'added'",
+        [],
+        |row| row.get(0),
+    )?;
+    assert_eq!(count, 1);
+
+    tripwire_tx.send(()).await.ok();
+    tripwire_worker.await;
+    wait_for_all_pending_handles().await;
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn schema_hash_detects_divergence() -> eyre::Result<()> {
+    _ = tracing_subscriber::fmt::try_init();
+
+    let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+    let ta1 = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+    let ta2 = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+
+    let hash = |agent: Agent| async move {
+        let conn = agent.pool().read().await?;
+        Ok::<_, eyre::Report>(schema_hash(&conn)?)
+    };
+
+    // same schema files, same hash
+    assert_eq!(
+        hash(ta1.agent.clone()).await?,
+        hash(ta2.agent.clone()).await?
+    );
+
+    let (status_code, _body) = api_v1_db_schema(
+        Extension(ta1.agent.clone()),
+        axum::extract::Query(SchemaParams::default()),
+        axum::Json(vec![
+            "CREATE TABLE diverged (id INTEGER NOT NULL PRIMARY KEY);".into(),
+        ]),
+    )
+    .await;
+    assert_eq!(status_code, StatusCode::OK);
+
+    assert_ne!(
+        hash(ta1.agent.clone()).await?,
+        hash(ta2.agent.clone()).await?
+    );
+
+    tripwire_tx.send(()).await.ok();
+    tripwire_worker.await;
+    wait_for_all_pending_handles().await;
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn bookkeeping_consistent_after_crash() -> eyre::Result<()> {
+    _ = tracing_subscriber::fmt::try_init();
+
+    let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+    let ta1 = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+    let ta2 = launch_test_agent(
+        |conf| {
+            conf.bootstrap(vec![ta1.agent.gossip_addr().to_string()])
+                .build()
+        },
+        tripwire.clone(),
+    )
+    .await?;
+
+    timeout(Duration::from_secs(10), async {
+        while !ta1
+            .agent
+            .members()
+            .read()
+            .states
+            .contains_key(&ta2.agent.actor_id())
+        {
+            sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await?;
+
+    let writes = tokio::spawn(insert_rows(ta1.agent.clone(), 1, 100));
+
+    // take copies of ta2's database files while it applies changes, each
+    // one is what a process killed at that moment leaves on disk
+    let db_path = ta2.agent.config().db.path.clone();
+    let snapshots = tempfile::tempdir()?;
+    let mut snapshot_paths = vec![];
+    for i in 0..10 {
+        let conn = ta2.agent.pool().read().await?;
+        // an open read transaction keeps checkpoints from writing pages
+        // that aren't in the copied WAL anymore
+        let tx = conn.unchecked_transaction()?;
+        tx.query_row("SELECT count(*) FROM __corro_bookkeeping", [], |row| {
+            row.get::<_, i64>(0)
+        })?;
+
+        let path = snapshots.path().join(format!("snapshot-{i}.db"));
+        std::fs::copy(&db_path, &path)?;
+        let wal_path = format!("{db_path}-wal");
+        if std::path::Path::new(&wal_path).exists() {
+            std::fs::copy(&wal_path, format!("{}-wal", path.display()))?;
+        }
+        snapshot_paths.push(path);
+
+        drop(tx);
+        drop(conn);
+        sleep(Duration::from_millis(20)).await;
+    }
+
+    writes.await?;
+
+    for path in snapshot_paths {
+        let conn = CrConn::init(rusqlite::Connection::open(&path)?)?;
+
+        let booked: Vec<CrsqlDbVersion> = conn
+            .prepare("SELECT db_version FROM __corro_bookkeeping WHERE actor_id = ? AND db_version IS NOT NULL")?
+            .query_map([ta1.agent.actor_id()], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+
+        for db_version in booked.iter() {
+            let count: i64 = conn.query_row(
+                "SELECT count(*) FROM crsql_changes WHERE db_version = ?",
+                [db_version],
+                |row| row.get(0),
+            )?;
+            assert!(
+                count > 0,
+                "{db_version:?} is bookkept but has no changes in {path:?}"
+            );
+        }
+
+        let applied: i64 = conn.query_row(
+            "SELECT count(DISTINCT db_version) FROM crsql_changes WHERE site_id = ?",
+            [ta1.agent.actor_id()],
+            |row| row.get(0),
+        )?;
+        assert_eq!(
+            applied,
+            booked.len() as i64,
+            "changes and bookkeeping diverged in {path:?}"
+        );
+    }
+
+    tripwire_tx.send(()).await.ok();
+    tripwire_worker.await;
+    wait_for_all_pending_handles().await;
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn sync_rejection_reports_load() -> eyre::Result<()> {
+    _ = tracing_subscriber::fmt::try_init();
+
+    let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+    let ta1 = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+    let ta2 = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+
+    // saturate ta1's sync capacity
+    let permits = ta1
+        .agent
+        .limits()
+        .sync
+        .clone()
+        .acquire_many_owned(MAX_CONCURRENT_SYNCS as u32)
+        .await?;
+
+    // a member ta1 knows to be caught up, which it can point ta2 to
+    let alternate = ActorId(Uuid::new_v4());
+    {
+        let mut members = ta1.agent.members().write();
+        members.add_member(&Actor::new(
+            alternate,
+            "127.0.0.1:1".parse()?,
+            Default::default(),
+            ta1.agent.cluster_id(),
+        ));
+        members.set_caught_up(&alternate, true);
+    }
+
+    let (rtt_tx, _rtt_rx) = mpsc::channel(1024);
+    let ta2_transport = Transport::new(&ta2.agent.config().gossip, rtt_tx).await?;
+
+    let res = parallel_sync(
+        &ta2.agent,
+        &ta2_transport,
+        vec![(ta1.agent.actor_id(), ta1.agent.gossip_addr())],
+        generate_sync(&ta2.bookie, ta2.agent.actor_id()).await,
+        HashMap::new(),
+    )
+    .await;
+
+    match res {
+        Err(SyncError::Rejection(SyncRejectionV1::Overloaded(load))) => {
+            assert_eq!(load.in_flight, MAX_CONCURRENT_SYNCS as u32);
+            assert_eq!(load.max, MAX_CONCURRENT_SYNCS as u32);
+            assert!(load.retry_after_ms > 0);
+            assert_eq!(load.alternate, Some(alternate));
+        }
+        res => panic!("expected an overloaded rejection, got: {res:?}"),
+    }
+
+    drop(permits);
+
+    parallel_sync(
+        &ta2.agent,
+        &ta2_transport,
+        vec![(ta1.agent.actor_id(), ta1.agent.gossip_addr())],
+        generate_sync(&ta2.bookie, ta2.agent.actor_id()).await,
+        HashMap::new(),
+    )
+    .await?;
+
+    tripwire_tx.send(()).await.ok();
+    tripwire_worker.await;
+    wait_for_all_pending_handles().await;
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn read_only_replica_syncs_as_client_only() -> eyre::Result<()> {
+    _ = tracing_subscriber::fmt::try_init();
+
+    let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+    let replica = launch_test_agent(
+        |conf| conf.node_role(NodeRole::ReadOnlyReplica).build(),
+        tripwire.clone(),
+    )
+    .await?;
+    let ta = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+
+    insert_rows(ta.agent.clone(), 1, 5).await;
+
+    let (rtt_tx, _rtt_rx) = mpsc::channel(1024);
+    let ta_transport = Transport::new(&ta.agent.config().gossip, rtt_tx.clone()).await?;
+    let replica_transport = Transport::new(&replica.agent.config().gossip, rtt_tx).await?;
+
+    // the replica refuses to be used as a sync source
+    let res = parallel_sync(
+        &ta.agent,
+        &ta_transport,
+        vec![(replica.agent.actor_id(), replica.agent.gossip_addr())],
+        generate_sync(&ta.bookie, ta.agent.actor_id()).await,
+        HashMap::new(),
+    )
+    .await;
+    assert!(
+        matches!(
+            res,
+            Err(SyncError::Rejection(SyncRejectionV1::ReadOnlyReplica))
+        ),
+        "expected a read-only replica rejection, got: {res:?}"
+    );
+
+    // but still catches up from its peers
+    let count = parallel_sync(
+        &replica.agent,
+        &replica_transport,
+        vec![(ta.agent.actor_id(), ta.agent.gossip_addr())],
+        generate_sync(&replica.bookie, replica.agent.actor_id()).await,
+        HashMap::new(),
+    )
+    .await?;
+    assert!(count > 0);
+
+    tripwire_tx.send(()).await.ok();
+    tripwire_worker.await;
+    wait_for_all_pending_handles().await;
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn draining_node_redirects_new_clients() -> eyre::Result<()> {
+    _ = tracing_subscriber::fmt::try_init();
+
+    let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+    let ta1 = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+    let ta2 = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+
+    insert_rows(ta1.agent.clone(), 1, 5).await;
+    insert_rows(ta2.agent.clone(), 6, 10).await;
+
+    let (rtt_tx, _rtt_rx) = mpsc::channel(1024);
+    let ta1_transport = Transport::new(&ta1.agent.config().gossip, rtt_tx.clone()).await?;
+    let ta2_transport = Transport::new(&ta2.agent.config().gossip, rtt_tx).await?;
+
+    let axum::Json(status) = api_v1_admin_drain(Extension(ta1.agent.clone())).await;
+    assert!(status.draining);
+
+    // not picked as a sync source
+    let res = parallel_sync(
+        &ta2.agent,
+        &ta2_transport,
+        vec![(ta1.agent.actor_id(), ta1.agent.gossip_addr())],
+        generate_sync(&ta2.bookie, ta2.agent.actor_id()).await,
+        HashMap::new(),
+    )
+    .await;
+    assert!(
+        matches!(res, Err(SyncError::Rejection(SyncRejectionV1::Draining))),
+        "expected a draining rejection, got: {res:?}"
+    );
+
+    // no new transactions
+    let (status_code, _) = api_v1_transactions(
+        Extension(ta1.agent.clone()),
+        axum::extract::Query(TransactionParams { timeout: None }),
+        axum::Json(vec![Statement::WithParams(
+            "INSERT INTO tests (id,text) VALUES (?,?)".into(),
+            vec![100i64.into(), "draining".into()],
+        )]),
+    )
+    .await;
+    assert_eq!(status_code, StatusCode::SERVICE_UNAVAILABLE);
+
+    let (status_code, axum::Json(health)) = api_v1_health(Extension(ta1.agent.clone())).await;
+    assert_eq!(status_code, StatusCode::SERVICE_UNAVAILABLE);
+    assert!(health.draining);
+    assert!(!health.accepting_writes);
+
+    // but still catches up with the cluster
+    let count = parallel_sync(
+        &ta1.agent,
+        &ta1_transport,
+        vec![(ta2.agent.actor_id(), ta2.agent.gossip_addr())],
+        generate_sync(&ta1.bookie, ta1.agent.actor_id()).await,
+        HashMap::new(),
+    )
+    .await?;
+    assert!(count > 0);
+
+    let axum::Json(status) = api_v1_admin_undrain(Extension(ta1.agent.clone())).await;
+    assert!(!status.draining);
+    let (status_code, axum::Json(health)) = api_v1_health(Extension(ta1.agent.clone())).await;
+    assert_eq!(status_code, StatusCode::OK);
+    assert!(!health.draining);
+
+    let count = parallel_sync(
+        &ta2.agent,
+        &ta2_transport,
+        vec![(ta1.agent.actor_id(), ta1.agent.gossip_addr())],
+        generate_sync(&ta2.bookie, ta2.agent.actor_id()).await,
+        HashMap::new(),
+    )
+    .await?;
+    assert!(count > 0);
+
+    tripwire_tx.send(()).await.ok();
+    tripwire_worker.await;
+    wait_for_all_pending_handles().await;
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn resync_pulls_everything_again() -> eyre::Result<()> {
+    _ = tracing_subscriber::fmt::try_init();
+
+    let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+    let ta1 = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+    let ta2 = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+
+    insert_rows(ta1.agent.clone(), 1, 5).await;
+
+    let (rtt_tx, _rtt_rx) = mpsc::channel(1024);
+    let ta2_transport = Transport::new(&ta2.agent.config().gossip, rtt_tx).await?;
+    let peer = vec![(ta1.agent.actor_id(), ta1.agent.gossip_addr())];
+
+    let count = parallel_sync(
+        &ta2.agent,
+        &ta2_transport,
+        peer.clone(),
+        generate_sync(&ta2.bookie, ta2.agent.actor_id()).await,
+        HashMap::new(),
+    )
+    .await?;
+    assert!(count > 0);
+
+    // wait for the synced changes to be applied
+    timeout(Duration::from_secs(5), async {
+        while generate_sync(&ta2.bookie, ta2.agent.actor_id())
+            .await
+            .heads
+            .get(&ta1.agent.actor_id())
+            != Some(&Version(5))
+        {
+            sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await?;
+
+    // caught up, a regular sync has nothing left to pull
+    let count = parallel_sync(
+        &ta2.agent,
+        &ta2_transport,
+        peer.clone(),
+        generate_sync(&ta2.bookie, ta2.agent.actor_id()).await,
+        HashMap::new(),
+    )
+    .await?;
+    assert_eq!(count, 0);
+
+    // while a resync pulls everything again
+    let resync_count = parallel_sync(
+        &ta2.agent,
+        &ta2_transport,
+        peer,
+        SyncStateV1::need_everything(ta2.agent.actor_id(), [ta1.agent.actor_id()]),
+        HashMap::new(),
+    )
+    .await?;
+    assert!(resync_count > 0);
+
+    let res = handle_resync(
+        &ta2.agent,
+        &ta2.bookie,
+        &ta2_transport,
+        Some(ActorId(Uuid::new_v4())),
+    )
+    .await;
+    assert!(matches!(res, Err(SyncClientError::NotCandidate(_))));
+
+    tripwire_tx.send(()).await.ok();
+    tripwire_worker.await;
+    wait_for_all_pending_handles().await;
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn resync_repairs_diverged_row() -> eyre::Result<()> {
+    _ = tracing_subscriber::fmt::try_init();
+
+    let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+    let ta1 = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+    let ta2 = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+
+    ta2.agent.members().write().add_member(&Actor::new(
+        ta1.agent.actor_id(),
+        ta1.agent.gossip_addr(),
+        Default::default(),
+        ta2.agent.cluster_id(),
+    ));
+
+    insert_rows(ta1.agent.clone(), 1, 5).await;
+
+    let (rtt_tx, _rtt_rx) = mpsc::channel(1024);
+    let ta2_transport = Transport::new(&ta2.agent.config().gossip, rtt_tx).await?;
+
+    parallel_sync(
+        &ta2.agent,
+        &ta2_transport,
+        vec![(ta1.agent.actor_id(), ta1.agent.gossip_addr())],
+        generate_sync(&ta2.bookie, ta2.agent.actor_id()).await,
+        HashMap::new(),
+    )
+    .await?;
+
+    let row_count = |agent: Agent| async move {
+        let conn = agent.pool().read().await?;
+        Ok::<_, eyre::Report>(conn.query_row(
+            "SELECT count(*) FROM tests3 WHERE id = 1 AND text = 'service-name'",
+            [],
+            |row| row.get::<_, i64>(0),
+        )?)
+    };
+
+    timeout(Duration::from_secs(5), async {
+        while row_count(ta2.agent.clone()).await? != 1 {
+            sleep(Duration::from_millis(50)).await;
+        }
+        Ok::<_, eyre::Report>(())
+    })
+    .await??;
+
+    // lose the row and its clocks behind cr-sqlite's back, bookkeeping
+    // still says we have every version
+    {
+        let conn = ta2.agent.pool().write_priority().await?;
+        conn.query_row("SELECT crsql_internal_sync_bit(1)", [], |_| Ok(()))?;
+        conn.execute_batch(
+            "DELETE FROM tests3 WHERE id = 1;
+            DELETE FROM tests3__crsql_clock WHERE key IN (SELECT __crsql_key FROM tests3__crsql_pks WHERE id = 1);
+            DELETE FROM tests3__crsql_pks WHERE id = 1;",
+        )?;
+        conn.query_row("SELECT crsql_internal_sync_bit(0)", [], |_| Ok(()))?;
+    }
+    assert_eq!(row_count(ta2.agent.clone()).await?, 0);
+    assert_eq!(
+        generate_sync(&ta2.bookie, ta2.agent.actor_id())
+            .await
+            .heads
+            .get(&ta1.agent.actor_id()),
+        Some(&Version(5))
+    );
+
+    let (actor_id, count) = handle_resync(
+        &ta2.agent,
+        &ta2.bookie,
+        &ta2_transport,
+        Some(ta1.agent.actor_id()),
+    )
+    .await?;
+    assert_eq!(actor_id, ta1.agent.actor_id());
+    assert!(count > 0);
+
+    timeout(Duration::from_secs(5), async {
+        while row_count(ta2.agent.clone()).await? != 1 {
+            sleep(Duration::from_millis(50)).await;
+        }
+        Ok::<_, eyre::Report>(())
+    })
+    .await??;
+
+    // a node syncing from the repaired one gets the repaired row too
+    let ta3 = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+    let (rtt_tx, _rtt_rx) = mpsc::channel(1024);
+    let ta3_transport = Transport::new(&ta3.agent.config().gossip, rtt_tx).await?;
+
+    parallel_sync(
+        &ta3.agent,
+        &ta3_transport,
+        vec![(ta2.agent.actor_id(), ta2.agent.gossip_addr())],
+        generate_sync(&ta3.bookie, ta3.agent.actor_id()).await,
+        HashMap::new(),
+    )
+    .await?;
+
+    timeout(Duration::from_secs(5), async {
+        while row_count(ta3.agent.clone()).await? != 1 {
+            sleep(Duration::from_millis(50)).await;
+        }
+        Ok::<_, eyre::Report>(())
+    })
+    .await??;
+
+    tripwire_tx.send(()).await.ok();
+    tripwire_worker.await;
+    wait_for_all_pending_handles().await;
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn api_sheds_requests_past_task_limit() -> eyre::Result<()> {
+    _ = tracing_subscriber::fmt::try_init();
+
+    let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+    let ta = launch_test_agent(
+        |conf| conf.max_concurrent_tasks(NonZeroUsize::MIN).build(),
+        tripwire.clone(),
+    )
+    .await?;
+
+    let client = hyper::Client::new();
+    let health_uri: hyper::Uri = format!("http://{}/v1/health", ta.agent.api_addr()).parse()?;
+
+    // hold the write connection so a transaction stays in flight
+    let conn = ta.agent.pool().write_priority().await?;
+    let req_body: Vec<Statement> = serde_json::from_value(json!([[
+        "INSERT INTO tests (id,text) VALUES (?,?)",
+        [1, "hello world 1"]
+    ],]))?;
+    let pending = tokio::spawn({
+        let client = client.clone();
+        let req = hyper::Request::builder()
+            .method(hyper::Method::POST)
+            .uri(format!("http://{}/v1/transactions", ta.agent.api_addr()))
+            .header(hyper::header::CONTENT_TYPE, "application/json")
+            .body(serde_json::to_vec(&req_body)?.into())?;
+        async move { client.request(req).await }
+    });
+    sleep(Duration::from_millis(200)).await;
+
+    let res = timeout(Duration::from_secs(5), client.get(health_uri.clone())).await??;
+    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+    let body = hyper::body::to_bytes(res.into_body()).await?;
+    assert_eq!(body, "max concurrent tasks reached");
+
+    drop(conn);
+    let res = timeout(Duration::from_secs(5), pending).await???;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res = timeout(Duration::from_secs(5), client.get(health_uri)).await??;
+    let body = hyper::body::to_bytes(res.into_body()).await?;
+    assert_ne!(body, "max concurrent tasks reached");
+
+    tripwire_tx.send(()).await.ok();
+    tripwire_worker.await;
+    wait_for_all_pending_handles().await;
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn prometheus_exporter() -> eyre::Result<()> {
+    _ = tracing_subscriber::fmt::try_init();
+
+    let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+
+    let prometheus_addr = std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+    let ta = launch_test_agent(
+        |conf| conf.prometheus_addr(prometheus_addr).build(),
+        tripwire.clone(),
+    )
+    .await?;
+
+    insert_rows(ta.agent.clone(), 1, 1).await;
+
+    let client = hyper::Client::new();
+    let uri: hyper::Uri = format!("http://{prometheus_addr}/metrics").parse()?;
+
+    let start = Instant::now();
+    loop {
+        let res = client.get(uri.clone()).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await?;
+        if String::from_utf8_lossy(&body).contains("corro_") {
+            break;
+        }
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "no corrosion metrics were exported"
+        );
+        sleep(Duration::from_millis(100)).await;
+    }
+
+    tripwire_tx.send(()).await.ok();
+    tripwire_worker.await;
+    wait_for_all_pending_handles().await;
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn healthy_cluster_broadcasts_are_never_suppressed() -> eyre::Result<()> {
+    _ = tracing_subscriber::fmt::try_init();
+
+    let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+    // a window long enough for every change of the test to land in it
+    let ta1 = launch_test_agent(
+        |conf| {
+            let mut conf = conf.build()?;
+            conf.gossip.redundant_window_secs = 60;
+            Ok(conf)
+        },
+        tripwire.clone(),
+    )
+    .await?;
+    let ta2 = launch_test_agent(
+        |conf| {
+            let mut conf = conf
+                .bootstrap(vec![ta1.agent.gossip_addr().to_string()])
+                .build()?;
+            conf.gossip.redundant_window_secs = 60;
+            Ok(conf)
+        },
+        tripwire.clone(),
+    )
+    .await?;
+    let ta3 = launch_test_agent(
+        |conf| {
+            let mut conf = conf
+                .bootstrap(vec![ta1.agent.gossip_addr().to_string()])
+                .build()?;
+            conf.gossip.redundant_window_secs = 60;
+            Ok(conf)
+        },
+        tripwire.clone(),
+    )
+    .await?;
+    let agents = [&ta1.agent, &ta2.agent, &ta3.agent];
+
+    // every node relays to both others
+    timeout(Duration::from_secs(10), async {
+        while agents
+            .iter()
+            .any(|agent| agent.members().read().states.len() < 2)
+        {
+            sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await?;
+
+    let per_node = REDUNDANT_MIN_CHANGES as i64 + 20;
+    for (i, agent) in agents.iter().enumerate() {
+        let start = i as i64 * per_node + 1;
+        insert_rows((*agent).clone(), start, start + per_node - 1).await;
+    }
+
+    timeout(Duration::from_secs(30), async {
+        for agent in agents {
+            loop {
+                let count: i64 = agent.pool().read().await?.query_row(
+                    "SELECT count(*) FROM tests3",
+                    [],
+                    |row| row.get(0),
+                )?;
+                if count == 3 * per_node {
+                    break;
+                }
+                sleep(Duration::from_millis(100)).await;
+            }
+        }
+        Ok::<_, eyre::Report>(())
+    })
+    .await??;
+
+    for agent in agents {
+        let redundancy = agent.broadcast_redundancy();
+        assert!(redundancy.suppressed(Instant::now()).is_empty());
+        for peer in agents {
+            if peer.actor_id() == agent.actor_id() {
+                continue;
+            }
+            let (received, repeated) = redundancy
+                .window_counts(peer.gossip_addr())
+                .expect("no broadcast received from peer");
+            assert!(received >= REDUNDANT_MIN_CHANGES);
+            assert_eq!(repeated, 0);
+        }
+    }
+
+    tripwire_tx.send(()).await.ok();
+    tripwire_worker.await;
+    wait_for_all_pending_handles().await;
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn concurrent_buffered_applies() -> eyre::Result<()> {
+    _ = tracing_subscriber::fmt::try_init();
+
+    let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+    let ta1 = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+    let ta2 = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+    let ta3 = launch_test_agent(
+        |conf| {
+            conf.apply(ApplyConfig {
+                concurrency: 4,
+                ..Default::default()
+            })
+            .build()
+        },
+        tripwire.clone(),
+    )
+    .await?;
+
+    for agent in [&ta1.agent, &ta2.agent, &ta3.agent] {
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::extract::Query(SchemaParams::default()),
+            axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+    }
+
+    // buffer versions from 2 actors while paused
+    ta3.agent.set_paused(true);
+
+    insert_rows(ta1.agent.clone(), 1, 5).await;
+    insert_rows(ta2.agent.clone(), 6, 10).await;
+
+    for agent in [&ta1.agent, &ta2.agent] {
+        let rows = get_rows(agent.clone(), vec![(Version(1)..=Version(5), None)]).await?;
+        process_multiple_changes(
+            ta3.agent.clone(),
+            ta3.bookie.clone(),
+            rows,
+            Duration::from_secs(60),
+        )
+        .await?;
+    }
+
+    // hold the only write connection so applies can't finish, both
+    // actors' must then be in flight at once
+    let conn = ta3.agent.pool().write_priority().await?;
+    ta3.agent.set_paused(false);
+
+    let start = Instant::now();
+    while ta3.agent.limits().apply_in_flight() < 2 {
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "buffered changes of distinct actors were not applied concurrently"
+        );
+        sleep(Duration::from_millis(50)).await;
+    }
+    drop(conn);
+
+    let start = Instant::now();
+    loop {
+        let count: i64 = {
+            let conn = ta3.agent.pool().read().await?;
+            conn.query_row("SELECT COUNT(*) FROM tests3", [], |row| row.get(0))?
+        };
+        if count == 10 {
+            break;
+        }
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "buffered changes were not applied"
+        );
+        sleep(Duration::from_millis(100)).await;
+    }
+
+    for agent in [&ta1.agent, &ta2.agent] {
+        let booked = ta3
+            .bookie
+            .write::<&str, _>("test", None)
+            .await
+            .ensure(agent.actor_id());
+        let booked = booked.read::<&str, _>("test", None).await;
+        assert!(booked.partials.is_empty());
+        assert!(booked.contains_all(Version(1)..=Version(5), None));
+    }
+
+    tripwire_tx.send(()).await.ok();
+    tripwire_worker.await;
+    wait_for_all_pending_handles().await;
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn buffered_batch_applies_in_one_transaction() -> eyre::Result<()> {
+    _ = tracing_subscriber::fmt::try_init();
+
+    let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+    let ta1 = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+    let ta2 = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+
+    for agent in [&ta1.agent, &ta2.agent] {
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::extract::Query(SchemaParams::default()),
+            axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+    }
+
+    // stay paused so the apply loop leaves the buffered versions alone
+    ta2.agent.set_paused(true);
+
+    insert_rows(ta1.agent.clone(), 1, 3).await;
+    let rows = get_rows(ta1.agent.clone(), vec![(Version(1)..=Version(3), None)]).await?;
+    process_multiple_changes(
+        ta2.agent.clone(),
+        ta2.bookie.clone(),
+        rows,
+        Duration::from_secs(60),
+    )
+    .await?;
+
+    let versions = [Version(1), Version(2), Version(3)];
+
+    // the last version of the batch fails, the first ones must not be
+    // committed without it
+    ta2.agent.pool().write_priority().await?.execute_batch(
+        "CREATE TRIGGER reject_third BEFORE INSERT ON tests3 WHEN NEW.id = 3 BEGIN SELECT RAISE(ABORT, 'rejected'); END;",
+    )?;
+
+    let res = process_fully_buffered_changes(
+        &ta2.agent,
+        &ta2.bookie,
+        ta1.agent.actor_id(),
+        &versions,
+        Duration::from_secs(60),
+    )
+    .await;
+    assert!(res.is_err());
+    let count: i64 =
+        ta2.agent
+            .pool()
+            .read()
+            .await?
+            .query_row("SELECT COUNT(*) FROM tests3", [], |row| row.get(0))?;
+    assert_eq!(count, 0);
+    let partials = ta2
+        .bookie
+        .write::<&str, _>("test", None)
+        .await
+        .ensure(ta1.agent.actor_id())
+        .read::<&str, _>("test", None)
+        .await
+        .partials
+        .len();
+    assert_eq!(partials, 3);
+
+    ta2.agent
+        .pool()
+        .write_priority()
+        .await?
+        .execute_batch("DROP TRIGGER reject_third;")?;
+
+    let applied = process_fully_buffered_changes(
+        &ta2.agent,
+        &ta2.bookie,
+        ta1.agent.actor_id(),
+        &versions,
+        Duration::from_secs(60),
+    )
+    .await?;
+    assert_eq!(
+        applied,
+        vec![(Version(1), true), (Version(2), true), (Version(3), true)]
+    );
+    let count: i64 =
+        ta2.agent
+            .pool()
+            .read()
+            .await?
+            .query_row("SELECT COUNT(*) FROM tests3", [], |row| row.get(0))?;
+    assert_eq!(count, 3);
+    let partials = ta2
+        .bookie
+        .write::<&str, _>("test", None)
+        .await
+        .ensure(ta1.agent.actor_id())
+        .read::<&str, _>("test", None)
+        .await
+        .partials
+        .len();
+    assert_eq!(partials, 0);
+
+    // each version still gets its own db version
+    let db_versions: Vec<CrsqlDbVersion> = ta2
+        .agent
+        .pool()
+        .read()
+        .await?
+        .prepare(
+            "SELECT db_version FROM __corro_bookkeeping WHERE actor_id = ? ORDER BY start_version",
+        )?
+        .query_map([ta1.agent.actor_id()], |row| row.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+    assert_eq!(db_versions.len(), 3);
+    assert!(db_versions.windows(2).all(|w| w[0] < w[1]));
+
+    tripwire_tx.send(()).await.ok();
+    tripwire_worker.await;
+    wait_for_all_pending_handles().await;
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn superseded_buffered_changes_are_compacted() -> eyre::Result<()> {
+    _ = tracing_subscriber::fmt::try_init();
+
+    let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+    let ta1 = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+    let ta2 = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+
+    let (status_code, _) = api_v1_transactions(
+        Extension(ta1.agent.clone()),
+        axum::extract::Query(TransactionParams { timeout: None }),
+        axum::Json(vec![
+            Statement::Simple("INSERT INTO tests (id, text) VALUES (1, 'one')".into()),
+            Statement::Simple("INSERT INTO tests (id, text) VALUES (2, 'two')".into()),
+        ]),
+    )
+    .await;
+    assert_eq!(status_code, StatusCode::OK);
+
+    let rows = get_rows(ta1.agent.clone(), vec![(Version(1)..=Version(1), None)]).await?;
+    let (change, source, _) = rows.into_iter().next().unwrap();
+    let Changeset::Full { changes, ts, .. } = change.changeset else {
+        panic!("expected a full changeset");
+    };
+    assert_eq!(changes.len(), 2);
+
+    // the first column is written twice in the same version, the first
+    // write loses to the second once applied
+    let stale = Change {
+        val: "stale".into(),
+        seq: CrsqlSeq(0),
+        ..changes[0].clone()
+    };
+    let newer = Change {
+        col_version: changes[0].col_version + 1,
+        seq: CrsqlSeq(1),
+        ..changes[0].clone()
+    };
+    let other = Change {
+        seq: CrsqlSeq(2),
+        ..changes[1].clone()
+    };
+
+    let partial = |changes: Vec<Change>, seqs: RangeInclusive<CrsqlSeq>| {
+        (
+            ChangeV1 {
+                actor_id: ta1.agent.actor_id(),
+                changeset: Changeset::Full {
+                    version: Version(1),
+                    changes,
+                    seqs,
+                    last_seq: CrsqlSeq(2),
+                    ts,
+                },
+                trace_id: None,
+            },
+            source,
+            Instant::now(),
+        )
+    };
+
+    let first = partial(vec![stale, newer.clone()], CrsqlSeq(0)..=CrsqlSeq(1));
+    process_multiple_changes(
+        ta2.agent.clone(),
+        ta2.bookie.clone(),
+        vec![first],
+        Duration::from_secs(60),
+    )
+    .await?;
+
+    let buffered: Vec<(CrsqlSeq, i64)> = ta2
+        .agent
+        .pool()
+        .read()
+        .await?
+        .prepare("SELECT seq, col_version FROM __corro_buffered_changes ORDER BY seq")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+    assert_eq!(buffered, vec![(CrsqlSeq(1), newer.col_version)]);
+
+    process_multiple_changes(
+        ta2.agent.clone(),
+        ta2.bookie.clone(),
+        vec![partial(vec![other], CrsqlSeq(2)..=CrsqlSeq(2))],
+        Duration::from_secs(60),
+    )
+    .await?;
+
+    // same outcome as applying every change
+    let rows: Vec<(i64, String)> = ta2
+        .agent
+        .pool()
+        .read()
+        .await?
+        .prepare("SELECT id, text FROM tests ORDER BY id")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+    assert_eq!(rows, vec![(1, "one".to_string()), (2, "two".to_string())]);
+
+    let booked = ta2
+        .bookie
+        .write::<&str, _>("test", None)
+        .await
+        .ensure(ta1.agent.actor_id());
+    let booked = booked.read::<&str, _>("test", None).await;
+    assert!(booked.partials.is_empty());
+    assert!(booked.contains_all(Version(1)..=Version(1), None));
+
+    tripwire_tx.send(()).await.ok();
+    tripwire_worker.await;
+    wait_for_all_pending_handles().await;
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn probe_member_reachability() -> eyre::Result<()> {
+    _ = tracing_subscriber::fmt::try_init();
+
+    let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+    let ta1 = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+    let ta2 = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+
+    ta1.agent.members().write().add_member(&Actor::new(
+        ta2.agent.actor_id(),
+        ta2.agent.gossip_addr(),
+        Default::default(),
+        ta1.agent.cluster_id(),
+    ));
+
+    let (rtt_tx, _rtt_rx) = mpsc::channel(1024);
+    let ta1_transport = Transport::new(&ta1.agent.config().gossip, rtt_tx).await?;
+
+    let (status_code, body) = api_v1_admin_member_probe(
+        Extension(ta1.agent.clone()),
+        Extension(ta1_transport.clone()),
+        axum::extract::Path(ta2.agent.actor_id()),
+    )
+    .await;
+    assert_eq!(status_code, StatusCode::OK);
+    assert_eq!(body.0["reachable"], json!(true));
+    assert_eq!(body.0["addr"], json!(ta2.agent.gossip_addr()));
+    assert!(body.0["rtt_ms"].as_f64().is_some());
+
+    let (status_code, _body) = api_v1_admin_member_probe(
+        Extension(ta1.agent.clone()),
+        Extension(ta1_transport),
+        axum::extract::Path(ActorId(Uuid::new_v4())),
+    )
+    .await;
+    assert_eq!(status_code, StatusCode::NOT_FOUND);
+
+    tripwire_tx.send(()).await.ok();
+    tripwire_worker.await;
+    wait_for_all_pending_handles().await;
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn announce_on_demand() -> eyre::Result<()> {
+    _ = tracing_subscriber::fmt::try_init();
+
+    let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+    let ta1 = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+    // no bootstrap, ta2 only learns about ta1 once it's in its members table
+    let ta2 = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+    assert!(!ta1
+        .agent
+        .members()
+        .read()
+        .states
+        .contains_key(&ta2.agent.actor_id()));
+
+    {
+        let conn = ta2.agent.pool().write_priority().await?;
+        conn.execute(
+            "INSERT INTO __corro_members (actor_id, address, foca_state, updated_at) VALUES (?, ?, '{}', ?)",
+            rusqlite::params![
+                ta1.agent.actor_id(),
+                ta1.agent.gossip_addr().to_string(),
+                time::OffsetDateTime::now_utc()
+            ],
+        )?;
+    }
+
+    let (status_code, body) = api_v1_admin_announce(Extension(ta2.agent.clone())).await;
+    assert_eq!(status_code, StatusCode::OK);
+    let addrs: Vec<SocketAddr> = serde_json::from_value(body.0["addrs"].clone())?;
+    assert!(addrs.contains(&ta1.agent.gossip_addr()));
+
+    timeout(Duration::from_secs(10), async {
+        while !ta1
+            .agent
+            .members()
+            .read()
+            .states
+            .contains_key(&ta2.agent.actor_id())
+        {
+            sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await?;
+
+    tripwire_tx.send(()).await.ok();
+    tripwire_worker.await;
+    wait_for_all_pending_handles().await;
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn prune_stale_members() -> eyre::Result<()> {
+    _ = tracing_subscriber::fmt::try_init();
+
+    let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+    let ta = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+
+    let stale_id = ActorId(Uuid::new_v4());
+    let fresh_id = ActorId(Uuid::new_v4());
+    {
+        let conn = ta.agent.pool().write_priority().await?;
+        let now = time::OffsetDateTime::now_utc();
+        let mut prepped = conn.prepare(
+            "INSERT INTO __corro_members (actor_id, address, foca_state, updated_at) VALUES (?, ?, '{}', ?)",
+        )?;
+        prepped.execute(rusqlite::params![
+            stale_id,
+            "127.0.0.1:1",
+            now - time::Duration::days(30)
+        ])?;
+        prepped.execute(rusqlite::params![fresh_id, "127.0.0.1:2", now])?;
+    }
+
+    let count_members = || async {
+        let conn = ta.agent.pool().read().await?;
+        let ids = conn
+            .prepare("SELECT actor_id FROM __corro_members")?
+            .query_map([], |row| row.get::<_, ActorId>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok::<_, eyre::Report>(ids)
+    };
+
+    let (status_code, body) = api_v1_admin_members_prune(
+        Extension(ta.agent.clone()),
+        axum::extract::Query(PruneMembersParams {
+            older_than_secs: Some(24 * 60 * 60),
+            dry_run: true,
+        }),
+    )
+    .await;
+    assert_eq!(status_code, StatusCode::OK);
+    assert_eq!(body.0["pruned"][0]["actor_id"], serde_json::json!(stale_id));
+    assert_eq!(body.0["pruned"].as_array().map(|a| a.len()), Some(1));
+    assert_eq!(count_members().await?.len(), 2);
+
+    // a cutoff before any representable time is refused, not a panic
+    for older_than_secs in [u64::MAX, i64::MAX as u64] {
+        let (status_code, _body) = api_v1_admin_members_prune(
+            Extension(ta.agent.clone()),
+            axum::extract::Query(PruneMembersParams {
+                older_than_secs: Some(older_than_secs),
+                dry_run: true,
+            }),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::BAD_REQUEST);
+    }
+    assert_eq!(count_members().await?.len(), 2);
+
+    let (status_code, _body) = api_v1_admin_members_prune(
+        Extension(ta.agent.clone()),
+        axum::extract::Query(PruneMembersParams {
+            older_than_secs: Some(24 * 60 * 60),
+            dry_run: false,
+        }),
+    )
+    .await;
+    assert_eq!(status_code, StatusCode::OK);
+    assert_eq!(count_members().await?, vec![fresh_id]);
+
+    tripwire_tx.send(()).await.ok();
+    tripwire_worker.await;
+    wait_for_all_pending_handles().await;
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn admin_storage_reports_table_sizes() -> eyre::Result<()> {
+    _ = tracing_subscriber::fmt::try_init();
+
+    let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+    let ta = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+
+    insert_rows(ta.agent.clone(), 1, 10).await;
+
+    let table = |body: &serde_json::Value, name: &str| {
+        body["tables"]
+            .as_array()
+            .and_then(|tables| tables.iter().find(|t| t["name"] == name).cloned())
+            .unwrap_or_else(|| panic!("missing table {name} in {body}"))
+    };
+
+    let (status_code, body) = api_v1_admin_storage(
+        Extension(ta.agent.clone()),
+        axum::extract::Query(StorageParams { dbstat: false }),
+    )
+    .await;
+    assert_eq!(status_code, StatusCode::OK);
+    let page_size = body.0["page_size"].as_u64().unwrap();
+    let page_count = body.0["page_count"].as_u64().unwrap();
+    assert_eq!(body.0["db_bytes"].as_u64(), Some(page_size * page_count));
+    let changes = table(&body.0, "crsql_changes");
+    assert!(changes["rows"].as_u64().unwrap() >= 10);
+    assert!(changes.get("bytes").is_none());
+    let bookkeeping = table(&body.0, "__corro_bookkeeping");
+    assert!(bookkeeping["rows"].as_u64().unwrap() > 0);
+
+    let (status_code, body) = api_v1_admin_storage(
+        Extension(ta.agent.clone()),
+        axum::extract::Query(StorageParams { dbstat: true }),
+    )
+    .await;
+    assert_eq!(status_code, StatusCode::OK);
+    assert!(table(&body.0, "crsql_changes")["bytes"].as_u64().unwrap() > 0);
+    let buffered = table(&body.0, "__corro_buffered_changes");
+    assert!(buffered["bytes"].as_u64().is_some());
+
+    tripwire_tx.send(()).await.ok();
+    tripwire_worker.await;
+    wait_for_all_pending_handles().await;
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn admin_config_redacts_secrets() -> eyre::Result<()> {
+    _ = tracing_subscriber::fmt::try_init();
+
+    let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+    let ta = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+
+    let mut config = Config::clone(&ta.agent.config());
+    config.api.authorization = Some(AuthzConfig::BearerToken("secret-token".into()));
+    ta.agent.set_config(config);
+
+    let (status_code, body) = api_v1_admin_config(Extension(ta.agent.clone())).await;
+    assert_eq!(status_code, StatusCode::OK);
+
+    assert_eq!(
+        body.0["db"]["path"].as_str(),
+        Some(ta.agent.config().db.path.as_str())
+    );
+    // defaults are filled in
+    assert_eq!(body.0["db"]["synchronous"], "normal");
+    assert_eq!(body.0["api"]["authorization"]["bearer-token"], "<redacted>");
+    assert!(!body.0.to_string().contains("secret-token"));
+
+    tripwire_tx.send(()).await.ok();
+    tripwire_worker.await;
+    wait_for_all_pending_handles().await;
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn admin_config_reload() -> eyre::Result<()> {
+    _ = tracing_subscriber::fmt::try_init();
+
+    let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+    let ta = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+
+    // started from a builder, there's nothing to reload
+    let (status_code, _body) = api_v1_admin_config_reload(Extension(ta.agent.clone())).await;
+    assert_eq!(status_code, StatusCode::CONFLICT);
+
+    let config_path = ta.tmpdir.path().join("config.toml");
+    tokio::fs::write(
+        &config_path,
+        r#"
+            [db]
+            path = "/somewhere/else.db"
+            schema_paths = ["/somewhere/schema"]
+
+            [api]
+            addr = "127.0.0.1:0"
+
+            [gossip]
+            addr = "127.0.0.1:0"
+            bootstrap = ["127.0.0.1:9999"]
+
+            [perf]
+            gossip_handler_concurrency = 7
+
+            [sync]
+            chunk_size = 1234
+
+            [apply]
+            concurrency = 9
+        "#,
+    )
+    .await?;
+
+    let mut config = Config::clone(&ta.agent.config());
+    config.config_path = Some(config_path.display().to_string().into());
+    let db_path = config.db.path.clone();
+    let schema_paths = config.db.schema_paths.clone();
+    let gossip_handler_concurrency = config.perf.gossip_handler_concurrency;
+    let apply_concurrency = config.apply.concurrency;
+    ta.agent.set_config(config);
+
+    let (status_code, body) = api_v1_admin_config_reload(Extension(ta.agent.clone())).await;
+    assert_eq!(status_code, StatusCode::OK);
+
+    let requires_restart: Vec<String> = serde_json::from_value(body.0["requires_restart"].clone())?;
+    for name in [
+        "db.path",
+        "db.schema_paths",
+        "perf.gossip_handler_concurrency",
+        "apply.concurrency",
+    ] {
+        assert!(requires_restart.contains(&name.to_string()), "{name}");
+    }
+    assert!(!requires_restart
+        .iter()
+        .any(|name| name.contains("bootstrap") || name == "sync.chunk_size"));
+
+    let config = ta.agent.config();
+    assert_eq!(config.gossip.bootstrap, vec!["127.0.0.1:9999".to_string()]);
+    assert_eq!(config.sync.chunk_size, 1234);
+    assert_eq!(config.db.path, db_path);
+    assert_eq!(config.db.schema_paths, schema_paths);
+    assert_eq!(
+        config.perf.gossip_handler_concurrency,
+        gossip_handler_concurrency
+    );
+    assert_eq!(config.apply.concurrency, apply_concurrency);
+    drop(config);
+
+    tripwire_tx.send(()).await.ok();
+    tripwire_worker.await;
+    wait_for_all_pending_handles().await;
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn paused_agent_buffers_changes() -> eyre::Result<()> {
+    _ = tracing_subscriber::fmt::try_init();
+
+    let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+    let ta1 = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+    let ta2 = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+
+    for agent in [&ta1.agent, &ta2.agent] {
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::extract::Query(SchemaParams::default()),
+            axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+    }
+
+    let axum::Json(status) = api_v1_admin_pause(Extension(ta2.agent.clone())).await;
+    assert!(status.paused);
+    assert!(ta2.agent.is_paused());
+
+    insert_rows(ta1.agent.clone(), 1, 5).await;
+    let rows = get_rows(ta1.agent.clone(), vec![(Version(1)..=Version(5), None)]).await?;
+    process_multiple_changes(
+        ta2.agent.clone(),
+        ta2.bookie.clone(),
+        rows,
+        Duration::from_secs(60),
+    )
+    .await?;
+
+    // nothing applied yet, everything is buffered
+    let conn = ta2.agent.pool().read().await?;
+    let count: i64 = conn.query_row("SELECT COUNT(*) FROM tests3", [], |row| row.get(0))?;
+    assert_eq!(count, 0);
+    drop(conn);
+
+    let partials = ta2
+        .bookie
+        .write::<&str, _>("test", None)
+        .await
+        .ensure(ta1.agent.actor_id())
+        .read::<&str, _>("test", None)
+        .await
+        .partials
+        .len();
+    assert_eq!(partials, 5);
+
+    let axum::Json(status) = api_v1_admin_resume(Extension(ta2.agent.clone())).await;
+    assert!(!status.paused);
+
+    let start = Instant::now();
+    loop {
+        let count: i64 = {
+            let conn = ta2.agent.pool().read().await?;
+            conn.query_row("SELECT COUNT(*) FROM tests3", [], |row| row.get(0))?
+        };
+        if count == 5 {
+            break;
+        }
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "buffered changes were not applied after resuming"
+        );
+        sleep(Duration::from_millis(100)).await;
+    }
+
+    tripwire_tx.send(()).await.ok();
+    tripwire_worker.await;
+    wait_for_all_pending_handles().await;
+
+    Ok(())
+}
+
 async fn check_bookie_versions(
     ta: TestAgent,
     actor_id: ActorId,
@@ -1331,6 +3966,7 @@ async fn get_rows(
                         last_seq: CrsqlSeq(last),
                         ts: agent.clock().new_timestamp().into(),
                     },
+                    trace_id: None,
                 },
                 ChangeSource::Broadcast,
                 Instant::now(),
@@ -1457,7 +4093,7 @@ async fn many_small_changes() -> eyre::Result<()> {
                             serde_json::from_slice(&hyper::body::to_bytes(res.into_body()).await?)?;
 
                         match &body.results[0] {
-                            ExecResult::Execute { .. } => {}
+                            ExecResult::Execute { .. } | ExecResult::CompareAndSet { .. } => {}
                             ExecResult::Error { error } => {
                                 eyre::bail!("error: {error}");
                             }
@@ -2194,6 +4830,7 @@ async fn test_automatic_bookkeeping_clearing() -> eyre::Result<()> {
     // setup the schema, for both nodes
     let (status_code, _body) = api_v1_db_schema(
         Extension(ta1.agent.clone()),
+        axum::extract::Query(SchemaParams::default()),
         axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
     )
     .await;
@@ -2202,6 +4839,7 @@ async fn test_automatic_bookkeeping_clearing() -> eyre::Result<()> {
 
     let (status_code, _body) = api_v1_db_schema(
         Extension(ta2.agent.clone()),
+        axum::extract::Query(SchemaParams::default()),
         axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
     )
     .await;
@@ -2272,6 +4910,7 @@ async fn test_automatic_bookkeeping_clearing() -> eyre::Result<()> {
                     last_seq,
                     ts: ta1.agent.clock().new_timestamp().into(),
                 },
+                trace_id: None,
             },
             ChangeSource::Broadcast,
             Instant::now(),
@@ -2341,6 +4980,7 @@ async fn test_automatic_bookkeeping_clearing() -> eyre::Result<()> {
                     last_seq,
                     ts: ta1.agent.clock().new_timestamp().into(),
                 },
+                trace_id: None,
             },
             ChangeSource::Broadcast,
             Instant::now(),
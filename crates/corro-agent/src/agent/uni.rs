@@ -1,19 +1,133 @@
 use corro_types::{
-    actor::ClusterId, broadcast::{BroadcastV1, ChangeSource, ChangeV1, UniPayload, UniPayloadV1}, channel::CorroSender
+    actor::ActorId, agent::{Agent, Bookie}, broadcast::{BroadcastDecodeError, BroadcastV1, ChangeSource, ChangeV1, UniPayload, UniPayloadV1}, channel::CorroSender
 };
-use metrics::counter;
-use speedy::Readable;
+use governor::{Quota, RateLimiter};
+use metrics::{counter, gauge};
+use std::{
+    collections::HashSet,
+    net::SocketAddr,
+    num::NonZeroU32,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 use tokio_stream::StreamExt;
-use tokio_util::codec::{FramedRead, LengthDelimitedCodec};
-use tracing::{debug, error, trace};
+use tokio_util::codec::FramedRead;
+use tracing::{debug, error, trace, warn};
 use tripwire::Tripwire;
 
+use crate::{
+    agent::{handlers::handle_schema_change, util::is_pow_10},
+    broadcast::broadcast_codec,
+};
+
+/// Broadcast frames decoded per second from a peer suppressed for sending
+/// mostly repeats, see `BroadcastRedundancy`
+const SUPPRESSED_FRAMES_PER_SEC: u32 = 100;
+
+/// Count an undecodable broadcast frame and, if a peer keeps sending
+/// them, log its address so it can be tracked down
+fn record_decode_error(kind: &'static str, remote_addr: SocketAddr, errors: &AtomicU64) {
+    counter!("corro.broadcast.decode.error", "kind" => kind).increment(1);
+
+    let count = errors.fetch_add(1, Ordering::Relaxed) + 1;
+    if is_pow_10(count) {
+        warn!(
+            %remote_addr,
+            "peer sent {count} undecodable broadcast frame(s), it may be running an incompatible version"
+        );
+    }
+}
+
+/// Record how many broadcast streams are being read, to see how close
+/// the handlers run to `perf.gossip_handler_concurrency`
+pub fn record_handler_in_flight(agent: &Agent) {
+    gauge!("corro.broadcast.handler.in_flight").set(agent.limits().gossip_in_flight() as f64);
+}
+
+/// Permit to read a broadcast stream, updating the in-flight gauge when
+/// it is released
+struct HandlerPermit {
+    permit: Option<OwnedSemaphorePermit>,
+    agent: Agent,
+}
+
+impl HandlerPermit {
+    fn new(permit: OwnedSemaphorePermit, agent: Agent) -> Self {
+        record_handler_in_flight(&agent);
+        Self {
+            permit: Some(permit),
+            agent,
+        }
+    }
+}
+
+impl Drop for HandlerPermit {
+    fn drop(&mut self) {
+        drop(self.permit.take());
+        record_handler_in_flight(&self.agent);
+    }
+}
+
+/// Drop the changes of `actor_ids` we already booked, reading each actor's
+/// bookkeeping once for all of its changes. Returns how many were dropped.
+async fn drop_booked(
+    bookie: &Bookie,
+    actor_ids: &HashSet<ActorId>,
+    changes: &mut Vec<(ChangeV1, ChangeSource)>,
+) -> u64 {
+    let booked: Vec<_> = {
+        let bookie = bookie.read::<&str, _>("uni_drop_booked(get)", None).await;
+        actor_ids
+            .iter()
+            .filter_map(|actor_id| {
+                bookie
+                    .get(actor_id)
+                    .cloned()
+                    .map(|booked| (*actor_id, booked))
+            })
+            .collect()
+    };
+
+    let before = changes.len();
+    for (actor_id, booked) in booked {
+        let booked = booked
+            .read("uni_drop_booked(contains?)", actor_id.as_simple())
+            .await;
+        changes.retain(|(change, _)| {
+            change.actor_id != actor_id || !booked.contains_all(change.versions(), change.seqs())
+        });
+    }
+    (before - changes.len()) as u64
+}
+
 /// Spawn a task that accepts unidirectional broadcast streams, then
 /// spawns another task for each incoming stream to handle.
-pub fn spawn_unipayload_handler(tripwire: &Tripwire, conn: &quinn::Connection, cluster_id: ClusterId, tx_changes: CorroSender<(ChangeV1, ChangeSource)>) {
+///
+/// At most as many streams as `permits` allows are handled at once,
+/// further streams aren't accepted until one is done, leaving QUIC flow
+/// control to push back on the sender. Frames longer than
+/// `max_frame_len` are counted as decode errors and end the stream.
+///
+/// Changes the peer already sent are dropped before processing. Once it
+/// sent mostly repeats, its frames are decoded at a throttled rate and its
+/// changes we already booked are dropped too, see `BroadcastRedundancy`.
+pub fn spawn_unipayload_handler(agent: &Agent, bookie: &Bookie, tripwire: &Tripwire, conn: &quinn::Connection, tx_changes: CorroSender<(ChangeV1, ChangeSource)>, permits: Arc<Semaphore>, max_frame_len: usize) {
+    let cluster_id = agent.cluster_id();
     tokio::spawn({
+        let agent = agent.clone();
+        let bookie = bookie.clone();
         let conn = conn.clone();
         let mut tripwire = tripwire.clone();
+        let remote_addr = conn.remote_address();
+        let decode_errors = Arc::new(AtomicU64::new(0));
+        // shared by all the streams of the connection
+        let throttle = Arc::new(RateLimiter::direct(Quota::per_second(
+            NonZeroU32::new(SUPPRESSED_FRAMES_PER_SEC).unwrap(),
+        )));
         async move {
             loop {
                 let rx = tokio::select! {
@@ -32,20 +146,37 @@ pub fn spawn_unipayload_handler(tripwire: &Tripwire, conn: &quinn::Connection, c
 
                 counter!("corro.peer.stream.accept.total", "type" => "uni").increment(1);
 
+                let permit = match permits.clone().try_acquire_owned() {
+                    Ok(permit) => permit,
+                    Err(_) => {
+                        counter!("corro.broadcast.handler.saturated").increment(1);
+                        tokio::select! {
+                            permit_res = permits.clone().acquire_owned() => match permit_res {
+                                Ok(permit) => permit,
+                                Err(_) => return,
+                            },
+                            _ = &mut tripwire => {
+                                debug!("connection cancelled");
+                                return;
+                            }
+                        }
+                    }
+                };
+
                 trace!(
                     "accepted a unidirectional stream from {}",
                     conn.remote_address()
                 );
 
                 tokio::spawn({
+                    let agent = agent.clone();
+                    let bookie = bookie.clone();
                     let tx_changes = tx_changes.clone();
+                    let decode_errors = decode_errors.clone();
+                    let throttle = throttle.clone();
                     async move {
-                        let mut framed = FramedRead::new(
-                            rx,
-                            LengthDelimitedCodec::builder()
-                                .max_frame_length(100 * 1_024 * 1_024)
-                                .new_codec(),
-                        );
+                        let _permit = HandlerPermit::new(permit, agent.clone());
+                        let mut framed = FramedRead::new(rx, broadcast_codec(max_frame_len));
 
                         let mut changes = vec![];
                         loop {
@@ -53,7 +184,16 @@ pub fn spawn_unipayload_handler(tripwire: &Tripwire, conn: &quinn::Connection, c
                                 Some(Ok(b)) => {
                                     counter!("corro.peer.stream.bytes.recv.total", "type" => "uni")
                                         .increment(b.len() as u64);
-                                    match UniPayload::read_from_buffer(&b) {
+                                    // don't spend decoding time on a peer sending
+                                    // mostly repeats, its new changes still come through
+                                    if agent
+                                        .broadcast_redundancy()
+                                        .is_suppressed(remote_addr, Instant::now())
+                                    {
+                                        counter!("corro.broadcast.recv.throttled").increment(1);
+                                        throttle.until_ready().await;
+                                    }
+                                    match UniPayload::decode(&b) {
                                         Ok(payload) => {
                                             trace!("parsed a payload: {payload:?}");
 
@@ -62,40 +202,109 @@ pub fn spawn_unipayload_handler(tripwire: &Tripwire, conn: &quinn::Connection, c
                                                 UniPayload::V1 {
                                                     data:
                                                         UniPayloadV1::Broadcast(BroadcastV1::Change(
-                                                            change,
+                                                            mut change,
                                                         )),
                                                     cluster_id: payload_cluster_id,
+                                                    trace_id,
                                                 } => {
                                                     if cluster_id != payload_cluster_id {
                                                         continue;
                                                     }
+                                                    change.trace_id = trace_id;
                                                     changes.push((change, ChangeSource::Broadcast));
                                                 }
+                                                UniPayload::V1 {
+                                                    data:
+                                                        UniPayloadV1::Broadcast(BroadcastV1::Schema(
+                                                            schema,
+                                                        )),
+                                                    cluster_id: payload_cluster_id,
+                                                    ..
+                                                } => {
+                                                    if cluster_id != payload_cluster_id {
+                                                        continue;
+                                                    }
+                                                    // changes from this stream are only sent for
+                                                    // processing once it ends, after the schema
+                                                    // they may depend on is applied
+                                                    handle_schema_change(&agent, schema).await;
+                                                }
                                             }
                                         }
+                                        Err(BroadcastDecodeError::UnsupportedVersion(version)) => {
+                                            debug!(%remote_addr, "ignoring UniPayload with unsupported version {version}");
+                                            record_decode_error("version", remote_addr, &decode_errors);
+                                            continue;
+                                        }
                                         Err(e) => {
-                                            error!("could not decode UniPayload: {e}");
+                                            error!(%remote_addr, "could not decode UniPayload: {e}");
+                                            record_decode_error("payload", remote_addr, &decode_errors);
                                             continue;
                                         }
                                     }
                                 }
                                 Some(Err(e)) => {
-                                    error!("decode error: {e}");
+                                    error!(%remote_addr, "decode error: {e}");
+                                    record_decode_error("frame", remote_addr, &decode_errors);
                                 }
                                 None => break,
                             }
                         }
 
+                        let (window, threshold) = {
+                            let config = agent.config();
+                            (
+                                Duration::from_secs(config.gossip.redundant_window_secs),
+                                config.gossip.redundant_threshold,
+                            )
+                        };
+                        let now = Instant::now();
+                        let redundancy = agent.broadcast_redundancy();
+                        let (repeated, newly_suppressed) = redundancy.drop_repeated(
+                            remote_addr,
+                            &mut changes,
+                            window,
+                            threshold,
+                            now,
+                        );
+                        if repeated > 0 {
+                            counter!("corro.broadcast.recv.redundant", "kind" => "repeated")
+                                .increment(repeated);
+                        }
+                        if let Some(suppressed) = newly_suppressed {
+                            counter!("corro.broadcast.source.suppressed", "addr" => remote_addr.to_string()).increment(1);
+                            let peer_id = agent
+                                .members()
+                                .read()
+                                .states
+                                .iter()
+                                .find(|(_, state)| state.addr == remote_addr)
+                                .map(|(actor_id, _)| *actor_id);
+                            warn!(
+                                %remote_addr,
+                                ?peer_id,
+                                "peer sent {} of {} broadcast change(s) more than once, throttling its broadcasts and dropping the changes we already have for {:?}",
+                                suppressed.repeated,
+                                suppressed.received,
+                                window * 2
+                            );
+                        }
+
+                        if redundancy.is_suppressed(remote_addr, now) {
+                            let actor_ids: HashSet<ActorId> =
+                                changes.iter().map(|(change, _)| change.actor_id).collect();
+                            let booked = drop_booked(&bookie, &actor_ids, &mut changes).await;
+                            if booked > 0 {
+                                counter!("corro.broadcast.recv.redundant", "kind" => "booked")
+                                    .increment(booked);
+                            }
+                        }
+
                         for change in changes.into_iter().rev() {
-                            if let Err(e) = tx_changes
-                                    .send(change)
-                                    .await
-                                {
-                                    error!(
-                                        "could not send change for processing: {e}"
-                                    );
-                                    return;
-                                }
+                            if let Err(e) = tx_changes.send(change).await {
+                                error!("could not send change for processing: {e}");
+                                return;
+                            }
                         }
                     }
                 });
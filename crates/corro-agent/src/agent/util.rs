@@ -6,9 +6,24 @@
 //! be pulled out of this file in future.
 
 use crate::{
-    agent::{handlers, CountedExecutor, MAX_SYNC_BACKOFF, TO_CLEAR_COUNT},
+    agent::{
+        handlers,
+        shutdown::{ShutdownStage, ShutdownStages},
+        CountedExecutor, MAX_SYNC_BACKOFF, TO_CLEAR_COUNT,
+    },
     api::public::{
-        api_v1_db_schema, api_v1_queries, api_v1_table_stats, api_v1_transactions,
+        admin::{
+            api_v1_admin_announce, api_v1_admin_config, api_v1_admin_config_reload,
+            api_v1_admin_drain, api_v1_admin_member_probe, api_v1_admin_members_prune,
+            api_v1_admin_pause, api_v1_admin_quarantine, api_v1_admin_quarantine_release,
+            api_v1_admin_resume, api_v1_admin_resync, api_v1_admin_storage,
+            api_v1_admin_sync_cancel, api_v1_admin_syncs, api_v1_admin_undrain,
+        },
+        api_v1_actor_status, api_v1_db_schema, api_v1_health, api_v1_partial_status,
+        api_v1_queries, api_v1_queries_batch, api_v1_table_stats, api_v1_transactions,
+        api_v1_transactions_stream,
+        changes::{api_v1_changes, api_v1_changes_since},
+        export::api_v1_export,
         pubsub::{api_v1_sub_by_id, api_v1_subs},
         update::SharedUpdateBroadcastCache,
     },
@@ -20,23 +35,28 @@ use corro_types::{
         find_overwritten_versions, Agent, Bookie, ChangeError, CurrentVersion, KnownDbVersion,
         PartialVersion,
     },
-    api::TableName,
+    api::{SqliteValue, TableName},
     base::{CrsqlDbVersion, CrsqlSeq, Version},
     broadcast::{ChangeSource, ChangeV1, Changeset, ChangesetParts, FocaCmd, FocaInput},
-    change::store_empty_changeset,
+    change::{row_to_change, store_empty_changeset, Change},
     channel::CorroReceiver,
-    config::AuthzConfig,
+    config::{ApplyConfig, AuthzConfig, MergeStrategy, OverloadPolicy, RateLimitConfig},
     pubsub::SubsManager,
     updates::{match_changes, match_changes_from_db_version},
 };
 
 use std::{
     cmp,
-    collections::{BTreeMap, HashSet},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     convert::Infallible,
-    net::SocketAddr,
+    io::{self, Read},
+    net::{IpAddr, SocketAddr},
     ops::{Deref, RangeInclusive},
-    sync::{atomic::AtomicI64, Arc},
+    panic::AssertUnwindSafe,
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc,
+    },
     time::{Duration, Instant},
 };
 
@@ -44,28 +64,61 @@ use crate::api::public::update::api_v1_updates;
 use sqlite_pool::{Committable, InterruptibleTransaction};
 use axum::{
     error_handling::HandleErrorLayer,
-    extract::DefaultBodyLimit,
+    extract::{ConnectInfo, DefaultBodyLimit, MatchedPath, State},
     headers::{authorization::Bearer, Authorization},
-    routing::{get, post},
+    response::IntoResponse,
+    routing::{delete, get, post},
     BoxError, Extension, Router, TypedHeader,
 };
+use bytes::BytesMut;
 use corro_types::broadcast::Timestamp;
 use foca::Member;
 use futures::FutureExt;
-use hyper::{server::conn::AddrIncoming, StatusCode};
-use metrics::{counter, histogram};
+use governor::{
+    clock::{Clock, DefaultClock},
+    state::keyed::DefaultKeyedStateStore,
+    Quota, RateLimiter,
+};
+use hyper::{
+    header::{CONTENT_ENCODING, CONTENT_LENGTH, RETRY_AFTER},
+    server::conn::AddrIncoming,
+    StatusCode,
+};
+use metrics::{counter, gauge, histogram};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
 use rangemap::{RangeInclusiveMap, RangeInclusiveSet};
 use rusqlite::{named_params, params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
 use spawn::spawn_counted;
-use tokio::{net::TcpListener, task::block_in_place};
-use tower::{limit::ConcurrencyLimitLayer, load_shed::LoadShedLayer};
+use time::OffsetDateTime;
+use tokio::{net::TcpListener, sync::Semaphore, task::block_in_place};
+use tower::{
+    limit::{ConcurrencyLimitLayer, GlobalConcurrencyLimitLayer},
+    load_shed::LoadShedLayer,
+};
 use tower_http::trace::TraceLayer;
 use tracing::{debug, error, info, trace, warn};
-use tripwire::{Outcome, PreemptibleFutureExt, Tripwire};
+use tripwire::{PreemptibleFutureExt, Tripwire};
 
 use super::BcastCache;
 
 pub async fn initialise_foca(agent: &Agent) {
+    let prune_after = agent.config().gossip.member_prune_after_secs;
+    if prune_after > 0 {
+        match prune_cutoff(prune_after) {
+            Some(cutoff) => match prune_member_states(agent, cutoff, false).await {
+                Ok(pruned) if !pruned.is_empty() => {
+                    info!("pruned {} stale member state(s)", pruned.len());
+                }
+                Ok(_) => {}
+                Err(e) => warn!("could not prune stale member states: {e}"),
+            },
+            None => warn!(
+                "gossip.member_prune_after_secs ({prune_after}) goes back too far, not pruning member states"
+            ),
+        }
+    }
+
     let states = load_member_states(agent).await;
     if !states.is_empty() {
         let mut foca_states = BTreeMap::<SocketAddr, Member<Actor>>::new();
@@ -128,6 +181,68 @@ pub async fn initialise_foca(agent: &Agent) {
     }
 }
 
+/// Persisted member state that hasn't been updated in a while
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StaleMember {
+    pub actor_id: ActorId,
+    pub address: String,
+    pub updated_at: String,
+}
+
+/// Time `older_than_secs` ago, `None` if that's too far back to represent
+pub fn prune_cutoff(older_than_secs: u64) -> Option<OffsetDateTime> {
+    let older_than = time::Duration::seconds(i64::try_from(older_than_secs).ok()?);
+    OffsetDateTime::now_utc().checked_sub(older_than)
+}
+
+/// Remove persisted states of members that weren't updated since
+/// `cutoff`, except for members currently known to be up. With `dry_run`
+/// set, they are only listed.
+pub async fn prune_member_states(
+    agent: &Agent,
+    cutoff: OffsetDateTime,
+    dry_run: bool,
+) -> eyre::Result<Vec<StaleMember>> {
+    let alive: HashSet<ActorId> = agent.members().read().states.keys().copied().collect();
+
+    let conn = agent.pool().write_low().await?;
+
+    let stale = block_in_place(|| {
+        let tx = conn.unchecked_transaction()?;
+
+        let stale = tx
+            .prepare(
+                "SELECT actor_id, address, CAST(updated_at AS TEXT) FROM __corro_members WHERE updated_at < ?",
+            )?
+            .query_map([cutoff], |row| {
+                Ok(StaleMember {
+                    actor_id: row.get(0)?,
+                    address: row.get(1)?,
+                    updated_at: row.get(2)?,
+                })
+            })?
+            .filter(|res| {
+                res.as_ref()
+                    .map(|member| !alive.contains(&member.actor_id))
+                    .unwrap_or(true)
+            })
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        if !dry_run {
+            let mut prepped = tx.prepare("DELETE FROM __corro_members WHERE actor_id = ?")?;
+            for member in stale.iter() {
+                prepped.execute([member.actor_id])?;
+            }
+            drop(prepped);
+            tx.commit()?;
+        }
+
+        Ok::<_, rusqlite::Error>(stale)
+    })?;
+
+    Ok(stale)
+}
+
 /// Load the existing known member state and addresses
 pub async fn load_member_states(agent: &Agent) -> Vec<(SocketAddr, Member<Actor>)> {
     match agent.pool().read().await {
@@ -170,19 +285,176 @@ pub async fn load_member_states(agent: &Agent) -> Vec<(SocketAddr, Member<Actor>
     }
 }
 
+/// `__corro_state` key of this node's persisted SWIM identity
+const SWIM_IDENTITY_KEY: &str = "swim_identity";
+
+/// Identity this node joins the cluster with. With `gossip.persist_identity`,
+/// the identity from before a restart is reused as long as its actor id,
+/// address and cluster id didn't change, so peers recognize the node as
+/// the same member instead of a new one.
+pub async fn swim_identity(agent: &Agent) -> Actor {
+    let current = agent.actor(None);
+    if !agent.config().gossip.persist_identity {
+        return current;
+    }
+
+    let res = async {
+        let conn = agent.pool().write_low().await?;
+        Ok::<_, eyre::Report>(block_in_place(|| {
+            restore_swim_identity(&conn, current.clone())
+        })?)
+    }
+    .await;
+
+    match res {
+        Ok(actor) => {
+            if actor.ts() != current.ts() {
+                info!("reusing persisted SWIM identity {actor:?}");
+            }
+            actor
+        }
+        Err(e) => {
+            warn!("could not restore persisted SWIM identity: {e}");
+            current
+        }
+    }
+}
+
+fn restore_swim_identity(conn: &Connection, current: Actor) -> rusqlite::Result<Actor> {
+    let persisted = conn
+        .query_row(
+            "SELECT value FROM __corro_state WHERE key = ?",
+            [SWIM_IDENTITY_KEY],
+            |row| row.get::<_, String>(0),
+        )
+        .optional()?
+        .and_then(|json| serde_json::from_str::<Actor>(&json).ok());
+
+    match persisted {
+        Some(persisted)
+            if persisted.id() == current.id()
+                && persisted.addr() == current.addr()
+                && persisted.cluster_id() == current.cluster_id() =>
+        {
+            Ok(persisted)
+        }
+        _ => {
+            persist_swim_identity(conn, &current)?;
+            Ok(current)
+        }
+    }
+}
+
+fn persist_swim_identity(conn: &Connection, actor: &Actor) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO __corro_state (key, value) VALUES (?, ?)",
+        params![
+            SWIM_IDENTITY_KEY,
+            serde_json::to_string(actor).expect("could not serialize actor")
+        ],
+    )?;
+    Ok(())
+}
+
+/// Remember the identity foca switched to, so it's reused on restart
+pub async fn save_swim_identity(agent: &Agent, actor: &Actor) {
+    if !agent.config().gossip.persist_identity {
+        return;
+    }
+
+    let res = async {
+        let conn = agent.pool().write_low().await?;
+        Ok::<_, eyre::Report>(block_in_place(|| persist_swim_identity(&conn, actor))?)
+    }
+    .await;
+
+    if let Err(e) = res {
+        warn!("could not persist SWIM identity: {e}");
+    }
+}
+
 pub async fn setup_http_api_handler(
     agent: &Agent,
-    tripwire: &Tripwire,
+    bookie: &Bookie,
+    transport: &Transport,
+    stages: &ShutdownStages,
     subs_bcast_cache: BcastCache,
     updates_bcast_cache: SharedUpdateBroadcastCache,
     subs_manager: &SubsManager,
     api_listeners: Vec<TcpListener>,
 ) -> eyre::Result<()> {
+    let tripwire = stages.tripwire(ShutdownStage::Accept);
+    let client_rate_limiter = ClientRateLimiter::new(agent.config().api.rate_limit);
+    {
+        // forget about clients that have not been limited in a while
+        let agent = agent.clone();
+        let client_rate_limiter = client_rate_limiter.clone();
+        let mut tripwire = tripwire.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(60));
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {
+                        if let Some(limiter) = client_rate_limiter.current(agent.config().api.rate_limit) {
+                            limiter.retain_recent();
+                            limiter.shrink_to_fit();
+                        }
+                    },
+                    _ = &mut tripwire => break,
+                }
+            }
+        });
+    }
+
+    let overload_policy = agent.config().api.overload_policy;
+
     let api = Router::new()
         // transactions
         .route(
             "/v1/transactions",
             post(api_v1_transactions).route_layer(
+                tower::ServiceBuilder::new()
+                    .layer(axum::middleware::from_fn(rate_limit_by_client))
+                    .layer(axum::middleware::from_fn_with_state(
+                        ConcurrencyLimit::new(overload_policy.transactions, 128),
+                        limit_concurrency,
+                    ))
+                    .layer(axum::middleware::from_fn(decompress_request_body)),
+            ),
+        )
+        .route(
+            "/v1/transactions/stream",
+            post(api_v1_transactions_stream).route_layer(axum::middleware::from_fn_with_state(
+                ConcurrencyLimit::new(overload_policy.transactions, 4),
+                limit_concurrency,
+            )),
+        )
+        // queries
+        .route(
+            "/v1/queries",
+            post(api_v1_queries).route_layer(
+                tower::ServiceBuilder::new()
+                    .layer(axum::middleware::from_fn(rate_limit_by_client))
+                    .layer(axum::middleware::from_fn_with_state(
+                        ConcurrencyLimit::new(overload_policy.queries, 128),
+                        limit_concurrency,
+                    )),
+            ),
+        )
+        .route(
+            "/v1/queries/batch",
+            post(api_v1_queries_batch).route_layer(
+                tower::ServiceBuilder::new()
+                    .layer(axum::middleware::from_fn(rate_limit_by_client))
+                    .layer(axum::middleware::from_fn_with_state(
+                        ConcurrencyLimit::new(overload_policy.queries, 128),
+                        limit_concurrency,
+                    )),
+            ),
+        )
+        .route(
+            "/v1/subscriptions",
+            post(api_v1_subs).route_layer(
                 tower::ServiceBuilder::new()
                     .layer(HandleErrorLayer::new(|_error: BoxError| async {
                         Ok::<_, Infallible>((
@@ -194,10 +466,9 @@ pub async fn setup_http_api_handler(
                     .layer(ConcurrencyLimitLayer::new(128)),
             ),
         )
-        // queries
         .route(
-            "/v1/queries",
-            post(api_v1_queries).route_layer(
+            "/v1/updates/:table",
+            post(api_v1_updates).route_layer(
                 tower::ServiceBuilder::new()
                     .layer(HandleErrorLayer::new(|_error: BoxError| async {
                         Ok::<_, Infallible>((
@@ -210,8 +481,8 @@ pub async fn setup_http_api_handler(
             ),
         )
         .route(
-            "/v1/subscriptions",
-            post(api_v1_subs).route_layer(
+            "/v1/subscriptions/:id",
+            get(api_v1_sub_by_id).route_layer(
                 tower::ServiceBuilder::new()
                     .layer(HandleErrorLayer::new(|_error: BoxError| async {
                         Ok::<_, Infallible>((
@@ -224,8 +495,36 @@ pub async fn setup_http_api_handler(
             ),
         )
         .route(
-            "/v1/updates/:table",
-            post(api_v1_updates).route_layer(
+            "/v1/migrations",
+            post(api_v1_db_schema).route_layer(
+                tower::ServiceBuilder::new()
+                    .layer(HandleErrorLayer::new(|_error: BoxError| async {
+                        Ok::<_, Infallible>((
+                            StatusCode::SERVICE_UNAVAILABLE,
+                            "max concurrency limit reached".to_string(),
+                        ))
+                    }))
+                    .layer(LoadShedLayer::new())
+                    .layer(ConcurrencyLimitLayer::new(4)),
+            ),
+        )
+        .route(
+            "/v1/table_stats",
+            post(api_v1_table_stats).route_layer(
+                tower::ServiceBuilder::new()
+                    .layer(HandleErrorLayer::new(|_error: BoxError| async {
+                        Ok::<_, Infallible>((
+                            StatusCode::SERVICE_UNAVAILABLE,
+                            "max concurrency limit reached".to_string(),
+                        ))
+                    }))
+                    .layer(LoadShedLayer::new())
+                    .layer(ConcurrencyLimitLayer::new(4)),
+            ),
+        )
+        .route(
+            "/v1/actors/:actor_id/status",
+            get(api_v1_actor_status).route_layer(
                 tower::ServiceBuilder::new()
                     .layer(HandleErrorLayer::new(|_error: BoxError| async {
                         Ok::<_, Infallible>((
@@ -238,8 +537,8 @@ pub async fn setup_http_api_handler(
             ),
         )
         .route(
-            "/v1/subscriptions/:id",
-            get(api_v1_sub_by_id).route_layer(
+            "/v1/partials/:actor_id/:version",
+            get(api_v1_partial_status).route_layer(
                 tower::ServiceBuilder::new()
                     .layer(HandleErrorLayer::new(|_error: BoxError| async {
                         Ok::<_, Infallible>((
@@ -252,8 +551,22 @@ pub async fn setup_http_api_handler(
             ),
         )
         .route(
-            "/v1/migrations",
-            post(api_v1_db_schema).route_layer(
+            "/v1/health",
+            get(api_v1_health).route_layer(
+                tower::ServiceBuilder::new()
+                    .layer(HandleErrorLayer::new(|_error: BoxError| async {
+                        Ok::<_, Infallible>((
+                            StatusCode::SERVICE_UNAVAILABLE,
+                            "max concurrency limit reached".to_string(),
+                        ))
+                    }))
+                    .layer(LoadShedLayer::new())
+                    .layer(ConcurrencyLimitLayer::new(128)),
+            ),
+        )
+        .route(
+            "/v1/changes",
+            get(api_v1_changes_since).route_layer(
                 tower::ServiceBuilder::new()
                     .layer(HandleErrorLayer::new(|_error: BoxError| async {
                         Ok::<_, Infallible>((
@@ -266,8 +579,139 @@ pub async fn setup_http_api_handler(
             ),
         )
         .route(
-            "/v1/table_stats",
-            post(api_v1_table_stats).route_layer(
+            "/v1/changes/:actor_id/:version",
+            get(api_v1_changes).route_layer(
+                tower::ServiceBuilder::new()
+                    .layer(HandleErrorLayer::new(|_error: BoxError| async {
+                        Ok::<_, Infallible>((
+                            StatusCode::SERVICE_UNAVAILABLE,
+                            "max concurrency limit reached".to_string(),
+                        ))
+                    }))
+                    .layer(LoadShedLayer::new())
+                    .layer(ConcurrencyLimitLayer::new(16)),
+            ),
+        )
+        .route(
+            "/v1/export",
+            get(api_v1_export).route_layer(
+                tower::ServiceBuilder::new()
+                    .layer(HandleErrorLayer::new(|_error: BoxError| async {
+                        Ok::<_, Infallible>((
+                            StatusCode::SERVICE_UNAVAILABLE,
+                            "max concurrency limit reached".to_string(),
+                        ))
+                    }))
+                    .layer(LoadShedLayer::new())
+                    .layer(ConcurrencyLimitLayer::new(4)),
+            ),
+        )
+        // admin
+        .route(
+            "/v1/admin/drain",
+            post(api_v1_admin_drain)
+                .delete(api_v1_admin_undrain)
+                .route_layer(
+                    tower::ServiceBuilder::new()
+                        .layer(HandleErrorLayer::new(|_error: BoxError| async {
+                            Ok::<_, Infallible>((
+                                StatusCode::SERVICE_UNAVAILABLE,
+                                "max concurrency limit reached".to_string(),
+                            ))
+                        }))
+                        .layer(LoadShedLayer::new())
+                        .layer(ConcurrencyLimitLayer::new(4)),
+                ),
+        )
+        .route(
+            "/v1/admin/pause",
+            post(api_v1_admin_pause).route_layer(
+                tower::ServiceBuilder::new()
+                    .layer(HandleErrorLayer::new(|_error: BoxError| async {
+                        Ok::<_, Infallible>((
+                            StatusCode::SERVICE_UNAVAILABLE,
+                            "max concurrency limit reached".to_string(),
+                        ))
+                    }))
+                    .layer(LoadShedLayer::new())
+                    .layer(ConcurrencyLimitLayer::new(4)),
+            ),
+        )
+        .route(
+            "/v1/admin/members/prune",
+            post(api_v1_admin_members_prune).route_layer(
+                tower::ServiceBuilder::new()
+                    .layer(HandleErrorLayer::new(|_error: BoxError| async {
+                        Ok::<_, Infallible>((
+                            StatusCode::SERVICE_UNAVAILABLE,
+                            "max concurrency limit reached".to_string(),
+                        ))
+                    }))
+                    .layer(LoadShedLayer::new())
+                    .layer(ConcurrencyLimitLayer::new(4)),
+            ),
+        )
+        .route(
+            "/v1/admin/members/:actor_id/probe",
+            post(api_v1_admin_member_probe).route_layer(
+                tower::ServiceBuilder::new()
+                    .layer(HandleErrorLayer::new(|_error: BoxError| async {
+                        Ok::<_, Infallible>((
+                            StatusCode::SERVICE_UNAVAILABLE,
+                            "max concurrency limit reached".to_string(),
+                        ))
+                    }))
+                    .layer(LoadShedLayer::new())
+                    .layer(ConcurrencyLimitLayer::new(4)),
+            ),
+        )
+        .route(
+            "/v1/admin/quarantine",
+            get(api_v1_admin_quarantine)
+                .delete(api_v1_admin_quarantine_release)
+                .route_layer(
+                    tower::ServiceBuilder::new()
+                        .layer(HandleErrorLayer::new(|_error: BoxError| async {
+                            Ok::<_, Infallible>((
+                                StatusCode::SERVICE_UNAVAILABLE,
+                                "max concurrency limit reached".to_string(),
+                            ))
+                        }))
+                        .layer(LoadShedLayer::new())
+                        .layer(ConcurrencyLimitLayer::new(4)),
+                ),
+        )
+        .route(
+            "/v1/admin/resume",
+            post(api_v1_admin_resume).route_layer(
+                tower::ServiceBuilder::new()
+                    .layer(HandleErrorLayer::new(|_error: BoxError| async {
+                        Ok::<_, Infallible>((
+                            StatusCode::SERVICE_UNAVAILABLE,
+                            "max concurrency limit reached".to_string(),
+                        ))
+                    }))
+                    .layer(LoadShedLayer::new())
+                    .layer(ConcurrencyLimitLayer::new(4)),
+            ),
+        )
+        .route(
+            "/v1/admin/resync",
+            post(api_v1_admin_resync).route_layer(
+                tower::ServiceBuilder::new()
+                    .layer(HandleErrorLayer::new(|_error: BoxError| async {
+                        Ok::<_, Infallible>((
+                            StatusCode::SERVICE_UNAVAILABLE,
+                            "max concurrency limit reached".to_string(),
+                        ))
+                    }))
+                    .layer(LoadShedLayer::new())
+                    .layer(ConcurrencyLimitLayer::new(1)),
+            ),
+        )
+        .route(
+            "/v1/admin/announce",
+            post(api_v1_admin_announce).route_layer(
                 tower::ServiceBuilder::new()
                     .layer(HandleErrorLayer::new(|_error: BoxError| async {
                         Ok::<_, Infallible>((
@@ -279,11 +723,85 @@ pub async fn setup_http_api_handler(
                     .layer(ConcurrencyLimitLayer::new(4)),
             ),
         )
+        .route(
+            "/v1/admin/syncs",
+            get(api_v1_admin_syncs).route_layer(
+                tower::ServiceBuilder::new()
+                    .layer(HandleErrorLayer::new(|_error: BoxError| async {
+                        Ok::<_, Infallible>((
+                            StatusCode::SERVICE_UNAVAILABLE,
+                            "max concurrency limit reached".to_string(),
+                        ))
+                    }))
+                    .layer(LoadShedLayer::new())
+                    .layer(ConcurrencyLimitLayer::new(4)),
+            ),
+        )
+        .route(
+            "/v1/admin/syncs/:id",
+            delete(api_v1_admin_sync_cancel).route_layer(
+                tower::ServiceBuilder::new()
+                    .layer(HandleErrorLayer::new(|_error: BoxError| async {
+                        Ok::<_, Infallible>((
+                            StatusCode::SERVICE_UNAVAILABLE,
+                            "max concurrency limit reached".to_string(),
+                        ))
+                    }))
+                    .layer(LoadShedLayer::new())
+                    .layer(ConcurrencyLimitLayer::new(4)),
+            ),
+        )
+        .route(
+            "/v1/admin/storage",
+            get(api_v1_admin_storage).route_layer(
+                tower::ServiceBuilder::new()
+                    .layer(HandleErrorLayer::new(|_error: BoxError| async {
+                        Ok::<_, Infallible>((
+                            StatusCode::SERVICE_UNAVAILABLE,
+                            "max concurrency limit reached".to_string(),
+                        ))
+                    }))
+                    .layer(LoadShedLayer::new())
+                    .layer(ConcurrencyLimitLayer::new(4)),
+            ),
+        )
+        .route(
+            "/v1/admin/config",
+            get(api_v1_admin_config).route_layer(
+                tower::ServiceBuilder::new()
+                    .layer(HandleErrorLayer::new(|_error: BoxError| async {
+                        Ok::<_, Infallible>((
+                            StatusCode::SERVICE_UNAVAILABLE,
+                            "max concurrency limit reached".to_string(),
+                        ))
+                    }))
+                    .layer(LoadShedLayer::new())
+                    .layer(ConcurrencyLimitLayer::new(4)),
+            ),
+        )
+        .route(
+            "/v1/admin/config/reload",
+            post(api_v1_admin_config_reload).route_layer(
+                tower::ServiceBuilder::new()
+                    .layer(HandleErrorLayer::new(|_error: BoxError| async {
+                        Ok::<_, Infallible>((
+                            StatusCode::SERVICE_UNAVAILABLE,
+                            "max concurrency limit reached".to_string(),
+                        ))
+                    }))
+                    .layer(LoadShedLayer::new())
+                    .layer(ConcurrencyLimitLayer::new(1)),
+            ),
+        )
         .layer(axum::middleware::from_fn(require_authz))
+        .layer(axum::middleware::from_fn(record_request_latency))
         .layer(
             tower::ServiceBuilder::new()
                 .layer(Extension(Arc::new(AtomicI64::new(0))))
                 .layer(Extension(agent.clone()))
+                .layer(Extension(bookie.clone()))
+                .layer(Extension(transport.clone()))
+                .layer(Extension(client_rate_limiter))
                 .layer(Extension(subs_bcast_cache))
                 .layer(Extension(updates_bcast_cache))
                 .layer(Extension(subs_manager.clone()))
@@ -292,6 +810,24 @@ pub async fn setup_http_api_handler(
         .layer(DefaultBodyLimit::disable())
         .layer(TraceLayer::new_for_http());
 
+    // last-resort ceiling shared by every route and listener, requests past
+    // it are shed before any work is spawned for them
+    let api = match agent.config().max_concurrent_tasks {
+        Some(max) => api.layer(
+            tower::ServiceBuilder::new()
+                .layer(HandleErrorLayer::new(|_error: BoxError| async {
+                    counter!("corro.api.tasks.throttled").increment(1);
+                    Ok::<_, Infallible>((
+                        StatusCode::SERVICE_UNAVAILABLE,
+                        "max concurrent tasks reached".to_string(),
+                    ))
+                }))
+                .layer(LoadShedLayer::new())
+                .layer(GlobalConcurrencyLimitLayer::new(max.get())),
+        ),
+        None => api,
+    };
+
     for api_listener in api_listeners {
         let api_addr = api_listener.local_addr()?;
         info!("Starting API listener on tcp/{api_addr}");
@@ -299,24 +835,270 @@ pub async fn setup_http_api_handler(
 
         incoming.set_nodelay(true);
         spawn_counted(
-            axum::Server::builder(incoming)
-                .executor(CountedExecutor)
-                .serve(
-                    api.clone()
-                        .into_make_service_with_connect_info::<SocketAddr>(),
-                )
-                .with_graceful_shutdown(
-                    tripwire
-                        .clone()
-                        .inspect(move |_| info!("corrosion api http tripped {api_addr}")),
-                )
-                .inspect(|_| info!("corrosion api is done")),
+            stages.track(
+                ShutdownStage::Accept,
+                axum::Server::builder(incoming)
+                    .executor(CountedExecutor)
+                    .serve(
+                        api.clone()
+                            .into_make_service_with_connect_info::<SocketAddr>(),
+                    )
+                    .with_graceful_shutdown(
+                        tripwire
+                            .clone()
+                            .inspect(move |_| info!("corrosion api http tripped {api_addr}")),
+                    )
+                    .inspect(|_| info!("corrosion api is done")),
+            ),
         );
     }
 
     Ok(())
 }
 
+type KeyedRateLimiter = RateLimiter<IpAddr, DefaultKeyedStateStore<IpAddr>, DefaultClock>;
+
+/// Rate limiter keyed by client IP address, a no-op when no
+/// `api.rate_limit` is configured. It is rebuilt when a config reload
+/// changes the limits.
+#[derive(Clone, Default)]
+struct ClientRateLimiter(Arc<parking_lot::Mutex<Option<(RateLimitConfig, Arc<KeyedRateLimiter>)>>>);
+
+impl ClientRateLimiter {
+    fn new(config: Option<RateLimitConfig>) -> Self {
+        let limiter = Self::default();
+        limiter.current(config);
+        limiter
+    }
+
+    /// Limiter enforcing `config`, replacing the previous one if it was
+    /// built for different limits
+    fn current(&self, config: Option<RateLimitConfig>) -> Option<Arc<KeyedRateLimiter>> {
+        let mut inner = self.0.lock();
+        if inner.as_ref().map(|(current, _)| *current) != config {
+            *inner = config.map(|config| {
+                let limiter = RateLimiter::keyed(
+                    Quota::per_second(config.per_second)
+                        .allow_burst(config.burst.unwrap_or(config.per_second)),
+                );
+                (config, Arc::new(limiter))
+            });
+        }
+        inner.as_ref().map(|(_, limiter)| limiter.clone())
+    }
+}
+
+async fn rate_limit_by_client<B>(
+    Extension(agent): Extension<Agent>,
+    Extension(limiter): Extension<ClientRateLimiter>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    request: axum::http::Request<B>,
+    next: axum::middleware::Next<B>,
+) -> axum::response::Response {
+    if let Some(limiter) = limiter.current(agent.config().api.rate_limit) {
+        if let Err(not_until) = limiter.check_key(&client_addr.ip()) {
+            let retry_after = not_until
+                .wait_time_from(DefaultClock::default().now())
+                .as_secs()
+                + 1;
+            counter!("corro.api.rate_limited", "path" => request.uri().path().to_owned())
+                .increment(1);
+            debug!(%client_addr, "rate limited client, retry after {retry_after}s");
+            return (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(RETRY_AFTER, retry_after.to_string())],
+                "rate limit exceeded",
+            )
+                .into_response();
+        }
+    }
+
+    next.run(request).await
+}
+
+/// Largest compressed or decompressed request body accepted, so a small
+/// compression bomb can't exhaust memory
+pub const MAX_DECOMPRESSED_BODY_LEN: usize = 64 * 1024 * 1024;
+
+/// Decompress request bodies sent with a `Content-Encoding`, so clients can
+/// compress large batches. Only gzip and zstd are supported, bodies past
+/// [`MAX_DECOMPRESSED_BODY_LEN`] are rejected.
+async fn decompress_request_body(
+    request: axum::http::Request<hyper::Body>,
+    next: axum::middleware::Next<hyper::Body>,
+) -> axum::response::Response {
+    let encoding = match request.headers().get(CONTENT_ENCODING) {
+        None => return next.run(request).await,
+        Some(value) => match value.to_str() {
+            Ok(encoding) => encoding.trim().to_ascii_lowercase(),
+            Err(_) => {
+                return (
+                    StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                    "invalid content encoding".to_string(),
+                )
+                    .into_response()
+            }
+        },
+    };
+
+    // decoders stop one byte past the limit, to tell it was exceeded
+    let decode: fn(&[u8], u64) -> io::Result<Vec<u8>> = match encoding.as_str() {
+        "identity" => return next.run(request).await,
+        "gzip" => |buf, limit| {
+            let mut decoded = vec![];
+            flate2::read::GzDecoder::new(buf)
+                .take(limit)
+                .read_to_end(&mut decoded)?;
+            Ok(decoded)
+        },
+        "zstd" => |buf, limit| {
+            let mut decoded = vec![];
+            zstd::stream::read::Decoder::new(buf)?
+                .take(limit)
+                .read_to_end(&mut decoded)?;
+            Ok(decoded)
+        },
+        _ => {
+            return (
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                format!("unsupported content encoding: {encoding}"),
+            )
+                .into_response()
+        }
+    };
+
+    let too_large = || {
+        (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("request body is larger than {MAX_DECOMPRESSED_BODY_LEN} bytes"),
+        )
+            .into_response()
+    };
+
+    let (mut parts, mut body) = request.into_parts();
+    let mut compressed = BytesMut::new();
+    while let Some(chunk) = hyper::body::HttpBody::data(&mut body).await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    format!("could not read request body: {e}"),
+                )
+                    .into_response()
+            }
+        };
+        if compressed.len() + chunk.len() > MAX_DECOMPRESSED_BODY_LEN {
+            return too_large();
+        }
+        compressed.extend_from_slice(&chunk);
+    }
+
+    let limit = MAX_DECOMPRESSED_BODY_LEN as u64 + 1;
+    let decoded = match block_in_place(|| decode(&compressed, limit)) {
+        Ok(decoded) => decoded,
+        Err(e) => {
+            debug!("could not decompress {encoding} request body: {e}");
+            return (
+                StatusCode::BAD_REQUEST,
+                format!("could not decompress {encoding} request body: {e}"),
+            )
+                .into_response();
+        }
+    };
+    if decoded.len() > MAX_DECOMPRESSED_BODY_LEN {
+        debug!("rejecting {encoding} request body decompressing past {MAX_DECOMPRESSED_BODY_LEN} bytes");
+        return too_large();
+    }
+    histogram!("corro.api.request.decompressed.bytes", "encoding" => encoding)
+        .record(decoded.len() as f64);
+
+    parts.headers.remove(CONTENT_ENCODING);
+    parts.headers.remove(CONTENT_LENGTH);
+
+    next.run(axum::http::Request::from_parts(parts, decoded.into()))
+        .await
+}
+
+/// Caps how many requests a route handles at once. Past the limit,
+/// requests are shed right away or wait for capacity, depending on the
+/// route's overload policy
+#[derive(Clone)]
+struct ConcurrencyLimit {
+    permits: Arc<Semaphore>,
+    /// `None` to shed requests instead of queueing them
+    max_wait: Option<Duration>,
+}
+
+impl ConcurrencyLimit {
+    fn new(policy: OverloadPolicy, limit: usize) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(limit)),
+            max_wait: match policy {
+                OverloadPolicy::Shed => None,
+                OverloadPolicy::Queue { max_wait_ms } => Some(Duration::from_millis(max_wait_ms)),
+            },
+        }
+    }
+}
+
+async fn limit_concurrency<B>(
+    State(limit): State<ConcurrencyLimit>,
+    request: axum::http::Request<B>,
+    next: axum::middleware::Next<B>,
+) -> axum::response::Response {
+    let permit = match limit.max_wait {
+        None => limit.permits.try_acquire_owned().ok(),
+        Some(max_wait) => {
+            match tokio::time::timeout(max_wait, limit.permits.acquire_owned()).await {
+                Ok(Ok(permit)) => Some(permit),
+                _ => {
+                    counter!("corro.api.queue.timeout", "path" => request.uri().path().to_owned())
+                        .increment(1);
+                    None
+                }
+            }
+        }
+    };
+
+    let Some(_permit) = permit else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "max concurrency limit reached",
+        )
+            .into_response();
+    };
+
+    next.run(request).await
+}
+
+/// Record how long each request took to respond, by route and status.
+/// For streaming endpoints this only covers the time until the response
+/// starts, not the whole stream.
+async fn record_request_latency<B>(
+    request: axum::http::Request<B>,
+    next: axum::middleware::Next<B>,
+) -> axum::response::Response {
+    // label with the route template, not the raw path, to bound cardinality
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_owned())
+        .unwrap_or_else(|| "unknown".to_owned());
+
+    let start = Instant::now();
+    let response = next.run(request).await;
+
+    histogram!(
+        "corro.api.request.seconds",
+        "route" => route,
+        "status" => response.status().as_u16().to_string()
+    )
+    .record(start.elapsed());
+
+    response
+}
+
 async fn require_authz<B>(
     Extension(agent): Extension<Agent>,
     maybe_authz_header: Option<TypedHeader<Authorization<Bearer>>>,
@@ -350,7 +1132,11 @@ pub async fn sync_loop(agent: Agent, bookie: Bookie, transport: Transport, mut t
     let mut sync_backoff = backoff::Backoff::new(0)
         .timeout_range(Duration::from_secs(1), MAX_SYNC_BACKOFF)
         .iter();
-    let next_sync_at = tokio::time::sleep(sync_backoff.next().unwrap());
+    let next_sync_at = tokio::time::sleep(initial_sync_delay(
+        agent.actor_id(),
+        sync_backoff.next().unwrap(),
+        Duration::from_millis(agent.config().sync.initial_jitter_ms),
+    ));
     tokio::pin!(next_sync_at);
 
     loop {
@@ -363,8 +1149,16 @@ pub async fn sync_loop(agent: Agent, bookie: Bookie, transport: Transport, mut t
             }
         };
 
+        if agent.is_paused() || agent.is_disk_full() {
+            debug!("agent is paused or the disk is full, skipping sync");
+            next_sync_at
+                .as_mut()
+                .reset(tokio::time::Instant::now() + sync_backoff.next().unwrap());
+            continue;
+        }
+
         // ignoring here, there is trying and logging going on inside
-        match tokio::time::timeout(
+        let synced = match tokio::time::timeout(
             Duration::from_secs(300),
             handlers::handle_sync(&agent, &bookie, &transport),
         )
@@ -379,17 +1173,78 @@ pub async fn sync_loop(agent: Agent, bookie: Bookie, transport: Transport, mut t
                 Ok(Err(e)) => {
                     error!("could not sync: {e}");
                     // keep syncing until we successfully sync
+                    0
                 }
                 Err(_e) => {
                     warn!("timed out waiting for sync to complete!");
+                    0
                 }
-                Ok(Ok(_)) => {}
+                Ok(Ok(n)) => n,
             },
-        }
+        };
         next_sync_at
             .as_mut()
-            .reset(tokio::time::Instant::now() + sync_backoff.next().unwrap());
+            .reset(tokio::time::Instant::now() + next_sync_delay(&mut sync_backoff, synced));
+    }
+}
+
+/// Rng seeded from the actor id, so a node draws the same values across
+/// restarts while different nodes draw different ones. `salt` tells apart
+/// the uses of the same actor id.
+fn actor_rng(actor_id: ActorId, salt: u64) -> SmallRng {
+    let id = actor_id.0.as_u128();
+    SmallRng::seed_from_u64((id as u64) ^ ((id >> 64) as u64) ^ salt)
+}
+
+/// Delay before the first sync. A cluster started all at once would
+/// otherwise have every node sync after the same `min` delay and stampede
+/// whoever has data, so it's pushed back by up to `max_jitter`, seeded
+/// from the actor id.
+fn initial_sync_delay(actor_id: ActorId, min: Duration, max_jitter: Duration) -> Duration {
+    if max_jitter.is_zero() {
+        return min;
+    }
+
+    let mut rng = actor_rng(actor_id, 0);
+    min + max_jitter.mul_f64(rng.gen_range(0.0..=1.0))
+}
+
+/// Delay until the next sync. Syncing resumes at the minimum interval
+/// right after a sync transferred changes, and slows down while syncs
+/// find nothing new.
+fn next_sync_delay(backoff: &mut backoff::Iter, synced: usize) -> Duration {
+    if synced > 0 {
+        backoff.reset();
+    }
+    backoff.next().unwrap()
+}
+
+/// Maximum jitter applied to periodic tasks, as a percentage of their period
+const MAX_PERIODIC_JITTER_PCT: u8 = 50;
+
+/// Spread `period` by up to `perf.periodic_jitter_pct` percent in either
+/// direction so that nodes started together don't run their periodic
+/// tasks (and the load that comes with them) in lockstep.
+pub fn jittered_period(agent: &Agent, period: Duration) -> Duration {
+    jitter_period(
+        agent.actor_id(),
+        period,
+        agent.config().perf.periodic_jitter_pct,
+    )
+}
+
+/// The jitter is seeded from the actor id and the period, so a node
+/// always gets the same offset for a given task.
+fn jitter_period(actor_id: ActorId, period: Duration, jitter_pct: u8) -> Duration {
+    let jitter_pct = cmp::min(jitter_pct, MAX_PERIODIC_JITTER_PCT);
+    if jitter_pct == 0 {
+        return period;
     }
+
+    let mut rng = actor_rng(actor_id, period.as_millis() as u64);
+
+    let max = f64::from(jitter_pct) / 100.0;
+    period.mul_f64(1.0 + rng.gen_range(-max..=max))
 }
 
 pub async fn apply_fully_buffered_changes_loop(
@@ -401,24 +1256,311 @@ pub async fn apply_fully_buffered_changes_loop(
     info!("Starting apply_fully_buffered_changes loop");
 
     let tx_timeout: Duration = Duration::from_secs(agent.config().perf.sql_tx_timeout as u64);
-    while let Outcome::Completed(Some((actor_id, version))) =
-        rx_apply.recv().preemptible(&mut tripwire).await
-    {
-        debug!(%actor_id, %version, "picked up background apply of buffered changes");
-        match process_fully_buffered_changes(&agent, &bookie, actor_id, version, tx_timeout).await {
-            Ok(false) => {
-                warn!(%actor_id, %version, "did not apply buffered changes");
+    let concurrency = cmp::max(agent.config().apply.concurrency, 1);
+    let debounce = Duration::from_millis(agent.config().apply.buffered_debounce_ms);
+
+    // versions waiting to be applied, partitioned by actor so that each
+    // actor's versions are applied in order, one batch at a time. versions
+    // are also held here while the agent is paused, and applied on resume.
+    let mut pending: BTreeMap<ActorId, BTreeSet<Version>> = BTreeMap::new();
+    let mut pending_count = 0usize;
+    // when the first and last pending versions of each actor arrived, to
+    // debounce bursts of versions becoming applicable
+    let mut arrivals: HashMap<ActorId, (tokio::time::Instant, tokio::time::Instant)> =
+        HashMap::new();
+    // actors with an apply in progress
+    let mut applying: HashSet<ActorId> = HashSet::new();
+    let mut join_set = tokio::task::JoinSet::new();
+    let mut paused_check = tokio::time::interval(Duration::from_secs(1));
+    let debounce_timer = tokio::time::sleep(Duration::ZERO);
+    tokio::pin!(debounce_timer);
+
+    // once tripped, keep applying queued versions up to this long, what's
+    // left is still buffered and picked up again on startup
+    let drain_timeout = Duration::from_secs(agent.config().perf.shutdown_drain_timeout_secs);
+    let drain_deadline = tokio::time::sleep(Duration::ZERO);
+    tokio::pin!(drain_deadline);
+    let mut tripped = false;
+
+    loop {
+        // earliest time a debounced actor becomes ready, if any is waiting
+        let mut next_ready: Option<tokio::time::Instant> = None;
+
+        if !agent.is_paused() && !agent.is_disk_full() {
+            let now = tokio::time::Instant::now();
+            while join_set.len() < concurrency {
+                let Some(actor_id) = pending
+                    .keys()
+                    .filter(|actor_id| !applying.contains(*actor_id))
+                    .find(|actor_id| {
+                        if tripped || debounce.is_zero() {
+                            return true;
+                        }
+                        let ready_at = arrivals
+                            .get(*actor_id)
+                            .map(|(first, last)| debounced_ready_at(*first, *last, debounce))
+                            .unwrap_or(now);
+                        if ready_at <= now {
+                            return true;
+                        }
+                        next_ready = Some(next_ready.map_or(ready_at, |next| next.min(ready_at)));
+                        false
+                    })
+                    .copied()
+                else {
+                    break;
+                };
+
+                let versions: Vec<Version> = pending
+                    .remove(&actor_id)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .collect();
+                arrivals.remove(&actor_id);
+                pending_count -= versions.len();
+                histogram!("corro.agent.buffered.apply.batch.size").record(versions.len() as f64);
+
+                applying.insert(actor_id);
+                let agent = agent.clone();
+                let bookie = bookie.clone();
+                join_set.spawn(async move {
+                    debug!(%actor_id, ?versions, "picked up background apply of buffered changes");
+                    let (results, leftover) =
+                        apply_buffered_batch(&agent, &bookie, actor_id, versions, tx_timeout).await;
+                    (actor_id, results, leftover)
+                });
             }
-            Ok(true) => {
-                debug!(%actor_id, %version, "succesfully applied buffered changes");
+        }
+
+        if let Some(next_ready) = next_ready {
+            debounce_timer.as_mut().reset(next_ready);
+        }
+
+        gauge!("corro.agent.buffered.apply.queue").set(pending_count as f64);
+        gauge!("corro.agent.buffered.apply.jobs").set(join_set.len() as f64);
+        agent
+            .limits()
+            .apply
+            .store(join_set.len(), Ordering::Release);
+
+        if tripped && pending_count == 0 && join_set.is_empty() && rx_apply.is_empty() {
+            info!("drained buffered changes to apply before shutdown");
+            break;
+        }
+
+        tokio::select! {
+            biased;
+
+            res = join_set.join_next(), if !join_set.is_empty() => {
+                let Some(Ok((actor_id, results, leftover))) = res else {
+                    continue;
+                };
+                applying.remove(&actor_id);
+                let mut leftover = leftover;
+                for (version, res) in results {
+                    match res {
+                        Err(_panic) => {
+                            counter!("corro.agent.buffered.apply.count", "result" => "error").increment(1);
+                            error!(%actor_id, %version, "panicked applying fully buffered changes");
+                            record_apply_failure(&agent, actor_id, version, "panicked");
+                        }
+                        Ok(Ok(false)) => {
+                            counter!("corro.agent.buffered.apply.count", "result" => "skipped").increment(1);
+                            warn!(%actor_id, %version, "did not apply buffered changes");
+                            agent.quarantine().write().record_success(actor_id, version);
+                        }
+                        Ok(Ok(true)) => {
+                            counter!("corro.agent.buffered.apply.count", "result" => "applied").increment(1);
+                            debug!(%actor_id, %version, "succesfully applied buffered changes");
+                            agent.quarantine().write().record_success(actor_id, version);
+                        }
+                        Ok(Err(e)) => {
+                            counter!("corro.agent.buffered.apply.count", "result" => "error").increment(1);
+                            error!(%actor_id, %version, "could not apply fully buffered changes: {e}");
+                            if e.is_disk_full() {
+                                check_disk_full(&agent, &e);
+                                // still buffered, retry once there's room
+                                leftover.push(version);
+                            } else {
+                                record_apply_failure(&agent, actor_id, version, &e.to_string());
+                            }
+                        }
+                    }
+                }
+                if !leftover.is_empty() {
+                    let versions = pending.entry(actor_id).or_default();
+                    for version in leftover {
+                        if versions.insert(version) {
+                            pending_count += 1;
+                        }
+                    }
+                }
+            },
+            res = rx_apply.recv() => match res {
+                Some((actor_id, version)) => {
+                    if agent.quarantine().read().is_quarantined(actor_id, version) {
+                        debug!(%actor_id, %version, "version is quarantined, not applying buffered changes");
+                        continue;
+                    }
+                    if agent.is_paused() {
+                        debug!(%actor_id, %version, "agent is paused, holding off applying buffered changes");
+                    }
+                    if pending.entry(actor_id).or_default().insert(version) {
+                        pending_count += 1;
+                    }
+                    let now = tokio::time::Instant::now();
+                    arrivals
+                        .entry(actor_id)
+                        .and_modify(|(_, last)| *last = now)
+                        .or_insert((now, now));
+                }
+                None => break,
+            },
+            _ = &mut debounce_timer, if next_ready.is_some() => {},
+            _ = paused_check.tick(), if pending_count > 0 => {},
+            _ = &mut tripwire, if !tripped => {
+                // nothing gets applied while paused, no use waiting
+                if drain_timeout.is_zero() || agent.is_paused() || agent.is_disk_full() {
+                    break;
+                }
+                info!("draining buffered changes to apply for up to {drain_timeout:?}");
+                tripped = true;
+                drain_deadline.as_mut().reset(tokio::time::Instant::now() + drain_timeout);
+            },
+            _ = &mut drain_deadline, if tripped => {
+                warn!("could not drain buffered changes before shutdown, {pending_count} version(s) left to apply on startup");
+                break;
+            },
+        }
+    }
+
+    // let in-flight applies finish instead of interrupting their transactions
+    while let Some(res) = join_set.join_next().await {
+        if let Ok((actor_id, results, _)) = res {
+            for (version, res) in results {
+                if let Ok(Err(e)) = res {
+                    error!(%actor_id, %version, "could not apply fully buffered changes: {e}");
+                }
             }
-            Err(e) => {
-                error!(%actor_id, %version, "could not apply fully buffered changes: {e}");
+        }
+    }
+
+    info!("fully_buffered_changes_loop ended");
+}
+
+/// Outcome of applying a fully buffered version, panics included
+type BufferedApplyResult = std::thread::Result<Result<bool, ChangeError>>;
+
+/// Apply a batch of fully buffered versions of an actor in one transaction.
+///
+/// When the batch fails, its versions are applied one by one so that only
+/// the offending version counts a failure. A full disk fails them all, the
+/// versions not attempted are returned to be queued again.
+async fn apply_buffered_batch(
+    agent: &Agent,
+    bookie: &Bookie,
+    actor_id: ActorId,
+    versions: Vec<Version>,
+    tx_timeout: Duration,
+) -> (Vec<(Version, BufferedApplyResult)>, Vec<Version>) {
+    // a panic must not leave the actor marked as applying
+    let res = AssertUnwindSafe(process_fully_buffered_changes(
+        agent, bookie, actor_id, &versions, tx_timeout,
+    ))
+    .catch_unwind()
+    .await;
+
+    match res {
+        Ok(Ok(applied)) => (
+            applied
+                .into_iter()
+                .map(|(version, applied)| (version, Ok(Ok(applied))))
+                .collect(),
+            vec![],
+        ),
+        Ok(Err(e)) if e.is_disk_full() => {
+            let mut versions = versions.into_iter();
+            let first = versions.next().expect("batches are never empty");
+            (vec![(first, Ok(Err(e)))], versions.collect())
+        }
+        res if versions.len() == 1 => (
+            vec![(versions[0], res.map(|res| res.map(|applied| applied[0].1)))],
+            vec![],
+        ),
+        _ => {
+            warn!(%actor_id, ?versions, "could not apply batch of buffered versions, applying them one by one");
+            let mut results = Vec::with_capacity(versions.len());
+            let mut versions = versions.into_iter();
+            for version in versions.by_ref() {
+                let res = AssertUnwindSafe(process_fully_buffered_changes(
+                    agent,
+                    bookie,
+                    actor_id,
+                    &[version],
+                    tx_timeout,
+                ))
+                .catch_unwind()
+                .await
+                .map(|res| res.map(|applied| applied[0].1));
+                let disk_full = matches!(&res, Ok(Err(e)) if e.is_disk_full());
+                results.push((version, res));
+
+                // versions left when paused mid-batch go back in the queue
+                if disk_full || agent.is_paused() {
+                    break;
+                }
             }
+            (results, versions.collect())
         }
     }
+}
+
+/// Count a failure to apply a fully buffered version, quarantining it once
+/// it failed `apply.quarantine_after` times, or scheduling another attempt
+/// after a backoff otherwise
+fn record_apply_failure(agent: &Agent, actor_id: ActorId, version: Version, error: &str) {
+    let threshold = agent.config().apply.quarantine_after;
+    let (quarantined, failures) = {
+        let mut quarantine = agent.quarantine().write();
+        let quarantined = quarantine.record_failure(actor_id, version, error, threshold);
+        (quarantined, quarantine.failures(actor_id, version))
+    };
+
+    if let Some(quarantined) = quarantined {
+        counter!("corro.apply.quarantined").increment(1);
+        error!(%actor_id, %version, failures = quarantined.failures, "quarantined version after repeated failures to apply it, it won't be attempted again until released: {error}");
+        return;
+    }
 
-    info!("fully_buffered_changes_loop ended");
+    let backoff = apply_retry_backoff(failures);
+    counter!("corro.apply.retried").increment(1);
+    debug!(%actor_id, %version, failures, "retrying to apply buffered changes in {backoff:?}");
+    let tx_apply = agent.tx_apply().clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(backoff).await;
+        if let Err(e) = tx_apply.send((actor_id, version)).await {
+            debug!("could not schedule applying buffered changes again: {e}");
+        }
+    });
+}
+
+/// Doubles from a second with each consecutive failure, up to a minute
+fn apply_retry_backoff(failures: u32) -> Duration {
+    const MAX_BACKOFF: Duration = Duration::from_secs(60);
+    Duration::from_secs(1)
+        .saturating_mul(2u32.saturating_pow(failures.saturating_sub(1)))
+        .min(MAX_BACKOFF)
+}
+
+/// A burst of versions from the same actor waits for `debounce` after the
+/// last one arrived, but never more than a few debounce periods overall
+fn debounced_ready_at(
+    first: tokio::time::Instant,
+    last: tokio::time::Instant,
+    debounce: Duration,
+) -> tokio::time::Instant {
+    const MAX_DEBOUNCE_PERIODS: u32 = 4;
+    cmp::min(last + debounce, first + debounce * MAX_DEBOUNCE_PERIODS)
 }
 
 /// Compact the database by finding cleared versions
@@ -479,7 +1621,7 @@ pub async fn clear_buffered_meta_loop(
     }
 }
 
-#[tracing::instrument(skip_all, err)]
+#[tracing::instrument(skip_all, fields(actor_id = %change.actor_id, trace_id = change.trace_id.map(tracing::field::display)), err)]
 pub fn process_single_version<T: Deref<Target = rusqlite::Connection> + Committable>(
     agent: &Agent,
     tx: &mut InterruptibleTransaction<T>,
@@ -489,13 +1631,18 @@ pub fn process_single_version<T: Deref<Target = rusqlite::Connection> + Committa
     let ChangeV1 {
         actor_id,
         changeset,
+        ..
     } = change;
 
     let versions = changeset.versions();
+    let changes_len = changeset.len();
+    let start = Instant::now();
 
     let sp = tx.savepoint()?;
     let mut changes_per_table = BTreeMap::new();
-    let (known, changeset) = if changeset.is_complete() {
+    // when paused, complete changesets are buffered like partial ones and
+    // applied once the agent resumes
+    let (known, changeset) = if changeset.is_complete() && !agent.is_paused() {
         let (known, changeset, table) = process_complete_version(
             agent.clone(),
             &sp,
@@ -527,6 +1674,15 @@ pub fn process_single_version<T: Deref<Target = rusqlite::Connection> + Committa
 
     sp.commit()?;
 
+    warn_if_slow_apply(
+        agent,
+        actor_id,
+        &changeset.versions(),
+        changes_len,
+        start.elapsed(),
+        "single",
+    );
+
     for (table_name, count) in changes_per_table {
         counter!("corro.changes.committed", "table" => table_name.to_string(), "source" => "remote").increment(count);
     }
@@ -534,18 +1690,38 @@ pub fn process_single_version<T: Deref<Target = rusqlite::Connection> + Committa
     Ok((known, changeset))
 }
 
+/// Applying a version holds the single write connection, make it visible
+/// when that takes longer than the configured threshold
+fn warn_if_slow_apply(
+    agent: &Agent,
+    actor_id: ActorId,
+    versions: &RangeInclusive<Version>,
+    changes: usize,
+    elapsed: Duration,
+    path: &'static str,
+) {
+    if elapsed < Duration::from_millis(agent.config().perf.slow_apply_threshold_ms) {
+        return;
+    }
+    counter!("corro.apply.slow", "path" => path).increment(1);
+    warn!(%actor_id, ?versions, changes, ?elapsed, path, "slow apply of version held the write connection");
+}
+
+/// Apply the fully buffered `versions` of an actor inside a single write
+/// transaction, in order. Returns whether each version impacted any rows,
+/// versions that can't be applied yet are skipped and reported as `false`.
 #[tracing::instrument(skip(agent, bookie), err)]
 pub async fn process_fully_buffered_changes(
     agent: &Agent,
     bookie: &Bookie,
     actor_id: ActorId,
-    version: Version,
+    versions: &[Version],
     tx_timeout: Duration,
-) -> Result<bool, ChangeError> {
-    let db_version = {
+) -> Result<Vec<(Version, bool)>, ChangeError> {
+    let (applied, db_versions) = {
         let mut conn = agent.pool().write_normal().await?;
 
-        debug!(%actor_id, %version, "acquired write (normal) connection to process fully buffered changes");
+        debug!(%actor_id, ?versions, "acquired write (normal) connection to process fully buffered changes");
 
         let booked = {
             bookie
@@ -560,137 +1736,167 @@ pub async fn process_fully_buffered_changes(
                 actor_id.as_simple(),
             )
             .await;
-        debug!(%actor_id, %version, "acquired Booked write lock to process fully buffered changes");
+        debug!(%actor_id, ?versions, "acquired Booked write lock to process fully buffered changes");
 
         block_in_place(|| {
-            let (last_seq, ts) = {
-                match bookedw.partials.get(&version) {
-                    Some(PartialVersion { seqs, last_seq, ts }) => {
-                        if seqs.gaps(&(CrsqlSeq(0)..=*last_seq)).count() != 0 {
-                            error!(%actor_id, %version, "found sequence gaps: {:?}, aborting!", seqs.gaps(&(CrsqlSeq(0)..=*last_seq)).collect::<RangeInclusiveSet<CrsqlSeq>>());
-                            // TODO: return an error here
-                            return Ok(None);
-                        }
-                        (*last_seq, *ts)
-                    }
-                    None => {
-                        warn!(%actor_id, %version, "version not found in cache, returning");
-                        return Ok(None);
-                    }
-                }
-            };
-
             let base_tx = conn
                 .immediate_transaction()
                 .map_err(|source| ChangeError::Rusqlite {
                     source,
                     actor_id: Some(actor_id),
-                    version: Some(version),
+                    version: versions.first().copied(),
                 })?;
 
+            let start = Instant::now();
             let tx = InterruptibleTransaction::new(base_tx, Some(tx_timeout), "process_buffered_changes");
 
-            info!(%actor_id, %version, "Processing buffered changes to crsql_changes (actor: {actor_id}, version: {version}, last_seq: {last_seq})");
-
-            let max_db_version: Option<Option<CrsqlDbVersion>> = tx.prepare_cached("SELECT MAX(db_version) FROM __corro_buffered_changes WHERE site_id = ? AND version = ?").map_err(|source| ChangeError::Rusqlite{source, actor_id: Some(actor_id), version: Some(version)})?.query_row(params![actor_id.as_bytes(), version], |row| row.get(0)).optional().map_err(|source| ChangeError::Rusqlite{source, actor_id: Some(actor_id), version: Some(version)})?;
+            let mut applied = Vec::with_capacity(versions.len());
+            let mut db_versions = vec![];
+            let mut processed = RangeInclusiveSet::new();
+            let mut last_db_version: Option<CrsqlDbVersion> = None;
+            let mut last_rows_impacted = 0;
+            let mut changes_len = 0;
+
+            for &version in versions {
+                let (last_seq, ts) = {
+                    match bookedw.partials.get(&version) {
+                        Some(PartialVersion { seqs, last_seq, ts }) => {
+                            if seqs.gaps(&(CrsqlSeq(0)..=*last_seq)).count() != 0 {
+                                error!(%actor_id, %version, "found sequence gaps: {:?}, aborting!", seqs.gaps(&(CrsqlSeq(0)..=*last_seq)).collect::<RangeInclusiveSet<CrsqlSeq>>());
+                                // TODO: return an error here
+                                applied.push((version, false));
+                                continue;
+                            }
+                            (*last_seq, *ts)
+                        }
+                        None => {
+                            warn!(%actor_id, %version, "version not found in cache, skipping");
+                            applied.push((version, false));
+                            continue;
+                        }
+                    }
+                };
 
-            let start = Instant::now();
+                info!(%actor_id, %version, "Processing buffered changes to crsql_changes (actor: {actor_id}, version: {version}, last_seq: {last_seq})");
 
-            if let Some(max_db_version) = max_db_version.flatten() {
-                // insert all buffered changes into crsql_changes directly from the buffered changes table
-                let count = tx
-            .prepare_cached(
-                r#"
-                INSERT INTO crsql_changes ("table", pk, cid, val, col_version, db_version, site_id, cl, seq)
-                    SELECT                 "table", pk, cid, val, col_version, ? as db_version, site_id, cl, seq
-                        FROM __corro_buffered_changes
-                            WHERE site_id = ?
-                              AND version = ?
-                            ORDER BY db_version ASC, seq ASC
-                            "#,
-            ).map_err(|source| ChangeError::Rusqlite{source, actor_id: Some(actor_id), version: Some(version)})?
-            .execute(params![max_db_version, actor_id.as_bytes(), version]).map_err(|source| ChangeError::Rusqlite{source, actor_id: Some(actor_id), version: Some(version)})?;
-                info!(%actor_id, %version, "Inserted {count} rows from buffered into crsql_changes in {:?}", start.elapsed());
-            } else {
-                info!(%actor_id, %version, "No buffered rows, skipped insertion into crsql_changes");
-            }
+                let max_db_version: Option<Option<CrsqlDbVersion>> = tx.prepare_cached("SELECT MAX(db_version) FROM __corro_buffered_changes WHERE site_id = ? AND version = ?").map_err(|source| ChangeError::Rusqlite{source, actor_id: Some(actor_id), version: Some(version)})?.query_row(params![actor_id.as_bytes(), version], |row| row.get(0)).optional().map_err(|source| ChangeError::Rusqlite{source, actor_id: Some(actor_id), version: Some(version)})?;
 
-            if let Err(e) = agent.tx_clear_buf().try_send((actor_id, version..=version)) {
-                error!("could not schedule buffered data clear: {e}");
-            }
+                if let Some(max_db_version) = max_db_version.flatten() {
+                    let config = agent.config();
+                    if !config.apply.column_merge.is_empty() {
+                        discard_merge_rejected_buffered(&tx, &config.apply, actor_id, version, ts)
+                            .map_err(|source| ChangeError::Rusqlite {
+                                source,
+                                actor_id: Some(actor_id),
+                                version: Some(version),
+                            })?;
+                    }
 
-            let rows_impacted: i64 = tx
-                .prepare_cached("SELECT crsql_rows_impacted()")
-                .map_err(|source| ChangeError::Rusqlite {
-                    source,
-                    actor_id: Some(actor_id),
-                    version: Some(version),
-                })?
-                .query_row((), |row| row.get(0))
-                .map_err(|source| ChangeError::Rusqlite {
-                    source,
-                    actor_id: Some(actor_id),
-                    version: Some(version),
-                })?;
+                    // like for complete versions, each version after the first
+                    // one needs its own db version
+                    if last_db_version.is_some() {
+                        tx.prepare_cached("SELECT CASE WHEN COALESCE(?, crsql_db_version()) >= ? THEN crsql_next_db_version(crsql_next_db_version() + 1) END")
+                            .and_then(|mut prepped| prepped.query_row(params![last_db_version, max_db_version], |_row| Ok(())))
+                            .map_err(|source| ChangeError::Rusqlite{source, actor_id: Some(actor_id), version: Some(version)})?;
+                    }
 
-            debug!(%actor_id, %version, "rows impacted by buffered changes insertion: {rows_impacted}");
+                    // insert all buffered changes into crsql_changes directly from the buffered changes table
+                    let count = tx
+                .prepare_cached(
+                    r#"
+                    INSERT INTO crsql_changes ("table", pk, cid, val, col_version, db_version, site_id, cl, seq)
+                        SELECT                 "table", pk, cid, val, col_version, ? as db_version, site_id, cl, seq
+                            FROM __corro_buffered_changes
+                                WHERE site_id = ?
+                                  AND version = ?
+                                ORDER BY db_version ASC, seq ASC
+                                "#,
+                ).map_err(|source| ChangeError::Rusqlite{source, actor_id: Some(actor_id), version: Some(version)})?
+                .execute(params![max_db_version, actor_id.as_bytes(), version]).map_err(|source| ChangeError::Rusqlite{source, actor_id: Some(actor_id), version: Some(version)})?;
+                    info!(%actor_id, %version, "Inserted {count} rows from buffered into crsql_changes in {:?}", start.elapsed());
+                    changes_len += count;
+                } else {
+                    info!(%actor_id, %version, "No buffered rows, skipped insertion into crsql_changes");
+                }
 
-            let db_version = if rows_impacted > 0 {
-                let db_version: CrsqlDbVersion = tx
-                    .query_row("SELECT crsql_next_db_version()", [], |row| row.get(0))
+                // crsql_rows_impacted() counts for the whole transaction
+                let rows_impacted: i64 = tx
+                    .prepare_cached("SELECT crsql_rows_impacted()")
+                    .map_err(|source| ChangeError::Rusqlite {
+                        source,
+                        actor_id: Some(actor_id),
+                        version: Some(version),
+                    })?
+                    .query_row((), |row| row.get(0))
                     .map_err(|source| ChangeError::Rusqlite {
                         source,
                         actor_id: Some(actor_id),
                         version: Some(version),
                     })?;
-                debug!("db version: {db_version}");
-
-                tx.prepare_cached(
-                "
-                INSERT OR IGNORE INTO __corro_bookkeeping (actor_id, start_version, db_version, last_seq, ts)
-                    VALUES (
-                        :actor_id,
-                        :version,
-                        :db_version,
-                        :last_seq,
-                        :ts
-                    );",
-                ).map_err(|source| ChangeError::Rusqlite{source, actor_id: Some(actor_id), version: Some(version)})?
-                .execute(named_params! {
-                    ":actor_id": actor_id,
-                    ":version": version,
-                    ":db_version": db_version,
-                    ":last_seq": last_seq,
-                    ":ts": ts
-                }).map_err(|source| ChangeError::Rusqlite{source, actor_id: Some(actor_id), version: Some(version)})?;
-
-                debug!(%actor_id, %version, "inserted bookkeeping row after buffered insert");
-
-                Some(db_version)
-            } else {
-                store_empty_changeset(
-                    &tx,
-                    actor_id,
-                    version..=version,
-                    Timestamp::from(agent.clock().new_timestamp()),
-                )?;
-                debug!(%actor_id, %version, "inserted CLEARED bookkeeping row after buffered insert");
-                None
-            };
+
+                debug!(%actor_id, %version, "rows impacted by buffered changes insertion: {}", rows_impacted - last_rows_impacted);
+
+                if rows_impacted > last_rows_impacted {
+                    let db_version: CrsqlDbVersion = tx
+                        .query_row("SELECT crsql_next_db_version()", [], |row| row.get(0))
+                        .map_err(|source| ChangeError::Rusqlite {
+                            source,
+                            actor_id: Some(actor_id),
+                            version: Some(version),
+                        })?;
+                    debug!("db version: {db_version}");
+
+                    tx.prepare_cached(
+                    "
+                    INSERT OR IGNORE INTO __corro_bookkeeping (actor_id, start_version, db_version, last_seq, ts)
+                        VALUES (
+                            :actor_id,
+                            :version,
+                            :db_version,
+                            :last_seq,
+                            :ts
+                        );",
+                    ).map_err(|source| ChangeError::Rusqlite{source, actor_id: Some(actor_id), version: Some(version)})?
+                    .execute(named_params! {
+                        ":actor_id": actor_id,
+                        ":version": version,
+                        ":db_version": db_version,
+                        ":last_seq": last_seq,
+                        ":ts": ts
+                    }).map_err(|source| ChangeError::Rusqlite{source, actor_id: Some(actor_id), version: Some(version)})?;
+
+                    debug!(%actor_id, %version, "inserted bookkeeping row after buffered insert");
+
+                    last_db_version = Some(db_version);
+                    db_versions.push(db_version);
+                    applied.push((version, true));
+                } else {
+                    store_empty_changeset(
+                        &tx,
+                        actor_id,
+                        version..=version,
+                        Timestamp::from(agent.clock().new_timestamp()),
+                    )?;
+                    debug!(%actor_id, %version, "inserted CLEARED bookkeeping row after buffered insert");
+                    applied.push((version, false));
+                }
+                last_rows_impacted = rows_impacted;
+                processed.insert(version..=version);
+            }
 
             let mut snap = bookedw.snapshot();
-            snap.insert_db(&tx, [version..=version].into())
+            snap.insert_db(&tx, processed.clone())
                 .map_err(|source| ChangeError::Rusqlite {
                     source,
                     actor_id: Some(actor_id),
-                    version: Some(version),
+                    version: None,
                 })?;
 
             let overwritten =
                 find_overwritten_versions(&tx).map_err(|source| ChangeError::Rusqlite {
                     source,
                     actor_id: Some(actor_id),
-                    version: Some(version),
+                    version: None,
                 })?;
 
             let mut last_cleared: Option<Timestamp> = None;
@@ -720,41 +1926,141 @@ pub async fn process_fully_buffered_changes(
                     .map_err(|source| ChangeError::Rusqlite {
                         source,
                         actor_id: Some(actor_id),
-                        version: Some(version),
+                        version: None,
                     })?;
             }
 
             tx.commit().map_err(|source| ChangeError::Rusqlite {
                 source,
                 actor_id: Some(actor_id),
-                version: Some(version),
+                version: None,
             })?;
 
+            if let (Some(first), Some(last)) = (processed.iter().next(), processed.iter().last()) {
+                warn_if_slow_apply(
+                    agent,
+                    actor_id,
+                    &(*first.start()..=*last.end()),
+                    changes_len,
+                    start.elapsed(),
+                    "buffered",
+                );
+            }
+
             bookedw.commit_snapshot(snap);
             agent_booked.commit_snapshot(agent_snap);
 
-            Ok::<_, ChangeError>(db_version)
+            // only clear what was committed, a failed batch is still buffered
+            for versions in processed.iter() {
+                if let Err(e) = agent.tx_clear_buf().try_send((actor_id, versions.clone())) {
+                    error!("could not schedule buffered data clear: {e}");
+                }
+            }
+
+            Ok::<_, ChangeError>((applied, db_versions))
         })
     }?;
 
-    if let Some(db_version) = db_version {
+    if !db_versions.is_empty() {
         let conn = agent.pool().read().await?;
-        block_in_place(|| {
-            if let Err(e) = match_changes_from_db_version(agent.subs_manager(), &conn, db_version) {
-                error!(%db_version, "could not match changes for subs from db version: {e}");
+        for db_version in db_versions {
+            block_in_place(|| {
+                if let Err(e) =
+                    match_changes_from_db_version(agent.subs_manager(), &conn, db_version)
+                {
+                    error!(%db_version, "could not match changes for subs from db version: {e}");
+                }
+            });
+
+            block_in_place(|| {
+                if let Err(e) =
+                    match_changes_from_db_version(agent.updates_manager(), &conn, db_version)
+                {
+                    error!(%db_version, "could not match changes for updates from db version: {e}");
+                }
+            });
+
+            if agent.has_apply_hooks() {
+                let changes = block_in_place(|| {
+                    conn.prepare_cached(
+                        r#"
+                        SELECT "table", pk, cid, val, col_version, db_version, seq, site_id, cl
+                            FROM crsql_changes
+                            WHERE db_version = ?
+                            ORDER BY seq ASC
+                        "#,
+                    )?
+                    .query_map([db_version], row_to_change)?
+                    .collect::<rusqlite::Result<Vec<_>>>()
+                });
+                match changes {
+                    Ok(changes) => agent.notify_applied(db_version, &changes),
+                    Err(e) => {
+                        error!(%db_version, "could not read changes for apply hooks: {e}");
+                    }
+                }
             }
-        });
+        }
+    }
 
-        block_in_place(|| {
-            if let Err(e) =
-                match_changes_from_db_version(agent.updates_manager(), &conn, db_version)
-            {
-                error!(%db_version, "could not match changes for updates from db version: {e}");
+    Ok(applied)
+}
+
+/// Time between probe writes while the disk is full
+const DISK_PROBE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Flag the disk as full when `e` says so, and start probing for free
+/// space to resume on our own once some is available
+pub fn check_disk_full(agent: &Agent, e: &ChangeError) {
+    if !e.is_disk_full() {
+        return;
+    }
+
+    if !agent.set_disk_full(true) {
+        error!("disk is full, rejecting writes and holding off applying changes until space is freed: {e}");
+        gauge!("corro.db.disk_full").set(1.0);
+        tokio::spawn(disk_probe_loop(agent.clone()));
+    }
+}
+
+async fn disk_probe_loop(agent: Agent) {
+    let mut tripwire = agent.tripwire().clone();
+    let mut interval = tokio::time::interval(DISK_PROBE_INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    // the first tick is immediate, the write just failed
+    interval.tick().await;
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {},
+            _ = &mut tripwire => return,
+        }
+
+        match probe_disk_write(&agent).await {
+            Ok(()) => {
+                info!("probe write succeeded, accepting writes and applying changes again");
+                agent.set_disk_full(false);
+                gauge!("corro.db.disk_full").set(0.0);
+                return;
             }
-        });
+            Err(e) => debug!("disk is still full: {e}"),
+        }
     }
+}
 
-    Ok(db_version.is_some())
+/// Write (and remove) a small blob, committing each step so pages really
+/// have to be allocated
+async fn probe_disk_write(agent: &Agent) -> eyre::Result<()> {
+    let conn = agent.pool().write_low().await?;
+    block_in_place(|| {
+        conn.execute(
+            "INSERT OR REPLACE INTO __corro_state (key, value) VALUES ('disk_probe', zeroblob(65536))",
+            [],
+        )?;
+        conn.execute("DELETE FROM __corro_state WHERE key = 'disk_probe'", [])?;
+        Ok::<_, rusqlite::Error>(())
+    })?;
+    Ok(())
 }
 
 #[tracing::instrument(skip(agent, bookie, changes), err)]
@@ -790,13 +2096,14 @@ pub async fn process_multiple_changes(
                 .await
                 .ensure(change.actor_id)
         };
-        if booked_writer
-            .read(
-                "process_multiple_changes(contains_all?)",
-                change.actor_id.as_simple(),
-            )
-            .await
-            .contains_all(change.versions(), change.seqs())
+        if !matches!(src, ChangeSource::Resync)
+            && booked_writer
+                .read(
+                    "process_multiple_changes(contains_all?)",
+                    change.actor_id.as_simple(),
+                )
+                .await
+                .contains_all(change.versions(), change.seqs())
         {
             continue;
         }
@@ -853,6 +2160,49 @@ pub async fn process_multiple_changes(
                 let seqs = change.seqs();
                 let ts = change.ts();
                 if booked_write.contains_all(change.versions(), change.seqs()) {
+                    // a resync re-applies complete versions we already have
+                    // to repair diverged rows
+                    if matches!(src, ChangeSource::Resync)
+                        && change.is_complete()
+                        && !change.is_empty()
+                        && !agent.is_paused()
+                    {
+                        match process_single_version(&agent, &mut tx, last_db_version, change) {
+                            Ok((
+                                KnownDbVersion::Current(CurrentVersion { db_version, .. }),
+                                changeset,
+                            )) => {
+                                // repaired rows were written under a new
+                                // db_version, the version is served from it
+                                let version = *changeset.versions().start();
+                                tx.prepare_cached(
+                                    "UPDATE __corro_bookkeeping SET db_version = :db_version WHERE actor_id = :actor_id AND start_version = :version",
+                                )
+                                .and_then(|mut prepped| {
+                                    prepped.execute(named_params! {
+                                        ":db_version": db_version,
+                                        ":actor_id": actor_id,
+                                        ":version": version,
+                                    })
+                                })
+                                .map_err(|source| ChangeError::Rusqlite {
+                                    source,
+                                    actor_id: Some(actor_id),
+                                    version: Some(version),
+                                })?;
+
+                                counter!("corro.sync.client.resync.repaired")
+                                    .increment(changeset.len() as u64);
+                                last_db_version = Some(db_version);
+                                changesets.push((actor_id, changeset, db_version, src));
+                            }
+                            Ok(_) => {}
+                            Err(e) => {
+                                error!("error re-applying resynced version: {e}");
+                            }
+                        }
+                        continue;
+                    }
                     trace!("previously unknown versions are now deemed known, aborting inserts");
                     continue;
                 }
@@ -1091,6 +2441,9 @@ pub async fn process_multiple_changes(
             if let Some(snap) = snapshots.remove(&actor_id) {
                 booked_write.commit_snapshot(snap);
             }
+            if !booked_write.needed().is_empty() {
+                agent.mark_missing_versions();
+            }
 
             for (versions, _, known) in knowns {
                 let version = *versions.start();
@@ -1111,6 +2464,7 @@ pub async fn process_multiple_changes(
                         });
                     } else {
                         debug!(%actor_id, %version, "still have {gaps_count} gaps in partially buffered seqs: {:?}", seqs.gaps(&full_seqs_range).collect::<Vec<_>>());
+                        agent.mark_missing_versions();
                     }
                 }
             }
@@ -1126,10 +2480,25 @@ pub async fn process_multiple_changes(
 
     let mut change_chunk_size = 0;
 
-    for (_actor_id, changeset, db_version, _src) in changesets {
+    let verify_sample_rate = agent.config().sync.verify_sample_rate;
+    let mut to_verify = vec![];
+
+    for (actor_id, changeset, db_version, src) in changesets {
         change_chunk_size += changeset.changes().len();
         match_changes(agent.subs_manager(), changeset.changes(), db_version);
         match_changes(agent.updates_manager(), changeset.changes(), db_version);
+        agent.notify_applied(db_version, changeset.changes());
+
+        if matches!(src, ChangeSource::Sync)
+            && verify_sample_rate > 0.0
+            && rand::thread_rng().gen_bool(verify_sample_rate.min(1.0))
+        {
+            to_verify.push((actor_id, changeset));
+        }
+    }
+
+    if !to_verify.is_empty() {
+        verify_sampled_changesets(&agent, to_verify).await;
     }
 
     histogram!("corro.agent.changes.processing.time.seconds", "source" => "remote").record(start.elapsed());
@@ -1138,6 +2507,81 @@ pub async fn process_multiple_changes(
     Ok(())
 }
 
+/// Read back the changes of versions just applied from a sync and count
+/// those that didn't take effect, e.g. because of schema drift
+async fn verify_sampled_changesets(agent: &Agent, changesets: Vec<(ActorId, Changeset)>) {
+    let conn = match agent.pool().read().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            warn!("could not get a read connection to verify applied changes: {e}");
+            return;
+        }
+    };
+
+    for (actor_id, changeset) in changesets {
+        counter!("corro.sync.verify.sampled").increment(1);
+        match block_in_place(|| unapplied_changes(&conn, changeset.changes())) {
+            Ok(unapplied) if unapplied.is_empty() => {}
+            Ok(unapplied) => {
+                warn!(%actor_id, versions = ?changeset.versions(), "{} synced changes did not take effect, first: {:?}", unapplied.len(), unapplied[0]);
+                counter!("corro.sync.verify.mismatch", "actor_id" => actor_id.to_string())
+                    .increment(unapplied.len() as u64);
+            }
+            Err(e) => {
+                warn!(%actor_id, versions = ?changeset.versions(), "could not verify applied changes: {e}");
+            }
+        }
+    }
+}
+
+/// Changes not reflected in `crsql_changes`, ignoring those superseded
+/// since by a newer causal length (delete or re-insert) or column version
+pub fn unapplied_changes<'a>(
+    conn: &Connection,
+    changes: &'a [Change],
+) -> rusqlite::Result<Vec<&'a Change>> {
+    let mut unapplied = vec![];
+
+    for change in changes {
+        let local_cl: Option<i64> = conn
+            .prepare_cached(r#"SELECT MAX(cl) FROM crsql_changes WHERE "table" = ? AND pk = ?"#)?
+            .query_row(params![change.table, change.pk], |row| row.get(0))?;
+
+        let applied = match local_cl {
+            None => false,
+            // row was deleted or re-inserted since
+            Some(cl) if cl > change.cl => true,
+            Some(cl) if change.cid.0 == "-1" => cl == change.cl,
+            Some(_) => {
+                let local: Option<(i64, [u8; 16], SqliteValue)> = conn
+                    .prepare_cached(
+                        r#"SELECT col_version, site_id, val FROM crsql_changes WHERE "table" = ? AND pk = ? AND cid = ?"#,
+                    )?
+                    .query_row(params![change.table, change.pk, change.cid], |row| {
+                        Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+                    })
+                    .optional()?;
+
+                match local {
+                    None => false,
+                    Some((col_version, _, _)) if col_version > change.col_version => true,
+                    // same write, it must have the same value
+                    Some((col_version, site_id, val)) if col_version == change.col_version => {
+                        site_id != change.site_id || val == change.val
+                    }
+                    Some(_) => false,
+                }
+            }
+        };
+
+        if !applied {
+            unapplied.push(change);
+        }
+    }
+
+    Ok(unapplied)
+}
+
 #[tracing::instrument(skip(sp, parts), err)]
 pub fn process_incomplete_version<T: Deref<Target = rusqlite::Connection> + Committable>(
     sp: &InterruptibleTransaction<T>,
@@ -1194,6 +2638,14 @@ pub fn process_incomplete_version<T: Deref<Target = rusqlite::Connection> + Comm
 
     debug!(%actor_id, %version, "buffered {inserted} changes");
 
+    if inserted > 0 {
+        let compacted = compact_buffered_changes(sp, actor_id, *version)?;
+        if compacted > 0 {
+            debug!(%actor_id, %version, "compacted {compacted} superseded buffered changes");
+            counter!("corro.changes.buffered.compacted").increment(compacted as u64);
+        }
+    }
+
     let deleted: Vec<RangeInclusive<CrsqlSeq>> = sp
         .prepare_cached(
             "
@@ -1301,6 +2753,9 @@ pub fn process_complete_version<T: Deref<Target = rusqlite::Connection> + Commit
 
     let mut changes_per_table = BTreeMap::new();
 
+    let config = agent.config();
+    let log_lost_conflicts = config.apply.log_lost_conflicts;
+
     // we need to manually increment the next db version for each changeset
     sp
         .prepare_cached("SELECT CASE WHEN COALESCE(?, crsql_db_version()) >= ? THEN crsql_next_db_version(crsql_next_db_version() + 1) END")?
@@ -1309,6 +2764,24 @@ pub fn process_complete_version<T: Deref<Target = rusqlite::Connection> + Commit
     for change in changes {
         trace!("inserting change! {change:?}");
 
+        if let Some(strategy) = config
+            .apply
+            .merge_strategy(change.table.as_str(), change.cid.as_str())
+        {
+            if merge_strategy_rejects(
+                sp,
+                strategy,
+                change.table.as_str(),
+                &change.pk,
+                change.cid.as_str(),
+                &change.val,
+                ts,
+            )? {
+                trace!("merge strategy {strategy:?} kept the local value");
+                continue;
+            }
+        }
+
         sp.prepare_cached(
             r#"
                 INSERT INTO crsql_changes
@@ -1343,6 +2816,8 @@ pub fn process_complete_version<T: Deref<Target = rusqlite::Connection> + Commit
                     changes_per_table.insert(c.table.clone(), 1);
                 }
             }
+        } else if log_lost_conflicts {
+            log_lost_conflict(sp, actor_id, version, &change)?;
         }
         last_rows_impacted = rows_impacted;
     }
@@ -1379,6 +2854,183 @@ pub fn process_complete_version<T: Deref<Target = rusqlite::Connection> + Commit
     Ok::<_, rusqlite::Error>((known_version, new_changeset, changes_per_table))
 }
 
+/// Whether the merge strategy configured for a column keeps the local
+/// value over an incoming change, which is then not applied at all.
+/// Changes to rows or columns we don't have are always let through.
+#[allow(clippy::too_many_arguments)]
+fn merge_strategy_rejects(
+    conn: &Connection,
+    strategy: MergeStrategy,
+    table: &str,
+    pk: &[u8],
+    cid: &str,
+    val: &SqliteValue,
+    ts: Timestamp,
+) -> rusqlite::Result<bool> {
+    let rejected = match strategy {
+        MergeStrategy::Max => {
+            // comparisons with NULL are NULL, those are left to cr-sqlite
+            let local_greater: Option<Option<bool>> = conn
+                .prepare_cached(
+                    r#"SELECT val > ? FROM crsql_changes WHERE "table" = ? AND pk = ? AND cid = ?"#,
+                )?
+                .query_row(params![val, table, pk, cid], |row| row.get(0))
+                .optional()?;
+            local_greater.flatten().unwrap_or(false)
+        }
+        MergeStrategy::NewestTimestamp => {
+            let local: Option<(ActorId, CrsqlDbVersion)> = conn
+                .prepare_cached(
+                    r#"SELECT site_id, db_version FROM crsql_changes WHERE "table" = ? AND pk = ? AND cid = ?"#,
+                )?
+                .query_row(params![table, pk, cid], |row| Ok((row.get(0)?, row.get(1)?)))
+                .optional()?;
+
+            let local_ts: Option<Timestamp> = match local {
+                Some((site_id, db_version)) => conn
+                    .prepare_cached(
+                        "SELECT ts FROM __corro_bookkeeping WHERE actor_id = ? AND db_version = ?",
+                    )?
+                    .query_row(params![site_id, db_version], |row| row.get(0))
+                    .optional()?
+                    .flatten(),
+                None => None,
+            };
+
+            local_ts.map(|local_ts| local_ts >= ts).unwrap_or(false)
+        }
+    };
+
+    if rejected {
+        counter!("corro.changes.merge_rejected", "table" => table.to_string(), "column" => cid.to_string()).increment(1);
+    }
+
+    Ok(rejected)
+}
+
+/// Drop the buffered changes of a version that another buffered change of
+/// the same version overwrites anyway, to keep slow partials small.
+///
+/// Only exact (table, pk, cid) matches with a greater causal length or
+/// col_version are dropped, those would lose once applied. The seq
+/// bookkeeping is left alone: gaps in buffered seqs are expected, like they
+/// are in crsql_changes for overwritten columns.
+fn compact_buffered_changes(
+    conn: &Connection,
+    actor_id: ActorId,
+    version: Version,
+) -> rusqlite::Result<usize> {
+    conn.prepare_cached(
+        r#"
+        DELETE FROM __corro_buffered_changes
+            WHERE site_id = :actor_id
+              AND version = :version
+              AND EXISTS (
+                SELECT 1 FROM __corro_buffered_changes AS newer
+                    WHERE newer.site_id = __corro_buffered_changes.site_id
+                      AND newer.version = __corro_buffered_changes.version
+                      AND newer."table" = __corro_buffered_changes."table"
+                      AND newer.pk = __corro_buffered_changes.pk
+                      AND newer.cid = __corro_buffered_changes.cid
+                      AND (
+                        newer.cl > __corro_buffered_changes.cl
+                        OR (
+                            newer.cl = __corro_buffered_changes.cl
+                            AND newer.col_version > __corro_buffered_changes.col_version
+                        )
+                      )
+              )
+        "#,
+    )?
+    .execute(named_params! {
+        ":actor_id": actor_id,
+        ":version": version,
+    })
+}
+
+/// Drop the buffered changes of a version that the configured column merge
+/// strategies reject, before the rest of it is applied
+fn discard_merge_rejected_buffered(
+    conn: &Connection,
+    apply: &ApplyConfig,
+    actor_id: ActorId,
+    version: Version,
+    ts: Timestamp,
+) -> rusqlite::Result<()> {
+    let mut rejected = vec![];
+    {
+        let mut prepped = conn.prepare_cached(
+            r#"SELECT "table", pk, cid, val, seq FROM __corro_buffered_changes WHERE site_id = ? AND version = ?"#,
+        )?;
+        let mut rows = prepped.query(params![actor_id.as_bytes(), version])?;
+        while let Some(row) = rows.next()? {
+            let table: String = row.get(0)?;
+            let cid: String = row.get(2)?;
+            let Some(strategy) = apply.merge_strategy(&table, &cid) else {
+                continue;
+            };
+            let pk: Vec<u8> = row.get(1)?;
+            let val: SqliteValue = row.get(3)?;
+            if merge_strategy_rejects(conn, strategy, &table, &pk, &cid, &val, ts)? {
+                rejected.push(row.get::<_, CrsqlSeq>(4)?);
+            }
+        }
+    }
+
+    for seq in rejected {
+        conn.prepare_cached(
+            "DELETE FROM __corro_buffered_changes WHERE site_id = ? AND version = ? AND seq = ?",
+        )?
+        .execute(params![actor_id.as_bytes(), version, seq])?;
+    }
+
+    Ok(())
+}
+
+/// Records an incoming change that did not impact the database because the
+/// local state won the conflict (or already had the change)
+fn log_lost_conflict(
+    conn: &Connection,
+    actor_id: ActorId,
+    version: Version,
+    change: &Change,
+) -> rusqlite::Result<()> {
+    let local: Option<(i64, i64, Vec<u8>)> = conn
+        .prepare_cached(
+            r#"SELECT col_version, cl, site_id FROM crsql_changes WHERE "table" = ? AND pk = ? AND cid = ?"#,
+        )?
+        .query_row(
+            params![change.table.as_str(), change.pk, change.cid.as_str()],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .optional()?;
+
+    let (local_col_version, local_cl, local_site_id) = match local {
+        Some((col_version, cl, site_id)) => {
+            (Some(col_version), Some(cl), Some(hex::encode(site_id)))
+        }
+        None => (None, None, None),
+    };
+
+    info!(
+        %actor_id,
+        %version,
+        table = %change.table,
+        pk = %hex::encode(&change.pk),
+        cid = %change.cid,
+        incoming_col_version = change.col_version,
+        incoming_cl = change.cl,
+        ?local_col_version,
+        ?local_cl,
+        ?local_site_id,
+        "incoming change lost conflict"
+    );
+
+    counter!("corro.changes.lost_conflict", "table" => change.table.to_string()).increment(1);
+
+    Ok(())
+}
+
 pub fn check_buffered_meta_to_clear(
     conn: &Connection,
     actor_id: ActorId,
@@ -1404,9 +3056,223 @@ pub fn log_at_pow_10(msg: &str, count: &mut u64) {
 }
 
 #[inline]
-fn is_pow_10(i: u64) -> bool {
+pub(crate) fn is_pow_10(i: u64) -> bool {
     matches!(
         i,
         1 | 10 | 100 | 1000 | 10000 | 1000000 | 10000000 | 100000000
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tower::ServiceExt;
+
+    fn slow_router(policy: OverloadPolicy) -> Router {
+        Router::new().route(
+            "/",
+            get(|| async {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                "ok"
+            })
+            .route_layer(axum::middleware::from_fn_with_state(
+                ConcurrencyLimit::new(policy, 1),
+                limit_concurrency,
+            )),
+        )
+    }
+
+    async fn burst(router: Router, n: usize) -> Vec<StatusCode> {
+        let requests = (0..n).map(|_| {
+            router.clone().oneshot(
+                axum::http::Request::get("/")
+                    .body(hyper::Body::empty())
+                    .unwrap(),
+            )
+        });
+        futures::future::join_all(requests)
+            .await
+            .into_iter()
+            .map(|res| res.unwrap().status())
+            .collect()
+    }
+
+    #[test]
+    fn test_sync_delay_resets_after_activity() {
+        let mut sync_backoff = backoff::Backoff::new(0)
+            .timeout_range(Duration::from_secs(1), MAX_SYNC_BACKOFF)
+            .iter();
+
+        // minimum interval, with up to 30% jitter
+        let min_with_jitter = Duration::from_millis(1300);
+
+        // quiet cluster, backing off to the max
+        let mut delay = Duration::ZERO;
+        for _ in 0..10 {
+            delay = next_sync_delay(&mut sync_backoff, 0);
+        }
+        assert!(delay > min_with_jitter);
+
+        // a sync that transferred changes brings the interval back down
+        let delay = next_sync_delay(&mut sync_backoff, 42);
+        assert!(delay <= min_with_jitter);
+
+        // and it grows again as syncs find nothing new
+        for _ in 0..10 {
+            delay = next_sync_delay(&mut sync_backoff, 0);
+        }
+        assert!(delay > min_with_jitter);
+    }
+
+    #[test]
+    fn test_restore_swim_identity() -> rusqlite::Result<()> {
+        let conn = Connection::open_in_memory()?;
+        conn.execute_batch("CREATE TABLE __corro_state (key TEXT NOT NULL PRIMARY KEY, value);")?;
+
+        let actor_id = ActorId(uuid::Uuid::new_v4());
+        let addr: SocketAddr = "127.0.0.1:8787".parse().unwrap();
+        let identity = |addr, ts| {
+            Actor::new(
+                actor_id,
+                addr,
+                Timestamp(uhlc::NTP64(ts)),
+                Default::default(),
+            )
+        };
+
+        let first = restore_swim_identity(&conn, identity(addr, 1))?;
+        assert_eq!(first, identity(addr, 1));
+
+        // a peer that knew the node before the restart sees the same member
+        let mut members = corro_types::members::Members::default();
+        members.add_member(&first);
+
+        let restarted = restore_swim_identity(&conn, identity(addr, 2))?;
+        assert_eq!(restarted, first);
+        assert_eq!(
+            members.add_member(&restarted),
+            corro_types::members::MemberAddedResult::Ignored
+        );
+
+        // moving to another address is a new identity
+        let other_addr: SocketAddr = "127.0.0.1:8788".parse().unwrap();
+        let moved = restore_swim_identity(&conn, identity(other_addr, 3))?;
+        assert_eq!(moved, identity(other_addr, 3));
+        assert_eq!(
+            restore_swim_identity(&conn, identity(other_addr, 4))?,
+            moved
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_jitter_period_is_bounded_and_stable() {
+        let period = Duration::from_secs(300);
+        let actor_id = ActorId(uuid::Uuid::new_v4());
+
+        assert_eq!(jitter_period(actor_id, period, 0), period);
+
+        let jittered = jitter_period(actor_id, period, 10);
+        assert!(jittered >= Duration::from_secs(270));
+        assert!(jittered <= Duration::from_secs(330));
+        // same node, same offset
+        assert_eq!(jitter_period(actor_id, period, 10), jittered);
+
+        // the jitter is capped, the period never collapses to zero
+        let jittered = jitter_period(actor_id, period, 100);
+        assert!(jittered >= Duration::from_secs(150));
+        assert!(jittered <= Duration::from_secs(450));
+
+        // nodes are spread out
+        let periods: HashSet<Duration> = (0..10)
+            .map(|_| jitter_period(ActorId(uuid::Uuid::new_v4()), period, 10))
+            .collect();
+        assert!(periods.len() > 1);
+    }
+
+    #[test]
+    fn test_initial_sync_delay_spreads_nodes() {
+        let min = Duration::from_secs(1);
+        let max_jitter = Duration::from_secs(30);
+
+        let a = ActorId(uuid::Uuid::new_v4());
+        let b = ActorId(uuid::Uuid::new_v4());
+
+        assert_eq!(initial_sync_delay(a, min, Duration::ZERO), min);
+
+        let delay_a = initial_sync_delay(a, min, max_jitter);
+        let delay_b = initial_sync_delay(b, min, max_jitter);
+        for delay in [delay_a, delay_b] {
+            assert!(delay >= min);
+            assert!(delay <= min + max_jitter);
+        }
+
+        // nodes started together don't fire their first sync at once
+        assert_ne!(delay_a, delay_b);
+        // but a node always gets the same delay
+        assert_eq!(initial_sync_delay(a, min, max_jitter), delay_a);
+    }
+
+    #[test]
+    fn test_debounced_ready_at_is_capped() {
+        let debounce = Duration::from_millis(100);
+        let first = tokio::time::Instant::now();
+
+        // a lone version waits for the debounce
+        assert_eq!(debounced_ready_at(first, first, debounce), first + debounce);
+
+        // trailing versions push it back
+        let last = first + Duration::from_millis(150);
+        assert_eq!(debounced_ready_at(first, last, debounce), last + debounce);
+
+        // but a steady stream can't hold off applying forever
+        let last = first + Duration::from_secs(10);
+        assert_eq!(
+            debounced_ready_at(first, last, debounce),
+            first + Duration::from_millis(400)
+        );
+    }
+
+    #[test]
+    fn test_apply_retry_backoff_is_capped() {
+        assert_eq!(apply_retry_backoff(1), Duration::from_secs(1));
+        assert_eq!(apply_retry_backoff(2), Duration::from_secs(2));
+        assert_eq!(apply_retry_backoff(4), Duration::from_secs(8));
+        assert_eq!(apply_retry_backoff(7), Duration::from_secs(60));
+        assert_eq!(apply_retry_backoff(u32::MAX), Duration::from_secs(60));
+    }
+
+    #[tokio::test]
+    async fn test_overload_policies() {
+        let statuses = burst(slow_router(OverloadPolicy::Shed), 3).await;
+        assert_eq!(
+            statuses
+                .iter()
+                .filter(|status| **status == StatusCode::OK)
+                .count(),
+            1
+        );
+        assert_eq!(
+            statuses
+                .iter()
+                .filter(|status| **status == StatusCode::SERVICE_UNAVAILABLE)
+                .count(),
+            2
+        );
+
+        // the burst fits within the max wait
+        let statuses = burst(slow_router(OverloadPolicy::Queue { max_wait_ms: 2000 }), 3).await;
+        assert!(statuses.iter().all(|status| *status == StatusCode::OK));
+
+        // queued requests give up after the max wait
+        let statuses = burst(slow_router(OverloadPolicy::Queue { max_wait_ms: 50 }), 3).await;
+        assert_eq!(
+            statuses
+                .iter()
+                .filter(|status| **status == StatusCode::OK)
+                .count(),
+            1
+        );
+    }
+}
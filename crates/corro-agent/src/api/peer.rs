@@ -7,22 +7,24 @@ use std::time::{Duration, Instant};
 
 use bytes::{BufMut, BytesMut};
 use corro_types::actor::ClusterId;
-use corro_types::agent::{Agent, SplitPool};
+use corro_types::agent::{Agent, SplitPool, MAX_CONCURRENT_SYNCS};
 use corro_types::base::{CrsqlSeq, Version};
 use corro_types::broadcast::{
     BiPayload, BiPayloadV1, ChangeSource, ChangeV1, Changeset, Timestamp,
 };
 use corro_types::change::{row_to_change, Change, ChunkedChanges};
-use corro_types::config::{GossipConfig, TlsClientConfig};
+use corro_types::config::{GossipConfig, TlsClientConfig, QUIC_DATAGRAM_OVERHEAD};
+use corro_types::schema::schema_hash;
 use corro_types::sync::{
-    generate_sync, SyncMessage, SyncMessageEncodeError, SyncMessageV1, SyncNeedV1, SyncRejectionV1,
-    SyncRequestV1, SyncStateV1, SyncTraceContextV1,
+    cap_needs, generate_sync, SyncLoadV1, SyncMessage, SyncMessageEncodeError, SyncMessageV1,
+    SyncNeedV1, SyncRejectionV1, SyncRequestV1, SyncStateV1, SyncTraceContextV1,
 };
 use futures::stream::FuturesUnordered;
 use futures::{Future, Stream, TryFutureExt, TryStreamExt};
 use itertools::Itertools;
 use metrics::counter;
 use quinn::{RecvStream, SendStream};
+use rand::seq::IteratorRandom;
 use rangemap::{RangeInclusiveMap, RangeInclusiveSet};
 use rusqlite::{named_params, Connection};
 use speedy::Writable;
@@ -116,6 +118,10 @@ fn build_quinn_transport_config(config: &GossipConfig) -> quinn::TransportConfig
         transport_config.min_mtu(max_mtu);
         // disable discovery
         transport_config.mtu_discovery_config(None);
+    } else {
+        // start big enough for SWIM payloads, they can't be split up. QUIC
+        // never goes below 1200 bytes, smaller values are ignored.
+        transport_config.initial_mtu(config.mtu + QUIC_DATAGRAM_OVERHEAD);
     }
 
     if config.disable_gso {
@@ -359,6 +365,8 @@ fn handle_need(
 
     let mut empties: RangeInclusiveMap<Version, Timestamp> = RangeInclusiveMap::new();
 
+    let chunk_size = agent.config().sync.chunk_size;
+
     // this is a read transaction!
     let tx = conn.transaction()?;
 
@@ -474,7 +482,8 @@ fn handle_need(
 
                 if let Some(empty) = send_change_chunks(
                     sender,
-                    ChunkedChanges::new(rows, CrsqlSeq(0), last_seq, MAX_CHANGES_BYTES_PER_MESSAGE),
+                    ChunkedChanges::new(rows, CrsqlSeq(0), last_seq, MAX_CHANGES_BYTES_PER_MESSAGE)
+                        .with_max_changes(chunk_size),
                     actor_id,
                     version,
                     last_seq,
@@ -527,7 +536,8 @@ fn handle_need(
                                 *start_seq,
                                 *end_seq,
                                 MAX_CHANGES_BYTES_PER_MESSAGE,
-                            ),
+                            )
+                            .with_max_changes(chunk_size),
                             actor_id,
                             version,
                             last_seq,
@@ -545,6 +555,7 @@ fn handle_need(
                             versions,
                             ts: Some(ts),
                         },
+                        trace_id: None,
                     })))?;
                 }
             }
@@ -575,6 +586,7 @@ fn handle_need(
                                     versions: version..=version,
                                     ts: Some(ts),
                                 },
+                                trace_id: None,
                             },
                         )))?;
                         return Ok(());
@@ -613,7 +625,8 @@ fn handle_need(
                                 *range_needed.start(),
                                 *range_needed.end(),
                                 MAX_CHANGES_BYTES_PER_MESSAGE,
-                            ),
+                            )
+                            .with_max_changes(chunk_size),
                             actor_id,
                             version,
                             last_seq,
@@ -626,6 +639,7 @@ fn handle_need(
                                         versions: empty..=empty,
                                         ts: Some(ts),
                                     },
+                                    trace_id: None,
                                 },
                             )))?;
                         }
@@ -702,7 +716,8 @@ fn handle_need(
                                     *start_seq,
                                     *end_seq,
                                     MAX_CHANGES_BYTES_PER_MESSAGE,
-                                ),
+                                )
+                                .with_max_changes(chunk_size),
                                 actor_id,
                                 version,
                                 last_seq,
@@ -750,6 +765,7 @@ fn handle_need(
                         versions: versions.clone(),
                         ts: *ts,
                     },
+                    trace_id: None,
                 })))?;
                 count += versions.len();
             }
@@ -790,6 +806,7 @@ fn send_change_chunks<I: Iterator<Item = rusqlite::Result<Change>>>(
                             last_seq,
                             ts,
                         },
+                        trace_id: None,
                     })))?;
                 }
 
@@ -978,7 +995,8 @@ async fn encode_write_bipayload_msg(
 ) -> Result<(), SyncSendError> {
     encode_bipayload_msg(codec, encode_buf, send_buf, msg)?;
 
-    write_buf(send_buf, write).await
+    write_buf(send_buf, write).await?;
+    Ok(())
 }
 
 fn encode_bipayload_msg(
@@ -1003,16 +1021,20 @@ async fn encode_write_sync_msg(
 ) -> Result<(), SyncSendError> {
     encode_sync_msg(codec, encode_buf, send_buf, msg)?;
 
-    write_buf(send_buf, write).await
+    write_buf(send_buf, write).await?;
+    Ok(())
 }
 
 #[tracing::instrument(skip_all, fields(buf_size = send_buf.len()), err)]
-async fn write_buf(send_buf: &mut BytesMut, write: &mut SendStream) -> Result<(), SyncSendError> {
+async fn write_buf(
+    send_buf: &mut BytesMut,
+    write: &mut SendStream,
+) -> Result<usize, SyncSendError> {
     let len = send_buf.len();
     write.write_chunk(send_buf.split().freeze()).await?;
     counter!("corro.sync.chunk.sent.bytes").increment(len as u64);
 
-    Ok(())
+    Ok(len)
 }
 
 #[tracing::instrument(skip(read), fields(buf_size = tracing::field::Empty), err)]
@@ -1035,13 +1057,131 @@ pub async fn read_sync_msg<R: Stream<Item = std::io::Result<BytesMut>> + Unpin>(
     }
 }
 
-#[tracing::instrument(skip_all, err)]
+/// Suggested delay before retrying a sync rejected for lack of capacity
+const SYNC_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+fn current_sync_load(agent: &Agent, requester: ActorId) -> SyncLoadV1 {
+    let available = agent.limits().sync.available_permits();
+    SyncLoadV1 {
+        in_flight: MAX_CONCURRENT_SYNCS.saturating_sub(available) as u32,
+        max: MAX_CONCURRENT_SYNCS as u32,
+        retry_after_ms: SYNC_RETRY_AFTER.as_millis() as u64,
+        alternate: suggest_sync_alternate(agent, requester),
+    }
+}
+
+/// Pick a random member that had everything we had the last time we
+/// synced with it, for a peer we're too busy to sync with
+fn suggest_sync_alternate(agent: &Agent, requester: ActorId) -> Option<ActorId> {
+    let members = agent.members().read();
+    members
+        .states
+        .iter()
+        .filter(|(id, state)| {
+            **id != requester
+                && **id != agent.actor_id()
+                && state.cluster_id == agent.cluster_id()
+                && state.caught_up
+                && state.sync_failures == 0
+        })
+        .map(|(id, _)| *id)
+        .choose(&mut rand::thread_rng())
+}
+
+/// Log and count why peers rejected a sync, warning when every peer
+/// turned us down for lack of capacity as the whole cluster is likely busy
+fn log_sync_rejections<'a>(
+    rejections: impl Iterator<Item = (ActorId, SocketAddr, Option<&'a SyncRejectionV1>)>,
+) {
+    let mut peers = 0;
+    let mut overloaded = 0;
+    for (actor_id, addr, rejection) in rejections {
+        peers += 1;
+        let reason = match rejection {
+            None => continue,
+            Some(SyncRejectionV1::Overloaded(load)) => {
+                overloaded += 1;
+                debug!(%actor_id, %addr, "peer is too busy to sync: {load}");
+                "overloaded"
+            }
+            Some(SyncRejectionV1::MaxConcurrencyReached) => {
+                overloaded += 1;
+                debug!(%actor_id, %addr, "peer is too busy to sync");
+                "overloaded"
+            }
+            Some(SyncRejectionV1::DifferentCluster) => "different_cluster",
+            Some(SyncRejectionV1::ReadOnlyReplica) => "read_only_replica",
+            Some(SyncRejectionV1::Draining) => "draining",
+        };
+        counter!("corro.sync.client.rejected", "reason" => reason).increment(1);
+    }
+
+    if overloaded > 0 && overloaded == peers {
+        warn!("all {peers} sync peers are at max sync concurrency, the cluster may be overloaded");
+        counter!("corro.sync.client.all_overloaded").increment(1);
+    }
+}
+
+/// Hash of our `__corro_schema`, exchanged with peers when syncing
+async fn local_schema_hash(agent: &Agent) -> Option<u64> {
+    let conn = match agent.pool().read().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            warn!("could not get a read connection to hash the schema: {e}");
+            return None;
+        }
+    };
+
+    match block_in_place(|| schema_hash(&conn)) {
+        Ok(hash) => Some(hash),
+        Err(e) => {
+            warn!("could not hash the schema: {e}");
+            None
+        }
+    }
+}
+
+/// Diagnostic only: a peer with a different schema can still sync, but
+/// changes for tables that differ may fail to apply on either side
+fn check_peer_schema_hash(actor_id: ActorId, ours: Option<u64>, theirs: Option<u64>) {
+    if let (Some(ours), Some(theirs)) = (ours, theirs) {
+        if ours != theirs {
+            warn!(
+                %actor_id,
+                "schema mismatch with peer (our hash: {ours:016x}, theirs: {theirs:016x}), changes to diverging tables may not converge"
+            );
+            counter!("corro.sync.schema.mismatch", "actor_id" => actor_id.to_string()).increment(1);
+        }
+    }
+}
+
 pub async fn parallel_sync(
     agent: &Agent,
     transport: &Transport,
     members: Vec<(ActorId, SocketAddr)>,
     our_sync_state: SyncStateV1,
     our_empty_ts: HashMap<ActorId, Option<Timestamp>>,
+) -> Result<usize, SyncError> {
+    parallel_sync_from(
+        agent,
+        transport,
+        members,
+        our_sync_state,
+        our_empty_ts,
+        ChangeSource::Sync,
+    )
+    .await
+}
+
+/// Same as [`parallel_sync`], queueing received changes as coming from `src`
+#[tracing::instrument(skip_all, err)]
+pub async fn parallel_sync_from(
+    agent: &Agent,
+    transport: &Transport,
+    members: Vec<(ActorId, SocketAddr)>,
+    our_sync_state: SyncStateV1,
+    our_empty_ts: HashMap<ActorId, Option<Timestamp>>,
+    src: ChangeSource,
 ) -> Result<usize, SyncError> {
     trace!(
         self_actor_id = %agent.actor_id(),
@@ -1058,6 +1198,9 @@ pub async fn parallel_sync(
         prop.inject_context(&tracing::Span::current().context(), &mut trace_ctx)
     });
 
+    let our_schema_hash = local_schema_hash(agent).await;
+    let max_versions_per_request = agent.config().sync.max_versions_per_request;
+
     let results = FuturesUnordered::from_iter(members.iter().map(|(actor_id, addr)| {
         let trace_ctx = trace_ctx.clone();
         async {
@@ -1065,6 +1208,7 @@ pub async fn parallel_sync(
                 *actor_id,
                 *addr,
                 async {
+                    let start = Instant::now();
                     let mut codec = LengthDelimitedCodec::builder().max_frame_length(100 * 1_024 * 1_024).new_codec();
                     let mut send_buf = BytesMut::new();
                     let mut encode_buf = BytesMut::new();
@@ -1077,7 +1221,7 @@ pub async fn parallel_sync(
                         &mut codec,
                         &mut encode_buf,
                         &mut send_buf,
-                        BiPayload::V1 {data: BiPayloadV1::SyncStart {actor_id: agent.actor_id(), trace_ctx}, cluster_id: agent.cluster_id()},
+                        BiPayload::V1 {data: BiPayloadV1::SyncStart {actor_id: agent.actor_id(), trace_ctx}, cluster_id: agent.cluster_id(), schema_hash: our_schema_hash, schema_broadcasts: true},
                         &mut tx,
                     ).instrument(info_span!("write_sync_start"))
                     .await?;
@@ -1108,6 +1252,14 @@ pub async fn parallel_sync(
                     };
                     trace!(%actor_id, self_actor_id = %agent.actor_id(), "read state payload: {their_sync_state:?}");
 
+                    check_peer_schema_hash(actor_id, our_schema_hash, their_sync_state.schema_hash);
+                    agent.members().write().set_schema_broadcasts(&actor_id, their_sync_state.schema_broadcasts);
+
+                    // remember whether the peer has everything we have, to point
+                    // other nodes to it when we're too busy to sync with them
+                    let peer_caught_up = their_sync_state.compute_available_needs(&our_sync_state).is_empty();
+                    agent.members().write().set_caught_up(&actor_id, peer_caught_up);
+
                     match timeout(Duration::from_secs(2), read_sync_msg(&mut read)).instrument(info_span!("read_sync_clock")).await.map_err(SyncRecvError::from)??  {
                         Some(SyncMessage::V1(SyncMessageV1::Clock(ts))) => match actor_id.try_into() {
                             Ok(id) => {
@@ -1131,6 +1283,17 @@ pub async fn parallel_sync(
 
                     let mut needs = our_sync_state.compute_available_needs(&their_sync_state);
 
+                    // far behind peers catch up over several syncs instead of one huge one
+                    if let Some(max_versions) = max_versions_per_request {
+                        let before = needs.values().flatten().map(SyncNeedV1::count).sum::<usize>();
+                        needs = cap_needs(needs, max_versions.get());
+                        let after = needs.values().flatten().map(SyncNeedV1::count).sum::<usize>();
+                        if after < before {
+                            debug!(%actor_id, "capped sync needs from {before} to {after} versions");
+                            counter!("corro.sync.client.needs.capped").increment(1);
+                        }
+                    }
+
                     debug!(%actor_id, self_actor_id = %agent.actor_id(), "computed needs: {:?}, their_sync_state: {:?}", needs, their_sync_state);
 
                     let cleared_ts = their_sync_state.last_cleared_ts;
@@ -1144,7 +1307,7 @@ pub async fn parallel_sync(
                         }
                     }
 
-                    Ok::<_, SyncError>((needs, tx, read))
+                    Ok::<_, SyncError>((needs, tx, read, start.elapsed()))
                 }.await
             )
         }.instrument(info_span!("sync_client_handshake", %actor_id, %addr))
@@ -1154,11 +1317,43 @@ pub async fn parallel_sync(
 
     debug!("collected member needs and such!");
 
+    {
+        // keep track of how each peer fared, for sync candidate scoring
+        let cooldown = Duration::from_secs(agent.config().sync.failure_cooldown_secs);
+        let mut members = agent.members().write();
+        for (actor_id, _, res) in results.iter() {
+            match res {
+                Ok((_, _, _, elapsed)) => members.record_sync_success(actor_id, *elapsed),
+                Err(e) => {
+                    members.record_sync_failure(actor_id, cooldown);
+                    if let SyncError::Rejection(SyncRejectionV1::Overloaded(SyncLoadV1 {
+                        alternate: Some(alternate),
+                        ..
+                    })) = e
+                    {
+                        members.set_sync_alternate(actor_id, *alternate);
+                    }
+                }
+            }
+        }
+    }
+
+    log_sync_rejections(results.iter().map(|(actor_id, addr, res)| {
+        (
+            *actor_id,
+            *addr,
+            res.as_ref().err().and_then(|e| match e {
+                SyncError::Rejection(rejection) => Some(rejection),
+                _ => None,
+            }),
+        )
+    }));
+
     #[allow(clippy::manual_try_fold)]
     let syncers = results
         .into_iter()
         .fold(Ok(vec![]), |agg, (actor_id, addr, res)| match res {
-            Ok((needs, tx, read)) => {
+            Ok((needs, tx, read, _)) => {
                 let mut v = agg.unwrap_or_default();
                 v.push((actor_id, addr, needs, tx, read));
                 Ok(v)
@@ -1188,7 +1383,8 @@ pub async fn parallel_sync(
                     trace!(%actor_id, "no needs!");
                     return (readers, servers);
                 }
-                readers.push((actor_id, read));
+                // listed for operators until done, who may cancel it
+                let sync = agent.syncs().register(actor_id, addr);
 
                 trace!(%actor_id, "needs: {needs:?}");
 
@@ -1226,7 +1422,9 @@ pub async fn parallel_sync(
                     addr,
                     actor_needs,
                     tx,
+                    sync.cancel_token(),
                 ));
+                readers.push((actor_id, read, sync));
 
                 (readers, servers)
             },
@@ -1256,10 +1454,14 @@ pub async fn parallel_sync(
                 break;
             }
             let mut next_servers = Vec::with_capacity(servers.len());
-            'servers: for (server_actor_id, addr, mut needs, mut tx) in servers {
+            'servers: for (server_actor_id, addr, mut needs, mut tx, cancel) in servers {
                 if needs.is_empty() {
                     continue;
                 }
+                if cancel.is_cancelled() {
+                    debug!(%server_actor_id, %addr, "sync was cancelled, not sending further requests");
+                    continue;
+                }
 
                 let mut drained = 0;
 
@@ -1347,9 +1549,14 @@ pub async fn parallel_sync(
                 }
 
                 if !send_buf.is_empty() {
-                    if let Err(e) = write_buf(&mut send_buf, &mut tx).await {
-                        error!(%server_actor_id, %addr, "could not write sync requests: {e} (elapsed: {:?})", start.elapsed());
-                        continue;
+                    match write_buf(&mut send_buf, &mut tx).await {
+                        Ok(len) => {
+                            counter!("corro.sync.client.bytes", "actor_id" => server_actor_id.to_string(), "direction" => "sent").increment(len as u64);
+                        }
+                        Err(e) => {
+                            error!(%server_actor_id, %addr, "could not write sync requests: {e} (elapsed: {:?})", start.elapsed());
+                            continue;
+                        }
                     }
                 } else {
                     // give some reprieve
@@ -1364,22 +1571,50 @@ pub async fn parallel_sync(
                     continue;
                 }
 
-                next_servers.push((server_actor_id, addr, needs, tx));
+                next_servers.push((server_actor_id, addr, needs, tx, cancel));
             }
             servers = next_servers;
         }
     }.instrument(info_span!("send_sync_requests")));
 
+    // a peer going quiet mid-sync would otherwise hold up this whole round
+    let idle_timeout = Duration::from_secs(agent.config().sync.idle_timeout_secs);
+    let failure_cooldown = Duration::from_secs(agent.config().sync.failure_cooldown_secs);
+
     // now handle receiving changesets!
-    let counts = FuturesUnordered::from_iter(readers.into_iter().map(|(actor_id, mut read)| {
+    let counts = FuturesUnordered::from_iter(readers.into_iter().map(|(actor_id, read, sync)| {
         let tx_changes = agent.tx_changes().clone();
         let tx_emptyset = agent.tx_emptyset().clone();
 
+        let recv_bytes = counter!("corro.sync.client.bytes", "actor_id" => actor_id.to_string(), "direction" => "recv");
+        let mut read = futures::StreamExt::inspect(read, move |buf_res| {
+            if let Ok(buf) = buf_res {
+                recv_bytes.increment(buf.len() as u64);
+            }
+        });
+
         async move {
+            let cancel = sync.cancel_token();
             let mut count = 0;
             let mut last_empty_ts = None;
             loop {
-                match read_sync_msg(&mut read).await {
+                let res = tokio::select! {
+                    biased;
+                    _ = cancel.cancelled() => {
+                        info!(%actor_id, sync_id = sync.id(), "sync cancelled after receiving {count} changes");
+                        counter!("corro.sync.client.cancelled").increment(1);
+                        return Err(SyncRecvError::Cancelled.into());
+                    }
+                    res = timeout(idle_timeout, read_sync_msg(&mut read)) => res,
+                };
+                let Ok(res) = res else {
+                    warn!(%actor_id, "received nothing from peer for {idle_timeout:?}, aborting sync");
+                    counter!("corro.sync.client.stalled").increment(1);
+                    agent.members().write().record_sync_failure(&actor_id, failure_cooldown);
+                    return Err(SyncRecvError::Stalled(idle_timeout).into());
+                };
+
+                match res {
                     Ok(None) => {
                         break;
                     }
@@ -1392,6 +1627,7 @@ pub async fn parallel_sync(
                             let changes_len = cmp::max(change.len(), 1);
                             // tracing::Span::current().record("changes_len", changes_len);
                             count += changes_len;
+                            sync.add_changes(changes_len);
                             counter!("corro.sync.changes.recv", "actor_id" => actor_id.to_string())
                                 .increment(changes_len as u64);
 
@@ -1415,7 +1651,7 @@ pub async fn parallel_sync(
                             }
 
                             tx_changes
-                                .send((change, ChangeSource::Sync))
+                                .send((change, src))
                                 .await
                                 .map_err(|_| SyncRecvError::ChangesChannelClosed)?;
                         }
@@ -1447,10 +1683,20 @@ pub async fn parallel_sync(
     .collect::<Vec<Result<(ActorId, usize, Option<Timestamp>), SyncError>>>()
     .await;
 
+    // every sync was cancelled through the admin api, nothing was done
+    if !counts.is_empty()
+        && counts
+            .iter()
+            .all(|res| matches!(res, Err(SyncError::Recv(SyncRecvError::Cancelled))))
+    {
+        return Err(SyncRecvError::Cancelled.into());
+    }
+
     let mut members = agent.members().write();
     let ts = Timestamp::from(agent.clock().new_timestamp());
     for res in counts.iter() {
         match res {
+            Err(SyncError::Recv(SyncRecvError::Cancelled)) => {}
             Err(e) => error!("could not properly recv from peer: {e}"),
             Ok((actor_id, _, last_empty_ts)) => {
                 members.update_sync_ts(actor_id, ts);
@@ -1471,6 +1717,8 @@ pub async fn serve_sync(
     bookie: &Bookie,
     their_actor_id: ActorId,
     trace_ctx: SyncTraceContextV1,
+    their_schema_hash: Option<u64>,
+    their_schema_broadcasts: bool,
     cluster_id: ClusterId,
     mut read: FramedRead<RecvStream, LengthDelimitedCodec>,
     mut write: SendStream,
@@ -1499,6 +1747,36 @@ pub async fn serve_sync(
         return Ok(0);
     }
 
+    // replicas may lag behind, they should not be used as a source of truth
+    if agent.config().node_role.is_read_only() {
+        counter!("corro.sync.server.rejected", "reason" => "read_only_replica").increment(1);
+        encode_write_sync_msg(
+            &mut codec,
+            &mut encode_buf,
+            &mut send_buf,
+            SyncMessage::V1(SyncMessageV1::Rejection(SyncRejectionV1::ReadOnlyReplica)),
+            &mut write,
+        )
+        .instrument(info_span!("write_rejection_read_only"))
+        .await?;
+        return Ok(0);
+    }
+
+    // draining ahead of maintenance, let the client pick another peer
+    if agent.is_draining() {
+        counter!("corro.sync.server.rejected", "reason" => "draining").increment(1);
+        encode_write_sync_msg(
+            &mut codec,
+            &mut encode_buf,
+            &mut send_buf,
+            SyncMessage::V1(SyncMessageV1::Rejection(SyncRejectionV1::Draining)),
+            &mut write,
+        )
+        .instrument(info_span!("write_rejection_draining"))
+        .await?;
+        return Ok(0);
+    }
+
     // read the clock
     match read_sync_msg(&mut read)
         .instrument(info_span!("read_peer_clock"))
@@ -1527,13 +1805,14 @@ pub async fn serve_sync(
         Ok(permit) => permit,
         Err(_) => {
             // no permits!
+            counter!("corro.sync.server.rejected", "reason" => "overloaded").increment(1);
             encode_write_sync_msg(
                 &mut codec,
                 &mut encode_buf,
                 &mut send_buf,
-                SyncMessage::V1(SyncMessageV1::Rejection(
-                    SyncRejectionV1::MaxConcurrencyReached,
-                )),
+                SyncMessage::V1(SyncMessageV1::Rejection(SyncRejectionV1::Overloaded(
+                    current_sync_load(agent, their_actor_id),
+                ))),
                 &mut write,
             )
             .instrument(info_span!("write_sync_rejection"))
@@ -1542,7 +1821,14 @@ pub async fn serve_sync(
         }
     };
 
-    let sync_state = generate_sync(bookie, agent.actor_id()).await;
+    let mut sync_state = generate_sync(bookie, agent.actor_id()).await;
+    sync_state.schema_hash = local_schema_hash(agent).await;
+    sync_state.schema_broadcasts = true;
+    check_peer_schema_hash(their_actor_id, sync_state.schema_hash, their_schema_hash);
+    agent
+        .members()
+        .write()
+        .set_schema_broadcasts(&their_actor_id, their_schema_broadcasts);
 
     // first, send the current sync state
     encode_write_sync_msg(
@@ -1592,6 +1878,14 @@ pub async fn serve_sync(
         .inspect_err(|e| error!("could not process sync request: {e}")),
     );
 
+    let sent_bytes = counter!("corro.sync.server.bytes", "actor_id" => their_actor_id.to_string(), "direction" => "sent");
+    let recv_bytes = counter!("corro.sync.server.bytes", "actor_id" => their_actor_id.to_string(), "direction" => "recv");
+    let mut read = futures::StreamExt::inspect(read, move |buf_res| {
+        if let Ok(buf) = buf_res {
+            recv_bytes.increment(buf.len() as u64);
+        }
+    });
+
     let (send_res, recv_res) = tokio::join!(
         async move {
             let mut count = 0;
@@ -1625,7 +1919,7 @@ pub async fn serve_sync(
                             encode_sync_msg(&mut codec, &mut encode_buf, &mut send_buf, msg)?;
 
                             if send_buf.len() >= 16 * 1024 {
-                                write_buf(&mut send_buf, &mut write).await?;
+                                sent_bytes.increment(write_buf(&mut send_buf, &mut write).await? as u64);
                             }
                         },
                         None => {
@@ -1635,7 +1929,7 @@ pub async fn serve_sync(
 
                     _ = check_buf.tick() => {
                         if !send_buf.is_empty() {
-                            write_buf(&mut send_buf, &mut write).await?;
+                            sent_bytes.increment(write_buf(&mut send_buf, &mut write).await? as u64);
                         }
                     }
                 }
@@ -1643,7 +1937,7 @@ pub async fn serve_sync(
 
             if !stopped {
                 if !send_buf.is_empty() {
-                    write_buf(&mut send_buf, &mut write).await?;
+                    sent_bytes.increment(write_buf(&mut send_buf, &mut write).await? as u64);
                 }
 
                 if let Err(e) = write.finish().await {
@@ -1651,7 +1945,7 @@ pub async fn serve_sync(
                 }
             }
 
-            debug!(actor_id = %agent.actor_id(), "done writing sync messages (count: {count})");
+            debug!(actor_id = %agent.actor_id(), "done writing sync messages, sent {count} changes");
 
             counter!("corro.sync.changes.sent", "actor_id" => their_actor_id.to_string()).increment(count as u64);
 
@@ -1728,18 +2022,22 @@ mod tests {
     use corro_types::{
         api::{ColumnName, TableName},
         base::CrsqlDbVersion,
-        config::{Config, TlsConfig, DEFAULT_GOSSIP_CLIENT_ADDR},
+        config::{Config, SyncConfig, TlsConfig, DEFAULT_GOSSIP_CLIENT_ADDR},
         pubsub::pack_columns,
         tls::{generate_ca, generate_client_cert, generate_server_cert},
     };
     use hyper::StatusCode;
     use rand::{Rng, RngCore};
+    use std::num::{NonZeroU64, NonZeroUsize};
     use tempfile::TempDir;
     use tripwire::Tripwire;
 
     use crate::{
         agent::{process_multiple_changes, setup},
-        api::public::{api_v1_db_schema, TransactionParams},
+        api::public::{
+            admin::{api_v1_admin_sync_cancel, api_v1_admin_syncs},
+            api_v1_db_schema, SchemaParams, TransactionParams,
+        },
     };
 
     use super::*;
@@ -1821,8 +2119,12 @@ mod tests {
         )
         .await?;
 
-        let (status_code, _res) =
-            api_v1_db_schema(Extension(agent.clone()), Json(vec![TEST_SCHEMA.to_owned()])).await;
+        let (status_code, _res) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::extract::Query(SchemaParams::default()),
+            Json(vec![TEST_SCHEMA.to_owned()]),
+        )
+        .await;
 
         assert_eq!(status_code, StatusCode::OK);
 
@@ -1870,6 +2172,7 @@ mod tests {
                             last_seq: CrsqlSeq(0),
                             ts,
                         },
+                        trace_id: None,
                     },
                     ChangeSource::Sync,
                     Instant::now(),
@@ -1884,6 +2187,7 @@ mod tests {
                             last_seq: CrsqlSeq(0),
                             ts,
                         },
+                        trace_id: None,
                     },
                     ChangeSource::Sync,
                     Instant::now(),
@@ -1949,7 +2253,8 @@ mod tests {
                         seqs: CrsqlSeq(0)..=CrsqlSeq(0),
                         last_seq: CrsqlSeq(0),
                         ts,
-                    }
+                    },
+                    trace_id: None,
                 }))
             );
 
@@ -1978,7 +2283,8 @@ mod tests {
                         seqs: CrsqlSeq(0)..=CrsqlSeq(0),
                         last_seq: CrsqlSeq(0),
                         ts,
-                    }
+                    },
+                    trace_id: None,
                 }))
             );
         }
@@ -2008,6 +2314,7 @@ mod tests {
                         last_seq: CrsqlSeq(0),
                         ts,
                     },
+                    trace_id: None,
                 },
                 ChangeSource::Sync,
                 Instant::now(),
@@ -2091,7 +2398,8 @@ mod tests {
                         seqs: CrsqlSeq(0)..=CrsqlSeq(0),
                         last_seq: CrsqlSeq(0),
                         ts,
-                    }
+                    },
+                    trace_id: None,
                 }))
             );
 
@@ -2106,7 +2414,8 @@ mod tests {
                         seqs: CrsqlSeq(0)..=CrsqlSeq(0),
                         last_seq: CrsqlSeq(0),
                         ts,
-                    }
+                    },
+                    trace_id: None,
                 }))
             );
 
@@ -2151,6 +2460,7 @@ mod tests {
                         last_seq: CrsqlSeq(0),
                         ts,
                     },
+                    trace_id: None,
                 },
                 ChangeSource::Sync,
                 Instant::now(),
@@ -2218,6 +2528,7 @@ mod tests {
                         last_seq,
                         ts: ts2,
                     },
+                    trace_id: None,
                 }
             })
             .collect::<Vec<_>>();
@@ -2261,7 +2572,8 @@ mod tests {
                         seqs: CrsqlSeq(0)..=CrsqlSeq(0),
                         last_seq: CrsqlSeq(0),
                         ts,
-                    }
+                    },
+                    trace_id: None,
                 }))
             );
 
@@ -2276,7 +2588,8 @@ mod tests {
                         seqs: CrsqlSeq(0)..=CrsqlSeq(0),
                         last_seq: CrsqlSeq(0),
                         ts,
-                    }
+                    },
+                    trace_id: None,
                 }))
             );
 
@@ -2291,7 +2604,8 @@ mod tests {
                         seqs: CrsqlSeq(0)..=last_seq,
                         last_seq,
                         ts: ts2,
-                    }
+                    },
+                    trace_id: None,
                 }))
             );
 
@@ -2348,7 +2662,8 @@ mod tests {
                         seqs: CrsqlSeq(4)..=CrsqlSeq(7),
                         last_seq,
                         ts: ts2,
-                    }
+                    },
+                    trace_id: None,
                 }))
             );
 
@@ -2382,7 +2697,8 @@ mod tests {
                         seqs: CrsqlSeq(2)..=CrsqlSeq(2),
                         last_seq,
                         ts: ts2,
-                    }
+                    },
+                    trace_id: None,
                 }))
             );
 
@@ -2406,7 +2722,8 @@ mod tests {
                         seqs: CrsqlSeq(15)..=CrsqlSeq(24),
                         last_seq,
                         ts: ts2,
-                    }
+                    },
+                    trace_id: None,
                 }))
             );
         }
@@ -2414,6 +2731,279 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_handle_need_respects_chunk_size() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let ta = launch_test_agent(
+            |conf| {
+                conf.sync(SyncConfig {
+                    chunk_size: 7,
+                    ..Default::default()
+                })
+                .build()
+            },
+            tripwire.clone(),
+        )
+        .await?;
+
+        // a single version with 50 small changes, well under the size limit
+        let (status_code, body) = api_v1_transactions(
+            Extension(ta.agent.clone()),
+            axum::extract::Query(TransactionParams { timeout: None }),
+            Json(
+                (0i64..50)
+                    .map(|i| {
+                        Statement::WithParams(
+                            "INSERT INTO tests (id, text) VALUES (?,?)".into(),
+                            vec![i.into(), format!("service-{i}").into()],
+                        )
+                    })
+                    .collect(),
+            ),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+        let version = body.0.version.unwrap();
+
+        let (tx, mut rx) = mpsc::channel(100);
+        let mut conn = ta.agent.pool().read().await?;
+
+        block_in_place(|| {
+            handle_need(
+                &mut conn,
+                &ta.agent,
+                ta.agent.actor_id(),
+                SyncNeedV1::Full {
+                    versions: Version(version)..=Version(version),
+                },
+                &tx,
+                None,
+            )
+        })?;
+        drop(tx);
+
+        let mut frames = 0;
+        let mut next_seq = CrsqlSeq(0);
+        while let Some(msg) = rx.recv().await {
+            let SyncMessage::V1(SyncMessageV1::Changeset(ChangeV1 {
+                changeset: Changeset::Full { changes, seqs, .. },
+                ..
+            })) = msg
+            else {
+                panic!("unexpected sync message: {msg:?}");
+            };
+            assert!(changes.len() <= 7, "frame with {} changes", changes.len());
+            assert_eq!(*seqs.start(), next_seq);
+            next_seq = *seqs.end() + 1;
+            frames += 1;
+        }
+
+        assert_eq!(next_seq, CrsqlSeq(50));
+        assert_eq!(frames, 8);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_sync_aborts_when_peer_stalls() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let ta = launch_test_agent(
+            |conf| {
+                conf.sync(SyncConfig {
+                    idle_timeout_secs: 1,
+                    ..Default::default()
+                })
+                .build()
+            },
+            tripwire.clone(),
+        )
+        .await?;
+
+        // a peer that answers the sync handshake and then goes quiet
+        let mut gossip_config = ta.agent.config().gossip.clone();
+        gossip_config.bind_addr = "127.0.0.1:0".parse()?;
+        let server = gossip_server_endpoint(&gossip_config).await?;
+        let addr = server.local_addr()?;
+
+        let stalled_actor_id = ActorId(uuid::Uuid::new_v4());
+        let clock: Timestamp = ta.agent.clock().new_timestamp().into();
+
+        tokio::spawn(async move {
+            let conn = server.accept().await.unwrap().await.unwrap();
+            let (mut tx, rx) = conn.accept_bi().await.unwrap();
+            let mut read = FramedRead::new(rx, LengthDelimitedCodec::new());
+
+            // sync start and clock
+            read.next().await;
+            read.next().await;
+
+            let mut codec = LengthDelimitedCodec::new();
+            let mut encode_buf = BytesMut::new();
+            let mut send_buf = BytesMut::new();
+            let state = SyncStateV1 {
+                actor_id: stalled_actor_id,
+                heads: [(stalled_actor_id, Version(10))].into(),
+                ..Default::default()
+            };
+            for msg in [
+                SyncMessage::V1(SyncMessageV1::State(state)),
+                SyncMessage::V1(SyncMessageV1::Clock(clock)),
+            ] {
+                encode_write_sync_msg(&mut codec, &mut encode_buf, &mut send_buf, msg, &mut tx)
+                    .await
+                    .unwrap();
+            }
+
+            // keep the connection open without sending anything else
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            drop((tx, read, conn));
+        });
+
+        let (rtt_tx, _rtt_rx) = mpsc::channel(1024);
+        let transport = Transport::new(&ta.agent.config().gossip, rtt_tx).await?;
+
+        let start = Instant::now();
+        let count = timeout(
+            Duration::from_secs(10),
+            parallel_sync(
+                &ta.agent,
+                &transport,
+                vec![(stalled_actor_id, addr)],
+                SyncStateV1 {
+                    actor_id: ta.agent.actor_id(),
+                    ..Default::default()
+                },
+                HashMap::new(),
+            ),
+        )
+        .await??;
+
+        assert_eq!(count, 0);
+        assert!(start.elapsed() < Duration::from_secs(10));
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_cancel_in_flight_sync() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let ta = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+
+        // a peer that answers the sync handshake and then never responds to
+        // our requests, so the sync stays in flight until it's cancelled
+        let mut gossip_config = ta.agent.config().gossip.clone();
+        gossip_config.bind_addr = "127.0.0.1:0".parse()?;
+        let server = gossip_server_endpoint(&gossip_config).await?;
+        let addr = server.local_addr()?;
+
+        let peer_actor_id = ActorId(uuid::Uuid::new_v4());
+        let clock: Timestamp = ta.agent.clock().new_timestamp().into();
+
+        tokio::spawn(async move {
+            let conn = server.accept().await.unwrap().await.unwrap();
+            let (mut tx, rx) = conn.accept_bi().await.unwrap();
+            let mut read = FramedRead::new(rx, LengthDelimitedCodec::new());
+
+            // sync start and clock
+            read.next().await;
+            read.next().await;
+
+            let mut codec = LengthDelimitedCodec::new();
+            let mut encode_buf = BytesMut::new();
+            let mut send_buf = BytesMut::new();
+            let state = SyncStateV1 {
+                actor_id: peer_actor_id,
+                heads: [(peer_actor_id, Version(10))].into(),
+                ..Default::default()
+            };
+            for msg in [
+                SyncMessage::V1(SyncMessageV1::State(state)),
+                SyncMessage::V1(SyncMessageV1::Clock(clock)),
+            ] {
+                encode_write_sync_msg(&mut codec, &mut encode_buf, &mut send_buf, msg, &mut tx)
+                    .await
+                    .unwrap();
+            }
+
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            drop((tx, read, conn));
+        });
+
+        let (rtt_tx, _rtt_rx) = mpsc::channel(1024);
+        let transport = Transport::new(&ta.agent.config().gossip, rtt_tx).await?;
+
+        let sync = tokio::spawn({
+            let agent = ta.agent.clone();
+            async move {
+                let sync_state = SyncStateV1 {
+                    actor_id: agent.actor_id(),
+                    ..Default::default()
+                };
+                parallel_sync(
+                    &agent,
+                    &transport,
+                    vec![(peer_actor_id, addr)],
+                    sync_state,
+                    HashMap::new(),
+                )
+                .await
+            }
+        });
+
+        let start = Instant::now();
+        let listed = loop {
+            let listed = api_v1_admin_syncs(Extension(ta.agent.clone()))
+                .await
+                .0
+                .syncs;
+            if !listed.is_empty() {
+                break listed;
+            }
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "sync was never registered"
+            );
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].actor_id, peer_actor_id);
+        assert_eq!(listed[0].addr, addr);
+        assert_eq!(listed[0].changes, 0);
+
+        let id = listed[0].id;
+        let (status_code, body) =
+            api_v1_admin_sync_cancel(Extension(ta.agent.clone()), axum::extract::Path(id)).await;
+        assert_eq!(status_code, StatusCode::OK);
+        assert_eq!(body.0["cancelled"]["id"], id);
+
+        let res = timeout(Duration::from_secs(5), sync).await??;
+        assert!(
+            matches!(res, Err(SyncError::Recv(SyncRecvError::Cancelled))),
+            "expected the sync to be cancelled, got: {res:?}"
+        );
+
+        // a finished sync is unregistered and can't be cancelled anymore
+        assert!(api_v1_admin_syncs(Extension(ta.agent.clone()))
+            .await
+            .0
+            .syncs
+            .is_empty());
+        let (status_code, _body) =
+            api_v1_admin_sync_cancel(Extension(ta.agent.clone()), axum::extract::Path(id)).await;
+        assert_eq!(status_code, StatusCode::NOT_FOUND);
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_mutual_tls() -> eyre::Result<()> {
         let ca_cert = generate_ca()?;
@@ -2464,7 +3054,17 @@ mod tests {
             idle_timeout_secs: 30,
             plaintext: false,
             max_mtu: None,
+            mtu: 1178,
             disable_gso: false,
+            bootstrap_fanout: NonZeroUsize::new(10).unwrap(),
+            bootstrap_fallback_limit: NonZeroUsize::new(5).unwrap(),
+            cluster_size_debounce_ms: NonZeroU64::new(1000).unwrap(),
+            dns: Default::default(),
+            member_prune_after_secs: 0,
+            max_broadcast_frame_len: 10 * 1024 * 1024,
+            persist_identity: true,
+            redundant_window_secs: 10,
+            redundant_threshold: 0.5,
         };
 
         let server = gossip_server_endpoint(&gossip_config).await?;
@@ -0,0 +1,610 @@
+//! Administrative endpoints for operators, not meant to be used by
+//! regular API clients
+
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{Path, Query},
+    Extension,
+};
+use corro_types::{
+    actor::ActorId,
+    agent::{ActiveSync, Agent, Bookie, QuarantinedVersion},
+    base::Version,
+    config::{AuthzConfig, Config},
+};
+use hyper::StatusCode;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use tokio::task::block_in_place;
+use tracing::{error, info, warn};
+
+use crate::{
+    agent::{
+        announce_now, handle_resync,
+        util::{prune_cutoff, prune_member_states, StaleMember},
+        SyncClientError,
+    },
+    transport::Transport,
+};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct PauseStatus {
+    /// Whether syncing and applying remote changes is paused
+    pub paused: bool,
+}
+
+/// Stop syncing and applying remote changes without leaving the
+/// cluster. Incoming changes are buffered until resumed.
+pub async fn api_v1_admin_pause(Extension(agent): Extension<Agent>) -> axum::Json<PauseStatus> {
+    if !agent.set_paused(true) {
+        info!("pausing sync and application of remote changes");
+    }
+    axum::Json(PauseStatus { paused: true })
+}
+
+/// Resume syncing and apply any changes buffered while paused
+pub async fn api_v1_admin_resume(Extension(agent): Extension<Agent>) -> axum::Json<PauseStatus> {
+    if agent.set_paused(false) {
+        info!("resuming sync and application of remote changes");
+    }
+    axum::Json(PauseStatus { paused: false })
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct DrainStatus {
+    /// Whether this node is draining ahead of maintenance
+    pub draining: bool,
+}
+
+/// Stop serving syncs, new transactions and new subscriptions ahead of
+/// maintenance, while staying in the cluster. Existing subscriptions keep
+/// streaming and remote changes are still applied.
+pub async fn api_v1_admin_drain(Extension(agent): Extension<Agent>) -> axum::Json<DrainStatus> {
+    if !agent.set_draining(true) {
+        info!("draining, rejecting syncs, new transactions and new subscriptions");
+    }
+    axum::Json(DrainStatus { draining: true })
+}
+
+/// Stop draining and serve syncs, transactions and subscriptions again
+pub async fn api_v1_admin_undrain(Extension(agent): Extension<Agent>) -> axum::Json<DrainStatus> {
+    if agent.set_draining(false) {
+        info!("done draining, serving syncs, transactions and subscriptions again");
+    }
+    axum::Json(DrainStatus { draining: false })
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuarantineResponse {
+    pub quarantined: Vec<QuarantinedVersion>,
+}
+
+/// List the buffered versions that are no longer attempted because they
+/// failed to apply too many times
+pub async fn api_v1_admin_quarantine(
+    Extension(agent): Extension<Agent>,
+) -> axum::Json<QuarantineResponse> {
+    axum::Json(QuarantineResponse {
+        quarantined: agent.quarantine().read().list(),
+    })
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct ReleaseQuarantineParams {
+    /// Only release this actor's versions, all actors by default
+    #[serde(default)]
+    pub actor_id: Option<ActorId>,
+    /// Only release this version, requires `actor_id`
+    #[serde(default)]
+    pub version: Option<Version>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReleaseQuarantineResponse {
+    pub released: Vec<QuarantinedVersion>,
+}
+
+/// Release quarantined versions once whatever made them fail was fixed,
+/// they are attempted again right away
+pub async fn api_v1_admin_quarantine_release(
+    Extension(agent): Extension<Agent>,
+    Query(params): Query<ReleaseQuarantineParams>,
+) -> (StatusCode, axum::Json<serde_json::Value>) {
+    if params.version.is_some() && params.actor_id.is_none() {
+        return (
+            StatusCode::BAD_REQUEST,
+            axum::Json(serde_json::json!({ "error": "version requires an actor_id" })),
+        );
+    }
+
+    let released = agent
+        .quarantine()
+        .write()
+        .release(params.actor_id, params.version);
+
+    for quarantined in released.iter() {
+        info!(actor_id = %quarantined.actor_id, version = %quarantined.version, "released quarantined version, applying it again");
+        if let Err(e) = agent
+            .tx_apply()
+            .send((quarantined.actor_id, quarantined.version))
+            .await
+        {
+            error!("could not schedule released version to be applied: {e}");
+        }
+    }
+
+    (
+        StatusCode::OK,
+        axum::Json(
+            serde_json::to_value(ReleaseQuarantineResponse { released })
+                .expect("could not serialize released versions"),
+        ),
+    )
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PruneMembersParams {
+    /// Members not updated for this long are pruned, defaults to the
+    /// `gossip.member_prune_after_secs` setting
+    #[serde(default)]
+    pub older_than_secs: Option<u64>,
+    /// Only list the members that would be pruned
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PruneMembersResponse {
+    pub pruned: Vec<StaleMember>,
+    pub dry_run: bool,
+}
+
+/// Remove persisted states of members that haven't been seen in a while,
+/// so defunct nodes are not probed again on the next restart
+pub async fn api_v1_admin_members_prune(
+    Extension(agent): Extension<Agent>,
+    Query(params): Query<PruneMembersParams>,
+) -> (StatusCode, axum::Json<serde_json::Value>) {
+    let older_than = params
+        .older_than_secs
+        .unwrap_or(agent.config().gossip.member_prune_after_secs);
+    let Some(cutoff) = prune_cutoff(older_than) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            axum::Json(serde_json::json!({
+                "error": format!("cannot prune members older than {older_than}s, that's too far back")
+            })),
+        );
+    };
+
+    match prune_member_states(&agent, cutoff, params.dry_run).await {
+        Ok(pruned) => {
+            if !params.dry_run && !pruned.is_empty() {
+                info!("pruned {} stale member state(s)", pruned.len());
+            }
+            (
+                StatusCode::OK,
+                axum::Json(
+                    serde_json::to_value(PruneMembersResponse {
+                        pruned,
+                        dry_run: params.dry_run,
+                    })
+                    .expect("could not serialize pruned members"),
+                ),
+            )
+        }
+        Err(e) => {
+            error!("could not prune member states: {e}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                axum::Json(serde_json::json!({ "error": e.to_string() })),
+            )
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProbeResponse {
+    pub actor_id: ActorId,
+    /// Gossip address the member was probed at
+    pub addr: SocketAddr,
+    pub reachable: bool,
+    /// Round-trip time, including connecting if there was no open
+    /// connection to the member
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rtt_ms: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Check whether a member can be reached right now, instead of inferring
+/// it from its SWIM state
+pub async fn api_v1_admin_member_probe(
+    Extension(agent): Extension<Agent>,
+    Extension(transport): Extension<Transport>,
+    Path(actor_id): Path<ActorId>,
+) -> (StatusCode, axum::Json<serde_json::Value>) {
+    let addr = match agent.members().read().get(&actor_id) {
+        Some(state) => state.addr,
+        None => {
+            return (
+                StatusCode::NOT_FOUND,
+                axum::Json(serde_json::json!({ "error": format!("unknown member {actor_id}") })),
+            );
+        }
+    };
+
+    let res = match transport.probe(addr).await {
+        Ok(rtt) => ProbeResponse {
+            actor_id,
+            addr,
+            reachable: true,
+            rtt_ms: Some(rtt.as_secs_f64() * 1000.0),
+            error: None,
+        },
+        Err(e) => {
+            warn!(%actor_id, %addr, "could not probe member: {e}");
+            ProbeResponse {
+                actor_id,
+                addr,
+                reachable: false,
+                rtt_ms: None,
+                error: Some(e.to_string()),
+            }
+        }
+    };
+
+    (
+        StatusCode::OK,
+        axum::Json(serde_json::to_value(res).expect("could not serialize probe response")),
+    )
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct ResyncParams {
+    /// Member to pull everything from, picked at random by default
+    #[serde(default)]
+    pub actor_id: Option<ActorId>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ResyncResponse {
+    /// Member the changes were pulled from
+    pub actor_id: ActorId,
+    /// Number of changes pulled, including ones we already had
+    pub changes: usize,
+}
+
+/// Pull everything a member has, ignoring what we think we already have.
+/// This is heavyweight and meant as a last resort for a node suspected
+/// to have diverged.
+pub async fn api_v1_admin_resync(
+    Extension(agent): Extension<Agent>,
+    Extension(bookie): Extension<Bookie>,
+    Extension(transport): Extension<Transport>,
+    Query(params): Query<ResyncParams>,
+) -> (StatusCode, axum::Json<serde_json::Value>) {
+    if agent.is_paused() {
+        return (
+            StatusCode::CONFLICT,
+            axum::Json(serde_json::json!({ "error": "agent is paused" })),
+        );
+    }
+
+    match handle_resync(&agent, &bookie, &transport, params.actor_id).await {
+        Ok((actor_id, changes)) => (
+            StatusCode::OK,
+            axum::Json(
+                serde_json::to_value(ResyncResponse { actor_id, changes })
+                    .expect("could not serialize resync response"),
+            ),
+        ),
+        Err(e) => {
+            error!("could not resync: {e}");
+            let status = match e {
+                SyncClientError::NotCandidate(_) => StatusCode::BAD_REQUEST,
+                SyncClientError::NoCandidate => StatusCode::SERVICE_UNAVAILABLE,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            (
+                status,
+                axum::Json(serde_json::json!({ "error": e.to_string() })),
+            )
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnnounceResponse {
+    /// Addresses this node announced itself to
+    pub addrs: Vec<SocketAddr>,
+}
+
+/// Announce this node to bootstrap addresses and known members right away,
+/// instead of waiting for the next scheduled announce. Useful once a
+/// network or DNS issue is fixed.
+pub async fn api_v1_admin_announce(
+    Extension(agent): Extension<Agent>,
+) -> (StatusCode, axum::Json<serde_json::Value>) {
+    // peers know us by our external address, if any
+    let gossip_addr = agent.external_addr().unwrap_or(agent.gossip_addr());
+
+    match announce_now(&agent, gossip_addr).await {
+        Ok(addrs) => {
+            info!("announced to {} address(es) on demand", addrs.len());
+            (
+                StatusCode::OK,
+                axum::Json(
+                    serde_json::to_value(AnnounceResponse { addrs })
+                        .expect("could not serialize announce response"),
+                ),
+            )
+        }
+        Err(e) => {
+            error!("could not announce: {e}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                axum::Json(serde_json::json!({ "error": e.to_string() })),
+            )
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncsResponse {
+    pub syncs: Vec<ActiveSync>,
+}
+
+/// List the syncs currently pulling changes from peers
+pub async fn api_v1_admin_syncs(Extension(agent): Extension<Agent>) -> axum::Json<SyncsResponse> {
+    axum::Json(SyncsResponse {
+        syncs: agent.syncs().list(),
+    })
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CancelSyncResponse {
+    pub cancelled: ActiveSync,
+}
+
+/// Cancel a sync, for instance an expensive catch-up from a far away
+/// peer. Changes already received are kept, the rest is pulled again by
+/// later syncs.
+pub async fn api_v1_admin_sync_cancel(
+    Extension(agent): Extension<Agent>,
+    Path(id): Path<u64>,
+) -> (StatusCode, axum::Json<serde_json::Value>) {
+    match agent.syncs().cancel(id) {
+        Some(cancelled) => {
+            info!(sync_id = id, actor_id = %cancelled.actor_id, "cancelling sync on demand");
+            (
+                StatusCode::OK,
+                axum::Json(
+                    serde_json::to_value(CancelSyncResponse { cancelled })
+                        .expect("could not serialize cancelled sync"),
+                ),
+            )
+        }
+        None => (
+            StatusCode::NOT_FOUND,
+            axum::Json(serde_json::json!({ "error": format!("no running sync with id {id}") })),
+        ),
+    }
+}
+
+/// Stands in for secrets in the config endpoint's response
+const REDACTED: &str = "<redacted>";
+
+/// Report the configuration this node is running with, after defaults and
+/// environment overrides were applied. Secrets are redacted, paths to
+/// TLS keys are kept since they don't reveal the keys themselves.
+pub async fn api_v1_admin_config(
+    Extension(agent): Extension<Agent>,
+) -> (StatusCode, axum::Json<serde_json::Value>) {
+    let mut config = Config::clone(&agent.config());
+    redact_config(&mut config);
+
+    match serde_json::to_value(config) {
+        Ok(config) => (StatusCode::OK, axum::Json(config)),
+        Err(e) => {
+            error!("could not serialize config: {e}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                axum::Json(serde_json::json!({ "error": e.to_string() })),
+            )
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReloadConfigResponse {
+    /// Settings that changed in the config file but only take effect
+    /// after a restart, they keep their current value until then
+    pub requires_restart: Vec<String>,
+}
+
+/// Read the config file again and swap in the settings that are read
+/// whenever they are used, see `Config::keep_restart_only`. Changes to any
+/// other setting are reported as requiring a restart.
+pub async fn api_v1_admin_config_reload(
+    Extension(agent): Extension<Agent>,
+) -> (StatusCode, axum::Json<serde_json::Value>) {
+    let current = agent.config();
+    let Some(config_path) = current.config_path.clone() else {
+        return (
+            StatusCode::CONFLICT,
+            axum::Json(serde_json::json!({ "error": "agent was not started from a config file" })),
+        );
+    };
+
+    let mut new = match block_in_place(|| Config::load(config_path.as_str())) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("could not reload config from {config_path}: {e}");
+            return (
+                StatusCode::BAD_REQUEST,
+                axum::Json(serde_json::json!({ "error": e.to_string() })),
+            );
+        }
+    };
+    if let Err(e) = new.validate() {
+        return (
+            StatusCode::BAD_REQUEST,
+            axum::Json(serde_json::json!({ "error": e.to_string() })),
+        );
+    }
+
+    let requires_restart = current.keep_restart_only(&mut new);
+    drop(current);
+    agent.set_config(new);
+
+    if requires_restart.is_empty() {
+        info!("reloaded config from {config_path}");
+    } else {
+        warn!(
+            "reloaded config from {config_path}, changes to {} require a restart",
+            requires_restart.join(", ")
+        );
+    }
+
+    (
+        StatusCode::OK,
+        axum::Json(
+            serde_json::to_value(ReloadConfigResponse { requires_restart })
+                .expect("could not serialize config reload response"),
+        ),
+    )
+}
+
+fn redact_config(config: &mut Config) {
+    if let Some(AuthzConfig::BearerToken(token)) = config.api.authorization.as_mut() {
+        *token = REDACTED.into();
+    }
+}
+
+/// Internal tables reported by the storage endpoint. `crsql_changes` is a
+/// virtual table, its size is the size of the underlying clock tables.
+const STORAGE_TABLES: &[&str] = &[
+    "crsql_changes",
+    "__corro_buffered_changes",
+    "__corro_bookkeeping",
+    "__corro_seq_bookkeeping",
+];
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct StorageParams {
+    /// Measure the bytes used by each table with the `dbstat` virtual
+    /// table, which reads every page of the database
+    #[serde(default)]
+    pub dbstat: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TableStorage {
+    pub name: String,
+    pub rows: u64,
+    /// Bytes used by the table and its indexes, only measured with `dbstat`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bytes: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StorageResponse {
+    pub page_size: u64,
+    pub page_count: u64,
+    pub freelist_count: u64,
+    /// Size of the main database file, `page_size * page_count`
+    pub db_bytes: u64,
+    /// Size of the write-ahead log, if any
+    pub wal_bytes: Option<u64>,
+    pub tables: Vec<TableStorage>,
+}
+
+/// Report how much space the database and corrosion's internal tables use
+pub async fn api_v1_admin_storage(
+    Extension(agent): Extension<Agent>,
+    Query(params): Query<StorageParams>,
+) -> (StatusCode, axum::Json<serde_json::Value>) {
+    let conn = match agent.pool().read().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            error!("could not acquire read connection for storage stats: {e}");
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                axum::Json(serde_json::json!({ "error": e.to_string() })),
+            );
+        }
+    };
+
+    let mut wal_path = agent.config().db.path.clone();
+    wal_path.set_extension(format!("{}-wal", wal_path.extension().unwrap_or_default()));
+    let wal_bytes = std::fs::metadata(&wal_path).ok().map(|meta| meta.len());
+
+    match block_in_place(|| storage_stats(&conn, params.dbstat)) {
+        Ok(mut storage) => {
+            storage.wal_bytes = wal_bytes;
+            (
+                StatusCode::OK,
+                axum::Json(
+                    serde_json::to_value(storage).expect("could not serialize storage stats"),
+                ),
+            )
+        }
+        Err(e) => {
+            error!("could not compute storage stats: {e}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                axum::Json(serde_json::json!({ "error": e.to_string() })),
+            )
+        }
+    }
+}
+
+fn storage_stats(conn: &Connection, dbstat: bool) -> rusqlite::Result<StorageResponse> {
+    let page_size: u64 = conn.pragma_query_value(None, "page_size", |row| row.get(0))?;
+    let page_count: u64 = conn.pragma_query_value(None, "page_count", |row| row.get(0))?;
+    let freelist_count: u64 = conn.pragma_query_value(None, "freelist_count", |row| row.get(0))?;
+
+    let tables = STORAGE_TABLES
+        .iter()
+        .map(|name| {
+            let rows = conn
+                .prepare(&format!("SELECT COUNT(*) FROM {name}"))?
+                .query_row([], |row| row.get(0))?;
+
+            let bytes = if dbstat {
+                // pages of the table and its indexes
+                let tbl_name = if *name == "crsql_changes" {
+                    "%\\_\\_crsql\\_clock".to_owned()
+                } else {
+                    name.replace('_', "\\_")
+                };
+                Some(conn.query_row(
+                    "SELECT COALESCE(SUM(pgsize), 0) FROM dbstat WHERE name IN (
+                        SELECT name FROM sqlite_schema WHERE tbl_name LIKE ? ESCAPE '\\'
+                    )",
+                    [&tbl_name],
+                    |row| row.get(0),
+                )?)
+            } else {
+                None
+            };
+
+            Ok(TableStorage {
+                name: name.to_string(),
+                rows,
+                bytes,
+            })
+        })
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    Ok(StorageResponse {
+        page_size,
+        page_count,
+        freelist_count,
+        db_bytes: page_size * page_count,
+        wal_bytes: None,
+        tables,
+    })
+}
@@ -0,0 +1,388 @@
+//! Inspect the changes a version is made of, for debugging convergence,
+//! and tail the change log for external consumers
+
+use std::{collections::HashSet, ops::RangeInclusive};
+
+use axum::{extract::Path, response::IntoResponse, Extension};
+use bytes::{BufMut, BytesMut};
+use corro_types::{
+    actor::ActorId,
+    agent::Agent,
+    base::{CrsqlDbVersion, CrsqlSeq, Version},
+    broadcast::Timestamp,
+    change::{row_to_change, Change},
+};
+use hyper::StatusCode;
+use rangemap::RangeInclusiveSet;
+use rusqlite::{named_params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::mpsc::{channel, Sender},
+    task::block_in_place,
+};
+use tracing::{debug, error};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum VersionChanges {
+    /// Fully applied version, changes read from `crsql_changes`
+    Current {
+        db_version: CrsqlDbVersion,
+        last_seq: CrsqlSeq,
+        ts: Timestamp,
+        changes: Vec<Change>,
+    },
+    /// Partially received version, changes read from the buffer
+    Partial {
+        last_seq: CrsqlSeq,
+        ts: Timestamp,
+        /// Sequences received so far
+        seqs: Vec<RangeInclusive<CrsqlSeq>>,
+        /// Sequences still missing
+        gaps: Vec<RangeInclusive<CrsqlSeq>>,
+        changes: Vec<Change>,
+    },
+    /// Version without changes, or whose changes were all overwritten
+    Cleared,
+}
+
+/// Reconstruct the changeset of a single version from this node's point of view
+pub async fn api_v1_changes(
+    Extension(agent): Extension<Agent>,
+    Path((actor_id, version)): Path<(ActorId, Version)>,
+) -> (StatusCode, axum::Json<serde_json::Value>) {
+    let conn = match agent.pool().read().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                axum::Json(serde_json::json!({ "error": e.to_string() })),
+            );
+        }
+    };
+
+    match block_in_place(|| read_version_changes(&conn, actor_id, version)) {
+        Ok(Some(changes)) => (
+            StatusCode::OK,
+            axum::Json(serde_json::to_value(changes).expect("could not serialize changes")),
+        ),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            axum::Json(serde_json::json!({
+                "error": format!("version {version} of actor {actor_id} is unknown")
+            })),
+        ),
+        Err(e) => {
+            error!(%actor_id, %version, "could not read version changes: {e}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                axum::Json(serde_json::json!({ "error": e.to_string() })),
+            )
+        }
+    }
+}
+
+fn read_version_changes(
+    conn: &Connection,
+    actor_id: ActorId,
+    version: Version,
+) -> rusqlite::Result<Option<VersionChanges>> {
+    // read everything from the same snapshot
+    let tx = conn.unchecked_transaction()?;
+
+    let booked: Option<(Option<CrsqlDbVersion>, Option<CrsqlSeq>, Timestamp, bool)> = tx
+        .prepare_cached(
+            "SELECT db_version, last_seq, ts, end_version IS NOT NULL
+                FROM __corro_bookkeeping
+                WHERE actor_id = :actor_id
+                  AND start_version <= :version
+                  AND COALESCE(end_version, start_version) >= :version",
+        )?
+        .query_row(
+            named_params! {
+                ":actor_id": actor_id,
+                ":version": version,
+            },
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+        )
+        .optional()?;
+
+    match booked {
+        Some((_, _, _, true)) => return Ok(Some(VersionChanges::Cleared)),
+        Some((Some(db_version), Some(last_seq), ts, false)) => {
+            let changes = tx
+                .prepare_cached(
+                    r#"
+                    SELECT "table", pk, cid, val, col_version, db_version, seq, site_id, cl
+                        FROM crsql_changes
+                        WHERE site_id = :actor_id
+                          AND db_version = :db_version
+                        ORDER BY seq ASC
+                    "#,
+                )?
+                .query_map(
+                    named_params! {
+                        ":actor_id": actor_id,
+                        ":db_version": db_version,
+                    },
+                    row_to_change,
+                )?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+
+            return Ok(Some(VersionChanges::Current {
+                db_version,
+                last_seq,
+                ts,
+                changes,
+            }));
+        }
+        // not cleared and no db_version, look for a partial version
+        _ => {}
+    }
+
+    let seqs_rows = tx
+        .prepare_cached(
+            "SELECT start_seq, end_seq, last_seq, ts
+                FROM __corro_seq_bookkeeping
+                WHERE site_id = :actor_id AND version = :version",
+        )?
+        .query_map(
+            named_params! {
+                ":actor_id": actor_id,
+                ":version": version,
+            },
+            |row| {
+                Ok((
+                    row.get::<_, CrsqlSeq>(0)?..=row.get::<_, CrsqlSeq>(1)?,
+                    row.get::<_, CrsqlSeq>(2)?,
+                    row.get::<_, Timestamp>(3)?,
+                ))
+            },
+        )?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    let Some((_, last_seq, ts)) = seqs_rows.first().cloned() else {
+        return Ok(None);
+    };
+
+    let seqs: RangeInclusiveSet<CrsqlSeq> =
+        seqs_rows.into_iter().map(|(range, _, _)| range).collect();
+    let gaps = seqs.gaps(&(CrsqlSeq(0)..=last_seq)).collect();
+
+    let changes = tx
+        .prepare_cached(
+            r#"
+            SELECT "table", pk, cid, val, col_version, db_version, seq, site_id, cl
+                FROM __corro_buffered_changes
+                WHERE site_id = :actor_id
+                  AND version = :version
+                ORDER BY seq ASC
+            "#,
+        )?
+        .query_map(
+            named_params! {
+                ":actor_id": actor_id,
+                ":version": version,
+            },
+            row_to_change,
+        )?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    Ok(Some(VersionChanges::Partial {
+        last_seq,
+        ts,
+        seqs: seqs.into_iter().collect(),
+        gaps,
+        changes,
+    }))
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ChangesSinceParams {
+    /// Only return changes with a greater local db_version
+    #[serde(default)]
+    pub since_db_version: Option<CrsqlDbVersion>,
+    /// Comma-separated tables to return changes for, all of them if unset
+    #[serde(default)]
+    pub tables: Option<String>,
+    /// Comma-separated columns to return changes for, all of them if unset.
+    /// Row deletions and primary key only inserts are always returned.
+    #[serde(default)]
+    pub columns: Option<String>,
+}
+
+/// Tables and columns a consumer of the stream is interested in
+#[derive(Debug, Default)]
+struct ChangesFilter {
+    tables: Option<HashSet<String>>,
+    columns: Option<HashSet<String>>,
+}
+
+impl ChangesFilter {
+    fn new(params: &ChangesSinceParams) -> Self {
+        Self {
+            tables: params.tables.as_deref().and_then(parse_names),
+            columns: params.columns.as_deref().and_then(parse_names),
+        }
+    }
+
+    fn matches(&self, change: &Change) -> bool {
+        if let Some(tables) = &self.tables {
+            if !tables.contains(change.table.as_str()) {
+                return false;
+            }
+        }
+
+        match &self.columns {
+            // the sentinel stands for the whole row
+            Some(columns) => {
+                change.cid.is_crsql_sentinel() || columns.contains(change.cid.as_str())
+            }
+            None => true,
+        }
+    }
+}
+
+/// Parses a comma-separated list of names, `None` if it has none
+fn parse_names(names: &str) -> Option<HashSet<String>> {
+    let names: HashSet<String> = names
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect();
+
+    (!names.is_empty()).then_some(names)
+}
+
+/// A line of the `/v1/changes` stream
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangesEvent {
+    Change(Change),
+    /// Last line of a complete stream. Passing `db_version` as the next
+    /// `since_db_version` resumes right after the changes just received.
+    #[serde(rename = "eoc")]
+    EndOfChanges {
+        db_version: CrsqlDbVersion,
+    },
+}
+
+/// Stream `crsql_changes` rows with a local db_version greater than
+/// `since_db_version` as newline-delimited JSON, ordered by db_version
+/// and seq, so external consumers can tail the change log incrementally.
+/// Changes can be restricted to some tables and columns.
+pub async fn api_v1_changes_since(
+    Extension(agent): Extension<Agent>,
+    axum::extract::Query(params): axum::extract::Query<ChangesSinceParams>,
+) -> impl IntoResponse {
+    let conn = match agent.pool().read().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            return hyper::Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(e.to_string().into())
+                .expect("could not build changes response body");
+        }
+    };
+
+    let (mut tx, body) = hyper::Body::channel();
+    let (data_tx, mut data_rx) = channel(512);
+
+    let filter = ChangesFilter::new(&params);
+
+    tokio::spawn(async move {
+        if let Err(e) =
+            block_in_place(|| read_changes_since(&conn, params.since_db_version, &filter, &data_tx))
+        {
+            _ = data_tx.send(Err(e)).await;
+        }
+    });
+
+    tokio::spawn(async move {
+        let mut buf = BytesMut::new();
+
+        while let Some(res) = data_rx.recv().await {
+            let event = match res {
+                Ok(event) => event,
+                Err(e) => {
+                    error!("could not read changes: {e}");
+                    // without the trailing `eoc`, the consumer knows to retry
+                    tx.abort();
+                    return;
+                }
+            };
+
+            {
+                let mut writer = (&mut buf).writer();
+                if let Err(e) = serde_json::to_writer(&mut writer, &event) {
+                    error!("could not serialize change: {e}");
+                    tx.abort();
+                    return;
+                }
+            }
+
+            buf.extend_from_slice(b"\n");
+
+            if let Err(e) = tx.send_data(buf.split().freeze()).await {
+                error!("could not send data through body's channel: {e}");
+                return;
+            }
+        }
+        debug!("changes body channel done");
+    });
+
+    hyper::Response::builder()
+        .status(StatusCode::OK)
+        .body(body)
+        .expect("could not build changes response body")
+}
+
+/// Read changes from a single snapshot, ending with the snapshot's
+/// db_version. Filtered out changes are skipped, the snapshot's db_version
+/// is still the one to resume from.
+fn read_changes_since(
+    conn: &Connection,
+    since_db_version: Option<CrsqlDbVersion>,
+    filter: &ChangesFilter,
+    data_tx: &Sender<rusqlite::Result<ChangesEvent>>,
+) -> rusqlite::Result<()> {
+    let tx = conn.unchecked_transaction()?;
+
+    // a deferred transaction only acquires its snapshot on the first read
+    tx.query_row("SELECT COUNT(*) FROM sqlite_master", [], |row| {
+        row.get::<_, i64>(0)
+    })?;
+
+    let db_version: CrsqlDbVersion =
+        tx.query_row("SELECT crsql_db_version()", [], |row| row.get(0))?;
+
+    let mut prepped = tx.prepare(
+        r#"
+        SELECT "table", pk, cid, val, col_version, db_version, seq, site_id, cl
+            FROM crsql_changes
+            WHERE db_version > COALESCE(?, 0)
+            ORDER BY db_version ASC, seq ASC
+        "#,
+    )?;
+    let mut rows = prepped.query([since_db_version])?;
+
+    while let Some(row) = rows.next()? {
+        let change = row_to_change(row)?;
+        if !filter.matches(&change) {
+            continue;
+        }
+
+        if data_tx
+            .blocking_send(Ok(ChangesEvent::Change(change)))
+            .is_err()
+        {
+            debug!("changes receiver is gone, stopping");
+            return Ok(());
+        }
+    }
+
+    _ = data_tx.blocking_send(Ok(ChangesEvent::EndOfChanges { db_version }));
+
+    Ok(())
+}
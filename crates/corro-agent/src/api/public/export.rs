@@ -0,0 +1,235 @@
+//! Logical dump of the current state of all CRR tables
+
+use std::collections::HashSet;
+
+use axum::{response::IntoResponse, Extension};
+use bytes::{BufMut, BytesMut};
+use corro_types::{
+    actor::ActorId, agent::Agent, api::Statement, change::SqliteValue, pubsub::pack_columns,
+};
+use hyper::StatusCode;
+use rusqlite::{params, Connection};
+use serde::Deserialize;
+use tokio::{
+    sync::mpsc::{channel, Sender},
+    task::block_in_place,
+};
+use tracing::{debug, error};
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ExportParams {
+    /// Only export this table
+    #[serde(default)]
+    pub table: Option<String>,
+    /// Only export rows with at least one column last written by this actor
+    #[serde(default)]
+    pub actor_id: Option<ActorId>,
+}
+
+/// A table to export, with the columns to select
+struct ExportTable {
+    name: String,
+    columns: Vec<String>,
+    /// Position of each primary key column in `columns`
+    pk: Vec<usize>,
+}
+
+impl ExportTable {
+    fn select(&self) -> String {
+        format!(
+            "SELECT {} FROM \"{}\"",
+            quoted_columns(&self.columns),
+            self.name
+        )
+    }
+
+    fn insert(&self) -> String {
+        format!(
+            "INSERT OR REPLACE INTO \"{}\" ({}) VALUES ({})",
+            self.name,
+            quoted_columns(&self.columns),
+            vec!["?"; self.columns.len()].join(",")
+        )
+    }
+}
+
+fn quoted_columns(columns: &[String]) -> String {
+    columns
+        .iter()
+        .map(|col| format!("\"{col}\""))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Stream every row of every CRR table as newline-delimited JSON
+/// statements, which can be fed to `/v1/transactions/stream` as-is.
+pub async fn api_v1_export(
+    Extension(agent): Extension<Agent>,
+    axum::extract::Query(params): axum::extract::Query<ExportParams>,
+) -> impl IntoResponse {
+    let tables: Vec<ExportTable> = {
+        let schema = agent.schema().read();
+        schema
+            .tables
+            .values()
+            .filter(|table| {
+                params
+                    .table
+                    .as_ref()
+                    .map_or(true, |name| *name == table.name)
+            })
+            .map(|table| {
+                // generated columns can't be inserted into
+                let columns: Vec<String> = table
+                    .columns
+                    .values()
+                    .filter(|col| col.generated.is_none())
+                    .map(|col| col.name.clone())
+                    .collect();
+                let pk = table
+                    .pk
+                    .iter()
+                    .filter_map(|pk| columns.iter().position(|col| col == pk))
+                    .collect();
+                ExportTable {
+                    name: table.name.clone(),
+                    columns,
+                    pk,
+                }
+            })
+            .collect()
+    };
+
+    if let Some(name) = params.table {
+        if tables.is_empty() {
+            return hyper::Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(format!("table '{name}' not found").into())
+                .expect("could not build export response body");
+        }
+    }
+
+    let conn = match agent.pool().read().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            return hyper::Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(e.to_string().into())
+                .expect("could not build export response body");
+        }
+    };
+
+    let (mut tx, body) = hyper::Body::channel();
+    let (data_tx, mut data_rx) = channel(512);
+
+    tokio::spawn(async move {
+        let res = block_in_place(|| export_tables(&conn, &tables, params.actor_id, &data_tx));
+        if let Err(e) = res {
+            _ = data_tx.send(Err(e)).await;
+        }
+    });
+
+    tokio::spawn(async move {
+        let mut buf = BytesMut::new();
+
+        while let Some(res) = data_rx.recv().await {
+            let stmt = match res {
+                Ok(stmt) => stmt,
+                Err(e) => {
+                    error!("could not export tables: {e}");
+                    // don't let a partial export look complete
+                    tx.abort();
+                    return;
+                }
+            };
+
+            {
+                let mut writer = (&mut buf).writer();
+                if let Err(e) = serde_json::to_writer(&mut writer, &stmt) {
+                    error!("could not serialize exported row: {e}");
+                    tx.abort();
+                    return;
+                }
+            }
+
+            buf.extend_from_slice(b"\n");
+
+            if let Err(e) = tx.send_data(buf.split().freeze()).await {
+                error!("could not send data through body's channel: {e}");
+                return;
+            }
+        }
+        debug!("export body channel done");
+    });
+
+    hyper::Response::builder()
+        .status(StatusCode::OK)
+        .body(body)
+        .expect("could not build export response body")
+}
+
+/// Read all tables within a single read transaction so the export is
+/// internally consistent
+fn export_tables(
+    conn: &Connection,
+    tables: &[ExportTable],
+    actor_id: Option<ActorId>,
+    data_tx: &Sender<rusqlite::Result<Statement>>,
+) -> rusqlite::Result<()> {
+    let tx = conn.unchecked_transaction()?;
+
+    // a deferred transaction only acquires its snapshot on the first read
+    tx.query_row("SELECT COUNT(*) FROM sqlite_master", [], |row| {
+        row.get::<_, i64>(0)
+    })?;
+
+    for table in tables {
+        // packed primary keys of the rows the actor last wrote to
+        let actor_pks = match actor_id {
+            Some(actor_id) => Some(
+                tx.prepare(
+                    r#"SELECT DISTINCT pk FROM crsql_changes WHERE "table" = ? AND site_id = ?"#,
+                )?
+                .query_map(params![table.name, actor_id], |row| {
+                    row.get::<_, Vec<u8>>(0)
+                })?
+                .collect::<rusqlite::Result<HashSet<_>>>()?,
+            ),
+            None => None,
+        };
+
+        let insert = table.insert();
+        let mut prepped = tx.prepare(&table.select())?;
+        let mut rows = prepped.query([])?;
+
+        while let Some(row) = rows.next()? {
+            let values = (0..table.columns.len())
+                .map(|i| row.get::<_, SqliteValue>(i))
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+
+            if let Some(actor_pks) = &actor_pks {
+                let pk = table
+                    .pk
+                    .iter()
+                    .map(|i| values[*i].clone())
+                    .collect::<Vec<_>>();
+                let pk = pack_columns(&pk)
+                    .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+                if !actor_pks.contains(&pk) {
+                    continue;
+                }
+            }
+
+            let params = values.into_iter().map(Into::into).collect();
+            if data_tx
+                .blocking_send(Ok(Statement::WithParams(insert.clone(), params)))
+                .is_err()
+            {
+                debug!("export receiver is gone, stopping");
+                return Ok(());
+            }
+        }
+    }
+
+    Ok(())
+}
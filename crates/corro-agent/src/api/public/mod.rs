@@ -1,6 +1,7 @@
 use std::{
-    collections::BTreeSet,
-    ops::Deref,
+    collections::{BTreeMap, BTreeSet},
+    ops::{Deref, RangeInclusive},
+    sync::atomic::{AtomicUsize, Ordering},
     time::{Duration, Instant},
 };
 
@@ -8,22 +9,30 @@ use axum::{response::IntoResponse, Extension};
 use bytes::{BufMut, BytesMut};
 use compact_str::ToCompactString;
 use corro_types::{
-    agent::{Agent, ChangeError},
+    actor::ActorId,
+    agent::{
+        Agent, BookedVersions, Bookie, ChangeError, CountedTokioRwLockWriteGuard, WriteConn,
+        WriteRejection,
+    },
     api::{
-        ColumnName, ExecResponse, ExecResult, QueryEvent, Statement, TableStatRequest,
-        TableStatResponse,
+        ColumnName, ExecResponse, ExecResult, QueryBatchResponse, QueryBatchResult, QueryEvent,
+        SchemaChange, Statement, StatementOutcome, TableStatRequest, TableStatResponse,
+        TransactionStreamEvent,
     },
-    base::Version,
+    base::{CrsqlDbVersion, CrsqlSeq, Version},
+    broadcast::{BroadcastInput, BroadcastV1, SchemaChangeV1, Timestamp, TraceId},
     change::{insert_local_changes, InsertChangesInfo, SqliteValue},
-    schema::{apply_schema, parse_sql},
+    schema::{apply_schema, parse_sql, ConstrainedSchemaError, Schema, SchemaError},
     sqlite::SqlitePoolError,
 };
+use futures::{StreamExt, TryStreamExt};
 use hyper::StatusCode;
-use metrics::histogram;
-use rusqlite::{params_from_iter, ToSql, Transaction};
-use serde::Deserialize;
+use metrics::{counter, histogram};
+use rusqlite::{params, params_from_iter, OptionalExtension, ToSql, Transaction};
+use serde::{Deserialize, Serialize};
 use sqlite_pool::{Committable, InterruptibleTransaction};
 use spawn::spawn_counted;
+use time::OffsetDateTime;
 
 
 use tokio::{
@@ -33,10 +42,19 @@ use tokio::{
     },
     task::block_in_place,
 };
-use tracing::{debug, error, info, trace};
+use tokio_util::{
+    codec::{FramedRead, LinesCodec},
+    io::StreamReader,
+};
+use tracing::{debug, error, info, trace, warn};
 
 use corro_types::broadcast::broadcast_changes;
 
+use crate::agent::util::check_disk_full;
+
+pub mod admin;
+pub mod changes;
+pub mod export;
 pub mod pubsub;
 
 pub mod update;
@@ -59,7 +77,6 @@ where
     let mut conn = agent.pool().write_priority().await?;
     trace!("got conn");
 
-    let actor_id = agent.actor_id();
     // maybe we should do this earlier, but there can only ever be 1 write conn at a time,
     // so it probably doesn't matter too much, except for reads of internal state
     let mut book_writer = agent
@@ -69,53 +86,78 @@ where
 
     let start = Instant::now();
     block_in_place(move || {
-        let tx = conn
-            .immediate_transaction()
-            .map_err(|source| ChangeError::Rusqlite {
-                source,
-                actor_id: Some(actor_id),
-                version: None,
-            })?;
-
         let timeout = params.timeout.map(Duration::from_secs);
-        let tx = InterruptibleTransaction::new(tx, timeout, "local_changes");
+        let (ret, version) =
+            commit_broadcastable_changes(agent, &mut conn, &mut book_writer, timeout, f)?;
+
+        Ok::<_, ChangeError>((ret, version, start.elapsed()))
+    })
+}
 
-        // Execute whatever might mutate state data
-        let ret = f(&tx)?;
+/// Run `f` in a new immediate transaction on an already acquired write
+/// connection, record the resulting local changes and schedule them
+/// for broadcast once committed.
+///
+/// This must be called from a blocking context.
+fn commit_broadcastable_changes<F, T>(
+    agent: &Agent,
+    conn: &mut WriteConn,
+    book_writer: &mut CountedTokioRwLockWriteGuard<'_, BookedVersions>,
+    timeout: Option<Duration>,
+    f: F,
+) -> Result<(T, Option<Version>), ChangeError>
+where
+    F: Fn(&InterruptibleTransaction<Transaction>) -> Result<T, ChangeError>,
+{
+    let actor_id = agent.actor_id();
+    let start = Instant::now();
 
-        let insert_info = insert_local_changes(agent, &tx, &mut book_writer)?;
-        tx.commit().map_err(|source| ChangeError::Rusqlite {
+    let tx = conn
+        .immediate_transaction()
+        .map_err(|source| ChangeError::Rusqlite {
             source,
             actor_id: Some(actor_id),
-            version: insert_info.as_ref().map(|info| info.version),
+            version: None,
         })?;
 
-        let elapsed = start.elapsed();
-        histogram!("corro.agent.changes.processing.time.seconds", "source" => "local").record(start.elapsed());
+    let tx = InterruptibleTransaction::new(tx, timeout, "local_changes");
+
+    // Execute whatever might mutate state data
+    let ret = f(&tx)?;
 
-        match insert_info {
-            None => Ok((ret, None, elapsed)),
-            Some(InsertChangesInfo {
-                version,
-                db_version,
-                last_seq,
-                ts,
-                snap,
-            }) => {
-                trace!("committed tx, db_version: {db_version}, last_seq: {last_seq:?}");
+    let insert_info = insert_local_changes(agent, &tx, book_writer)?;
+    tx.commit().map_err(|source| ChangeError::Rusqlite {
+        source,
+        actor_id: Some(actor_id),
+        version: insert_info.as_ref().map(|info| info.version),
+    })?;
 
-                book_writer.commit_snapshot(snap);
+    histogram!("corro.agent.changes.processing.time.seconds", "source" => "local")
+        .record(start.elapsed());
 
-                let agent = agent.clone();
+    match insert_info {
+        None => Ok((ret, None)),
+        Some(InsertChangesInfo {
+            version,
+            db_version,
+            last_seq,
+            ts,
+            snap,
+        }) => {
+            let trace_id = TraceId::generate();
+            trace!(%trace_id, "committed tx, db_version: {db_version}, last_seq: {last_seq:?}");
 
-                spawn_counted(async move {
-                    broadcast_changes(agent, db_version, last_seq, version, ts).await
-                });
+            book_writer.commit_snapshot(snap);
 
-                Ok::<_, ChangeError>((ret, Some(version), elapsed))
-            }
+            let agent = agent.clone();
+
+            spawn_counted(async move {
+                broadcast_changes(agent, db_version, last_seq, version, ts, trace_id).await
+            });
+
+            Ok((ret, Some(version)))
         }
-    })
+    }
 }
 
 #[tracing::instrument(skip_all, err)]
@@ -126,9 +168,10 @@ fn execute_statement<T>(
 where
     T: Deref<Target = rusqlite::Connection> + Committable,
 {
-    let mut prepped = tx.prepare(stmt.query())?;
+    let mut prepped = tx.prepare(&stmt.query())?;
 
     match stmt {
+        Statement::CompareAndSet(cas) => prepped.execute(params_from_iter(cas.to_sql().1)),
         Statement::Simple(_)
         | Statement::Verbose {
             params: None,
@@ -154,6 +197,76 @@ where
     }
 }
 
+/// Make sure a compare-and-set statement targets a single row of a
+/// known CRR table, using existing columns
+fn check_compare_and_set(schema: &Schema, stmt: &Statement) -> Result<(), String> {
+    let Statement::CompareAndSet(cas) = stmt else {
+        return Ok(());
+    };
+
+    let table = schema
+        .tables
+        .get(&cas.table)
+        .ok_or_else(|| format!("table '{}' is not a CRR", cas.table))?;
+
+    if cas.set.is_empty() {
+        return Err("compare-and-set requires at least 1 column to set".into());
+    }
+
+    if cas.pk.len() != table.pk.len() || !cas.pk.keys().all(|col| table.pk.contains(col)) {
+        return Err(format!(
+            "compare-and-set on '{}' must specify the full primary key: {}",
+            cas.table,
+            table.pk.iter().cloned().collect::<Vec<_>>().join(", ")
+        ));
+    }
+
+    if let Some(col) = cas
+        .expected
+        .keys()
+        .chain(cas.set.keys())
+        .find(|col| !table.columns.contains_key(*col))
+    {
+        return Err(format!("no column '{col}' in table '{}'", cas.table));
+    }
+
+    Ok(())
+}
+
+/// Status code and error to reject a write with, if this node can't
+/// accept writes right now
+fn write_rejection(agent: &Agent) -> Option<(StatusCode, String)> {
+    let rejection = agent.write_rejection()?;
+    let status_code = match rejection {
+        WriteRejection::ReadOnly => {
+            counter!("corro.api.transactions.rejected.read_only").increment(1);
+            StatusCode::FORBIDDEN
+        }
+        WriteRejection::Draining => {
+            counter!("corro.api.transactions.rejected.draining").increment(1);
+            StatusCode::SERVICE_UNAVAILABLE
+        }
+        WriteRejection::DiskFull => {
+            counter!("corro.api.transactions.rejected.disk_full").increment(1);
+            StatusCode::INSUFFICIENT_STORAGE
+        }
+        WriteRejection::TooFewMembers(missing) => {
+            debug!("rejecting write, {missing} cluster member(s) missing");
+            counter!("corro.api.transactions.rejected.cluster_size").increment(1);
+            StatusCode::SERVICE_UNAVAILABLE
+        }
+        WriteRejection::Saturated(channel) => {
+            warn!("rejecting write, {channel} channel is above its high watermark");
+            counter!("corro.api.transactions.shed", "channel" => channel).increment(1);
+            StatusCode::SERVICE_UNAVAILABLE
+        }
+    };
+    Some((status_code, rejection.to_string()))
+}
+
+const COMPARE_AND_SET_QUERY_ERROR: &str =
+    "compare-and-set statements write, send them to /v1/transactions";
+
 #[tracing::instrument(skip_all)]
 pub async fn api_v1_transactions(
     // axum::extract::RawQuery(raw_query): axum::extract::RawQuery,
@@ -170,29 +283,81 @@ pub async fn api_v1_transactions(
                 }],
                 time: 0.0,
                 version: None,
+                schema_diff: None,
+                outcomes: vec![],
+            }),
+        );
+    }
+
+    if let Err(error) = {
+        let schema = agent.schema().read();
+        statements
+            .iter()
+            .try_for_each(|stmt| check_compare_and_set(&schema, stmt))
+    } {
+        return (
+            StatusCode::BAD_REQUEST,
+            axum::Json(ExecResponse {
+                results: vec![ExecResult::Error { error }],
+                time: 0.0,
+                version: None,
+                schema_diff: None,
+                outcomes: vec![],
+            }),
+        );
+    }
+
+    if let Some((status_code, error)) = write_rejection(&agent) {
+        return (
+            status_code,
+            axum::Json(ExecResponse {
+                results: vec![ExecResult::Error { error }],
+                time: 0.0,
+                version: None,
+                schema_diff: None,
+                outcomes: vec![],
             }),
         );
     }
 
+    let statements_len = statements.len();
+    // index of the statement that failed, if any, to report outcomes
+    let failed = AtomicUsize::new(usize::MAX);
+    let failed_ref = &failed;
+
     let res = make_broadcastable_changes(&agent, params, move |tx| {
         let mut total_rows_affected = 0;
 
         let results = statements
             .iter()
-            .map(|stmt| {
+            .enumerate()
+            .map(|(i, stmt)| {
                 let start = Instant::now();
-                let res = execute_statement(tx, stmt).map_err(|e| ChangeError::Rusqlite{
-                    source: e,
-                    actor_id: None,
-                    version: None,
+                // only inserts into rowid tables move it, whatever the table
+                let rowid_before = tx.last_insert_rowid();
+                let res = execute_statement(tx, stmt).map_err(|e| {
+                    failed_ref.store(i, Ordering::Release);
+                    ChangeError::Rusqlite {
+                        source: e,
+                        actor_id: None,
+                        version: None,
+                    }
                 });
 
                 match res {
                     Ok(rows_affected) => {
                         total_rows_affected += rows_affected;
-                        Ok(ExecResult::Execute {
-                            rows_affected,
-                            time: start.elapsed().as_secs_f64(),
+                        Ok(match stmt {
+                            Statement::CompareAndSet(_) => ExecResult::CompareAndSet {
+                                applied: rows_affected > 0,
+                                time: start.elapsed().as_secs_f64(),
+                            },
+                            _ => ExecResult::Execute {
+                                rows_affected,
+                                time: start.elapsed().as_secs_f64(),
+                                last_insert_rowid: Some(tx.last_insert_rowid())
+                                    .filter(|rowid| *rowid != rowid_before),
+                            },
                         })
                     }
                     Err(e) => Err(e),
@@ -208,29 +373,255 @@ pub async fn api_v1_transactions(
         Ok(res) => res,
         Err(e) => {
             error!("could not execute statement(s): {e}");
+            check_disk_full(&agent, &e);
+            let status_code = match e {
+                ChangeError::TooManyChanges { .. } => StatusCode::BAD_REQUEST,
+                ref e if e.is_disk_full() => StatusCode::INSUFFICIENT_STORAGE,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            // everything was rolled back, when no statement failed it's the
+            // transaction as a whole that did
+            let failed = failed.load(Ordering::Acquire);
+            let outcomes = (0..statements_len)
+                .map(|i| {
+                    if i == failed || failed == usize::MAX {
+                        StatementOutcome::error(e.to_string())
+                    } else {
+                        StatementOutcome::noop()
+                    }
+                })
+                .collect();
             return (
-                StatusCode::INTERNAL_SERVER_ERROR,
+                status_code,
                 axum::Json(ExecResponse {
                     results: vec![ExecResult::Error {
                         error: e.to_string(),
                     }],
                     time: 0.0,
                     version: None,
+                    schema_diff: None,
+                    outcomes,
                 }),
             );
         }
     };
 
+    let version: Option<u64> = version.map(Into::into);
+    let outcomes = results
+        .iter()
+        .map(|result| result.outcome(version))
+        .collect();
+
     (
         StatusCode::OK,
         axum::Json(ExecResponse {
             results,
             time: elapsed.as_secs_f64(),
-            version: version.map(Into::into),
+            version,
+            schema_diff: None,
+            outcomes,
         }),
     )
 }
 
+/// Default number of statements applied per transaction by the streaming
+/// transactions endpoint
+const DEFAULT_STREAM_BATCH_SIZE: usize = 500;
+
+/// Maximum length of a single newline-delimited statement in a stream
+const MAX_STREAM_LINE_LENGTH: usize = 1024 * 1024;
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+pub struct TransactionStreamParams {
+    #[serde(default)]
+    pub timeout: Option<u64>,
+    #[serde(default)]
+    pub batch_size: Option<usize>,
+}
+
+/// Apply newline-delimited statements from the request body in bounded
+/// batches, each batch in its own transaction. The write connection is
+/// only taken once a batch is fully read, so a slow client doesn't hold
+/// up other writes.
+///
+/// One event is streamed back per batch. The stream stops at the first
+/// failing batch, previously committed batches are left untouched.
+pub async fn api_v1_transactions_stream(
+    Extension(agent): Extension<Agent>,
+    axum::extract::Query(params): axum::extract::Query<TransactionStreamParams>,
+    body: hyper::Body,
+) -> impl IntoResponse {
+    if let Some((status_code, error)) = write_rejection(&agent) {
+        let error = ExecResult::Error { error };
+        return hyper::Response::builder()
+            .status(status_code)
+            .header(hyper::header::CONTENT_TYPE, "application/json")
+            .body(hyper::Body::from(
+                serde_json::to_vec(&error).expect("could not serialize error"),
+            ))
+            .expect("could not build transactions stream response body");
+    }
+
+    let (mut tx, res_body) = hyper::Body::channel();
+
+    let batch_size = params
+        .batch_size
+        .unwrap_or(DEFAULT_STREAM_BATCH_SIZE)
+        .max(1);
+    let timeout = params.timeout.map(Duration::from_secs);
+
+    tokio::spawn(async move {
+        let reader =
+            StreamReader::new(body.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e)));
+        let mut lines = FramedRead::new(
+            reader,
+            LinesCodec::new_with_max_length(MAX_STREAM_LINE_LENGTH),
+        );
+
+        let mut batch = 0;
+        let mut statements = Vec::with_capacity(batch_size);
+
+        loop {
+            let done = match lines.next().await {
+                Some(Ok(line)) => {
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    let parsed = serde_json::from_str::<Statement>(&line)
+                        .map_err(|e| format!("could not parse statement: {e}"))
+                        .and_then(|stmt| {
+                            check_compare_and_set(&agent.schema().read(), &stmt)?;
+                            Ok(stmt)
+                        });
+                    match parsed {
+                        Ok(stmt) => {
+                            statements.push(stmt);
+                            false
+                        }
+                        Err(error) => {
+                            _ = send_stream_event(
+                                &mut tx,
+                                &TransactionStreamEvent::Error { batch, error },
+                            )
+                            .await;
+                            return;
+                        }
+                    }
+                }
+                Some(Err(e)) => {
+                    _ = send_stream_event(
+                        &mut tx,
+                        &TransactionStreamEvent::Error {
+                            batch,
+                            error: format!("could not read statement: {e}"),
+                        },
+                    )
+                    .await;
+                    return;
+                }
+                None => true,
+            };
+
+            if statements.len() >= batch_size || (done && !statements.is_empty()) {
+                let event =
+                    apply_stream_batch(&agent, batch, timeout, std::mem::take(&mut statements))
+                        .await;
+
+                let failed = matches!(event, TransactionStreamEvent::Error { .. });
+
+                if let Err(e) = send_stream_event(&mut tx, &event).await {
+                    error!("could not send data through body's channel: {e}");
+                    return;
+                }
+
+                if failed {
+                    return;
+                }
+
+                batch += 1;
+            }
+
+            if done {
+                break;
+            }
+        }
+
+        debug!("transactions stream done after {batch} batch(es)");
+    });
+
+    hyper::Response::builder()
+        .status(StatusCode::OK)
+        .body(res_body)
+        .expect("could not build transactions stream response body")
+}
+
+async fn apply_stream_batch(
+    agent: &Agent,
+    batch: usize,
+    timeout: Option<Duration>,
+    statements: Vec<Statement>,
+) -> TransactionStreamEvent {
+    trace!("getting conn...");
+    let mut conn = match agent.pool().write_priority().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            error!("could not acquire write conn for streaming transactions: {e}");
+            return TransactionStreamEvent::Error {
+                batch,
+                error: e.to_string(),
+            };
+        }
+    };
+    trace!("got conn");
+
+    let mut book_writer = agent
+        .booked()
+        .write::<&str, _>("api_v1_transactions_stream(booked writer)", None)
+        .await;
+
+    let start = Instant::now();
+    let res = block_in_place(|| {
+        commit_broadcastable_changes(agent, &mut conn, &mut book_writer, timeout, |tx| {
+            statements.iter().try_fold(0, |total, stmt| {
+                execute_statement(tx, stmt)
+                    .map(|rows_affected| total + rows_affected)
+                    .map_err(|source| ChangeError::Rusqlite {
+                        source,
+                        actor_id: None,
+                        version: None,
+                    })
+            })
+        })
+    });
+
+    match res {
+        Ok((rows_affected, version)) => TransactionStreamEvent::Batch {
+            batch,
+            statements: statements.len(),
+            rows_affected,
+            version: version.map(Into::into),
+            time: start.elapsed().as_secs_f64(),
+        },
+        Err(e) => {
+            error!("could not execute statement(s) in batch {batch}: {e}");
+            check_disk_full(agent, &e);
+            TransactionStreamEvent::Error {
+                batch,
+                error: e.to_string(),
+            }
+        }
+    }
+}
+
+async fn send_stream_event(
+    tx: &mut hyper::body::Sender,
+    event: &TransactionStreamEvent,
+) -> Result<(), hyper::Error> {
+    let mut buf = serde_json::to_vec(event).expect("could not serialize stream event");
+    buf.push(b'\n');
+    tx.send_data(buf.into()).await
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum QueryError {
     #[error("pool connection acquisition error")]
@@ -239,10 +630,81 @@ pub enum QueryError {
     Rusqlite(#[from] rusqlite::Error),
 }
 
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+pub struct QueryParams {
+    /// Only run the query if the local database is at least at this
+    /// `crsql_db_version()`
+    #[serde(default)]
+    pub at_db_version: Option<CrsqlDbVersion>,
+    #[serde(default)]
+    pub consistency: Consistency,
+    /// Maximum staleness tolerated by `Consistency::Bounded` reads
+    #[serde(default)]
+    pub max_staleness_ms: Option<u64>,
+}
+
+/// How fresh the local data has to be to serve a query
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Consistency {
+    /// Read whatever is applied locally
+    #[default]
+    Local,
+    /// Refuse to read if this node has been missing versions for longer
+    /// than `max_staleness_ms`
+    Bounded,
+}
+
+/// How long this node has been missing versions it knows about, zero if
+/// it isn't missing any. `None` if it never caught up since starting.
+///
+/// This reads what the sync loop found last time it generated its sync
+/// state rather than generating it for every query. Falling behind is
+/// noticed as soon as received changes leave a gap, catching up only on
+/// the next sync.
+fn staleness(agent: &Agent) -> Option<Duration> {
+    if !agent.is_missing_versions() {
+        return Some(Duration::ZERO);
+    }
+    agent.caught_up_at().map(|at| at.elapsed())
+}
+
+fn check_consistency(agent: &Agent, params: &QueryParams) -> Result<(), (StatusCode, ExecResult)> {
+    if params.consistency == Consistency::Local {
+        return Ok(());
+    }
+
+    let max_staleness = match params.max_staleness_ms {
+        Some(ms) => Duration::from_millis(ms),
+        None => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                ExecResult::Error {
+                    error: "bounded consistency requires max_staleness_ms".into(),
+                },
+            ))
+        }
+    };
+
+    let error = match staleness(agent) {
+        Some(staleness) if staleness <= max_staleness => return Ok(()),
+        Some(staleness) => format!(
+            "node has been missing changes for {}ms, more than the allowed {}ms",
+            staleness.as_millis(),
+            max_staleness.as_millis()
+        ),
+        None => "node has not caught up with the cluster yet".into(),
+    };
+
+    counter!("corro.api.queries.rejected.stale").increment(1);
+    Err((StatusCode::SERVICE_UNAVAILABLE, ExecResult::Error { error }))
+}
+
 async fn build_query_rows_response(
     agent: &Agent,
     data_tx: mpsc::Sender<QueryEvent>,
     stmt: Statement,
+    params: QueryParams,
 ) -> Result<(), (StatusCode, ExecResult)> {
     let (res_tx, res_rx) = oneshot::channel();
 
@@ -262,7 +724,20 @@ async fn build_query_rows_response(
             }
         };
 
-        let prepped_res = block_in_place(|| conn.prepare(stmt.query()));
+        // hold a read transaction for the whole query so it sees the same
+        // WAL snapshot the db_version was checked against
+        let _snapshot = match params.at_db_version {
+            Some(at_db_version) => match block_in_place(|| pin_db_version(&conn, at_db_version)) {
+                Ok(tx) => Some(tx),
+                Err(e) => {
+                    _ = res_tx.send(Err(e));
+                    return;
+                }
+            },
+            None => None,
+        };
+
+        let prepped_res = block_in_place(|| conn.prepare(&stmt.query()));
 
         let mut prepped = match prepped_res {
             Ok(prepped) => prepped,
@@ -304,32 +779,7 @@ async fn build_query_rows_response(
 
             let start = Instant::now();
 
-            let query = match stmt {
-                Statement::Simple(_)
-                | Statement::Verbose {
-                    params: None,
-                    named_params: None,
-                    ..
-                } => prepped.query(()),
-                Statement::WithParams(_, params)
-                | Statement::Verbose {
-                    params: Some(params),
-                    ..
-                } => prepped.query(params_from_iter(params)),
-                Statement::WithNamedParams(_, params)
-                | Statement::Verbose {
-                    named_params: Some(params),
-                    ..
-                } => prepped.query(
-                    params
-                        .iter()
-                        .map(|(k, v)| (k.as_str(), v as &dyn ToSql))
-                        .collect::<Vec<(&str, &dyn ToSql)>>()
-                        .as_slice(),
-                ),
-            };
-
-            let mut rows = match query {
+            let mut rows = match query_statement(&mut prepped, &stmt) {
                 Ok(rows) => rows,
                 Err(e) => {
                     _ = res_tx.send(Err((
@@ -404,48 +854,221 @@ async fn build_query_rows_response(
     }
 }
 
-pub async fn api_v1_queries(
-    Extension(agent): Extension<Agent>,
-    axum::extract::Json(stmt): axum::extract::Json<Statement>,
-) -> impl IntoResponse {
-    let (mut tx, body) = hyper::Body::channel();
+/// Bind a statement's parameters and start executing it
+fn query_statement<'a>(
+    prepped: &'a mut rusqlite::Statement<'_>,
+    stmt: &Statement,
+) -> rusqlite::Result<rusqlite::Rows<'a>> {
+    match stmt {
+        Statement::CompareAndSet(cas) => prepped.query(params_from_iter(cas.to_sql().1)),
+        Statement::Simple(_)
+        | Statement::Verbose {
+            params: None,
+            named_params: None,
+            ..
+        } => prepped.query(()),
+        Statement::WithParams(_, params)
+        | Statement::Verbose {
+            params: Some(params),
+            ..
+        } => prepped.query(params_from_iter(params)),
+        Statement::WithNamedParams(_, params)
+        | Statement::Verbose {
+            named_params: Some(params),
+            ..
+        } => prepped.query(
+            params
+                .iter()
+                .map(|(k, v)| (k.as_str(), v as &dyn ToSql))
+                .collect::<Vec<(&str, &dyn ToSql)>>()
+                .as_slice(),
+        ),
+    }
+}
 
-    // TODO: timeout on data send instead of infinitely waiting for channel space.
-    let (data_tx, mut data_rx) = channel(512);
+/// Run a batch of read-only statements within a single read transaction
+/// so they all observe the same snapshot of the database
+fn query_batch(
+    conn: &rusqlite::Connection,
+    statements: &[Statement],
+    at_db_version: Option<CrsqlDbVersion>,
+) -> Result<Vec<QueryBatchResult>, (StatusCode, ExecResult)> {
+    let tx = match at_db_version {
+        Some(at_db_version) => pin_db_version(conn, at_db_version)?,
+        None => conn.unchecked_transaction().map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                ExecResult::Error {
+                    error: e.to_string(),
+                },
+            )
+        })?,
+    };
 
-    tokio::spawn(async move {
-        let mut buf = BytesMut::new();
+    let mut results = Vec::with_capacity(statements.len());
 
-        while let Some(row_res) = data_rx.recv().await {
-            {
-                let mut writer = (&mut buf).writer();
-                if let Err(e) = serde_json::to_writer(&mut writer, &row_res) {
-                    _ = tx
-                        .send_data(
-                            serde_json::to_vec(&serde_json::json!(QueryEvent::Error(
-                                e.to_compact_string()
-                            )))
-                            .expect("could not serialize error json")
-                            .into(),
-                        )
-                        .await;
-                    return;
-                }
-            }
+    for (i, stmt) in statements.iter().enumerate() {
+        let stmt_error = |status: StatusCode, e: String| {
+            (
+                status,
+                ExecResult::Error {
+                    error: format!("statement {i}: {e}"),
+                },
+            )
+        };
 
-            buf.extend_from_slice(b"\n");
+        let mut prepped = tx
+            .prepare(&stmt.query())
+            .map_err(|e| stmt_error(StatusCode::BAD_REQUEST, e.to_string()))?;
 
-            if let Err(e) = tx.send_data(buf.split().freeze()).await {
-                error!("could not send data through body's channel: {e}");
-                return;
-            }
+        if !prepped.readonly() {
+            return Err(stmt_error(
+                StatusCode::BAD_REQUEST,
+                "statement is not readonly".into(),
+            ));
         }
-        debug!("query body channel done");
+
+        let start = Instant::now();
+
+        let columns = prepped
+            .columns()
+            .into_iter()
+            .map(|col| ColumnName(col.name().to_compact_string()))
+            .collect::<Vec<_>>();
+        let col_count = columns.len();
+
+        let internal_error =
+            |e: rusqlite::Error| stmt_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+
+        let mut rows = Vec::new();
+        let mut query = query_statement(&mut prepped, stmt).map_err(internal_error)?;
+        while let Some(row) = query.next().map_err(internal_error)? {
+            rows.push(
+                (0..col_count)
+                    .map(|idx| row.get::<_, SqliteValue>(idx))
+                    .collect::<rusqlite::Result<Vec<_>>>()
+                    .map_err(internal_error)?,
+            );
+        }
+
+        results.push(QueryBatchResult::Rows {
+            columns,
+            rows,
+            time: start.elapsed().as_secs_f64(),
+        });
+    }
+
+    Ok(results)
+}
+
+/// Start a read transaction and make sure the database is at least at
+/// `at_db_version` within it
+fn pin_db_version(
+    conn: &rusqlite::Connection,
+    at_db_version: CrsqlDbVersion,
+) -> Result<Transaction<'_>, (StatusCode, ExecResult)> {
+    let internal_error = |e: rusqlite::Error| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            ExecResult::Error {
+                error: e.to_string(),
+            },
+        )
+    };
+
+    let tx = conn.unchecked_transaction().map_err(internal_error)?;
+
+    // a deferred transaction only acquires its snapshot on the first read
+    tx.query_row("SELECT COUNT(*) FROM sqlite_master", [], |row| {
+        row.get::<_, i64>(0)
+    })
+    .map_err(internal_error)?;
+
+    let db_version: CrsqlDbVersion = tx
+        .query_row("SELECT crsql_db_version()", [], |row| row.get(0))
+        .map_err(internal_error)?;
+
+    if db_version < at_db_version {
+        return Err((
+            StatusCode::CONFLICT,
+            ExecResult::Error {
+                error: format!(
+                    "database is at db_version {db_version}, behind the requested {at_db_version}"
+                ),
+            },
+        ));
+    }
+
+    Ok(tx)
+}
+
+pub async fn api_v1_queries(
+    Extension(agent): Extension<Agent>,
+    axum::extract::Query(params): axum::extract::Query<QueryParams>,
+    axum::extract::Json(stmt): axum::extract::Json<Statement>,
+) -> impl IntoResponse {
+    if matches!(stmt, Statement::CompareAndSet(_)) {
+        return hyper::Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(
+                serde_json::to_vec(&ExecResult::Error {
+                    error: COMPARE_AND_SET_QUERY_ERROR.into(),
+                })
+                .expect("could not serialize query error response")
+                .into(),
+            )
+            .expect("could not build query response body");
+    }
+
+    if let Err((status, res)) = check_consistency(&agent, &params) {
+        return hyper::Response::builder()
+            .status(status)
+            .body(
+                serde_json::to_vec(&res)
+                    .expect("could not serialize query error response")
+                    .into(),
+            )
+            .expect("could not build query response body");
+    }
+
+    let (mut tx, body) = hyper::Body::channel();
+
+    // TODO: timeout on data send instead of infinitely waiting for channel space.
+    let (data_tx, mut data_rx) = channel(512);
+
+    tokio::spawn(async move {
+        let mut buf = BytesMut::new();
+
+        while let Some(row_res) = data_rx.recv().await {
+            {
+                let mut writer = (&mut buf).writer();
+                if let Err(e) = serde_json::to_writer(&mut writer, &row_res) {
+                    _ = tx
+                        .send_data(
+                            serde_json::to_vec(&serde_json::json!(QueryEvent::Error(
+                                e.to_compact_string()
+                            )))
+                            .expect("could not serialize error json")
+                            .into(),
+                        )
+                        .await;
+                    return;
+                }
+            }
+
+            buf.extend_from_slice(b"\n");
+
+            if let Err(e) = tx.send_data(buf.split().freeze()).await {
+                error!("could not send data through body's channel: {e}");
+                return;
+            }
+        }
+        debug!("query body channel done");
     });
 
     trace!("building query rows response...");
 
-    match build_query_rows_response(&agent, data_tx, stmt).await {
+    match build_query_rows_response(&agent, data_tx, stmt, params).await {
         Ok(_) => {
             #[allow(clippy::needless_return)]
             return hyper::Response::builder()
@@ -467,7 +1090,138 @@ pub async fn api_v1_queries(
     }
 }
 
-async fn execute_schema(agent: &Agent, statements: Vec<String>) -> eyre::Result<()> {
+pub async fn api_v1_queries_batch(
+    Extension(agent): Extension<Agent>,
+    axum::extract::Query(params): axum::extract::Query<QueryParams>,
+    axum::extract::Json(statements): axum::extract::Json<Vec<Statement>>,
+) -> (StatusCode, axum::Json<QueryBatchResponse>) {
+    let error_response = |status: StatusCode, error: String| {
+        (
+            status,
+            axum::Json(QueryBatchResponse {
+                results: vec![QueryBatchResult::Error { error }],
+                time: 0.0,
+            }),
+        )
+    };
+
+    if statements.is_empty() {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "at least 1 statement is required".into(),
+        );
+    }
+
+    if let Some(i) = statements
+        .iter()
+        .position(|stmt| matches!(stmt, Statement::CompareAndSet(_)))
+    {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            format!("statement {i}: {COMPARE_AND_SET_QUERY_ERROR}"),
+        );
+    }
+
+    if let Err((status, res)) = check_consistency(&agent, &params) {
+        let error = match res {
+            ExecResult::Error { error } => error,
+            _ => status.to_string(),
+        };
+        return error_response(status, error);
+    }
+
+    let start = Instant::now();
+
+    let res = match agent.pool().read().await {
+        // the connection is returned to the pool as soon as the batch is
+        // read, before serializing the response
+        Ok(conn) => block_in_place(|| query_batch(&conn, &statements, params.at_db_version)),
+        Err(e) => {
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+        }
+    };
+
+    match res {
+        Ok(results) => (
+            StatusCode::OK,
+            axum::Json(QueryBatchResponse {
+                results,
+                time: start.elapsed().as_secs_f64(),
+            }),
+        ),
+        Err((status, res)) => {
+            let error = match res {
+                ExecResult::Error { error } => error,
+                _ => status.to_string(),
+            };
+            error_response(status, error)
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+pub struct SchemaParams {
+    /// Validate the migration and report what it would change, without
+    /// committing anything
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+type SchemaRows = BTreeMap<(String, String), String>;
+
+fn read_corro_schema(tx: &Transaction, tbl_name: &str) -> rusqlite::Result<SchemaRows> {
+    tx.prepare_cached("SELECT type, name, sql FROM __corro_schema WHERE tbl_name = ?")?
+        .query_map([tbl_name], |row| {
+            Ok(((row.get(0)?, row.get(1)?), row.get(2)?))
+        })?
+        .collect()
+}
+
+fn diff_corro_schema(
+    tbl_name: &str,
+    before: SchemaRows,
+    mut after: SchemaRows,
+) -> Vec<SchemaChange> {
+    let mut changes = vec![];
+
+    for ((kind, name), before_sql) in before {
+        let after_sql = after.remove(&(kind.clone(), name.clone()));
+        if after_sql.as_ref() != Some(&before_sql) {
+            changes.push(SchemaChange {
+                tbl_name: tbl_name.to_owned(),
+                kind,
+                name,
+                before: Some(before_sql),
+                after: after_sql,
+            });
+        }
+    }
+
+    changes.extend(
+        after
+            .into_iter()
+            .map(|((kind, name), after_sql)| SchemaChange {
+                tbl_name: tbl_name.to_owned(),
+                kind,
+                name,
+                before: None,
+                after: Some(after_sql),
+            }),
+    );
+
+    changes
+}
+
+/// Merge the given statements into the current schema
+///
+/// When `dry_run` is set, the migration is applied within a
+/// transaction that is always rolled back and the resulting changes to
+/// `__corro_schema` are returned.
+pub async fn execute_schema(
+    agent: &Agent,
+    statements: Vec<String>,
+    dry_run: bool,
+) -> eyre::Result<Vec<SchemaChange>> {
     let new_sql: String = statements.join(";");
 
     let partial_schema = parse_sql(&new_sql)?;
@@ -491,30 +1245,66 @@ async fn execute_schema(agent: &Agent, statements: Vec<String>) -> eyre::Result<
 
     new_schema.constrain()?;
 
-    block_in_place(|| {
+    let diff = block_in_place(|| {
         let tx = conn.immediate_transaction()?;
 
         apply_schema(&tx, &schema_write, &mut new_schema)?;
 
+        let mut diff = vec![];
+
         for tbl_name in partial_schema.tables.keys() {
+            let before = read_corro_schema(&tx, tbl_name)?;
+
             tx.execute("DELETE FROM __corro_schema WHERE tbl_name = ?", [tbl_name])?;
 
             let n = tx.execute("INSERT INTO __corro_schema SELECT tbl_name, type, name, sql, 'api' AS source FROM sqlite_schema WHERE tbl_name = ? AND type IN ('table', 'index') AND name IS NOT NULL AND sql IS NOT NULL", [tbl_name])?;
             info!("Updated {n} rows in __corro_schema for table {tbl_name}");
+
+            diff.extend(diff_corro_schema(
+                tbl_name,
+                before,
+                read_corro_schema(&tx, tbl_name)?,
+            ));
         }
 
-        tx.commit()?;
+        if dry_run {
+            info!("Rolling back dry run migration");
+            tx.rollback()?;
+        } else {
+            tx.commit()?;
+        }
 
-        Ok::<_, eyre::Report>(())
+        Ok::<_, eyre::Report>(diff)
     })?;
 
-    *schema_write = new_schema;
+    if !dry_run {
+        *schema_write = new_schema;
+    }
+
+    Ok(diff)
+}
 
-    Ok(())
+/// Whether the schema was rejected because of the submitted SQL itself
+fn is_invalid_schema(e: &eyre::Report) -> bool {
+    if e.is::<ConstrainedSchemaError>() {
+        return true;
+    }
+    matches!(
+        e.downcast_ref::<SchemaError>(),
+        Some(
+            SchemaError::Parse(_)
+                | SchemaError::NothingParsed
+                | SchemaError::UnsupportedCmd(_)
+                | SchemaError::IndexWithoutTable { .. }
+                | SchemaError::TemporaryTable(_)
+                | SchemaError::TableAsSelect(_)
+        )
+    )
 }
 
 pub async fn api_v1_db_schema(
     Extension(agent): Extension<Agent>,
+    axum::extract::Query(params): axum::extract::Query<SchemaParams>,
     axum::extract::Json(statements): axum::extract::Json<Vec<String>>,
 ) -> (StatusCode, axum::Json<ExecResponse>) {
     if statements.is_empty() {
@@ -526,24 +1316,52 @@ pub async fn api_v1_db_schema(
                 }],
                 time: 0.0,
                 version: None,
+                schema_diff: None,
+                outcomes: vec![],
             }),
         );
     }
 
     let start = Instant::now();
 
-    if let Err(e) = execute_schema(&agent, statements).await {
-        error!("could not merge schemas: {e}");
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            axum::Json(ExecResponse {
-                results: vec![ExecResult::Error {
-                    error: e.to_string(),
-                }],
-                time: 0.0,
-                version: None,
-            }),
-        );
+    let diff = match execute_schema(&agent, statements.clone(), params.dry_run).await {
+        Ok(diff) => diff,
+        Err(e) => {
+            error!("could not merge schemas: {e}");
+            return (
+                if is_invalid_schema(&e) {
+                    StatusCode::BAD_REQUEST
+                } else {
+                    StatusCode::INTERNAL_SERVER_ERROR
+                },
+                axum::Json(ExecResponse {
+                    results: vec![ExecResult::Error {
+                        error: e.to_string(),
+                    }],
+                    time: 0.0,
+                    version: None,
+                    schema_diff: None,
+                    outcomes: vec![],
+                }),
+            );
+        }
+    };
+
+    if !params.dry_run && !diff.is_empty() {
+        // peers need the new schema before they can apply data changes
+        // relying on it
+        if let Err(e) = agent
+            .tx_bcast()
+            .send(BroadcastInput::AddBroadcast(BroadcastV1::Schema(
+                SchemaChangeV1 {
+                    actor_id: agent.actor_id(),
+                    statements,
+                },
+            )))
+            .await
+        {
+            error!("could not send schema change for broadcast: {e}");
+        }
     }
 
     (
@@ -552,6 +1370,8 @@ pub async fn api_v1_db_schema(
             results: vec![],
             time: start.elapsed().as_secs_f64(),
             version: None,
+            schema_diff: params.dry_run.then_some(diff),
+            outcomes: vec![],
         }),
     )
 }
@@ -618,14 +1438,217 @@ pub async fn api_v1_table_stats(
     }
 }
 
+/// Freshness information about the changes known from a single actor
+#[derive(Debug, Serialize)]
+pub struct ActorStatus {
+    pub actor_id: ActorId,
+    /// Highest version known for this actor, applied or not
+    pub max_version: Option<Version>,
+    /// Highest fully applied version
+    pub last_applied_version: Option<Version>,
+    /// HLC timestamp of the last applied version
+    pub last_applied_ts: Option<Timestamp>,
+    /// Wall-clock representation of `last_applied_ts`
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub last_applied_time: Option<OffsetDateTime>,
+    /// Number of versions only partially received
+    pub pending_partials: usize,
+    /// Number of versions known to exist but not yet received
+    pub needed_versions: u64,
+}
+
+/// Report the highest applied version, its timestamp and pending
+/// versions for a given actor
+pub async fn api_v1_actor_status(
+    Extension(agent): Extension<Agent>,
+    Extension(bookie): Extension<Bookie>,
+    axum::extract::Path(actor_id): axum::extract::Path<ActorId>,
+) -> Result<axum::Json<ActorStatus>, (StatusCode, String)> {
+    let booked = bookie
+        .read("api_v1_actor_status", actor_id.as_simple())
+        .await
+        .get(&actor_id)
+        .cloned()
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                format!("unknown actor id: {actor_id}"),
+            )
+        })?;
+
+    let (max_version, pending_partials, needed_versions) = {
+        let booked = booked
+            .read("api_v1_actor_status(booked)", actor_id.as_simple())
+            .await;
+        (
+            booked.last(),
+            booked.partials.len(),
+            booked
+                .needed()
+                .iter()
+                .map(|range| range.end().0 - range.start().0 + 1)
+                .sum(),
+        )
+    };
+
+    let last_applied = async {
+        let conn = agent.pool().read().await?;
+        block_in_place(|| {
+            conn.prepare_cached("SELECT start_version, ts FROM __corro_bookkeeping WHERE actor_id = ? AND db_version IS NOT NULL ORDER BY start_version DESC LIMIT 1")?
+                .query_row([actor_id], |row| Ok((row.get::<_, Version>(0)?, row.get::<_, Option<Timestamp>>(1)?)))
+                .optional()
+                .map_err(QueryError::from)
+        })
+    }
+    .await
+    .map_err(|e| {
+        error!(%actor_id, "could not read last applied version: {e}");
+        (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+    })?;
+
+    let (last_applied_version, last_applied_ts) = match last_applied {
+        Some((version, ts)) => (Some(version), ts),
+        None => (None, None),
+    };
+
+    Ok(axum::Json(ActorStatus {
+        actor_id,
+        max_version,
+        last_applied_version,
+        last_applied_ts,
+        last_applied_time: last_applied_ts.map(|ts| ts.to_time()),
+        pending_partials,
+        needed_versions,
+    }))
+}
+
+/// Progress of a version only partially received from an actor
+#[derive(Debug, Serialize)]
+pub struct PartialStatus {
+    pub actor_id: ActorId,
+    pub version: Version,
+    /// Ranges of sequences received so far
+    pub seqs: Vec<RangeInclusive<CrsqlSeq>>,
+    /// Last sequence of the version, as produced by the actor
+    pub last_seq: CrsqlSeq,
+    /// HLC timestamp of the version
+    pub ts: Timestamp,
+    /// Ranges of sequences still missing before the version can be applied
+    pub gaps: Vec<RangeInclusive<CrsqlSeq>>,
+    /// Number of changes buffered for this version
+    pub buffered_changes: u64,
+}
+
+/// Report which sequences of a partially received version are recorded
+/// and which are still missing
+pub async fn api_v1_partial_status(
+    Extension(agent): Extension<Agent>,
+    Extension(bookie): Extension<Bookie>,
+    axum::extract::Path((actor_id, version)): axum::extract::Path<(ActorId, Version)>,
+) -> Result<axum::Json<PartialStatus>, (StatusCode, String)> {
+    let not_found = || {
+        (
+            StatusCode::NOT_FOUND,
+            format!("version {version} of actor {actor_id} is not partial"),
+        )
+    };
+
+    let booked = bookie
+        .read("api_v1_partial_status", actor_id.as_simple())
+        .await
+        .get(&actor_id)
+        .cloned()
+        .ok_or_else(not_found)?;
+
+    let partial = booked
+        .read("api_v1_partial_status(booked)", actor_id.as_simple())
+        .await
+        .get_partial(&version)
+        .cloned()
+        .ok_or_else(not_found)?;
+
+    let buffered_changes = async {
+        let conn = agent.pool().read().await?;
+        block_in_place(|| {
+            conn.prepare_cached(
+                "SELECT COUNT(*) FROM __corro_buffered_changes WHERE site_id = ? AND version = ?",
+            )?
+            .query_row(params![actor_id, version], |row| row.get(0))
+            .map_err(QueryError::from)
+        })
+    }
+    .await
+    .map_err(|e| {
+        error!(%actor_id, %version, "could not count buffered changes: {e}");
+        (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+    })?;
+
+    Ok(axum::Json(PartialStatus {
+        actor_id,
+        version,
+        seqs: partial.seqs.iter().cloned().collect(),
+        last_seq: partial.last_seq,
+        ts: partial.ts,
+        gaps: partial.seqs.gaps(&partial.full_range()).collect(),
+        buffered_changes,
+    }))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Health {
+    pub actor_id: ActorId,
+    /// Sync and application of remote changes is paused for maintenance,
+    /// the node is otherwise healthy
+    pub paused: bool,
+    /// Transactions are accepted, false on read-only replicas, until
+    /// `api.min_cluster_size_for_writes` other members have been seen,
+    /// while the disk is full and while draining
+    pub accepting_writes: bool,
+    /// The last write failed for lack of disk space, writes and applying
+    /// remote changes resume once a probe write succeeds
+    #[serde(default)]
+    pub disk_full: bool,
+    /// Draining ahead of maintenance, rejecting syncs, new transactions and
+    /// new subscriptions while existing ones finish
+    #[serde(default)]
+    pub draining: bool,
+}
+
+/// Basic liveness information about this node, responding with a
+/// `503 Service Unavailable` status while draining so load balancers stop
+/// routing to it
+pub async fn api_v1_health(Extension(agent): Extension<Agent>) -> (StatusCode, axum::Json<Health>) {
+    let draining = agent.is_draining();
+    let status = if draining {
+        StatusCode::SERVICE_UNAVAILABLE
+    } else {
+        StatusCode::OK
+    };
+    (
+        status,
+        axum::Json(Health {
+            actor_id: agent.actor_id(),
+            paused: agent.is_paused(),
+            accepting_writes: !agent.config().node_role.is_read_only()
+                && !agent.is_disk_full()
+                && !draining
+                && agent.missing_members_for_writes().is_none(),
+            disk_full: agent.is_disk_full(),
+            draining,
+        }),
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use bytes::Bytes;
     use corro_types::{
-        api::RowId,
+        actor::Actor,
+        agent::PartialVersion,
+        api::{CompareAndSet, OutcomeKind, RowId},
         base::Version,
-        broadcast::{BroadcastInput, BroadcastV1, ChangeV1, Changeset},
-        config::Config,
+        broadcast::{BroadcastInput, BroadcastV1, ChangeSource, ChangeV1, Changeset},
+        config::{Config, NodeRole},
         schema::SqliteType,
     };
     use futures::Stream;
@@ -636,7 +1659,7 @@ mod tests {
 
     use super::*;
 
-    use crate::agent::setup;
+    use crate::agent::{process_multiple_changes, setup};
 
     struct UnsyncBodyStream(std::pin::Pin<Box<UnsyncBoxBody<Bytes, axum::Error>>>);
 
@@ -673,12 +1696,25 @@ mod tests {
 
         let (status_code, _body) = api_v1_db_schema(
             Extension(agent.clone()),
+            axum::extract::Query(SchemaParams::default()),
             axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
         )
         .await;
 
         assert_eq!(status_code, StatusCode::OK);
 
+        // the new schema is broadcast first
+        let msg = rx_bcast
+            .recv()
+            .await
+            .expect("not msg received on bcast channel");
+
+        assert!(matches!(
+            msg,
+            BroadcastInput::AddBroadcast(BroadcastV1::Schema(SchemaChangeV1 { actor_id, .. }))
+                if actor_id == agent.actor_id()
+        ));
+
         let (status_code, body) = api_v1_transactions(
             Extension(agent.clone()),
             axum::extract::Query(TransactionParams { timeout: None }),
@@ -741,7 +1777,7 @@ mod tests {
     }
 
     #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
-    async fn test_api_db_query() -> eyre::Result<()> {
+    async fn test_api_db_execute_max_changes_per_version() -> eyre::Result<()> {
         _ = tracing_subscriber::fmt::try_init();
 
         let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
@@ -753,6 +1789,7 @@ mod tests {
                 .db_path(dir.path().join("corrosion.db").display().to_string())
                 .gossip_addr("127.0.0.1:0".parse()?)
                 .api_addr("127.0.0.1:0".parse()?)
+                .max_changes_per_version(std::num::NonZeroU64::new(5).unwrap())
                 .build()?,
             tripwire,
         )
@@ -760,100 +1797,271 @@ mod tests {
 
         let (status_code, _body) = api_v1_db_schema(
             Extension(agent.clone()),
+            axum::extract::Query(SchemaParams::default()),
             axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
         )
         .await;
 
         assert_eq!(status_code, StatusCode::OK);
 
+        let statements = (0..10)
+            .map(|i| {
+                Statement::WithParams(
+                    "insert into tests (id, text) values (?,?)".into(),
+                    vec![format!("service-id-{i}").into(), "service-name".into()],
+                )
+            })
+            .collect();
+
         let (status_code, body) = api_v1_transactions(
             Extension(agent.clone()),
             axum::extract::Query(TransactionParams { timeout: None }),
-            axum::Json(vec![
-                Statement::WithParams(
-                    "insert into tests (id, text) values (?,?)".into(),
-                    vec!["service-id".into(), "service-name".into()],
-                ),
-                Statement::WithParams(
-                    "insert into tests (id, text) values (?,?)".into(),
-                    vec!["service-id-2".into(), "service-name-2".into()],
-                ),
-            ]),
+            axum::Json(statements),
         )
         .await;
 
-        // println!("{body:?}");
-
-        assert_eq!(status_code, StatusCode::OK);
-
-        assert!(body.0.results.len() == 2);
+        assert_eq!(status_code, StatusCode::BAD_REQUEST);
+        assert!(matches!(
+            body.0.results.as_slice(),
+            [ExecResult::Error { error }] if error.contains("split it into smaller transactions")
+        ));
+        assert_eq!(
+            agent.booked().read::<&str, _>("test", None).await.last(),
+            None
+        );
 
-        println!("transaction body: {body:?}");
+        let conn = agent.pool().read().await?;
+        let count: i64 = conn.query_row("SELECT COUNT(*) FROM tests", [], |row| row.get(0))?;
+        assert_eq!(count, 0);
+        drop(conn);
 
-        let res = api_v1_queries(
+        let (status_code, _body) = api_v1_transactions(
             Extension(agent.clone()),
-            axum::Json(Statement::Simple("select * from tests".into())),
+            axum::extract::Query(TransactionParams { timeout: None }),
+            axum::Json(vec![Statement::WithParams(
+                "insert into tests (id, text) values (?,?)".into(),
+                vec!["service-id".into(), "service-name".into()],
+            )]),
         )
-        .await
-        .into_response();
+        .await;
 
-        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(status_code, StatusCode::OK);
+        assert_eq!(
+            agent.booked().read::<&str, _>("test", None).await.last(),
+            Some(Version(1))
+        );
 
-        let mut body = res.into_body();
+        Ok(())
+    }
 
-        let mut lines = LinesCodec::new();
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_compare_and_set() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
 
-        let mut buf = BytesMut::new();
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
 
-        buf.extend_from_slice(&body.data().await.unwrap()?);
+        let dir = tempfile::tempdir()?;
 
-        let s = lines.decode(&mut buf).unwrap().unwrap();
+        let (agent, _agent_options) = setup(
+            Config::builder()
+                .db_path(dir.path().join("corrosion.db").display().to_string())
+                .gossip_addr("127.0.0.1:0".parse()?)
+                .api_addr("127.0.0.1:0".parse()?)
+                .build()?,
+            tripwire,
+        )
+        .await?;
 
-        let cols: QueryEvent = serde_json::from_str(&s).unwrap();
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::extract::Query(SchemaParams::default()),
+            axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
 
-        assert_eq!(cols, QueryEvent::Columns(vec!["id".into(), "text".into()]));
+        let (status_code, _body) = api_v1_transactions(
+            Extension(agent.clone()),
+            axum::extract::Query(TransactionParams { timeout: None }),
+            axum::Json(vec![Statement::WithParams(
+                "insert into tests (id, text) values (?,?)".into(),
+                vec![1i64.into(), "before".into()],
+            )]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
 
-        buf.extend_from_slice(&body.data().await.unwrap()?);
+        let cas = |expected: &str| {
+            Statement::CompareAndSet(CompareAndSet {
+                table: "tests".into(),
+                pk: [("id".to_string(), 1i64.into())].into_iter().collect(),
+                expected: [("text".to_string(), expected.into())]
+                    .into_iter()
+                    .collect(),
+                set: [("text".to_string(), "after".into())].into_iter().collect(),
+            })
+        };
 
-        let s = lines.decode(&mut buf).unwrap().unwrap();
+        // stale expectation, nothing changes
+        let (status_code, body) = api_v1_transactions(
+            Extension(agent.clone()),
+            axum::extract::Query(TransactionParams { timeout: None }),
+            axum::Json(vec![cas("nope")]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+        assert!(matches!(
+            body.0.results[..],
+            [ExecResult::CompareAndSet { applied: false, .. }]
+        ));
+        assert_eq!(body.0.version, None);
 
-        let row: QueryEvent = serde_json::from_str(&s).unwrap();
+        let (status_code, body) = api_v1_transactions(
+            Extension(agent.clone()),
+            axum::extract::Query(TransactionParams { timeout: None }),
+            axum::Json(vec![cas("before")]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+        assert!(matches!(
+            body.0.results[..],
+            [ExecResult::CompareAndSet { applied: true, .. }]
+        ));
 
-        assert_eq!(
-            row,
-            QueryEvent::Row(RowId(1), vec!["service-id".into(), "service-name".into()])
-        );
+        let text: String = agent.pool().read().await?.query_row(
+            "SELECT text FROM tests WHERE id = 1",
+            [],
+            |row| row.get(0),
+        )?;
+        assert_eq!(text, "after");
+
+        // only CRR tables and full primary keys are accepted
+        for stmt in [
+            Statement::CompareAndSet(CompareAndSet {
+                table: "not_a_crr".into(),
+                pk: Default::default(),
+                expected: Default::default(),
+                set: [("name".to_string(), "x".into())].into_iter().collect(),
+            }),
+            Statement::CompareAndSet(CompareAndSet {
+                table: "tests".into(),
+                pk: Default::default(),
+                expected: Default::default(),
+                set: [("text".to_string(), "x".into())].into_iter().collect(),
+            }),
+        ] {
+            let (status_code, _body) = api_v1_transactions(
+                Extension(agent.clone()),
+                axum::extract::Query(TransactionParams { timeout: None }),
+                axum::Json(vec![stmt]),
+            )
+            .await;
+            assert_eq!(status_code, StatusCode::BAD_REQUEST);
+        }
 
-        buf.extend_from_slice(&body.data().await.unwrap()?);
+        // compare-and-set writes, queries turn it away
+        let res = api_v1_queries(
+            Extension(agent.clone()),
+            axum::extract::Query(QueryParams::default()),
+            axum::Json(cas("after")),
+        )
+        .await
+        .into_response();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
 
-        let s = lines.decode(&mut buf).unwrap().unwrap();
+        let (status_code, _body) = api_v1_queries_batch(
+            Extension(agent.clone()),
+            axum::extract::Query(QueryParams::default()),
+            axum::Json(vec![
+                Statement::Simple("SELECT * FROM tests".into()),
+                cas("after"),
+            ]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::BAD_REQUEST);
 
-        let row: QueryEvent = serde_json::from_str(&s).unwrap();
+        Ok(())
+    }
 
-        assert_eq!(
-            row,
-            QueryEvent::Row(
-                RowId(2),
-                vec!["service-id-2".into(), "service-name-2".into()]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_db_execute_backpressure() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let dir = tempfile::tempdir()?;
+
+        let (agent, mut agent_options) = setup(
+            Config::builder()
+                .db_path(dir.path().join("corrosion.db").display().to_string())
+                .gossip_addr("127.0.0.1:0".parse()?)
+                .api_addr("127.0.0.1:0".parse()?)
+                .perf(corro_types::config::PerfConfig {
+                    bcast_channel_len: 2,
+                    channel_high_watermark_pct: 50,
+                    ..Default::default()
+                })
+                .build()?,
+            tripwire,
+        )
+        .await?;
+
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::extract::Query(SchemaParams::default()),
+            axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
+        )
+        .await;
+
+        assert_eq!(status_code, StatusCode::OK);
+
+        // drop the schema broadcast, only transactions should fill it up
+        while agent_options.rx_bcast.try_recv().is_ok() {}
+
+        let insert = |i: usize| {
+            api_v1_transactions(
+                Extension(agent.clone()),
+                axum::extract::Query(TransactionParams { timeout: None }),
+                axum::Json(vec![Statement::WithParams(
+                    "insert into tests (id, text) values (?,?)".into(),
+                    vec![format!("service-id-{i}").into(), "service-name".into()],
+                )]),
             )
-        );
+        };
 
-        buf.extend_from_slice(&body.data().await.unwrap()?);
+        // nothing is draining the broadcast channel, fill it up
+        for i in 0..2 {
+            let (status_code, _body) = insert(i).await;
+            assert_eq!(status_code, StatusCode::OK);
+        }
 
-        let s = lines.decode(&mut buf).unwrap().unwrap();
+        // broadcasts are sent in the background
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while agent.tx_bcast().capacity() > 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await?;
 
-        let query_evt: QueryEvent = serde_json::from_str(&s).unwrap();
+        let (status_code, body) = insert(2).await;
+        assert_eq!(status_code, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(matches!(
+            body.0.results.as_slice(),
+            [ExecResult::Error { error }] if error.contains("broadcast queue is saturated")
+        ));
 
-        assert!(matches!(query_evt, QueryEvent::EndOfQuery { .. }));
+        while agent_options.rx_bcast.try_recv().is_ok() {}
 
-        assert!(body.data().await.is_none());
+        let (status_code, _body) = insert(2).await;
+        assert_eq!(status_code, StatusCode::OK);
 
         Ok(())
     }
 
     #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
-    async fn test_api_db_schema() -> eyre::Result<()> {
+    async fn test_api_db_execute_stream() -> eyre::Result<()> {
         _ = tracing_subscriber::fmt::try_init();
+
         let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
 
         let dir = tempfile::tempdir()?;
@@ -870,124 +2078,708 @@ mod tests {
 
         let (status_code, _body) = api_v1_db_schema(
             Extension(agent.clone()),
-            axum::Json(vec![
-                "CREATE TABLE tests (id BIGINT NOT NULL PRIMARY KEY, foo TEXT);".into(),
-            ]),
+            axum::extract::Query(SchemaParams::default()),
+            axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
         )
         .await;
 
         assert_eq!(status_code, StatusCode::OK);
 
-        // scope the schema reader in here
-        {
-            let schema = agent.schema().read();
-            let tests = schema
-                .tables
-                .get("tests")
-                .expect("no tests table in schema");
-
-            let id_col = tests.columns.get("id").unwrap();
-            assert_eq!(id_col.name, "id");
-            assert_eq!(id_col.sql_type(), (SqliteType::Integer, Some("BIGINT")));
-            assert!(!id_col.nullable);
-            assert!(id_col.primary_key);
-
-            let foo_col = tests.columns.get("foo").unwrap();
-            assert_eq!(foo_col.name, "foo");
-            assert_eq!(foo_col.sql_type(), (SqliteType::Text, Some("TEXT")));
-            assert!(foo_col.nullable);
-            assert!(!foo_col.primary_key);
+        let mut req_body = String::new();
+        for stmt in [
+            Statement::WithParams(
+                "insert into tests (id, text) values (?,?)".into(),
+                vec!["service-id".into(), "service-name".into()],
+            ),
+            Statement::WithParams(
+                "insert into tests (id, text) values (?,?)".into(),
+                vec!["service-id-2".into(), "service-name-2".into()],
+            ),
+            Statement::Simple("insert into does_not_exist (id) values (1)".into()),
+        ] {
+            req_body.push_str(&serde_json::to_string(&stmt)?);
+            req_body.push('\n');
         }
 
-        let (status_code, _body) = api_v1_db_schema(
+        let res = api_v1_transactions_stream(
             Extension(agent.clone()),
-            axum::Json(vec![
-                "CREATE TABLE tests2 (id BIGINT NOT NULL PRIMARY KEY, foo TEXT);".into(),
-                "CREATE TABLE tests (id BIGINT NOT NULL PRIMARY KEY, foo TEXT);".into(),
-            ]),
+            axum::extract::Query(TransactionStreamParams {
+                timeout: None,
+                batch_size: Some(2),
+            }),
+            hyper::Body::from(req_body),
         )
-        .await;
-
-        assert_eq!(status_code, StatusCode::OK);
-
-        {
-            let schema = agent.schema().read();
-            let tests = schema
-                .tables
-                .get("tests")
-                .expect("no tests table in schema");
+        .await
+        .into_response();
 
-            let id_col = tests.columns.get("id").unwrap();
-            assert_eq!(id_col.name, "id");
-            assert_eq!(id_col.sql_type(), (SqliteType::Integer, Some("BIGINT")));
-            assert!(!id_col.nullable);
-            assert!(id_col.primary_key);
+        assert_eq!(res.status(), StatusCode::OK);
 
-            let foo_col = tests.columns.get("foo").unwrap();
-            assert_eq!(foo_col.name, "foo");
-            assert_eq!(foo_col.sql_type(), (SqliteType::Text, Some("TEXT")));
-            assert!(foo_col.nullable);
-            assert!(!foo_col.primary_key);
+        let mut body = res.into_body();
 
-            let tests = schema
-                .tables
-                .get("tests2")
-                .expect("no tests2 table in schema");
+        let mut lines = LinesCodec::new();
 
-            let id_col = tests.columns.get("id").unwrap();
-            assert_eq!(id_col.name, "id");
-            assert_eq!(id_col.sql_type(), (SqliteType::Integer, Some("BIGINT")));
-            assert!(!id_col.nullable);
-            assert!(id_col.primary_key);
+        let mut buf = BytesMut::new();
 
-            let foo_col = tests.columns.get("foo").unwrap();
-            assert_eq!(foo_col.name, "foo");
-            assert_eq!(foo_col.sql_type(), (SqliteType::Text, Some("TEXT")));
-            assert!(foo_col.nullable);
-            assert!(!foo_col.primary_key);
+        while let Some(data) = body.data().await {
+            buf.extend_from_slice(&data?);
         }
 
-        // w/ existing table!
+        let s = lines.decode(&mut buf).unwrap().unwrap();
+        let evt: TransactionStreamEvent = serde_json::from_str(&s)?;
 
-        let create_stmt = "CREATE TABLE tests3 (id BIGINT NOT NULL PRIMARY KEY, foo TEXT, updated_at INTEGER NOT NULL DEFAULT 0);";
+        assert!(matches!(
+            evt,
+            TransactionStreamEvent::Batch {
+                batch: 0,
+                statements: 2,
+                rows_affected: 2,
+                version: Some(1),
+                ..
+            }
+        ));
 
-        {
-            // adding the table and an index
-            let conn = agent.pool().write_priority().await?;
-            conn.execute_batch(create_stmt)?;
-            conn.execute_batch("CREATE INDEX tests3_updated_at ON tests3 (updated_at);")?;
-            assert_eq!(
-                conn.execute(
-                    "INSERT INTO tests3 VALUES (123, 'some foo text', 123456789);",
-                    ()
-                )?,
-                1
-            );
-            assert_eq!(
-                conn.execute(
-                    "INSERT INTO tests3 VALUES (1234, 'some foo text 2', 1234567890);",
-                    ()
-                )?,
-                1
-            );
-        }
+        let s = lines.decode(&mut buf).unwrap().unwrap();
+        let evt: TransactionStreamEvent = serde_json::from_str(&s)?;
 
-        let (status_code, _body) = api_v1_db_schema(
-            Extension(agent.clone()),
-            axum::Json(vec![create_stmt.into()]),
-        )
-        .await;
+        assert!(matches!(evt, TransactionStreamEvent::Error { batch: 1, .. }));
 
-        assert_eq!(status_code, StatusCode::OK);
+        assert!(lines.decode(&mut buf).unwrap().is_none());
 
-        {
-            let schema = agent.schema().read();
+        // the first batch was committed regardless of the failure
+        assert_eq!(
+            agent.booked().read::<&str, _>("test", None).await.last(),
+            Some(Version(1))
+        );
 
-            // check that the tests table is still there!
-            let tests = schema
-                .tables
-                .get("tests")
-                .expect("no tests table in schema");
+        let conn = agent.pool().read().await?;
+        let count: i64 = conn.query_row("SELECT count(*) FROM tests", [], |row| row.get(0))?;
+        assert_eq!(count, 2);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_export() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let mut agents = vec![];
+        let mut dirs = vec![];
+        for _ in 0..2 {
+            let dir = tempfile::tempdir()?;
+            let (agent, agent_options) = setup(
+                Config::builder()
+                    .db_path(dir.path().join("corrosion.db").display().to_string())
+                    .gossip_addr("127.0.0.1:0".parse()?)
+                    .api_addr("127.0.0.1:0".parse()?)
+                    .build()?,
+                tripwire.clone(),
+            )
+            .await?;
+
+            let (status_code, _body) = api_v1_db_schema(
+                Extension(agent.clone()),
+                axum::extract::Query(SchemaParams::default()),
+                axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
+            )
+            .await;
+            assert_eq!(status_code, StatusCode::OK);
+
+            agents.push((agent, agent_options));
+            dirs.push(dir);
+        }
+
+        let (source, _) = &agents[0];
+        let (dest, _) = &agents[1];
+
+        let (status_code, _body) = api_v1_transactions(
+            Extension(source.clone()),
+            axum::extract::Query(TransactionParams { timeout: None }),
+            axum::Json(vec![
+                Statement::WithParams(
+                    "insert into tests (id, text) values (?,?)".into(),
+                    vec!["service-id".into(), "service-name".into()],
+                ),
+                Statement::WithParams(
+                    "insert into tests (id, text) values (?,?)".into(),
+                    vec!["service-id-2".into(), "service-name-2".into()],
+                ),
+            ]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+
+        let res = export::api_v1_export(
+            Extension(source.clone()),
+            axum::extract::Query(export::ExportParams {
+                table: Some("does_not_exist".into()),
+                ..Default::default()
+            }),
+        )
+        .await
+        .into_response();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        // only rows written by the given actor
+        for (actor_id, expected) in [(source.actor_id(), 2), (dest.actor_id(), 0)] {
+            let res = export::api_v1_export(
+                Extension(source.clone()),
+                axum::extract::Query(export::ExportParams {
+                    table: Some("tests".into()),
+                    actor_id: Some(actor_id),
+                }),
+            )
+            .await
+            .into_response();
+            assert_eq!(res.status(), StatusCode::OK);
+
+            let body = hyper::body::to_bytes(res.into_body()).await?;
+            let rows = body
+                .split(|b| *b == b'\n')
+                .filter(|line| !line.is_empty())
+                .count();
+            assert_eq!(rows, expected);
+        }
+
+        let res = export::api_v1_export(
+            Extension(source.clone()),
+            axum::extract::Query(export::ExportParams {
+                table: Some("tests".into()),
+                ..Default::default()
+            }),
+        )
+        .await
+        .into_response();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let mut body = res.into_body();
+        let mut exported = BytesMut::new();
+        while let Some(data) = body.data().await {
+            exported.extend_from_slice(&data?);
+        }
+
+        // the export can be fed to another node as-is
+        let res = api_v1_transactions_stream(
+            Extension(dest.clone()),
+            axum::extract::Query(TransactionStreamParams {
+                timeout: None,
+                batch_size: None,
+            }),
+            hyper::Body::from(exported.freeze()),
+        )
+        .await
+        .into_response();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let mut body = res.into_body();
+        while let Some(data) = body.data().await {
+            data?;
+        }
+
+        let conn = dest.pool().read().await?;
+        let rows = conn
+            .prepare("SELECT id, text FROM tests ORDER BY id")?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<Vec<(String, String)>>>()?;
+        assert_eq!(
+            rows,
+            vec![
+                ("service-id".to_string(), "service-name".to_string()),
+                ("service-id-2".to_string(), "service-name-2".to_string()),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_db_query() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let dir = tempfile::tempdir()?;
+
+        let (agent, _agent_options) = setup(
+            Config::builder()
+                .db_path(dir.path().join("corrosion.db").display().to_string())
+                .gossip_addr("127.0.0.1:0".parse()?)
+                .api_addr("127.0.0.1:0".parse()?)
+                .build()?,
+            tripwire,
+        )
+        .await?;
+
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::extract::Query(SchemaParams::default()),
+            axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
+        )
+        .await;
+
+        assert_eq!(status_code, StatusCode::OK);
+
+        let (status_code, body) = api_v1_transactions(
+            Extension(agent.clone()),
+            axum::extract::Query(TransactionParams { timeout: None }),
+            axum::Json(vec![
+                Statement::WithParams(
+                    "insert into tests (id, text) values (?,?)".into(),
+                    vec!["service-id".into(), "service-name".into()],
+                ),
+                Statement::WithParams(
+                    "insert into tests (id, text) values (?,?)".into(),
+                    vec!["service-id-2".into(), "service-name-2".into()],
+                ),
+            ]),
+        )
+        .await;
+
+        // println!("{body:?}");
+
+        assert_eq!(status_code, StatusCode::OK);
+
+        assert!(body.0.results.len() == 2);
+
+        println!("transaction body: {body:?}");
+
+        let res = api_v1_queries(
+            Extension(agent.clone()),
+            axum::extract::Query(QueryParams::default()),
+            axum::Json(Statement::Simple("select * from tests".into())),
+        )
+        .await
+        .into_response();
+
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let mut body = res.into_body();
+
+        let mut lines = LinesCodec::new();
+
+        let mut buf = BytesMut::new();
+
+        buf.extend_from_slice(&body.data().await.unwrap()?);
+
+        let s = lines.decode(&mut buf).unwrap().unwrap();
+
+        let cols: QueryEvent = serde_json::from_str(&s).unwrap();
+
+        assert_eq!(cols, QueryEvent::Columns(vec!["id".into(), "text".into()]));
+
+        buf.extend_from_slice(&body.data().await.unwrap()?);
+
+        let s = lines.decode(&mut buf).unwrap().unwrap();
+
+        let row: QueryEvent = serde_json::from_str(&s).unwrap();
+
+        assert_eq!(
+            row,
+            QueryEvent::Row(RowId(1), vec!["service-id".into(), "service-name".into()])
+        );
+
+        buf.extend_from_slice(&body.data().await.unwrap()?);
+
+        let s = lines.decode(&mut buf).unwrap().unwrap();
+
+        let row: QueryEvent = serde_json::from_str(&s).unwrap();
+
+        assert_eq!(
+            row,
+            QueryEvent::Row(
+                RowId(2),
+                vec!["service-id-2".into(), "service-name-2".into()]
+            )
+        );
+
+        buf.extend_from_slice(&body.data().await.unwrap()?);
+
+        let s = lines.decode(&mut buf).unwrap().unwrap();
+
+        let query_evt: QueryEvent = serde_json::from_str(&s).unwrap();
+
+        assert!(matches!(query_evt, QueryEvent::EndOfQuery { .. }));
+
+        assert!(body.data().await.is_none());
+
+        let db_version: CrsqlDbVersion = {
+            let conn = agent.pool().read().await?;
+            conn.query_row("SELECT crsql_db_version()", [], |row| row.get(0))?
+        };
+
+        let res = api_v1_queries(
+            Extension(agent.clone()),
+            axum::extract::Query(QueryParams {
+                at_db_version: Some(db_version + 1),
+                ..Default::default()
+            }),
+            axum::Json(Statement::Simple("select * from tests".into())),
+        )
+        .await
+        .into_response();
+
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+
+        let res = api_v1_queries(
+            Extension(agent.clone()),
+            axum::extract::Query(QueryParams {
+                at_db_version: Some(db_version),
+                ..Default::default()
+            }),
+            axum::Json(Statement::Simple("select * from tests".into())),
+        )
+        .await
+        .into_response();
+
+        assert_eq!(res.status(), StatusCode::OK);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_db_query_batch() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let dir = tempfile::tempdir()?;
+
+        let (agent, _agent_options) = setup(
+            Config::builder()
+                .db_path(dir.path().join("corrosion.db").display().to_string())
+                .gossip_addr("127.0.0.1:0".parse()?)
+                .api_addr("127.0.0.1:0".parse()?)
+                .build()?,
+            tripwire,
+        )
+        .await?;
+
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::extract::Query(SchemaParams::default()),
+            axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
+        )
+        .await;
+
+        assert_eq!(status_code, StatusCode::OK);
+
+        let (status_code, _body) = api_v1_transactions(
+            Extension(agent.clone()),
+            axum::extract::Query(TransactionParams { timeout: None }),
+            axum::Json(vec![
+                Statement::WithParams(
+                    "insert into tests (id, text) values (?,?)".into(),
+                    vec![1i64.into(), "one".into()],
+                ),
+                Statement::WithParams(
+                    "insert into tests (id, text) values (?,?)".into(),
+                    vec![2i64.into(), "two".into()],
+                ),
+            ]),
+        )
+        .await;
+
+        assert_eq!(status_code, StatusCode::OK);
+
+        let (status_code, body) = api_v1_queries_batch(
+            Extension(agent.clone()),
+            axum::extract::Query(QueryParams::default()),
+            axum::Json(vec![
+                Statement::WithParams(
+                    "select text from tests where id = ?".into(),
+                    vec![2i64.into()],
+                ),
+                Statement::Simple("select count(*) from tests".into()),
+            ]),
+        )
+        .await;
+
+        assert_eq!(status_code, StatusCode::OK);
+        match body.0.results.as_slice() {
+            [QueryBatchResult::Rows {
+                columns: cols_a,
+                rows: rows_a,
+                ..
+            }, QueryBatchResult::Rows {
+                columns: cols_b,
+                rows: rows_b,
+                ..
+            }] => {
+                assert_eq!(cols_a, &vec![ColumnName::from("text")]);
+                assert_eq!(rows_a, &vec![vec![SqliteValue::from("two")]]);
+                assert_eq!(cols_b, &vec![ColumnName::from("count(*)")]);
+                assert_eq!(rows_b, &vec![vec![SqliteValue::Integer(2)]]);
+            }
+            res => panic!("unexpected batch results: {res:?}"),
+        }
+
+        // one bad statement fails the whole batch, without partial results
+        let (status_code, body) = api_v1_queries_batch(
+            Extension(agent.clone()),
+            axum::extract::Query(QueryParams::default()),
+            axum::Json(vec![
+                Statement::Simple("select * from tests".into()),
+                Statement::Simple("delete from tests".into()),
+            ]),
+        )
+        .await;
+
+        assert_eq!(status_code, StatusCode::BAD_REQUEST);
+        assert!(matches!(
+            body.0.results.as_slice(),
+            [QueryBatchResult::Error { error }] if error.starts_with("statement 1:")
+        ));
+
+        // the rejected batch left the data untouched
+        let conn = agent.pool().read().await?;
+        let count: i64 = conn.query_row("SELECT COUNT(*) FROM tests", [], |row| row.get(0))?;
+        assert_eq!(count, 2);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_blob_roundtrip() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let dir = tempfile::tempdir()?;
+
+        let (agent, _agent_options) = setup(
+            Config::builder()
+                .db_path(dir.path().join("corrosion.db").display().to_string())
+                .gossip_addr("127.0.0.1:0".parse()?)
+                .api_addr("127.0.0.1:0".parse()?)
+                .build()?,
+            tripwire,
+        )
+        .await?;
+
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::extract::Query(SchemaParams::default()),
+            axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+
+        // not valid UTF-8, includes a NUL byte
+        let bytes: Vec<u8> = vec![0, 159, 146, 150, 255, 10];
+
+        let stmts: Vec<Statement> = serde_json::from_str(
+            r#"[["insert into testsblob (id, text) values (?, ?)", [{"$blob": "AJ+Slv8K"}, "binary"]]]"#,
+        )?;
+
+        let (status_code, _body) = api_v1_transactions(
+            Extension(agent.clone()),
+            axum::extract::Query(TransactionParams { timeout: None }),
+            axum::Json(stmts),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+
+        let stored: Vec<u8> = agent.pool().read().await?.query_row(
+            "SELECT id FROM testsblob WHERE text = 'binary'",
+            [],
+            |row| row.get(0),
+        )?;
+        assert_eq!(stored, bytes);
+
+        let res = api_v1_queries(
+            Extension(agent.clone()),
+            axum::extract::Query(QueryParams::default()),
+            axum::Json(Statement::Simple("select id from testsblob".into())),
+        )
+        .await
+        .into_response();
+
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let mut body = res.into_body();
+        let mut lines = LinesCodec::new();
+        let mut buf = BytesMut::new();
+
+        // columns
+        buf.extend_from_slice(&body.data().await.unwrap()?);
+        lines.decode(&mut buf).unwrap().unwrap();
+
+        buf.extend_from_slice(&body.data().await.unwrap()?);
+        let s = lines.decode(&mut buf).unwrap().unwrap();
+
+        let raw: serde_json::Value = serde_json::from_str(&s)?;
+        assert_eq!(
+            raw,
+            serde_json::json!({ "row": [1, [{ "$blob": "AJ+Slv8K" }]] })
+        );
+
+        let row: QueryEvent = serde_json::from_str(&s)?;
+        assert_eq!(
+            row,
+            QueryEvent::Row(RowId(1), vec![SqliteValue::Blob(bytes.into())])
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_db_schema() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let dir = tempfile::tempdir()?;
+
+        let (agent, _agent_options) = setup(
+            Config::builder()
+                .db_path(dir.path().join("corrosion.db").display().to_string())
+                .gossip_addr("127.0.0.1:0".parse()?)
+                .api_addr("127.0.0.1:0".parse()?)
+                .build()?,
+            tripwire,
+        )
+        .await?;
+
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::extract::Query(SchemaParams::default()),
+            axum::Json(vec![
+                "CREATE TABLE tests (id BIGINT NOT NULL PRIMARY KEY, foo TEXT);".into(),
+            ]),
+        )
+        .await;
+
+        assert_eq!(status_code, StatusCode::OK);
+
+        // scope the schema reader in here
+        {
+            let schema = agent.schema().read();
+            let tests = schema
+                .tables
+                .get("tests")
+                .expect("no tests table in schema");
+
+            let id_col = tests.columns.get("id").unwrap();
+            assert_eq!(id_col.name, "id");
+            assert_eq!(id_col.sql_type(), (SqliteType::Integer, Some("BIGINT")));
+            assert!(!id_col.nullable);
+            assert!(id_col.primary_key);
+
+            let foo_col = tests.columns.get("foo").unwrap();
+            assert_eq!(foo_col.name, "foo");
+            assert_eq!(foo_col.sql_type(), (SqliteType::Text, Some("TEXT")));
+            assert!(foo_col.nullable);
+            assert!(!foo_col.primary_key);
+        }
+
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::extract::Query(SchemaParams::default()),
+            axum::Json(vec![
+                "CREATE TABLE tests2 (id BIGINT NOT NULL PRIMARY KEY, foo TEXT);".into(),
+                "CREATE TABLE tests (id BIGINT NOT NULL PRIMARY KEY, foo TEXT);".into(),
+            ]),
+        )
+        .await;
+
+        assert_eq!(status_code, StatusCode::OK);
+
+        {
+            let schema = agent.schema().read();
+            let tests = schema
+                .tables
+                .get("tests")
+                .expect("no tests table in schema");
+
+            let id_col = tests.columns.get("id").unwrap();
+            assert_eq!(id_col.name, "id");
+            assert_eq!(id_col.sql_type(), (SqliteType::Integer, Some("BIGINT")));
+            assert!(!id_col.nullable);
+            assert!(id_col.primary_key);
+
+            let foo_col = tests.columns.get("foo").unwrap();
+            assert_eq!(foo_col.name, "foo");
+            assert_eq!(foo_col.sql_type(), (SqliteType::Text, Some("TEXT")));
+            assert!(foo_col.nullable);
+            assert!(!foo_col.primary_key);
+
+            let tests = schema
+                .tables
+                .get("tests2")
+                .expect("no tests2 table in schema");
+
+            let id_col = tests.columns.get("id").unwrap();
+            assert_eq!(id_col.name, "id");
+            assert_eq!(id_col.sql_type(), (SqliteType::Integer, Some("BIGINT")));
+            assert!(!id_col.nullable);
+            assert!(id_col.primary_key);
+
+            let foo_col = tests.columns.get("foo").unwrap();
+            assert_eq!(foo_col.name, "foo");
+            assert_eq!(foo_col.sql_type(), (SqliteType::Text, Some("TEXT")));
+            assert!(foo_col.nullable);
+            assert!(!foo_col.primary_key);
+        }
+
+        // w/ existing table!
+
+        let create_stmt = "CREATE TABLE tests3 (id BIGINT NOT NULL PRIMARY KEY, foo TEXT, updated_at INTEGER NOT NULL DEFAULT 0);";
+
+        {
+            // adding the table and an index
+            let conn = agent.pool().write_priority().await?;
+            conn.execute_batch(create_stmt)?;
+            conn.execute_batch("CREATE INDEX tests3_updated_at ON tests3 (updated_at);")?;
+            assert_eq!(
+                conn.execute(
+                    "INSERT INTO tests3 VALUES (123, 'some foo text', 123456789);",
+                    ()
+                )?,
+                1
+            );
+            assert_eq!(
+                conn.execute(
+                    "INSERT INTO tests3 VALUES (1234, 'some foo text 2', 1234567890);",
+                    ()
+                )?,
+                1
+            );
+        }
+
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::extract::Query(SchemaParams::default()),
+            axum::Json(vec![create_stmt.into()]),
+        )
+        .await;
+
+        assert_eq!(status_code, StatusCode::OK);
+
+        {
+            let schema = agent.schema().read();
+
+            // check that the tests table is still there!
+            let tests = schema
+                .tables
+                .get("tests")
+                .expect("no tests table in schema");
+
+            let id_col = tests.columns.get("id").unwrap();
+            assert_eq!(id_col.name, "id");
+            assert_eq!(id_col.sql_type(), (SqliteType::Integer, Some("BIGINT")));
+            assert!(!id_col.nullable);
+            assert!(id_col.primary_key);
+
+            let foo_col = tests.columns.get("foo").unwrap();
+            assert_eq!(foo_col.name, "foo");
+            assert_eq!(foo_col.sql_type(), (SqliteType::Text, Some("TEXT")));
+            assert!(foo_col.nullable);
+            assert!(!foo_col.primary_key);
+
+            let tests = schema
+                .tables
+                .get("tests3")
+                .expect("no tests3 table in schema");
 
             let id_col = tests.columns.get("id").unwrap();
             assert_eq!(id_col.name, "id");
@@ -995,52 +2787,824 @@ mod tests {
             assert!(!id_col.nullable);
             assert!(id_col.primary_key);
 
-            let foo_col = tests.columns.get("foo").unwrap();
-            assert_eq!(foo_col.name, "foo");
-            assert_eq!(foo_col.sql_type(), (SqliteType::Text, Some("TEXT")));
-            assert!(foo_col.nullable);
-            assert!(!foo_col.primary_key);
+            let foo_col = tests.columns.get("foo").unwrap();
+            assert_eq!(foo_col.name, "foo");
+            assert_eq!(foo_col.sql_type(), (SqliteType::Text, Some("TEXT")));
+            assert!(foo_col.nullable);
+            assert!(!foo_col.primary_key);
+
+            let updated_at_col = tests.columns.get("updated_at").unwrap();
+            assert_eq!(updated_at_col.name, "updated_at");
+            assert_eq!(
+                updated_at_col.sql_type(),
+                (SqliteType::Integer, Some("INTEGER"))
+            );
+            assert!(!updated_at_col.nullable);
+            assert!(!updated_at_col.primary_key);
+
+            let updated_at_idx = tests.indexes.get("tests3_updated_at").unwrap();
+            assert_eq!(updated_at_idx.name, "tests3_updated_at");
+            assert_eq!(updated_at_idx.tbl_name, "tests3");
+            assert_eq!(updated_at_idx.columns.len(), 1);
+            assert!(updated_at_idx.where_clause.is_none());
+        }
+
+        let conn = agent.pool().read().await?;
+        let count: usize =
+            conn.query_row("SELECT COUNT(*) FROM tests3__crsql_clock;", (), |row| {
+                row.get(0)
+            })?;
+        // should've created a specific qty of clock table rows, just a sanity check!
+        assert_eq!(count, 4);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_db_schema_dry_run() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let dir = tempfile::tempdir()?;
+
+        let (agent, _agent_options) = setup(
+            Config::builder()
+                .db_path(dir.path().join("corrosion.db").display().to_string())
+                .gossip_addr("127.0.0.1:0".parse()?)
+                .api_addr("127.0.0.1:0".parse()?)
+                .build()?,
+            tripwire,
+        )
+        .await?;
+
+        let create_stmt = "CREATE TABLE tests (id BIGINT NOT NULL PRIMARY KEY, foo TEXT);";
+
+        let (status_code, body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::extract::Query(SchemaParams { dry_run: true }),
+            axum::Json(vec![create_stmt.into()]),
+        )
+        .await;
+
+        assert_eq!(status_code, StatusCode::OK);
+
+        let diff = body.0.schema_diff.expect("no schema diff for dry run");
+        assert_eq!(diff.len(), 1);
+        assert_eq!(diff[0].tbl_name, "tests");
+        assert_eq!(diff[0].kind, "table");
+        assert_eq!(diff[0].before, None);
+        assert!(diff[0].after.is_some());
+
+        // nothing was committed
+        assert!(agent.schema().read().tables.get("tests").is_none());
+        let count: i64 = agent.pool().read().await?.query_row(
+            "SELECT count(*) FROM sqlite_schema WHERE tbl_name = 'tests'",
+            [],
+            |row| row.get(0),
+        )?;
+        assert_eq!(count, 0);
+
+        let (status_code, body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::extract::Query(SchemaParams::default()),
+            axum::Json(vec![create_stmt.into()]),
+        )
+        .await;
+
+        assert_eq!(status_code, StatusCode::OK);
+        assert!(body.0.schema_diff.is_none());
+        assert!(agent.schema().read().tables.get("tests").is_some());
+
+        // destructive changes are still rejected
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::extract::Query(SchemaParams { dry_run: true }),
+            axum::Json(vec![
+                "CREATE TABLE tests (id BIGINT NOT NULL PRIMARY KEY);".into()
+            ]),
+        )
+        .await;
+
+        assert_eq!(status_code, StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(agent
+            .schema()
+            .read()
+            .tables
+            .get("tests")
+            .unwrap()
+            .columns
+            .contains_key("foo"));
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_min_cluster_size_for_writes() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let dir = tempfile::tempdir()?;
+
+        let (agent, _agent_options) = setup(
+            Config::builder()
+                .db_path(dir.path().join("corrosion.db").display().to_string())
+                .gossip_addr("127.0.0.1:0".parse()?)
+                .api_addr("127.0.0.1:0".parse()?)
+                .min_cluster_size_for_writes(1)
+                .build()?,
+            tripwire,
+        )
+        .await?;
+
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::extract::Query(SchemaParams::default()),
+            axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+
+        let statements = || {
+            vec![Statement::WithParams(
+                "insert into tests (id, text) values (?,?)".into(),
+                vec!["service-id".into(), "service-name".into()],
+            )]
+        };
+
+        // alone, writes are refused
+        let (status_code, body) = api_v1_transactions(
+            Extension(agent.clone()),
+            axum::extract::Query(TransactionParams { timeout: None }),
+            axum::Json(statements()),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(matches!(body.0.results[0], ExecResult::Error { .. }));
+        let (status_code, health) = api_v1_health(Extension(agent.clone())).await;
+        assert_eq!(status_code, StatusCode::OK);
+        assert!(!health.0.accepting_writes);
+
+        agent.members().write().add_member(&Actor::new(
+            ActorId(uuid::Uuid::new_v4()),
+            "127.0.0.1:1".parse()?,
+            Default::default(),
+            agent.cluster_id(),
+        ));
+
+        let (status_code, _body) = api_v1_transactions(
+            Extension(agent.clone()),
+            axum::extract::Query(TransactionParams { timeout: None }),
+            axum::Json(statements()),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+        let (status_code, health) = api_v1_health(Extension(agent.clone())).await;
+        assert_eq!(status_code, StatusCode::OK);
+        assert!(health.0.accepting_writes);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_read_only_replica_rejects_writes() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let dir = tempfile::tempdir()?;
+
+        let (agent, _agent_options) = setup(
+            Config::builder()
+                .db_path(dir.path().join("corrosion.db").display().to_string())
+                .gossip_addr("127.0.0.1:0".parse()?)
+                .api_addr("127.0.0.1:0".parse()?)
+                .node_role(NodeRole::ReadOnlyReplica)
+                .build()?,
+            tripwire,
+        )
+        .await?;
+
+        // schema changes are still applied on replicas
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::extract::Query(SchemaParams::default()),
+            axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+
+        let (status_code, body) = api_v1_transactions(
+            Extension(agent.clone()),
+            axum::extract::Query(TransactionParams { timeout: None }),
+            axum::Json(vec![Statement::WithParams(
+                "insert into tests (id, text) values (?,?)".into(),
+                vec!["service-id".into(), "service-name".into()],
+            )]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::FORBIDDEN);
+        assert!(matches!(body.0.results[0], ExecResult::Error { .. }));
+        assert_eq!(body.0.version, None);
+
+        let (status_code, health) = api_v1_health(Extension(agent.clone())).await;
+        assert_eq!(status_code, StatusCode::OK);
+        assert!(!health.0.accepting_writes);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_transaction_outcomes() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let dir = tempfile::tempdir()?;
+
+        let (agent, _agent_options) = setup(
+            Config::builder()
+                .db_path(dir.path().join("corrosion.db").display().to_string())
+                .gossip_addr("127.0.0.1:0".parse()?)
+                .api_addr("127.0.0.1:0".parse()?)
+                .build()?,
+            tripwire,
+        )
+        .await?;
+
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::extract::Query(SchemaParams::default()),
+            axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+
+        let (status_code, body) = api_v1_transactions(
+            Extension(agent.clone()),
+            axum::extract::Query(TransactionParams { timeout: None }),
+            axum::Json(vec![
+                Statement::WithParams(
+                    "insert into tests (id, text) values (?,?)".into(),
+                    vec![1i64.into(), "one".into()],
+                ),
+                Statement::WithParams(
+                    "update tests set text = ? where id = ?".into(),
+                    vec!["nobody".into(), 2i64.into()],
+                ),
+            ]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+        assert_eq!(body.0.version, Some(1));
+        assert_eq!(
+            body.0.outcomes,
+            vec![
+                StatementOutcome {
+                    result: OutcomeKind::Applied,
+                    version: Some(1),
+                    rows_affected: 1,
+                    error: None,
+                },
+                StatementOutcome::noop(),
+            ]
+        );
+
+        // nothing changes, no version is created
+        let (status_code, body) = api_v1_transactions(
+            Extension(agent.clone()),
+            axum::extract::Query(TransactionParams { timeout: None }),
+            axum::Json(vec![Statement::WithParams(
+                "delete from tests where id = ?".into(),
+                vec![2i64.into()],
+            )]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+        assert_eq!(body.0.version, None);
+        assert_eq!(body.0.outcomes, vec![StatementOutcome::noop()]);
+
+        // a failed statement rolls back the ones before it
+        let (status_code, body) = api_v1_transactions(
+            Extension(agent.clone()),
+            axum::extract::Query(TransactionParams { timeout: None }),
+            axum::Json(vec![
+                Statement::WithParams(
+                    "insert into tests (id, text) values (?,?)".into(),
+                    vec![3i64.into(), "three".into()],
+                ),
+                Statement::Simple("insert into nope (id) values (1)".into()),
+                Statement::Simple("delete from tests".into()),
+            ]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::INTERNAL_SERVER_ERROR);
+        let kinds: Vec<OutcomeKind> = body.0.outcomes.iter().map(|o| o.result).collect();
+        assert_eq!(
+            kinds,
+            vec![OutcomeKind::Noop, OutcomeKind::Error, OutcomeKind::Noop]
+        );
+        assert!(body.0.outcomes[1].error.is_some());
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_transaction_last_insert_rowid() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let dir = tempfile::tempdir()?;
+
+        let (agent, _agent_options) = setup(
+            Config::builder()
+                .db_path(dir.path().join("corrosion.db").display().to_string())
+                .gossip_addr("127.0.0.1:0".parse()?)
+                .api_addr("127.0.0.1:0".parse()?)
+                .build()?,
+            tripwire,
+        )
+        .await?;
+
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::extract::Query(SchemaParams::default()),
+            axum::Json(vec![
+                corro_tests::TEST_SCHEMA.into(),
+                "CREATE TABLE notes (id INTEGER NOT NULL PRIMARY KEY, text TEXT NOT NULL DEFAULT '');".into(),
+            ]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+
+        let (status_code, body) = api_v1_transactions(
+            Extension(agent.clone()),
+            axum::extract::Query(TransactionParams { timeout: None }),
+            axum::Json(vec![
+                Statement::WithParams(
+                    "insert into notes (id, text) values (?,?)".into(),
+                    vec![41i64.into(), "explicit".into()],
+                ),
+                Statement::WithParams(
+                    "insert into notes (text) values (?)".into(),
+                    vec!["generated".into()],
+                ),
+                Statement::WithParams(
+                    "update notes set text = ? where id = ?".into(),
+                    vec!["updated".into(), 41i64.into()],
+                ),
+                // WITHOUT ROWID tables have no rowid to report
+                Statement::WithParams(
+                    "insert into tests (id, text) values (?,?)".into(),
+                    vec![1i64.into(), "one".into()],
+                ),
+            ]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+
+        let rowids: Vec<Option<i64>> = body
+            .0
+            .results
+            .iter()
+            .map(|res| match res {
+                ExecResult::Execute {
+                    last_insert_rowid, ..
+                } => *last_insert_rowid,
+                res => panic!("unexpected result: {res:?}"),
+            })
+            .collect();
+        assert_eq!(rowids[0], Some(41));
+        assert!(rowids[1].is_some());
+        assert_eq!(rowids[2], None);
+        assert_eq!(rowids[3], None);
+
+        let conn = agent.pool().read().await?;
+        let generated: i64 =
+            conn.query_row("SELECT id FROM notes WHERE text = 'generated'", [], |row| {
+                row.get(0)
+            })?;
+        assert_eq!(rowids[1], Some(generated));
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_disk_full_rejects_writes() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let dir = tempfile::tempdir()?;
+
+        let (agent, _agent_options) = setup(
+            Config::builder()
+                .db_path(dir.path().join("corrosion.db").display().to_string())
+                .gossip_addr("127.0.0.1:0".parse()?)
+                .api_addr("127.0.0.1:0".parse()?)
+                .build()?,
+            tripwire,
+        )
+        .await?;
+
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::extract::Query(SchemaParams::default()),
+            axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+
+        let insert = |id: &str, text: String| {
+            axum::Json(vec![Statement::WithParams(
+                "insert into tests (id, text) values (?,?)".into(),
+                vec![id.into(), text.into()],
+            )])
+        };
+
+        let (status_code, _body) = api_v1_transactions(
+            Extension(agent.clone()),
+            axum::extract::Query(TransactionParams { timeout: None }),
+            insert("service-id", "service-name".into()),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
 
-            let tests = schema
-                .tables
-                .get("tests3")
-                .expect("no tests3 table in schema");
+        // the database can't grow any further, as if the disk was full
+        {
+            let conn = agent.pool().write_priority().await?;
+            let page_count: i64 = conn.query_row("PRAGMA page_count", [], |row| row.get(0))?;
+            conn.query_row(
+                &format!("PRAGMA max_page_count = {page_count}"),
+                [],
+                |row| row.get::<_, i64>(0),
+            )?;
+        }
 
-            let id_col = tests.columns.get("id").unwrap();
-            assert_eq!(id_col.name, "id");
-            assert_eq!(id_col.sql_type(), (SqliteType::Integer, Some("BIGINT")));
-            assert!(!id_col.nullable);
-            assert!(id_col.primary_key);
+        let (status_code, body) = api_v1_transactions(
+            Extension(agent.clone()),
+            axum::extract::Query(TransactionParams { timeout: None }),
+            insert("service-id-2", "a".repeat(256 * 1024)),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::INSUFFICIENT_STORAGE);
+        assert_eq!(body.0.version, None);
+        assert!(agent.is_disk_full());
 
-            let foo_col = tests.columns.get("foo").unwrap();
-            assert_eq!(foo_col.name, "foo");
-            assert_eq!(foo_col.sql_type(), (SqliteType::Text, Some("TEXT")));
-            assert!(foo_col.nullable);
-            assert!(!foo_col.primary_key);
+        // further writes are turned away without trying
+        let (status_code, body) = api_v1_transactions(
+            Extension(agent.clone()),
+            axum::extract::Query(TransactionParams { timeout: None }),
+            insert("service-id-3", "service-name".into()),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::INSUFFICIENT_STORAGE);
+        assert!(matches!(body.0.results[0], ExecResult::Error { .. }));
 
-            let updated_at_col = tests.columns.get("updated_at").unwrap();
-            assert_eq!(updated_at_col.name, "updated_at");
-            assert_eq!(
-                updated_at_col.sql_type(),
-                (SqliteType::Integer, Some("INTEGER"))
-            );
-            assert!(!updated_at_col.nullable);
-            assert!(!updated_at_col.primary_key);
+        let (status_code, health) = api_v1_health(Extension(agent.clone())).await;
+        assert_eq!(status_code, StatusCode::OK);
+        assert!(health.0.disk_full);
+        assert!(!health.0.accepting_writes);
 
-            let updated_at_idx = tests.indexes.get("tests3_updated_at").unwrap();
-            assert_eq!(updated_at_idx.name, "tests3_updated_at");
-            assert_eq!(updated_at_idx.tbl_name, "tests3");
-            assert_eq!(updated_at_idx.columns.len(), 1);
-            assert!(updated_at_idx.where_clause.is_none());
+        // once space is freed, the probe write notices and writes resume
+        {
+            let conn = agent.pool().write_priority().await?;
+            conn.query_row("PRAGMA max_page_count = 1073741823", [], |row| {
+                row.get::<_, i64>(0)
+            })?;
         }
 
-        let conn = agent.pool().read().await?;
-        let count: usize =
-            conn.query_row("SELECT COUNT(*) FROM tests3__crsql_clock;", (), |row| {
-                row.get(0)
-            })?;
-        // should've created a specific qty of clock table rows, just a sanity check!
-        assert_eq!(count, 4);
+        // probes run every 10s
+        tokio::time::timeout(Duration::from_secs(30), async {
+            while agent.is_disk_full() {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        })
+        .await?;
+
+        let (status_code, body) = api_v1_transactions(
+            Extension(agent.clone()),
+            axum::extract::Query(TransactionParams { timeout: None }),
+            insert("service-id-2", "a".repeat(256 * 1024)),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+        assert!(body.0.version.is_some());
+
+        let (status_code, health) = api_v1_health(Extension(agent.clone())).await;
+        assert_eq!(status_code, StatusCode::OK);
+        assert!(!health.0.disk_full);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_db_schema_validation() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let dir = tempfile::tempdir()?;
+
+        let (agent, _agent_options) = setup(
+            Config::builder()
+                .db_path(dir.path().join("corrosion.db").display().to_string())
+                .gossip_addr("127.0.0.1:0".parse()?)
+                .api_addr("127.0.0.1:0".parse()?)
+                .build()?,
+            tripwire,
+        )
+        .await?;
+
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::extract::Query(SchemaParams::default()),
+            axum::Json(vec![
+                "CREATE TABLE tests (id BIGINT NOT NULL PRIMARY KEY, foo TEXT);".into(),
+                "CREATE INDEX tests_foo ON tests (foo);".into(),
+            ]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+
+        let crr_clock: i64 = agent.pool().read().await?.query_row(
+            "SELECT count(*) FROM sqlite_schema WHERE name = 'tests__crsql_clock'",
+            [],
+            |row| row.get(0),
+        )?;
+        assert_eq!(crr_clock, 1);
+
+        // a stray DML statement rejects the whole migration
+        let (status_code, body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::extract::Query(SchemaParams::default()),
+            axum::Json(vec![
+                "CREATE TABLE tests2 (id BIGINT NOT NULL PRIMARY KEY);".into(),
+                "DELETE FROM tests;".into(),
+            ]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::BAD_REQUEST);
+        assert!(matches!(
+            &body.0.results[..],
+            [ExecResult::Error { error }] if error.contains("DELETE")
+        ));
+        assert!(agent.schema().read().tables.get("tests2").is_none());
+
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::extract::Query(SchemaParams::default()),
+            axum::Json(vec!["CREATE TABLE tests3 AS SELECT * FROM tests;".into()]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::BAD_REQUEST);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_actor_status() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let dir = tempfile::tempdir()?;
+
+        let (agent, _agent_options) = setup(
+            Config::builder()
+                .db_path(dir.path().join("corrosion.db").display().to_string())
+                .gossip_addr("127.0.0.1:0".parse()?)
+                .api_addr("127.0.0.1:0".parse()?)
+                .build()?,
+            tripwire,
+        )
+        .await?;
+
+        let bookie = Bookie::new(Default::default());
+        bookie
+            .write::<&str, _>("test", None)
+            .await
+            .insert(agent.actor_id(), agent.booked().clone());
+
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::extract::Query(SchemaParams::default()),
+            axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
+        )
+        .await;
+
+        assert_eq!(status_code, StatusCode::OK);
+
+        let axum::Json(status) = api_v1_actor_status(
+            Extension(agent.clone()),
+            Extension(bookie.clone()),
+            axum::extract::Path(agent.actor_id()),
+        )
+        .await
+        .expect("could not get actor status");
+
+        assert_eq!(status.max_version, None);
+        assert_eq!(status.last_applied_version, None);
+        assert!(status.last_applied_ts.is_none());
+
+        let (status_code, _body) = api_v1_transactions(
+            Extension(agent.clone()),
+            axum::extract::Query(TransactionParams { timeout: None }),
+            axum::Json(vec![Statement::WithParams(
+                "insert into tests (id, text) values (?,?)".into(),
+                vec!["service-id".into(), "service-name".into()],
+            )]),
+        )
+        .await;
+
+        assert_eq!(status_code, StatusCode::OK);
+
+        let axum::Json(status) = api_v1_actor_status(
+            Extension(agent.clone()),
+            Extension(bookie.clone()),
+            axum::extract::Path(agent.actor_id()),
+        )
+        .await
+        .expect("could not get actor status");
+
+        assert_eq!(status.max_version, Some(Version(1)));
+        assert_eq!(status.last_applied_version, Some(Version(1)));
+        assert!(status.last_applied_ts.is_some());
+        assert!(status.last_applied_time.is_some());
+        assert_eq!(status.pending_partials, 0);
+        assert_eq!(status.needed_versions, 0);
+
+        let (status_code, _) = api_v1_actor_status(
+            Extension(agent.clone()),
+            Extension(bookie.clone()),
+            axum::extract::Path(ActorId(uuid::Uuid::new_v4())),
+        )
+        .await
+        .expect_err("unknown actor should not have a status");
+
+        assert_eq!(status_code, StatusCode::NOT_FOUND);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_partial_status() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let dir = tempfile::tempdir()?;
+
+        let (agent, _agent_options) = setup(
+            Config::builder()
+                .db_path(dir.path().join("corrosion.db").display().to_string())
+                .gossip_addr("127.0.0.1:0".parse()?)
+                .api_addr("127.0.0.1:0".parse()?)
+                .build()?,
+            tripwire,
+        )
+        .await?;
+
+        let actor_id = ActorId(uuid::Uuid::new_v4());
+        let bookie = Bookie::new(Default::default());
+        let booked = bookie.write::<&str, _>("test", None).await.ensure(actor_id);
+
+        let mut seqs = rangemap::RangeInclusiveSet::new();
+        seqs.insert(CrsqlSeq(1)..=CrsqlSeq(2));
+        seqs.insert(CrsqlSeq(5)..=CrsqlSeq(6));
+        booked.write::<&str, _>("test", None).await.insert_partial(
+            Version(1),
+            PartialVersion {
+                seqs,
+                last_seq: CrsqlSeq(9),
+                ts: Default::default(),
+            },
+        );
+
+        let axum::Json(status) = api_v1_partial_status(
+            Extension(agent.clone()),
+            Extension(bookie.clone()),
+            axum::extract::Path((actor_id, Version(1))),
+        )
+        .await
+        .expect("could not get partial status");
+
+        assert_eq!(
+            status.seqs,
+            vec![CrsqlSeq(1)..=CrsqlSeq(2), CrsqlSeq(5)..=CrsqlSeq(6)]
+        );
+        assert_eq!(status.last_seq, CrsqlSeq(9));
+        assert_eq!(
+            status.gaps,
+            vec![CrsqlSeq(3)..=CrsqlSeq(4), CrsqlSeq(7)..=CrsqlSeq(9)]
+        );
+        assert_eq!(status.buffered_changes, 0);
+
+        let (status_code, _) = api_v1_partial_status(
+            Extension(agent.clone()),
+            Extension(bookie.clone()),
+            axum::extract::Path((actor_id, Version(2))),
+        )
+        .await
+        .expect_err("complete version should not have a partial status");
+
+        assert_eq!(status_code, StatusCode::NOT_FOUND);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_query_bounded_consistency() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let dir = tempfile::tempdir()?;
+
+        let (agent, _agent_options) = setup(
+            Config::builder()
+                .db_path(dir.path().join("corrosion.db").display().to_string())
+                .gossip_addr("127.0.0.1:0".parse()?)
+                .api_addr("127.0.0.1:0".parse()?)
+                .build()?,
+            tripwire,
+        )
+        .await?;
+
+        let query = |consistency, max_staleness_ms| {
+            api_v1_queries(
+                Extension(agent.clone()),
+                axum::extract::Query(QueryParams {
+                    consistency,
+                    max_staleness_ms,
+                    ..Default::default()
+                }),
+                axum::Json(Statement::Simple("select 1".into())),
+            )
+        };
+
+        // the sync loop hasn't generated its sync state yet
+        let res = query(Consistency::Bounded, Some(60_000))
+            .await
+            .into_response();
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        // nothing missing, the node is caught up
+        agent.record_sync_needs(0);
+        let res = query(Consistency::Bounded, Some(0)).await.into_response();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let res = query(Consistency::Bounded, None).await.into_response();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        // now missing a version
+        agent.record_sync_needs(1);
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let res = query(Consistency::Bounded, Some(60_000))
+            .await
+            .into_response();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let res = query(Consistency::Bounded, Some(10)).await.into_response();
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        // local reads don't care
+        let res = query(Consistency::Local, None).await.into_response();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // caught up again, then a change leaves a gap before the next sync
+        agent.record_sync_needs(0);
+        let res = query(Consistency::Bounded, Some(10)).await.into_response();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let bookie = Bookie::new(Default::default());
+        process_multiple_changes(
+            agent.clone(),
+            bookie,
+            vec![(
+                ChangeV1 {
+                    actor_id: ActorId(uuid::Uuid::new_v4()),
+                    changeset: Changeset::Empty {
+                        versions: Version(2)..=Version(2),
+                        ts: None,
+                    },
+                    trace_id: None,
+                },
+                ChangeSource::Broadcast,
+                Instant::now(),
+            )],
+            Duration::from_secs(5),
+        )
+        .await?;
+        assert!(agent.is_missing_versions());
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let res = query(Consistency::Bounded, Some(10)).await.into_response();
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
 
         Ok(())
     }
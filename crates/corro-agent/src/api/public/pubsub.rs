@@ -1,18 +1,29 @@
-use std::{collections::HashMap, io::Write, sync::Arc, time::Duration};
+use std::{
+    collections::{BTreeSet, HashMap},
+    io::Write,
+    sync::Arc,
+    time::Duration,
+};
 
 use axum::{http::StatusCode, response::IntoResponse, Extension};
 use bytes::{BufMut, Bytes, BytesMut};
 use compact_str::{format_compact, ToCompactString};
 use corro_types::updates::Handle;
 use corro_types::{
+    actor::ActorId,
     agent::Agent,
-    api::{ChangeId, QueryEvent, QueryEventMeta, Statement},
-    pubsub::{MatcherCreated, MatcherError, MatcherHandle, NormalizeStatementError, SubsManager},
+    api::{ChangeId, ChangeOrigin, QueryEvent, QueryEventMeta, RowId, SqliteValue, Statement},
+    pubsub::{
+        ChangeType, MatcherCreated, MatcherError, MatcherHandle, NormalizeStatementError,
+        SubOrigins, SubsManager,
+    },
     sqlite::SqlitePoolError,
 };
 use futures::future::poll_fn;
+use metrics::counter;
 use rusqlite::Connection;
 use serde::Deserialize;
+use tokio::time::Instant;
 use tokio::{
     sync::{
         broadcast::{self, error::RecvError},
@@ -26,12 +37,60 @@ use tracing::{debug, error, info, warn};
 use tripwire::Tripwire;
 use uuid::Uuid;
 
-#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[derive(Clone, Debug, Default, Deserialize)]
 pub struct SubParams {
     #[serde(default)]
     from: Option<ChangeId>,
     #[serde(default)]
     skip_rows: bool,
+    /// comma-separated actor ids (or `local`) to restrict changes to
+    #[serde(default)]
+    origin: Option<String>,
+    /// coalesce changes to the same row until it's been quiet for this many ms
+    #[serde(default)]
+    debounce_ms: Option<u64>,
+    /// precede each live change with an event telling where it was made
+    #[serde(default)]
+    with_origin: bool,
+}
+
+impl SubParams {
+    fn debounce(&self) -> Option<Duration> {
+        self.debounce_ms
+            .filter(|ms| *ms > 0)
+            .map(Duration::from_millis)
+    }
+}
+
+/// Parses the `origin` query param, `local` stands for this node's actor id
+fn parse_sub_origins(
+    origin: Option<&str>,
+    local: ActorId,
+) -> Result<SubOrigins, MatcherUpsertError> {
+    let Some(origin) = origin else {
+        return Ok(None);
+    };
+
+    let origins = origin
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|s| {
+            if s == "local" {
+                Ok(local)
+            } else {
+                s.parse::<Uuid>()
+                    .map(ActorId)
+                    .map_err(|_| MatcherUpsertError::InvalidOrigin(s.to_owned()))
+            }
+        })
+        .collect::<Result<BTreeSet<_>, _>>()?;
+
+    if origins.is_empty() {
+        return Err(MatcherUpsertError::InvalidOrigin(origin.to_owned()));
+    }
+
+    Ok(Some(origins))
 }
 
 pub async fn api_v1_sub_by_id(
@@ -97,11 +156,14 @@ async fn sub_by_id(
     let (evt_tx, evt_rx) = mpsc::channel(512);
 
     let query_hash = matcher.hash().to_owned();
+    let debounced = params.debounce().is_some();
     tokio::spawn(catch_up_sub(matcher, params, rx, evt_tx));
 
     let (tx, body) = hyper::Body::channel();
 
-    tokio::spawn(forward_bytes_to_body_sender(id, evt_rx, tx, tripwire));
+    tokio::spawn(forward_bytes_to_body_sender(
+        id, evt_rx, tx, tripwire, debounced,
+    ));
 
     hyper::Response::builder()
         .status(StatusCode::OK)
@@ -198,6 +260,12 @@ pub async fn process_sub_channel(
             }
         };
 
+        if let QueryEvent::Error(e) = &query_evt {
+            // the matcher is done, the error is the last event subscribers get
+            warn!(sub_id = %id, "subscription errored, closing: {e}");
+            break;
+        }
+
         if is_still_active {
             deadline = None;
         } else {
@@ -228,6 +296,10 @@ pub async fn process_sub_channel(
 
 fn expanded_statement(conn: &Connection, stmt: &Statement) -> rusqlite::Result<Option<String>> {
     Ok(match stmt {
+        Statement::CompareAndSet(cas) => {
+            let (query, params) = cas.to_sql();
+            expanded_statement(conn, &Statement::WithParams(query, params))?
+        }
         Statement::Simple(query)
         | Statement::Verbose {
             query,
@@ -286,18 +358,27 @@ pub enum MatcherUpsertError {
     SubFromWithoutMatcher,
     #[error("found a subscription, but missing broadcaster")]
     MissingBroadcaster,
+    #[error("invalid subscription origin: {0}")]
+    InvalidOrigin(String),
+    #[error("node is draining for maintenance, subscribe on another node")]
+    Draining,
 }
 
 impl MatcherUpsertError {
     fn status_code(&self) -> StatusCode {
         match self {
+            MatcherUpsertError::Matcher(MatcherError::TooManySubscriptions(_)) => {
+                StatusCode::TOO_MANY_REQUESTS
+            }
             MatcherUpsertError::Pool(_)
             | MatcherUpsertError::CouldNotExpand
             | MatcherUpsertError::MissingBroadcaster => StatusCode::INTERNAL_SERVER_ERROR,
             MatcherUpsertError::Sqlite(_)
             | MatcherUpsertError::NormalizeStatement(_)
             | MatcherUpsertError::Matcher(_)
-            | MatcherUpsertError::SubFromWithoutMatcher => StatusCode::BAD_REQUEST,
+            | MatcherUpsertError::SubFromWithoutMatcher
+            | MatcherUpsertError::InvalidOrigin(_) => StatusCode::BAD_REQUEST,
+            MatcherUpsertError::Draining => StatusCode::SERVICE_UNAVAILABLE,
         }
     }
 }
@@ -616,7 +697,15 @@ pub async fn catch_up_sub(
         }
     };
 
-    forward_sub_to_sender(matcher, sub_rx, evt_tx, params.skip_rows).await
+    forward_sub_to_sender(
+        matcher,
+        sub_rx,
+        evt_tx,
+        params.skip_rows,
+        params.with_origin,
+        params.debounce(),
+    )
+    .await
 }
 
 pub async fn upsert_sub(
@@ -639,6 +728,8 @@ pub async fn upsert_sub(
             sub_rx,
             tx,
             params.skip_rows,
+            params.with_origin,
+            params.debounce(),
         ));
 
         bcast_write.insert(handle.id(), sub_tx.clone());
@@ -672,6 +763,11 @@ pub async fn api_v1_subs(
     axum::extract::Query(params): axum::extract::Query<SubParams>,
     axum::extract::Json(stmt): axum::extract::Json<Statement>,
 ) -> impl IntoResponse {
+    // existing subscriptions keep streaming, only new ones are turned away
+    if agent.is_draining() {
+        return hyper::Response::<hyper::Body>::from(MatcherUpsertError::Draining);
+    }
+
     let stmt = match expand_sql(&agent, &stmt).await {
         Ok(stmt) => stmt,
         Err(e) => return hyper::Response::<hyper::Body>::from(e),
@@ -679,12 +775,19 @@ pub async fn api_v1_subs(
 
     info!("Received subscription request for query: {stmt}");
 
+    let origins = match parse_sub_origins(params.origin.as_deref(), agent.actor_id()) {
+        Ok(origins) => origins,
+        Err(e) => return hyper::Response::<hyper::Body>::from(e),
+    };
+
     let mut bcast_write = bcast_cache.write().await;
 
     let subs = agent.subs_manager();
 
     let upsert_res = subs.get_or_insert(
         &stmt,
+        origins,
+        agent.config().api.max_subscriptions,
         &agent.config().db.subscriptions_path(),
         &agent.schema().read(),
         agent.pool(),
@@ -704,6 +807,7 @@ pub async fn api_v1_subs(
         forward_rx,
         tx,
         tripwire,
+        params.debounce().is_some(),
     ));
 
     let query_hash = handle.hash().to_owned();
@@ -731,16 +835,193 @@ pub async fn api_v1_subs(
 
 const MAX_EVENTS_BUFFER_SIZE: usize = 1024;
 
+/// Holds back changes for a debounced subscriber. Changes to the same row
+/// are merged into one and emitted once the row has been quiet for the
+/// whole window, every new change resets the row's timer.
+struct Debouncer {
+    window: Duration,
+    pending: HashMap<RowId, PendingChange>,
+}
+
+struct PendingChange {
+    origin: Option<ChangeOrigin>,
+    change_type: ChangeType,
+    cells: Vec<SqliteValue>,
+    change_id: ChangeId,
+    deadline: Instant,
+}
+
+/// Change type a subscriber should see for a row changed as `first` then
+/// as `then`, `None` if the row is back to not existing
+fn merge_change_types(first: ChangeType, then: ChangeType) -> Option<ChangeType> {
+    match (first, then) {
+        // the subscriber never knew about the row
+        (ChangeType::Insert, ChangeType::Delete) => None,
+        (ChangeType::Insert, _) => Some(ChangeType::Insert),
+        // the row the subscriber knows about was replaced
+        (ChangeType::Delete, ChangeType::Insert) => Some(ChangeType::Update),
+        (_, then) => Some(then),
+    }
+}
+
+impl Debouncer {
+    fn new(window: Duration) -> Self {
+        Self {
+            window,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Buffers a change, merging it with the pending change of its row.
+    /// Returns how many changes won't be emitted because of it.
+    fn push(
+        &mut self,
+        origin: Option<ChangeOrigin>,
+        change_type: ChangeType,
+        rowid: RowId,
+        cells: Vec<SqliteValue>,
+        change_id: ChangeId,
+    ) -> u64 {
+        let deadline = Instant::now() + self.window;
+        match self.pending.remove(&rowid) {
+            None => {
+                self.pending.insert(
+                    rowid,
+                    PendingChange {
+                        origin,
+                        change_type,
+                        cells,
+                        change_id,
+                        deadline,
+                    },
+                );
+                0
+            }
+            Some(pending) => match merge_change_types(pending.change_type, change_type) {
+                Some(change_type) => {
+                    self.pending.insert(
+                        rowid,
+                        PendingChange {
+                            origin,
+                            change_type,
+                            cells,
+                            change_id,
+                            deadline,
+                        },
+                    );
+                    1
+                }
+                None => 2,
+            },
+        }
+    }
+
+    fn next_deadline(&self) -> Option<Instant> {
+        self.pending.values().map(|pending| pending.deadline).min()
+    }
+
+    /// Removes every change whose window elapsed, in change id order, each
+    /// preceded by its origin when known
+    fn take_due(&mut self, now: Instant) -> Vec<QueryEvent> {
+        let due: Vec<RowId> = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| pending.deadline <= now)
+            .map(|(rowid, _)| *rowid)
+            .collect();
+
+        let mut changes: Vec<_> = due
+            .into_iter()
+            .filter_map(|rowid| self.pending.remove(&rowid).map(|pending| (rowid, pending)))
+            .collect();
+        changes.sort_by_key(|(_, pending)| pending.change_id);
+
+        let mut events = vec![];
+        for (rowid, pending) in changes {
+            if let Some(origin) = pending.origin {
+                events.push(QueryEvent::Origin(origin));
+            }
+            events.push(QueryEvent::Change(
+                pending.change_type,
+                rowid,
+                pending.cells,
+                pending.change_id,
+            ));
+        }
+        events
+    }
+
+    fn take_all(&mut self) -> Vec<QueryEvent> {
+        self.take_due(Instant::now() + self.window)
+    }
+}
+
+/// Sends changes released by a `Debouncer`, returns false if the receiver
+/// is gone
+async fn send_debounced(
+    sub_id: Uuid,
+    buf: &mut BytesMut,
+    events: Vec<QueryEvent>,
+    tx: &mpsc::Sender<(Bytes, QueryEventMeta)>,
+) -> bool {
+    for evt in events {
+        let evt = match make_query_event_bytes(buf, &evt) {
+            Ok(evt) => evt,
+            Err(e) => {
+                error!(%sub_id, "could not serialize debounced change: {e}");
+                continue;
+            }
+        };
+        if let Err(e) = tx.send(evt).await {
+            warn!(%sub_id, "could not send subscription event to channel: {e}");
+            return false;
+        }
+    }
+    true
+}
+
 async fn forward_sub_to_sender(
     handle: MatcherHandle,
     mut sub_rx: broadcast::Receiver<(Bytes, QueryEventMeta)>,
     tx: mpsc::Sender<(Bytes, QueryEventMeta)>,
     skip_rows: bool,
+    with_origin: bool,
+    debounce: Option<Duration>,
 ) {
     info!(sub_id = %handle.id(), "forwarding subscription events to a sender");
 
+    if with_origin {
+        handle.want_origins();
+    }
+
+    let mut debouncer = debounce.map(Debouncer::new);
+    let mut buf = BytesMut::new();
+    // origin of the next change, held back along with it when debouncing
+    let mut origin = None;
+
     loop {
+        let debounce_deadline = debouncer.as_ref().and_then(Debouncer::next_deadline);
+        let debounce_check = async {
+            match debounce_deadline {
+                Some(deadline) => tokio::time::sleep_until(deadline).await,
+                None => futures::future::pending().await,
+            }
+        };
+
         let (event_buf, meta) = tokio::select! {
+            // drain pending events first, a cancelled subscription might
+            // have sent a final error
+            biased;
+            // a hot row must not starve the rows that are due
+            _ = debounce_check => {
+                if let Some(debouncer) = debouncer.as_mut() {
+                    let due = debouncer.take_due(Instant::now());
+                    if !send_debounced(handle.id(), &mut buf, due, &tx).await {
+                        return;
+                    }
+                }
+                continue;
+            },
             res = sub_rx.recv() => {
                 match res {
                     Ok((event_buf, meta)) => (event_buf, meta),
@@ -750,6 +1031,9 @@ async fn forward_sub_to_sender(
                     },
                     Err(RecvError::Closed) => {
                         info!(sub_id = %handle.id(), "events subcription ran out");
+                        if let Some(debouncer) = debouncer.as_mut() {
+                            send_debounced(handle.id(), &mut buf, debouncer.take_all(), &tx).await;
+                        }
                         return;
                     },
                 }
@@ -768,6 +1052,49 @@ async fn forward_sub_to_sender(
         {
             continue;
         }
+
+        if !with_origin && matches!(meta, QueryEventMeta::Origin) {
+            continue;
+        }
+
+        if let Some(debouncer) = debouncer.as_mut() {
+            match meta {
+                QueryEventMeta::Origin => {
+                    if let Ok(QueryEvent::Origin(change_origin)) =
+                        serde_json::from_slice::<QueryEvent>(&event_buf)
+                    {
+                        origin = Some(change_origin);
+                        continue;
+                    }
+                }
+                QueryEventMeta::Change(_) => {
+                    // changes are only serialized once, merging them needs
+                    // their type and cells back
+                    match serde_json::from_slice::<QueryEvent>(&event_buf) {
+                        Ok(QueryEvent::Change(change_type, rowid, cells, change_id)) => {
+                            let coalesced =
+                                debouncer.push(origin.take(), change_type, rowid, cells, change_id);
+                            if coalesced > 0 {
+                                counter!("corro.subs.changes.coalesced").increment(coalesced);
+                            }
+                            continue;
+                        }
+                        Ok(_) => {}
+                        Err(e) => {
+                            warn!(sub_id = %handle.id(), "could not decode change to debounce, sending it as-is: {e}");
+                        }
+                    }
+                }
+                QueryEventMeta::Error => {
+                    // the error is the last event, flush what we held back before it
+                    if !send_debounced(handle.id(), &mut buf, debouncer.take_all(), &tx).await {
+                        return;
+                    }
+                }
+                _ => {}
+            }
+        }
+
         if let Err(e) = tx.send((event_buf, meta)).await {
             warn!(sub_id = %handle.id(), "could not send subscription event to channel: {e}");
             return;
@@ -782,15 +1109,19 @@ async fn handle_sub_event(
     meta: QueryEventMeta,
     tx: &mut hyper::body::Sender,
     last_change_id: &mut ChangeId,
+    debounced: bool,
 ) -> hyper::Result<()> {
     match meta {
         QueryEventMeta::EndOfQuery(Some(change_id)) | QueryEventMeta::Change(change_id) => {
-            if !last_change_id.is_zero() && change_id > *last_change_id + 1 {
-                warn!(%sub_id, "non-contiguous change id (> + 1) received: {change_id:?}, last seen: {last_change_id:?}");
-            } else if !last_change_id.is_zero() && change_id == *last_change_id {
-                warn!(%sub_id, "duplicate change id received: {change_id:?}, last seen: {last_change_id:?}");
-            } else if change_id < *last_change_id {
-                warn!(%sub_id, "smaller change id received: {change_id:?}, last seen: {last_change_id:?}");
+            // coalesced changes leave gaps and are emitted out of order
+            if !debounced {
+                if !last_change_id.is_zero() && change_id > *last_change_id + 1 {
+                    warn!(%sub_id, "non-contiguous change id (> + 1) received: {change_id:?}, last seen: {last_change_id:?}");
+                } else if !last_change_id.is_zero() && change_id == *last_change_id {
+                    warn!(%sub_id, "duplicate change id received: {change_id:?}, last seen: {last_change_id:?}");
+                } else if change_id < *last_change_id {
+                    warn!(%sub_id, "smaller change id received: {change_id:?}, last seen: {last_change_id:?}");
+                }
             }
             *last_change_id = change_id;
         }
@@ -813,6 +1144,7 @@ async fn forward_bytes_to_body_sender(
     mut rx: mpsc::Receiver<(Bytes, QueryEventMeta)>,
     mut tx: hyper::body::Sender,
     mut tripwire: Tripwire,
+    debounced: bool,
 ) {
     let mut buf = BytesMut::new();
 
@@ -827,7 +1159,7 @@ async fn forward_bytes_to_body_sender(
             res = rx.recv() => {
                 match res {
                     Some((event_buf, meta)) => {
-                        if let Err(e) = handle_sub_event(sub_id, &mut buf, event_buf, meta, &mut tx, &mut last_change_id).await {
+                        if let Err(e) = handle_sub_event(sub_id, &mut buf, event_buf, meta, &mut tx, &mut last_change_id, debounced).await {
                             warn!(%sub_id, "could not forward subscription query event to receiver: {e}");
                             return;
                         }
@@ -864,6 +1196,7 @@ async fn forward_bytes_to_body_sender(
             meta,
             &mut tx,
             &mut last_change_id,
+            debounced,
         )
         .await
         {
@@ -893,6 +1226,7 @@ mod tests {
         config::Config,
         pubsub::ChangeType,
     };
+    use futures::StreamExt;
     use http_body::Body;
     use serde::de::DeserializeOwned;
     use spawn::wait_for_all_pending_handles;
@@ -908,7 +1242,7 @@ mod tests {
     use crate::api::public::TransactionParams;
     use crate::{
         agent::setup,
-        api::public::{api_v1_db_schema, api_v1_transactions},
+        api::public::{api_v1_db_schema, api_v1_transactions, SchemaParams},
     };
     use corro_tests::launch_test_agent;
     use corro_tests::tempdir::TempDir;
@@ -934,6 +1268,7 @@ mod tests {
 
         let (status_code, _body) = api_v1_db_schema(
             Extension(agent.clone()),
+            axum::extract::Query(SchemaParams::default()),
             axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
         )
         .await;
@@ -1413,6 +1748,7 @@ mod tests {
             axum::extract::Query(SubParams {
                 skip_rows: true,
                 from: Some(ChangeId(3)),
+                ..Default::default()
             }),
             axum::Json(Statement::Simple("select * from tests".into())),
         )
@@ -1446,6 +1782,248 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_parse_sub_origins() {
+        let local = ActorId(uuid::Uuid::new_v4());
+        let remote = ActorId(uuid::Uuid::new_v4());
+
+        assert_eq!(parse_sub_origins(None, local).unwrap(), None);
+        assert_eq!(
+            parse_sub_origins(Some("local"), local).unwrap(),
+            Some([local].into())
+        );
+
+        let origin = format!("local, {}", remote.0);
+        assert_eq!(
+            parse_sub_origins(Some(&origin), local).unwrap(),
+            Some([local, remote].into())
+        );
+
+        assert!(matches!(
+            parse_sub_origins(Some(""), local),
+            Err(MatcherUpsertError::InvalidOrigin(_))
+        ));
+        assert!(matches!(
+            parse_sub_origins(Some("local,nope"), local),
+            Err(MatcherUpsertError::InvalidOrigin(_))
+        ));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_v1_subs_max_subscriptions() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+        let ta =
+            launch_test_agent(|conf| conf.max_subscriptions(1).build(), tripwire.clone()).await?;
+
+        let bcast_cache: SharedMatcherBroadcastCache = Default::default();
+
+        let subscribe = |sql: &'static str| {
+            api_v1_subs(
+                Extension(ta.agent.clone()),
+                Extension(bcast_cache.clone()),
+                Extension(tripwire.clone()),
+                axum::extract::Query(SubParams::default()),
+                axum::Json(Statement::Simple(sql.into())),
+            )
+        };
+
+        let res = subscribe("select * from tests").await.into_response();
+        assert_eq!(res.status(), StatusCode::OK);
+        let id: Uuid = res
+            .headers()
+            .get("corro-query-id")
+            .unwrap()
+            .to_str()?
+            .parse()?;
+
+        // the same query reuses the existing subscription
+        let same_res = subscribe("select * from tests").await.into_response();
+        assert_eq!(same_res.status(), StatusCode::OK);
+
+        let other_res = subscribe("select * from tests2").await.into_response();
+        assert_eq!(other_res.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        // freeing up the slot lets a new query in
+        let handle = ta.agent.subs_manager().remove(&id).unwrap();
+        bcast_cache.write().await.remove(&id);
+        handle.cleanup().await;
+
+        let other_res = subscribe("select * from tests2").await.into_response();
+        assert_eq!(other_res.status(), StatusCode::OK);
+
+        drop((res, same_res, other_res));
+
+        tripwire_tx.send(()).await.ok();
+        tripwire_worker.await;
+        wait_for_all_pending_handles().await;
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_local_subscription() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+        let ta = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+
+        let (status_code, _) = api_v1_transactions(
+            Extension(ta.agent.clone()),
+            axum::extract::Query(TransactionParams { timeout: None }),
+            axum::Json(vec![Statement::WithParams(
+                "insert into tests (id, text) values (?,?)".into(),
+                vec![1i64.into(), "one".into()],
+            )]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+
+        let mut sub = ta.agent.subscribe("select id, text from tests")?;
+        let id = sub.id();
+        assert!(ta.agent.subs_manager().get(&id).is_some());
+
+        // a local subscription never gets reused by the HTTP API
+        assert!(ta
+            .agent
+            .subs_manager()
+            .get_by_query("select id, text from tests")
+            .is_none());
+
+        let mut events = vec![];
+        while !matches!(events.last(), Some(QueryEvent::EndOfQuery { .. })) {
+            let batch = timeout(Duration::from_secs(5), sub.next())
+                .await?
+                .expect("subscription ended early");
+            events.extend(batch);
+        }
+
+        assert_eq!(
+            events[..2],
+            [
+                QueryEvent::Columns(vec!["id".into(), "text".into()]),
+                QueryEvent::Row(RowId(1), vec![Integer(1), "one".into()]),
+            ]
+        );
+
+        let (status_code, _) = api_v1_transactions(
+            Extension(ta.agent.clone()),
+            axum::extract::Query(TransactionParams { timeout: None }),
+            axum::Json(vec![Statement::WithParams(
+                "insert into tests (id, text) values (?,?)".into(),
+                vec![2i64.into(), "two".into()],
+            )]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+
+        let batch = timeout(Duration::from_secs(5), sub.next())
+            .await?
+            .expect("subscription ended early");
+        assert_eq!(
+            batch,
+            vec![QueryEvent::Change(
+                ChangeType::Insert,
+                RowId(2),
+                vec![Integer(2), "two".into()],
+                ChangeId(1)
+            )]
+        );
+
+        // dropping the stream removes the matcher
+        drop(sub);
+        assert!(ta.agent.subs_manager().get(&id).is_none());
+
+        tripwire_tx.send(()).await.ok();
+        tripwire_worker.await;
+        wait_for_all_pending_handles().await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_debouncer_coalesces_rows() {
+        let window = Duration::from_secs(10);
+        let mut debouncer = Debouncer::new(window);
+
+        assert!(debouncer.next_deadline().is_none());
+
+        let cells = |v: i64| vec![Integer(v)];
+
+        assert_eq!(
+            debouncer.push(None, ChangeType::Insert, RowId(1), cells(1), ChangeId(1)),
+            0
+        );
+        assert_eq!(
+            debouncer.push(None, ChangeType::Update, RowId(2), cells(2), ChangeId(2)),
+            0
+        );
+        // inserted then updated, the subscriber only needs the insert
+        assert_eq!(
+            debouncer.push(None, ChangeType::Update, RowId(1), cells(3), ChangeId(3)),
+            1
+        );
+
+        // nothing is due before the window elapsed
+        assert!(debouncer.take_due(tokio::time::Instant::now()).is_empty());
+
+        let deadline = debouncer.next_deadline().unwrap();
+        assert_eq!(
+            debouncer.take_due(deadline),
+            vec![QueryEvent::Change(
+                ChangeType::Update,
+                RowId(2),
+                cells(2),
+                ChangeId(2)
+            )]
+        );
+
+        assert_eq!(
+            debouncer.take_all(),
+            vec![QueryEvent::Change(
+                ChangeType::Insert,
+                RowId(1),
+                cells(3),
+                ChangeId(3)
+            )]
+        );
+        assert!(debouncer.next_deadline().is_none());
+
+        // inserted then deleted, the subscriber never hears about the row
+        debouncer.push(None, ChangeType::Insert, RowId(3), cells(4), ChangeId(4));
+        assert_eq!(
+            debouncer.push(None, ChangeType::Delete, RowId(3), cells(4), ChangeId(5)),
+            2
+        );
+        assert!(debouncer.take_all().is_empty());
+
+        // updated then deleted
+        debouncer.push(None, ChangeType::Update, RowId(4), cells(6), ChangeId(6));
+        debouncer.push(None, ChangeType::Delete, RowId(4), cells(6), ChangeId(7));
+        // deleted then inserted again, the latest origin is kept
+        let origin = ChangeOrigin {
+            actor_id: Uuid::new_v4(),
+            local: false,
+        };
+        debouncer.push(None, ChangeType::Delete, RowId(5), cells(8), ChangeId(8));
+        debouncer.push(
+            Some(origin),
+            ChangeType::Insert,
+            RowId(5),
+            cells(9),
+            ChangeId(9),
+        );
+        assert_eq!(
+            debouncer.take_all(),
+            vec![
+                QueryEvent::Change(ChangeType::Delete, RowId(4), cells(6), ChangeId(7)),
+                QueryEvent::Origin(origin),
+                QueryEvent::Change(ChangeType::Update, RowId(5), cells(9), ChangeId(9)),
+            ]
+        );
+    }
+
     #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
     async fn match_buffered_changes() -> eyre::Result<()> {
         _ = tracing_subscriber::fmt::try_init();
@@ -1463,6 +2041,7 @@ mod tests {
 
         let (status_code, _body) = api_v1_db_schema(
             Extension(ta1.agent.clone()),
+            axum::extract::Query(SchemaParams::default()),
             axum::Json(vec![schema.into()]),
         )
         .await;
@@ -1503,6 +2082,7 @@ mod tests {
                 last_seq: CrsqlSeq(1),
                 ts: Default::default(),
             },
+            trace_id: None,
         };
 
         process_multiple_changes(
@@ -1578,6 +2158,41 @@ mod tests {
             QueryEvent::EndOfQuery { .. }
         ));
 
+        // same query, asking for the origin of changes
+        let res = api_v1_subs(
+            Extension(ta1.agent.clone()),
+            Extension(bcast_cache.clone()),
+            Extension(tripwire.clone()),
+            axum::extract::Query(SubParams {
+                with_origin: true,
+                ..Default::default()
+            }),
+            axum::Json(Statement::Simple("select * from buftests".into())),
+        )
+        .await
+        .into_response();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let mut origin_rows = RowsIter {
+            body: res.into_body(),
+            codec: LinesCodec::new(),
+            buf: BytesMut::new(),
+            done: false,
+        };
+
+        assert_eq!(
+            origin_rows.recv::<QueryEvent>().await.unwrap().unwrap(),
+            QueryEvent::Columns(vec!["pk".into(), "col1".into(), "col2".into()])
+        );
+        assert_eq!(
+            origin_rows.recv::<QueryEvent>().await.unwrap().unwrap(),
+            QueryEvent::Row(RowId(1), vec![Integer(1), "one".into(), "one line".into()])
+        );
+        assert!(matches!(
+            origin_rows.recv::<QueryEvent>().await.unwrap().unwrap(),
+            QueryEvent::EndOfQuery { .. }
+        ));
+
         // send partial change so it is buffered
         let change3 = Change {
             table: TableName("buftests".into()),
@@ -1600,6 +2215,7 @@ mod tests {
                 last_seq: CrsqlSeq(1),
                 ts: Default::default(),
             },
+            trace_id: None,
         };
 
         process_multiple_changes(
@@ -1642,6 +2258,7 @@ mod tests {
                 last_seq: CrsqlSeq(1),
                 ts: Default::default(),
             },
+            trace_id: None,
         };
 
         process_multiple_changes(
@@ -1664,6 +2281,20 @@ mod tests {
             )
         );
 
+        let res = timeout(Duration::from_secs(5), origin_rows.recv::<QueryEvent>()).await?;
+        assert_eq!(
+            res.unwrap().unwrap(),
+            QueryEvent::Origin(ChangeOrigin {
+                actor_id: actor_id.0,
+                local: false,
+            })
+        );
+        let res = timeout(Duration::from_secs(5), origin_rows.recv::<QueryEvent>()).await?;
+        assert!(matches!(
+            res.unwrap().unwrap(),
+            QueryEvent::Change(ChangeType::Insert, RowId(2), _, ChangeId(1))
+        ));
+
         let notify_res = timeout(Duration::from_secs(5), notify_rows.recv::<NotifyEvent>()).await?;
         assert_eq!(
             notify_res.unwrap().unwrap(),
@@ -39,11 +39,19 @@ use tripwire::Tripwire;
 use corro_types::{
     actor::{Actor, ActorId},
     agent::Agent,
-    broadcast::{BroadcastInput, DispatchRuntime, FocaCmd, FocaInput, UniPayload, UniPayloadV1},
+    broadcast::{
+        BroadcastInput, BroadcastV1, DispatchRuntime, FocaCmd, FocaInput, UniPayload, UniPayloadV1,
+    },
     channel::{bounded, CorroReceiver, CorroSender},
 };
 
-use crate::{agent::util::log_at_pow_10, transport::Transport};
+use crate::{
+    agent::{
+        shutdown::{ShutdownStage, ShutdownStages},
+        util::log_at_pow_10,
+    },
+    transport::Transport,
+};
 
 #[derive(Clone)]
 struct TimerSpawner {
@@ -127,12 +135,13 @@ pub fn runtime_loop(
     rx_bcast: CorroReceiver<BroadcastInput>,
     to_send_tx: CorroSender<(Actor, Bytes)>,
     notifications_tx: CorroSender<Notification<Actor>>,
-    tripwire: Tripwire,
+    stages: &ShutdownStages,
 ) {
     debug!("starting runtime loop for actor: {actor:?}");
     let rng = StdRng::from_entropy();
 
-    let config = Arc::new(RwLock::new(make_foca_config(1.try_into().unwrap())));
+    let mtu = agent.config().gossip.mtu;
+    let config = Arc::new(RwLock::new(make_foca_config(1.try_into().unwrap(), mtu)));
 
     let mut foca = Foca::with_custom_broadcast(
         actor,
@@ -161,11 +170,13 @@ pub fn runtime_loop(
 
     // foca SWIM operations loop.
     // NOTE: every turn of that loop should be fast or else we risk being a down suspect
-    spawn_counted({
+    // the cluster is only left once changes are drained, they may still
+    // need to be broadcast to members
+    spawn_counted(stages.track(ShutdownStage::Gossip, {
         let config = config.clone();
         let agent = agent.clone();
         let cluster_size = cluster_size.clone();
-        let mut tripwire = tripwire.clone();
+        let mut tripwire = stages.tripwire(ShutdownStage::Gossip);
         async move {
             let mut metrics_interval = tokio::time::interval(Duration::from_secs(10));
             let mut last_cluster_size = unsafe { NonZeroU32::new_unchecked(1) };
@@ -242,7 +253,7 @@ pub fn runtime_loop(
 
                             if size != last_cluster_size {
                                 debug!("Adjusting cluster size to {size}");
-                                let new_config = make_foca_config(size);
+                                let new_config = make_foca_config(size, mtu);
                                 if let Err(e) = foca.set_config(new_config.clone()) {
                                     error!("foca set_config error: {e}");
                                 } else {
@@ -260,6 +271,30 @@ pub fn runtime_loop(
                                 error!("foca apply_many error: {e}");
                             }
                         }
+                        FocaInput::Suspect(actor) => {
+                            trace!("handling FocaInput::Suspect");
+                            let member = foca
+                                .iter_membership_state()
+                                .find(|member| {
+                                    member.id().id() == actor.id()
+                                        && matches!(member.state(), foca::State::Alive)
+                                })
+                                .map(|member| {
+                                    foca::Member::new(
+                                        member.id().clone(),
+                                        member.incarnation(),
+                                        foca::State::Suspect,
+                                    )
+                                });
+                            if let Some(member) = member {
+                                debug!(actor_id = %actor.id(), "declaring member as suspect");
+                                if let Err(e) =
+                                    foca.apply_many(std::iter::once(member), &mut runtime)
+                                {
+                                    error!("foca apply_many error: {e}");
+                                }
+                            }
+                        }
                         FocaInput::Cmd(cmd) => match cmd {
                             FocaCmd::Rejoin(callback) => {
                                 let renewed = foca.identity().renew().unwrap();
@@ -373,10 +408,19 @@ pub fn runtime_loop(
             }
             info!("foca runtime loop is done, leaving cluster");
         }
-    });
+    }));
 
-    tokio::spawn(handle_broadcasts(
-        agent, rx_bcast, transport, config, tripwire, Default::default()
+    // counted, so that shutdown waits for queued broadcasts to drain
+    spawn_counted(stages.track(
+        ShutdownStage::Drain,
+        handle_broadcasts(
+            agent,
+            rx_bcast,
+            transport,
+            config,
+            stages.tripwire(ShutdownStage::Drain),
+            Default::default(),
+        ),
     ));
 }
 
@@ -402,6 +446,15 @@ impl Default for BroadcastOpts {
     }
 }
 
+/// Codec framing broadcast payloads, frames longer than `max_frame_len`
+/// fail to encode or decode instead of being buffered
+pub fn broadcast_codec(max_frame_len: usize) -> LengthDelimitedCodec {
+    LengthDelimitedCodec::builder()
+        .length_field_type::<u32>()
+        .max_frame_length(max_frame_len)
+        .new_codec()
+}
+
 async fn handle_broadcasts(
     agent: Agent,
     mut rx_bcast: CorroReceiver<BroadcastInput>,
@@ -415,9 +468,7 @@ async fn handle_broadcasts(
     // max broadcast size
     let broadcast_cutoff: usize = opts.bcast_cutoff;
 
-    let mut bcast_codec = LengthDelimitedCodec::builder()
-        .max_frame_length(10 * 1_024 * 1_024)
-        .new_codec();
+    let mut bcast_codec = broadcast_codec(agent.config().gossip.max_broadcast_frame_len);
 
     let mut bcast_buf = BytesMut::new();
     let mut local_bcast_buf = BytesMut::new();
@@ -437,9 +488,16 @@ async fn handle_broadcasts(
         BroadcastDeadline,
         WokePendingBroadcast(PendingBroadcast),
         Tripped,
+        DrainTimeout,
         Metrics,
     }
 
+    // once tripped, keep going until queued broadcasts are sent, up to
+    // this long
+    let drain_timeout = Duration::from_secs(agent.config().perf.shutdown_drain_timeout_secs);
+    let drain_deadline = tokio::time::sleep(Duration::ZERO);
+    tokio::pin!(drain_deadline);
+
     let mut tripped = false;
     let mut ser_buf = BytesMut::new();
 
@@ -460,6 +518,16 @@ async fn handle_broadcasts(
     let mut rate_limited = false;
 
     loop {
+        if tripped
+            && rx_bcast.is_empty()
+            && to_local_broadcast.is_empty()
+            && to_broadcast.is_empty()
+            && join_set.is_empty()
+        {
+            info!("drained broadcasts before shutdown");
+            break;
+        }
+
         let branch = tokio::select! {
             biased;
             input = rx_bcast.recv() => match input {
@@ -491,6 +559,9 @@ async fn handle_broadcasts(
                 tripped = true;
                 Branch::Tripped
             },
+            _ = &mut drain_deadline, if tripped => {
+                Branch::DrainTimeout
+            },
             _ = metrics_interval.tick() => {
                 Branch::Metrics
             }
@@ -498,32 +569,46 @@ async fn handle_broadcasts(
 
         match branch {
             Branch::Tripped => {
-                // nothing to do here, yet!
-                warn!("tripped broadcast loop");
+                if drain_timeout.is_zero() {
+                    warn!("tripped broadcast loop");
+                    break;
+                }
+                info!("tripped broadcast loop, draining queued broadcasts for up to {drain_timeout:?}");
+                drain_deadline
+                    .as_mut()
+                    .reset(tokio::time::Instant::now() + drain_timeout);
+            }
+            Branch::DrainTimeout => {
+                warn!(
+                    "could not drain broadcasts before shutdown, dropping {} queued broadcast(s)",
+                    to_broadcast.len() + to_local_broadcast.len()
+                );
+                counter!("corro.broadcast.shutdown.dropped")
+                    .increment((to_broadcast.len() + to_local_broadcast.len()) as u64);
                 break;
             }
             Branch::BroadcastDeadline => {
-                if !bcast_buf.is_empty() {
-                    to_broadcast.push_front(PendingBroadcast::new(bcast_buf.split().freeze()));
-                }
-                if !local_bcast_buf.is_empty() {
-                    to_broadcast.push_front(PendingBroadcast::new_local(
-                        local_bcast_buf.split().freeze(),
-                    ));
-                }
+                flush_broadcast_bufs(&mut bcast_buf, &mut local_bcast_buf, &mut to_broadcast);
             }
             Branch::Broadcast(input) => {
                 trace!("handling Branch::Broadcast");
 
-                let (bcast, is_local) = match input {
-                    BroadcastInput::Rebroadcast(bcast) => (bcast, false),
-                    BroadcastInput::AddBroadcast(bcast) => (bcast, true),
+                let (bcast, is_local, is_priority) = match input {
+                    BroadcastInput::Rebroadcast(bcast) => (bcast, false, false),
+                    BroadcastInput::AddBroadcast(bcast) => (bcast, true, false),
+                    BroadcastInput::PriorityRebroadcast(bcast) => (bcast, false, true),
+                };
+                trace!("adding broadcast: {bcast:?}, local? {is_local}, priority? {is_priority}");
+
+                let (trace_id, is_schema) = match &bcast {
+                    BroadcastV1::Change(change) => (change.trace_id, false),
+                    BroadcastV1::Schema(_) => (None, true),
                 };
-                trace!("adding broadcast: {bcast:?}, local? {is_local}");
 
                 if let Err(e) = (UniPayload::V1 {
                     data: UniPayloadV1::Broadcast(bcast.clone()),
                     cluster_id: agent.cluster_id(),
+                    trace_id,
                 })
                 .write_to_stream((&mut ser_buf).writer())
                 {
@@ -533,7 +618,21 @@ async fn handle_broadcasts(
                 }
                 trace!("ser buf len: {}", ser_buf.len());
 
-                if is_local {
+                if is_schema {
+                    // sent on its own and ahead of queued changes, only to
+                    // members known to decode it
+                    if let Err(e) =
+                        bcast_codec.encode(ser_buf.split().freeze(), &mut single_bcast_buf)
+                    {
+                        error!("could not encode schema broadcast: {e}");
+                        single_bcast_buf.clear();
+                        continue;
+                    }
+
+                    to_broadcast.push_front(PendingBroadcast::new_schema(
+                        single_bcast_buf.split().freeze(),
+                    ));
+                } else if is_local {
                     if let Err(e) =
                         bcast_codec.encode(ser_buf.split().freeze(), &mut single_bcast_buf)
                     {
@@ -553,6 +652,20 @@ async fn handle_broadcasts(
                             local_bcast_buf.split().freeze(),
                         ));
                     }
+                } else if is_priority {
+                    // don't wait for the buffer to fill up or the next deadline,
+                    // send it out before anything already queued
+                    if let Err(e) =
+                        bcast_codec.encode(ser_buf.split().freeze(), &mut single_bcast_buf)
+                    {
+                        error!("could not encode priority broadcast: {e}");
+                        single_bcast_buf.clear();
+                        continue;
+                    }
+
+                    to_broadcast
+                        .push_front(PendingBroadcast::new(single_bcast_buf.split().freeze()));
+                    counter!("corro.broadcast.priority").increment(1);
                 } else {
                     if let Err(e) = bcast_codec.encode(ser_buf.split().freeze(), &mut bcast_buf) {
                         error!("could not encode broadcast: {e}");
@@ -567,7 +680,11 @@ async fn handle_broadcasts(
             }
             Branch::WokePendingBroadcast(pending) => {
                 trace!("handling Branch::WokePendingBroadcast");
-                to_broadcast.push_front(pending);
+                // re-sends only help dissemination, they aren't worth
+                // holding up a shutdown for
+                if !tripped {
+                    to_broadcast.push_front(pending);
+                }
             }
             Branch::Metrics => {
                 trace!("handling Branch::Metrics");
@@ -579,6 +696,11 @@ async fn handle_broadcasts(
             }
         }
 
+        if tripped {
+            // don't wait for the next deadline to send what's buffered
+            flush_broadcast_bufs(&mut bcast_buf, &mut local_bcast_buf, &mut to_broadcast);
+        }
+
         let prev_rate_limited = rate_limited;
 
         // start with local broadcasts, they're higher priority
@@ -683,6 +805,7 @@ async fn handle_broadcasts(
                             if *member_id == actor_id
                                 || state.cluster_id != agent.cluster_id()
                                 || (pending.is_local && ring0.contains(&state.addr))
+                                || (pending.is_schema && !state.schema_broadcasts)
                                 || pending.sent_to.contains(&state.addr)
                             // don't broadcast to this peer
                             {
@@ -778,6 +901,22 @@ async fn handle_broadcasts(
     info!("broadcasts are done");
 }
 
+/// Queue buffered broadcasts for sending
+fn flush_broadcast_bufs(
+    bcast_buf: &mut BytesMut,
+    local_bcast_buf: &mut BytesMut,
+    to_broadcast: &mut VecDeque<PendingBroadcast>,
+) {
+    if !bcast_buf.is_empty() {
+        to_broadcast.push_front(PendingBroadcast::new(bcast_buf.split().freeze()));
+    }
+    if !local_bcast_buf.is_empty() {
+        to_broadcast.push_front(PendingBroadcast::new_local(
+            local_bcast_buf.split().freeze(),
+        ));
+    }
+}
+
 // Drop the oldest, most sent item or the oldest local item
 fn drop_oldest_broadcast(
     queue: &mut VecDeque<PendingBroadcast>,
@@ -934,13 +1073,14 @@ fn diff_member_states(
     }))
 }
 
-fn make_foca_config(cluster_size: NonZeroU32) -> foca::Config {
+fn make_foca_config(cluster_size: NonZeroU32, mtu: u16) -> foca::Config {
     let mut config = foca::Config::new_wan(cluster_size);
     config.remove_down_after = Duration::from_secs(2 * 24 * 3600);
 
-    // max payload size for udp datagrams, use a safe value here...
+    // max payload size for QUIC datagrams, `gossip.mtu` is validated to be
+    // well above zero
     // TODO: calculate from smallest max datagram size for all QUIC conns
-    config.max_packet_size = 1178.try_into().unwrap();
+    config.max_packet_size = (mtu as usize).try_into().unwrap();
 
     config
 }
@@ -949,6 +1089,8 @@ fn make_foca_config(cluster_size: NonZeroU32) -> foca::Config {
 struct PendingBroadcast {
     payload: Bytes,
     is_local: bool,
+    /// Schema changes, only sent to members that can decode them
+    is_schema: bool,
     sent_to: HashSet<SocketAddr>,
     send_count: u8,
 }
@@ -958,6 +1100,7 @@ impl PendingBroadcast {
         Self {
             payload,
             is_local: false,
+            is_schema: false,
             sent_to: Default::default(),
             send_count: 0,
         }
@@ -967,6 +1110,17 @@ impl PendingBroadcast {
         Self {
             payload,
             is_local: true,
+            is_schema: false,
+            sent_to: Default::default(),
+            send_count: 0,
+        }
+    }
+
+    pub fn new_schema(payload: Bytes) -> Self {
+        Self {
+            payload,
+            is_local: false,
+            is_schema: true,
             sent_to: Default::default(),
             send_count: 0,
         }
@@ -1033,11 +1187,174 @@ mod tests {
     use crate::agent::spawn_unipayload_handler;
     use corro_tests::launch_test_agent;
     use corro_types::{
+        actor::ClusterId,
         base::{CrsqlSeq, Version},
-        broadcast::{BroadcastV1, ChangeV1, Changeset},
+        broadcast::{
+            BiPayload, BiPayloadV1, BroadcastDecodeError, BroadcastV1, ChangeV1, Changeset, TraceId,
+        },
+        sync::SyncTraceContextV1,
     };
+    use speedy::Readable;
+    use tokio_util::codec::Decoder;
     use uuid::Uuid;
 
+    #[test]
+    fn test_broadcast_codec_rejects_oversized_frames() {
+        let mut codec = broadcast_codec(1024);
+
+        let mut buf = BytesMut::new();
+        codec
+            .encode(Bytes::from(vec![0u8; 1024]), &mut buf)
+            .unwrap();
+        assert_eq!(codec.decode(&mut buf).unwrap().unwrap().len(), 1024);
+
+        assert!(codec
+            .encode(Bytes::from(vec![0u8; 1025]), &mut BytesMut::new())
+            .is_err());
+
+        // a peer announcing a huge frame is rejected from the length
+        // prefix alone, before anything is allocated for it
+        let mut buf = BytesMut::new();
+        buf.put_u32(u32::MAX);
+        buf.put_slice(b"garbage");
+        assert!(codec.decode(&mut buf).is_err());
+        assert!(buf.capacity() < 1024);
+    }
+
+    #[test]
+    fn test_unipayload_trace_id() -> eyre::Result<()> {
+        let trace_id = TraceId::generate();
+        let change = ChangeV1 {
+            actor_id: ActorId(Uuid::new_v4()),
+            changeset: Changeset::Full {
+                version: Version(1),
+                changes: vec![],
+                seqs: CrsqlSeq(0)..=CrsqlSeq(0),
+                last_seq: CrsqlSeq(0),
+                ts: Default::default(),
+            },
+            trace_id: Some(trace_id),
+        };
+
+        let payload = UniPayload::V1 {
+            data: UniPayloadV1::Broadcast(BroadcastV1::Change(change.clone())),
+            cluster_id: ClusterId(1),
+            trace_id: change.trace_id,
+        };
+        let bytes = payload.write_to_vec()?;
+
+        let UniPayload::V1 {
+            data: UniPayloadV1::Broadcast(BroadcastV1::Change(decoded)),
+            cluster_id,
+            trace_id: decoded_trace_id,
+        } = UniPayload::read_from_buffer(&bytes)?
+        else {
+            panic!("expected a change payload");
+        };
+        assert_eq!(cluster_id, ClusterId(1));
+        assert_eq!(decoded_trace_id, Some(trace_id));
+        // the change itself doesn't carry it over the wire, only the envelope
+        assert_eq!(decoded.trace_id, None);
+        assert_eq!(decoded.changeset, change.changeset);
+
+        // payloads from nodes that don't send a trace id still decode
+        let legacy = UniPayload::V1 {
+            data: UniPayloadV1::Broadcast(BroadcastV1::Change(change)),
+            cluster_id: ClusterId(1),
+            trace_id: None,
+        }
+        .write_to_vec()?;
+        let UniPayload::V1 { trace_id, .. } =
+            UniPayload::read_from_buffer(&legacy[..legacy.len() - 1])?;
+        assert_eq!(trace_id, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bipayload_schema_hash() -> eyre::Result<()> {
+        let actor_id = ActorId(Uuid::new_v4());
+        let payload = BiPayload::V1 {
+            data: BiPayloadV1::SyncStart {
+                actor_id,
+                trace_ctx: SyncTraceContextV1::default(),
+            },
+            cluster_id: ClusterId(3),
+            schema_hash: Some(42),
+            schema_broadcasts: true,
+        };
+        let bytes = payload.write_to_vec()?;
+
+        let BiPayload::V1 {
+            data:
+                BiPayloadV1::SyncStart {
+                    actor_id: decoded_actor_id,
+                    ..
+                },
+            cluster_id,
+            schema_hash,
+            schema_broadcasts,
+        } = BiPayload::read_from_buffer(&bytes)?;
+        assert_eq!(decoded_actor_id, actor_id);
+        assert_eq!(cluster_id, ClusterId(3));
+        assert_eq!(schema_hash, Some(42));
+        assert!(schema_broadcasts);
+
+        // nodes that don't send a schema hash still have their cluster id read
+        let BiPayload::V1 {
+            cluster_id,
+            schema_hash,
+            schema_broadcasts,
+            ..
+        } = BiPayload::read_from_buffer(&bytes[..bytes.len() - 10])?;
+        assert_eq!(cluster_id, ClusterId(3));
+        assert_eq!(schema_hash, None);
+        assert!(!schema_broadcasts);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unipayload_versions() -> eyre::Result<()> {
+        let payload = UniPayload::V1 {
+            data: UniPayloadV1::Broadcast(BroadcastV1::Change(ChangeV1 {
+                actor_id: ActorId(Uuid::new_v4()),
+                changeset: Changeset::Empty {
+                    versions: Version(1)..=Version(2),
+                    ts: None,
+                },
+                trace_id: None,
+            })),
+            cluster_id: ClusterId(1),
+            trace_id: None,
+        };
+        let mut bytes = payload.write_to_vec()?;
+
+        let decoded = UniPayload::decode(&bytes)?;
+        assert_eq!(decoded.version(), 0);
+        assert!(matches!(
+            decoded,
+            UniPayload::V1 {
+                cluster_id: ClusterId(1),
+                ..
+            }
+        ));
+
+        // a payload from a node using a newer encoding
+        bytes[..4].copy_from_slice(&7u32.write_to_vec()?);
+        assert!(matches!(
+            UniPayload::decode(&bytes),
+            Err(BroadcastDecodeError::UnsupportedVersion(7))
+        ));
+
+        assert!(matches!(
+            UniPayload::decode(&bytes[..2]),
+            Err(BroadcastDecodeError::InsufficientLength(2))
+        ));
+
+        Ok(())
+    }
+
     #[test]
     fn test_behaviour_when_queue_is_full() -> eyre::Result<()> {
         let max = 4;
@@ -1082,6 +1399,7 @@ mod tests {
         PendingBroadcast {
             payload: Bytes::copy_from_slice(&id.to_be_bytes()),
             is_local: false,
+            is_schema: false,
             send_count,
             sent_to: HashSet::new(),
         }
@@ -1099,7 +1417,10 @@ mod tests {
         let (tx_bcast, rx_bcast) = bounded(100, "bcast");
         let (tx_rtt, _) = mpsc::channel(100);
 
-        let config = Arc::new(RwLock::new(make_foca_config(1.try_into().unwrap())));
+        let config = Arc::new(RwLock::new(make_foca_config(
+            1.try_into().unwrap(),
+            ta1.agent.config().gossip.mtu,
+        )));
         let transport = Transport::new(&ta1.config.gossip, tx_rtt).await?;
 
         let server_config = quinn_plaintext::server_config();
@@ -1124,11 +1445,13 @@ mod tests {
                 last_seq: CrsqlSeq(0),
                 ts: Default::default(),
             },
+            trace_id: None,
         });
         let mut ser_buf = BytesMut::new();
         let _ = UniPayload::V1 {
             data: UniPayloadV1::Broadcast(bcast),
             cluster_id: ta1.agent.cluster_id(),
+            trace_id: None,
         }
         .write_to_stream((&mut ser_buf).writer())?;
         let estimated_size = ser_buf.len();
@@ -1157,6 +1480,7 @@ mod tests {
                         last_seq: CrsqlSeq(0),
                         ts: Default::default(),
                     },
+                    trace_id: None,
                 })))
                 .await?;
         }
@@ -1166,7 +1490,15 @@ mod tests {
             let conn = conn.await.unwrap();
 
             let (tx_changes, mut rx_changes) = bounded(100, "changes");
-            spawn_unipayload_handler(&tripwire, &conn, ta1.agent.cluster_id(), tx_changes);
+            spawn_unipayload_handler(
+                &ta1.agent,
+                &ta1.bookie,
+                &tripwire,
+                &conn,
+                tx_changes,
+                ta1.agent.limits().gossip.clone(),
+                ta1.agent.config().gossip.max_broadcast_frame_len,
+            );
 
             // we should receive five items starting from the biggest version
             for i in (0..5).rev() {
@@ -1183,4 +1515,108 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_priority_broadcast_skips_queue() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+        let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+        let ta1 = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+
+        let (tx_bcast, rx_bcast) = bounded(100, "bcast");
+        let (tx_rtt, _) = mpsc::channel(100);
+
+        let config = Arc::new(RwLock::new(make_foca_config(
+            1.try_into().unwrap(),
+            ta1.agent.config().gossip.mtu,
+        )));
+        let transport = Transport::new(&ta1.config.gossip, tx_rtt).await?;
+
+        let server_config = quinn_plaintext::server_config();
+        let endpoint = quinn::Endpoint::server(server_config, "127.0.0.1:0".parse().unwrap())?;
+        let ta2_gossip_addr = endpoint.local_addr()?;
+
+        let ta2_actor = Actor::new(
+            ActorId(Uuid::new_v4()),
+            ta2_gossip_addr,
+            Default::default(),
+            ta1.agent.cluster_id(),
+        );
+        ta1.agent.members().write().add_member(&ta2_actor);
+
+        let actor_id = ta1.agent.actor_id();
+        let build_bcast = |version| {
+            BroadcastV1::Change(ChangeV1 {
+                actor_id,
+                changeset: Changeset::Full {
+                    version: Version(version),
+                    changes: vec![],
+                    seqs: CrsqlSeq(0)..=CrsqlSeq(0),
+                    last_seq: CrsqlSeq(0),
+                    ts: Default::default(),
+                },
+                trace_id: None,
+            })
+        };
+
+        // long interval and a big cutoff: normal rebroadcasts stay buffered
+        // until the deadline
+        tokio::spawn(handle_broadcasts(
+            ta1.agent.clone(),
+            rx_bcast,
+            transport,
+            config,
+            tripwire.clone(),
+            BroadcastOpts {
+                interval: Duration::from_secs(2),
+                bcast_cutoff: 64 * 1024,
+            },
+        ));
+
+        for i in 0..3 {
+            tx_bcast
+                .send(BroadcastInput::Rebroadcast(build_bcast(i)))
+                .await?;
+        }
+        tx_bcast
+            .send(BroadcastInput::PriorityRebroadcast(build_bcast(10)))
+            .await?;
+
+        if let Some(conn) = endpoint.accept().await {
+            let conn = conn.await.unwrap();
+
+            let (tx_changes, mut rx_changes) = bounded(100, "changes");
+            spawn_unipayload_handler(
+                &ta1.agent,
+                &ta1.bookie,
+                &tripwire,
+                &conn,
+                tx_changes,
+                ta1.agent.limits().gossip.clone(),
+                ta1.agent.config().gossip.max_broadcast_frame_len,
+            );
+
+            // the priority broadcast comes in first, well before the deadline
+            let changes = tokio::time::timeout(Duration::from_secs(1), rx_changes.recv())
+                .await?
+                .unwrap();
+            assert_eq!(changes.0.versions(), Version(10)..=Version(10));
+
+            // the buffered ones follow once the interval ticks
+            let mut versions = vec![];
+            for _ in 0..3 {
+                let changes = tokio::time::timeout(Duration::from_secs(5), rx_changes.recv())
+                    .await?
+                    .unwrap();
+                versions.push(*changes.0.versions().start());
+            }
+            versions.sort();
+            assert_eq!(versions, vec![Version(0), Version(1), Version(2)]);
+        }
+
+        tripwire_tx.send(()).await.ok();
+        tripwire_worker.await;
+        spawn::wait_for_all_pending_handles().await;
+
+        Ok(())
+    }
 }
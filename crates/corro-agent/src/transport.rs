@@ -46,6 +46,19 @@ pub enum TransportError {
     TimedOut(#[from] Elapsed),
 }
 
+impl TransportError {
+    /// Whether the peer closed the connection, as it does when shutting down
+    pub fn is_closed_by_peer(&self) -> bool {
+        matches!(
+            self,
+            TransportError::Connection(ConnectionError::ApplicationClosed(_))
+                | TransportError::Datagram(SendDatagramError::ConnectionLost(
+                    ConnectionError::ApplicationClosed(_)
+                ))
+        )
+    }
+}
+
 impl Transport {
     pub async fn new(
         config: &GossipConfig,
@@ -137,6 +150,15 @@ impl Transport {
         Ok(())
     }
 
+    /// Time a round trip to a peer: sending an empty unidirectional stream
+    /// only finishes once the peer acknowledged it
+    #[tracing::instrument(skip(self), level = "debug", err)]
+    pub async fn probe(&self, addr: SocketAddr) -> Result<Duration, TransportError> {
+        let start = Instant::now();
+        tokio::time::timeout(Duration::from_secs(5), self.send_uni(addr, Bytes::new())).await??;
+        Ok(start.elapsed())
+    }
+
     #[tracing::instrument(skip(self), level = "debug", err)]
     pub async fn open_bi(
         &self,
@@ -1,6 +1,6 @@
 use std::{
-    borrow::Borrow,
-    collections::HashMap,
+    borrow::{Borrow, Cow},
+    collections::{BTreeMap, HashMap},
     fmt::{self, Write},
     hash::Hash,
     ops::{AddAssign, Deref},
@@ -16,6 +16,7 @@ use serde_json::value::RawValue;
 use smallvec::{SmallVec, ToSmallVec};
 use speedy::{Context, Readable, Reader, Writable, Writer};
 use sqlite::ChangeType;
+use uuid::Uuid;
 
 pub mod sqlite;
 
@@ -33,6 +34,9 @@ pub enum TypedQueryEvent<T> {
         change_id: Option<ChangeId>,
     },
     Change(ChangeType, RowId, T, ChangeId),
+    /// Where the change that follows was made, only sent to subscribers
+    /// asking for it
+    Origin(ChangeOrigin),
     Error(CompactString),
 }
 
@@ -43,6 +47,7 @@ impl<T> TypedQueryEvent<T> {
             TypedQueryEvent::Row(rowid, _) => QueryEventMeta::Row(*rowid),
             TypedQueryEvent::EndOfQuery { change_id, .. } => QueryEventMeta::EndOfQuery(*change_id),
             TypedQueryEvent::Change(_, _, _, id) => QueryEventMeta::Change(*id),
+            TypedQueryEvent::Origin(_) => QueryEventMeta::Origin,
             TypedQueryEvent::Error(_) => QueryEventMeta::Error,
         }
     }
@@ -54,6 +59,7 @@ pub enum QueryEventMeta {
     Row(RowId),
     EndOfQuery(Option<ChangeId>),
     Change(ChangeId),
+    Origin,
     Error,
     Notify,
 }
@@ -67,6 +73,15 @@ pub enum TypedNotifyEvent<T> {
     Error(CompactString),
 }
 
+/// Where a change was made
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ChangeOrigin {
+    /// Actor (site id) the change originated from
+    pub actor_id: Uuid,
+    /// Whether the change was made on this node
+    pub local: bool,
+}
+
 /// RowId newtype to differentiate from ChangeId
 #[derive(
     Debug,
@@ -196,16 +211,60 @@ pub enum Statement {
     Simple(String),
     WithParams(String, Vec<SqliteParam>),
     WithNamedParams(String, HashMap<String, SqliteParam>),
+    CompareAndSet(CompareAndSet),
 }
 
 impl Statement {
-    pub fn query(&self) -> &str {
+    pub fn query(&self) -> Cow<'_, str> {
         match self {
             Statement::Verbose { query, .. }
             | Statement::Simple(query)
             | Statement::WithParams(query, _)
-            | Statement::WithNamedParams(query, _) => query,
+            | Statement::WithNamedParams(query, _) => Cow::Borrowed(query),
+            Statement::CompareAndSet(cas) => Cow::Owned(cas.to_sql().0),
+        }
+    }
+}
+
+/// Update a single row only if its current values match the expected ones
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompareAndSet {
+    pub table: String,
+    /// Primary key values identifying the row
+    pub pk: BTreeMap<String, SqliteParam>,
+    /// Values the row's columns must currently hold, a null value
+    /// matches a NULL column
+    #[serde(default)]
+    pub expected: BTreeMap<String, SqliteParam>,
+    /// Values to set when all expectations are met
+    pub set: BTreeMap<String, SqliteParam>,
+}
+
+impl CompareAndSet {
+    /// Guarded `UPDATE` statement and its positional parameters
+    pub fn to_sql(&self) -> (String, Vec<SqliteParam>) {
+        let mut params = Vec::with_capacity(self.set.len() + self.pk.len() + self.expected.len());
+
+        let mut query = format!("UPDATE \"{}\" SET ", self.table.replace('"', "\"\""));
+        for (i, (col, value)) in self.set.iter().enumerate() {
+            if i > 0 {
+                query.push_str(", ");
+            }
+            _ = write!(query, "\"{}\" = ?", col.replace('"', "\"\""));
+            params.push(value.clone());
+        }
+
+        query.push_str(" WHERE ");
+        for (i, (col, value)) in self.pk.iter().chain(self.expected.iter()).enumerate() {
+            if i > 0 {
+                query.push_str(" AND ");
+            }
+            // `IS` so an expected NULL matches
+            _ = write!(query, "\"{}\" IS ?", col.replace('"', "\"\""));
+            params.push(value.clone());
         }
+
+        (query, params)
     }
 }
 
@@ -220,14 +279,159 @@ pub struct ExecResponse {
     pub results: Vec<ExecResult>,
     pub time: f64,
     pub version: Option<u64>,
+    /// Changes to `__corro_schema` a migration would make, only set
+    /// for dry runs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema_diff: Option<Vec<SchemaChange>>,
+    /// What each statement of a transaction did, in the order the
+    /// statements were given. Empty when the transaction was rejected
+    /// before running anything.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub outcomes: Vec<StatementOutcome>,
+}
+
+/// Outcome of a single statement of a transaction
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StatementOutcome {
+    pub result: OutcomeKind,
+    /// Version created by the transaction, only set for applied
+    /// statements of a transaction that recorded changes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<u64>,
+    #[serde(default)]
+    pub rows_affected: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutcomeKind {
+    /// The statement changed at least one row
+    Applied,
+    /// The statement ran without changing anything, or was rolled back
+    /// along with the rest of a failed transaction
+    Noop,
+    /// The statement failed, rolling back the whole transaction
+    Error,
+}
+
+impl StatementOutcome {
+    pub fn noop() -> Self {
+        Self {
+            result: OutcomeKind::Noop,
+            version: None,
+            rows_affected: 0,
+            error: None,
+        }
+    }
+
+    pub fn error<E: Into<String>>(error: E) -> Self {
+        Self {
+            result: OutcomeKind::Error,
+            version: None,
+            rows_affected: 0,
+            error: Some(error.into()),
+        }
+    }
+}
+
+/// A single table or index definition change resulting from a migration
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SchemaChange {
+    pub tbl_name: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub name: String,
+    pub before: Option<String>,
+    pub after: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum ExecResult {
-    Execute { rows_affected: usize, time: f64 },
-    Error { error: String },
+    Execute {
+        rows_affected: usize,
+        time: f64,
+        /// Rowid of the last row inserted by the statement, if it inserted
+        /// into a rowid table
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        last_insert_rowid: Option<i64>,
+    },
+    /// Outcome of a compare-and-set statement, `applied` is false when
+    /// the row didn't hold the expected values
+    CompareAndSet {
+        applied: bool,
+        time: f64,
+    },
+    Error {
+        error: String,
+    },
+}
+
+impl ExecResult {
+    /// Outcome of the statement this is the result of, `version` being
+    /// the version created by its transaction, if any
+    pub fn outcome(&self, version: Option<u64>) -> StatementOutcome {
+        let rows_affected = match self {
+            ExecResult::Execute { rows_affected, .. } => *rows_affected,
+            // a compare-and-set targets a single row
+            ExecResult::CompareAndSet { applied, .. } => usize::from(*applied),
+            ExecResult::Error { error } => return StatementOutcome::error(error.as_str()),
+        };
+
+        if rows_affected == 0 {
+            return StatementOutcome::noop();
+        }
+
+        StatementOutcome {
+            result: OutcomeKind::Applied,
+            version,
+            rows_affected,
+            error: None,
+        }
+    }
+}
+
+/// Results of a batch of read statements run within a single read
+/// transaction, in the order the statements were given
+#[derive(Debug, Serialize, Deserialize)]
+pub struct QueryBatchResponse {
+    pub results: Vec<QueryBatchResult>,
+    pub time: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum QueryBatchResult {
+    Rows {
+        columns: Vec<ColumnName>,
+        rows: Vec<Vec<SqliteValue>>,
+        time: f64,
+    },
+    Error {
+        error: String,
+    },
+}
+
+/// Newline-delimited events emitted by the streaming transactions
+/// endpoint, one per batch of statements
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionStreamEvent {
+    Batch {
+        batch: usize,
+        statements: usize,
+        rows_affected: usize,
+        version: Option<u64>,
+        time: f64,
+    },
+    Error {
+        batch: usize,
+        error: String,
+    },
 }
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TableStatRequest {
     pub tables: Vec<String>,
@@ -355,6 +559,7 @@ pub enum SqliteParam {
     Integer(i64),
     Real(f64),
     Text(CompactString),
+    #[serde(with = "blob")]
     Blob(SmallVec<[u8; 512]>),
     Json(Box<RawValue>),
 }
@@ -389,6 +594,18 @@ impl From<i64> for SqliteParam {
     }
 }
 
+impl From<SqliteValue> for SqliteParam {
+    fn from(value: SqliteValue) -> Self {
+        match value {
+            SqliteValue::Null => Self::Null,
+            SqliteValue::Integer(i) => Self::Integer(i),
+            SqliteValue::Real(f) => Self::Real(f.0),
+            SqliteValue::Text(t) => Self::Text(t),
+            SqliteValue::Blob(b) => Self::Blob(b),
+        }
+    }
+}
+
 impl ToSql for SqliteParam {
     fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
         Ok(match self {
@@ -424,9 +641,91 @@ pub enum SqliteValue {
     Integer(i64),
     Real(Real),
     Text(CompactString),
+    #[serde(with = "blob")]
     Blob(SmallVec<[u8; 512]>),
 }
 
+/// JSON representation of binary values: a `{"$blob": "<base64>"}` object.
+///
+/// Arrays of bytes are also accepted when deserializing, for clients
+/// predating the tagged representation.
+pub mod blob {
+    use std::fmt;
+
+    use base64::{engine::general_purpose::STANDARD, Engine as _};
+    use serde::{
+        de::{self, MapAccess, SeqAccess, Visitor},
+        ser::SerializeMap,
+        Deserializer, Serializer,
+    };
+    use smallvec::SmallVec;
+
+    pub const KEY: &str = "$blob";
+
+    pub fn serialize<T, S>(bytes: T, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: AsRef<[u8]>,
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(1))?;
+        map.serialize_entry(KEY, &STANDARD.encode(bytes))?;
+        map.end()
+    }
+
+    pub fn deserialize<'de, D, const N: usize>(
+        deserializer: D,
+    ) -> Result<SmallVec<[u8; N]>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(BlobVisitor::<N>)
+    }
+
+    struct BlobVisitor<const N: usize>;
+
+    impl<'de, const N: usize> Visitor<'de> for BlobVisitor<N> {
+        type Value = SmallVec<[u8; N]>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(
+                f,
+                "a {{\"{KEY}\": \"<base64>\"}} object or an array of bytes"
+            )
+        }
+
+        fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+            Ok(SmallVec::from_slice(v))
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            let mut bytes = SmallVec::with_capacity(seq.size_hint().unwrap_or(0));
+            while let Some(b) = seq.next_element::<u8>()? {
+                bytes.push(b);
+            }
+            Ok(bytes)
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+            let Some(key) = map.next_key::<String>()? else {
+                return Err(de::Error::missing_field(KEY));
+            };
+            if key != KEY {
+                return Err(de::Error::unknown_field(&key, &[KEY]));
+            }
+            let encoded: String = map.next_value()?;
+            if map.next_key::<String>()?.is_some() {
+                return Err(de::Error::custom(format!(
+                    "unexpected field next to \"{KEY}\""
+                )));
+            }
+            STANDARD
+                .decode(encoded)
+                .map(SmallVec::from_vec)
+                .map_err(de::Error::custom)
+        }
+    }
+}
+
 #[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, PartialEq)]
 #[serde(transparent)]
 pub struct Real(pub f64);
@@ -895,4 +1194,26 @@ mod tests {
         let stmts: Vec<Statement> = serde_json::from_str(json).unwrap();
         println!("stmts: {stmts:?}");
     }
+
+    #[test]
+    fn test_blob_serialization() {
+        let bytes = vec![0u8, 1, 2, 254, 255];
+
+        let s = serde_json::to_string(&SqliteValue::Blob(bytes.clone().into())).unwrap();
+        assert_eq!(s, r#"{"$blob":"AAEC/v8="}"#);
+
+        let value: SqliteValue = serde_json::from_str(&s).unwrap();
+        assert_eq!(value, SqliteValue::Blob(bytes.clone().into()));
+
+        let param: SqliteParam = serde_json::from_str(&s).unwrap();
+        assert!(matches!(param, SqliteParam::Blob(b) if b.as_slice() == bytes.as_slice()));
+
+        // arrays of bytes are still accepted
+        let param: SqliteParam = serde_json::from_str("[0,1,2,254,255]").unwrap();
+        assert!(matches!(param, SqliteParam::Blob(b) if b.as_slice() == bytes.as_slice()));
+
+        // other objects are still passed as JSON
+        let param: SqliteParam = serde_json::from_str(r#"{"blob":"AAEC/v8="}"#).unwrap();
+        assert!(matches!(param, SqliteParam::Json(_)));
+    }
 }
@@ -7,7 +7,10 @@ use hickory_resolver::{
     AsyncResolver,
 };
 use http::uri::PathAndQuery;
-use hyper::{client::HttpConnector, http::HeaderName, Body, StatusCode};
+use hyper::{
+    client::HttpConnector, header::RETRY_AFTER, http::HeaderName, Body, Request, Response,
+    StatusCode,
+};
 use serde::de::DeserializeOwned;
 use std::{
     net::SocketAddr,
@@ -19,7 +22,7 @@ use std::{
 use sub::{QueryStream, SubscriptionStream, UpdatesStream};
 use tokio::{
     sync::{RwLock, RwLockReadGuard},
-    time::timeout,
+    time::{sleep, timeout},
 };
 use tracing::{debug, info, warn};
 use uuid::Uuid;
@@ -28,10 +31,67 @@ const HTTP2_CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
 const HTTP2_KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(10);
 const DNS_RESOLVE_TIMEOUT: Duration = Duration::from_secs(3);
 
+/// How requests rejected because the server is overloaded (`503`) or
+/// rate limited (`429`) are retried. Those requests were not executed,
+/// so retrying them is always safe.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Maximum number of retries before the rejection is returned
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled on every attempt
+    pub initial_backoff: Duration,
+    /// Upper bound for the delay between attempts, including delays
+    /// requested by the server via `Retry-After`
+    pub max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// Never retry, rejections are returned right away
+    pub const fn none() -> Self {
+        Self {
+            max_retries: 0,
+            initial_backoff: Duration::ZERO,
+            max_backoff: Duration::ZERO,
+        }
+    }
+
+    fn delay(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        retry_after
+            .unwrap_or_else(|| {
+                self.initial_backoff
+                    .saturating_mul(2u32.saturating_pow(attempt))
+            })
+            .min(self.max_backoff)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+}
+
+fn is_retryable(status: StatusCode) -> bool {
+    status == StatusCode::SERVICE_UNAVAILABLE || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+fn retry_after<B>(res: &Response<B>) -> Option<Duration> {
+    res.headers()
+        .get(RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+        .map(Duration::from_secs)
+}
+
 #[derive(Clone)]
 pub struct CorrosionApiClient {
     api_addr: SocketAddr,
     api_client: hyper::Client<HttpConnector, Body>,
+    retry: RetryPolicy,
 }
 
 impl CorrosionApiClient {
@@ -45,6 +105,33 @@ impl CorrosionApiClient {
                 .http2_keep_alive_interval(Some(HTTP2_KEEP_ALIVE_INTERVAL))
                 .http2_keep_alive_timeout(HTTP2_KEEP_ALIVE_INTERVAL / 2)
                 .build(connector),
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Send the request built by `make_req`, building and sending it again
+    /// while the server sheds it, as allowed by the retry policy
+    async fn request<F>(&self, make_req: F) -> Result<Response<Body>, Error>
+    where
+        F: Fn() -> Result<Request<Body>, Error>,
+    {
+        let mut attempt = 0;
+        loop {
+            let res = self.api_client.request(make_req()?).await?;
+            let status = res.status();
+            if !is_retryable(status) || attempt >= self.retry.max_retries {
+                return Ok(res);
+            }
+
+            let delay = self.retry.delay(attempt, retry_after(&res));
+            debug!(%status, attempt, "request rejected by {}, retrying in {delay:?}", self.api_addr);
+            sleep(delay).await;
+            attempt += 1;
         }
     }
 
@@ -52,14 +139,17 @@ impl CorrosionApiClient {
         &self,
         statement: &Statement,
     ) -> Result<QueryStream<T>, Error> {
-        let req = hyper::Request::builder()
-            .method(hyper::Method::POST)
-            .uri(format!("http://{}/v1/queries", self.api_addr))
-            .header(hyper::header::CONTENT_TYPE, "application/json")
-            .header(hyper::header::ACCEPT, "application/json")
-            .body(Body::from(serde_json::to_vec(statement)?))?;
-
-        let res = self.api_client.request(req).await?;
+        let body = serde_json::to_vec(statement)?;
+        let res = self
+            .request(|| {
+                Ok(Request::builder()
+                    .method(hyper::Method::POST)
+                    .uri(format!("http://{}/v1/queries", self.api_addr))
+                    .header(hyper::header::CONTENT_TYPE, "application/json")
+                    .header(hyper::header::ACCEPT, "application/json")
+                    .body(Body::from(body.clone()))?)
+            })
+            .await?;
 
         if !res.status().is_success() {
             let status = res.status();
@@ -118,14 +208,17 @@ impl CorrosionApiClient {
             .path_and_query(p_and_q)
             .build()?;
 
-        let req = hyper::Request::builder()
-            .method(hyper::Method::POST)
-            .uri(url)
-            .header(hyper::header::CONTENT_TYPE, "application/json")
-            .header(hyper::header::ACCEPT, "application/json")
-            .body(Body::from(serde_json::to_vec(statement)?))?;
-
-        let res = self.api_client.request(req).await?;
+        let body = serde_json::to_vec(statement)?;
+        let res = self
+            .request(|| {
+                Ok(Request::builder()
+                    .method(hyper::Method::POST)
+                    .uri(url.clone())
+                    .header(hyper::header::CONTENT_TYPE, "application/json")
+                    .header(hyper::header::ACCEPT, "application/json")
+                    .body(Body::from(body.clone()))?)
+            })
+            .await?;
 
         if !res.status().is_success() {
             return Err(Error::UnexpectedStatusCode(res.status()));
@@ -182,13 +275,15 @@ impl CorrosionApiClient {
             .path_and_query(p_and_q)
             .build()?;
 
-        let req = hyper::Request::builder()
-            .method(hyper::Method::GET)
-            .uri(url)
-            .header(hyper::header::ACCEPT, "application/json")
-            .body(hyper::Body::empty())?;
-
-        let res = self.api_client.request(req).await?;
+        let res = self
+            .request(|| {
+                Ok(Request::builder()
+                    .method(hyper::Method::GET)
+                    .uri(url.clone())
+                    .header(hyper::header::ACCEPT, "application/json")
+                    .body(hyper::Body::empty())?)
+            })
+            .await?;
 
         if !res.status().is_success() {
             return Err(Error::UnexpectedStatusCode(res.status()));
@@ -230,14 +325,16 @@ impl CorrosionApiClient {
             .path_and_query(p_and_q)
             .build()?;
 
-        let req = hyper::Request::builder()
-            .method(hyper::Method::POST)
-            .uri(url)
-            .header(hyper::header::CONTENT_TYPE, "application/json")
-            .header(hyper::header::ACCEPT, "application/json")
-            .body(hyper::Body::empty())?;
-
-        let res = self.api_client.request(req).await?;
+        let res = self
+            .request(|| {
+                Ok(Request::builder()
+                    .method(hyper::Method::POST)
+                    .uri(url.clone())
+                    .header(hyper::header::CONTENT_TYPE, "application/json")
+                    .header(hyper::header::ACCEPT, "application/json")
+                    .body(hyper::Body::empty())?)
+            })
+            .await?;
 
         if !res.status().is_success() {
             return Err(Error::UnexpectedStatusCode(res.status()));
@@ -264,14 +361,17 @@ impl CorrosionApiClient {
             format!("http://{}/v1/transactions", self.api_addr)
         };
         println!("uri: {:?}", uri);
-        let req = hyper::Request::builder()
-            .method(hyper::Method::POST)
-            .uri(uri)
-            .header(hyper::header::CONTENT_TYPE, "application/json")
-            .header(hyper::header::ACCEPT, "application/json")
-            .body(Body::from(serde_json::to_vec(statements)?))?;
-
-        let res = self.api_client.request(req).await?;
+        let body = serde_json::to_vec(statements)?;
+        let res = self
+            .request(|| {
+                Ok(Request::builder()
+                    .method(hyper::Method::POST)
+                    .uri(&uri)
+                    .header(hyper::header::CONTENT_TYPE, "application/json")
+                    .header(hyper::header::ACCEPT, "application/json")
+                    .body(Body::from(body.clone()))?)
+            })
+            .await?;
 
         if !res.status().is_success() {
             return Err(Error::UnexpectedStatusCode(res.status()));
@@ -283,14 +383,17 @@ impl CorrosionApiClient {
     }
 
     pub async fn schema(&self, statements: &[Statement]) -> Result<ExecResponse, Error> {
-        let req = hyper::Request::builder()
-            .method(hyper::Method::POST)
-            .uri(format!("http://{}/v1/migrations", self.api_addr))
-            .header(hyper::header::CONTENT_TYPE, "application/json")
-            .header(hyper::header::ACCEPT, "application/json")
-            .body(Body::from(serde_json::to_vec(statements)?))?;
-
-        let res = self.api_client.request(req).await?;
+        let body = serde_json::to_vec(statements)?;
+        let res = self
+            .request(|| {
+                Ok(Request::builder()
+                    .method(hyper::Method::POST)
+                    .uri(format!("http://{}/v1/migrations", self.api_addr))
+                    .header(hyper::header::CONTENT_TYPE, "application/json")
+                    .header(hyper::header::ACCEPT, "application/json")
+                    .body(Body::from(body.clone()))?)
+            })
+            .await?;
 
         if !res.status().is_success() {
             return Err(Error::UnexpectedStatusCode(res.status()));
@@ -710,15 +813,19 @@ pub enum Error {
 
 #[cfg(test)]
 mod tests {
-    use crate::{CorrosionPooledClient, Error};
-    use corro_api_types::SqliteValue;
+    use crate::{CorrosionApiClient, CorrosionPooledClient, Error, RetryPolicy};
+    use corro_api_types::{ExecResponse, SqliteValue};
     use hickory_resolver::AsyncResolver;
-    use hyper::{header::HeaderValue, service::service_fn, Body, Request, Response};
+    use hyper::{
+        header::{HeaderValue, RETRY_AFTER},
+        service::service_fn,
+        Body, Request, Response, StatusCode,
+    };
     use std::{
         convert::Infallible,
         net::SocketAddr,
         sync::{
-            atomic::{AtomicBool, Ordering},
+            atomic::{AtomicBool, AtomicUsize, Ordering},
             Arc,
         },
         time::Duration,
@@ -807,6 +914,94 @@ mod tests {
         (servers, addrs)
     }
 
+    /// Serves `503`s for the first `overloaded` requests, then successful
+    /// transaction responses
+    async fn overloaded_server(overloaded: usize) -> (SocketAddr, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let count = Arc::new(AtomicUsize::new(0));
+
+        tokio::spawn({
+            let count = count.clone();
+            async move {
+                loop {
+                    let (stream, _) = listener.accept().await.unwrap();
+                    let count = count.clone();
+                    tokio::spawn(async move {
+                        let http = hyper::server::conn::Http::new();
+                        _ = http
+                            .serve_connection(
+                                stream,
+                                service_fn(move |_: Request<Body>| {
+                                    let n = count.fetch_add(1, Ordering::SeqCst);
+                                    async move {
+                                        let res = if n < overloaded {
+                                            Response::builder()
+                                                .status(StatusCode::SERVICE_UNAVAILABLE)
+                                                .header(RETRY_AFTER, "0")
+                                                .body(Body::empty())
+                                                .unwrap()
+                                        } else {
+                                            Response::new(Body::from(
+                                                serde_json::to_vec(&ExecResponse {
+                                                    results: vec![],
+                                                    time: 0.0,
+                                                    version: Some(1),
+                                                    schema_diff: None,
+                                                    outcomes: vec![],
+                                                })
+                                                .unwrap(),
+                                            ))
+                                        };
+                                        Ok::<_, Infallible>(res)
+                                    }
+                                }),
+                            )
+                            .await;
+                    });
+                }
+            }
+        });
+
+        (addr, count)
+    }
+
+    #[tokio::test]
+    async fn test_retries_overloaded_requests() {
+        let statements = vec!["SELECT 1".into()];
+
+        let (addr, count) = overloaded_server(2).await;
+        let client = CorrosionApiClient::new(addr);
+        let res = client.execute(&statements, None).await.unwrap();
+        assert_eq!(res.version, Some(1));
+        assert_eq!(count.load(Ordering::SeqCst), 3);
+
+        let (addr, count) = overloaded_server(2).await;
+        let client = CorrosionApiClient::new(addr).with_retry_policy(RetryPolicy::none());
+        let res = client.execute(&statements, None).await;
+        assert!(matches!(
+            res,
+            Err(Error::UnexpectedStatusCode(StatusCode::SERVICE_UNAVAILABLE))
+        ));
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.delay(0, None), Duration::from_millis(100));
+        assert_eq!(policy.delay(2, None), Duration::from_millis(400));
+        assert_eq!(policy.delay(10, None), policy.max_backoff);
+        assert_eq!(
+            policy.delay(0, Some(Duration::from_secs(2))),
+            Duration::from_secs(2)
+        );
+        assert_eq!(
+            policy.delay(0, Some(Duration::from_secs(60))),
+            policy.max_backoff
+        );
+    }
+
     #[tokio::test]
     async fn test_single_address() {
         let statement = "".into();
@@ -14,8 +14,8 @@ use bytes::Buf;
 use chrono::NaiveDateTime;
 use compact_str::CompactString;
 use corro_types::{
-    agent::{Agent, ChangeError},
-    broadcast::broadcast_changes,
+    agent::{Agent, ChangeError, WriteRejection},
+    broadcast::{broadcast_changes, TraceId},
     change::{insert_local_changes, InsertChangesInfo},
     config::PgConfig,
     schema::{parse_sql, Column, Schema, SchemaError, SqliteType, Table},
@@ -1994,6 +1994,12 @@ impl<'conn> Session<'conn> {
                 self.conn.prepare(&cmd.to_string())?
             };
 
+            if !prepped.readonly() {
+                if let Some(rejection) = self.agent.write_rejection() {
+                    return Err(rejection.into());
+                }
+            }
+
             let fields = field_types(&prepped, cmd, FieldFormats::All(FieldFormat::Text))?;
 
             if send_row_desc {
@@ -2088,6 +2094,12 @@ impl<'conn> Session<'conn> {
         max_rows: usize,
         back_tx: &Sender<BackendResponse>,
     ) -> Result<(), QueryError> {
+        if !prepped.readonly() {
+            if let Some(rejection) = self.agent.write_rejection() {
+                return Err(rejection.into());
+            }
+        }
+
         // TODO: maybe we don't need to recompute this...
         let fields = field_types(prepped, cmd, FieldFormats::Each(result_formats))?;
 
@@ -2320,14 +2332,15 @@ impl<'conn> Session<'conn> {
             snap,
         }) = insert_info
         {
-            trace!("committed tx, db_version: {db_version}, last_seq: {last_seq:?}");
+            let trace_id = TraceId::generate();
+            trace!(%trace_id, "committed tx, db_version: {db_version}, last_seq: {last_seq:?}");
 
             book_writer.commit_snapshot(snap);
 
             let agent = self.agent.clone();
 
             spawn_counted(async move {
-                broadcast_changes(agent, db_version, last_seq, version, ts).await
+                broadcast_changes(agent, db_version, last_seq, version, ts, trace_id).await
             });
         }
 
@@ -2403,6 +2416,8 @@ enum QueryError {
     PermitAcquire(#[from] AcquireError),
     #[error(transparent)]
     Change(#[from] ChangeError),
+    #[error(transparent)]
+    WriteRejected(#[from] WriteRejection),
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -2441,6 +2456,17 @@ impl TryFrom<QueryError> for PgWireBackendMessage {
             QueryError::Change(e) => {
                 ErrorInfo::new("ERROR".to_owned(), "XX000".to_owned(), e.to_string()).into()
             }
+            QueryError::WriteRejected(e) => {
+                let code = match e {
+                    WriteRejection::ReadOnly => SqlState::READ_ONLY_SQL_TRANSACTION,
+                    WriteRejection::DiskFull => SqlState::DISK_FULL,
+                    WriteRejection::Saturated(_) => SqlState::INSUFFICIENT_RESOURCES,
+                    WriteRejection::Draining | WriteRejection::TooFewMembers(_) => {
+                        SqlState::CANNOT_CONNECT_NOW
+                    }
+                };
+                ErrorInfo::new("ERROR".to_owned(), code.code().into(), e.to_string()).into()
+            }
         }))
     }
 }
@@ -3669,6 +3695,60 @@ mod tests {
         })
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_pg_rejects_writes_while_draining() -> Result<(), BoxError> {
+        let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+
+        let (ta, server) = setup_pg_test_server(tripwire, None).await?;
+
+        let conn_str = format!(
+            "host={} port={} user=testuser",
+            server.local_addr.ip(),
+            server.local_addr.port()
+        );
+
+        {
+            let (client, client_conn) = tokio_postgres::connect(&conn_str, NoTls).await?;
+            tokio::spawn(client_conn);
+
+            ta.agent.set_draining(true);
+
+            let e = client
+                .execute("INSERT INTO tests VALUES (1,2)", &[])
+                .await
+                .unwrap_err();
+            assert_eq!(
+                e.code().map(|code| code.code()),
+                Some(SqlState::CANNOT_CONNECT_NOW.code())
+            );
+            let e = client
+                .simple_query("INSERT INTO tests VALUES (1,2)")
+                .await
+                .unwrap_err();
+            assert_eq!(
+                e.code().map(|code| code.code()),
+                Some(SqlState::CANNOT_CONNECT_NOW.code())
+            );
+
+            // reads are still served
+            client.simple_query("SELECT 1").await?;
+
+            ta.agent.set_draining(false);
+            assert_eq!(
+                client
+                    .execute("INSERT INTO tests VALUES (1,2)", &[])
+                    .await?,
+                1
+            );
+        }
+
+        tripwire_tx.send(()).await.ok();
+        tripwire_worker.await;
+        wait_for_all_pending_handles().await;
+
+        Ok(())
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn test_pg_ssl() -> Result<(), BoxError> {
         let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
@@ -306,6 +306,8 @@ impl QueryResponseIter {
                             }
                         }
                     }
+                    // origins are never asked for
+                    QueryEvent::Origin(_) => {}
                     QueryEvent::Error(e) => {
                         self.done = true;
                         return Some(Err(Box::new(EvalAltResult::from(e))));
@@ -8,7 +8,7 @@ use std::{
     ops::{Deref, DerefMut, RangeInclusive},
     path::{Path, PathBuf},
     sync::{
-        atomic::{AtomicUsize, Ordering},
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
         Arc,
     },
     time::{Duration, Instant},
@@ -18,14 +18,15 @@ use arc_swap::ArcSwap;
 use camino::Utf8PathBuf;
 use compact_str::{CompactString, ToCompactString};
 use indexmap::IndexMap;
-use metrics::{gauge, histogram};
-use parking_lot::RwLock;
+use metrics::{counter, gauge, histogram};
+use parking_lot::{Mutex, RwLock};
 use rangemap::RangeInclusiveSet;
 use rusqlite::{named_params, Connection, OptionalExtension, Transaction};
 use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
 use tokio::{
     runtime::Handle,
-    sync::{oneshot, Semaphore},
+    sync::{mpsc::error::TrySendError, oneshot, watch, Semaphore},
 };
 use tokio::{
     sync::{
@@ -36,7 +37,7 @@ use tokio::{
     time::timeout,
 };
 use tokio_util::sync::{CancellationToken, DropGuard};
-use tracing::{debug, error, trace, warn};
+use tracing::{debug, error, info, trace, warn};
 use tripwire::Tripwire;
 
 use crate::updates::UpdatesManager;
@@ -44,9 +45,10 @@ use crate::{
     actor::{Actor, ActorId, ClusterId},
     base::{CrsqlDbVersion, CrsqlSeq, Version},
     broadcast::{BroadcastInput, ChangeSource, ChangeV1, FocaInput, Timestamp},
+    change::Change,
     channel::{bounded, CorroSender},
-    config::Config,
-    pubsub::SubsManager,
+    config::{Config, Synchronous},
+    pubsub::{LocalSubscription, MatcherError, SubsManager},
     schema::Schema,
     sqlite::{rusqlite_to_crsqlite, setup_conn, CrConn, Migration, SqlitePool, SqlitePoolError},
 };
@@ -109,15 +111,452 @@ pub struct AgentInner {
     limits: Limits,
     subs_manager: SubsManager,
     updates_manager: UpdatesManager,
+    tripwire: Tripwire,
+    paused: AtomicBool,
+    disk_full: AtomicBool,
+    draining: AtomicBool,
+    joined: AtomicBool,
+    synced: AtomicBool,
+    caught_up_at: RwLock<Option<Instant>>,
+    missing_versions: AtomicBool,
+    ready_tx: watch::Sender<bool>,
+    apply_hooks: RwLock<Vec<CorroSender<AppliedChanges>>>,
+    quarantine: RwLock<ApplyQuarantine>,
+    syncs: ActiveSyncs,
+    redundancy: BroadcastRedundancy,
 }
 
+/// Changes committed to the local database, local or remote, as handed
+/// to apply hooks
+#[derive(Debug, Clone)]
+pub struct AppliedChanges {
+    pub db_version: CrsqlDbVersion,
+    pub changes: Arc<[Change]>,
+}
+
+/// A fully buffered version skipped after failing to apply too many times
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QuarantinedVersion {
+    pub actor_id: ActorId,
+    pub version: Version,
+    /// Failed attempts before it was quarantined
+    pub failures: u32,
+    pub last_error: String,
+}
+
+/// Failing versions tracked at most, the ones with the fewest failures
+/// are forgotten first past that
+pub const MAX_TRACKED_APPLY_FAILURES: usize = 10_000;
+
+/// Counts failures to apply fully buffered versions, which are retried
+/// with a backoff based on that count. A version failing `threshold` times
+/// in a row is quarantined: it stays buffered but isn't attempted again,
+/// so it doesn't keep wasting resources, until released by an operator.
+/// This is not persisted, a restart releases everything.
+#[derive(Debug, Default)]
+pub struct ApplyQuarantine {
+    failures: HashMap<(ActorId, Version), u32>,
+    quarantined: BTreeMap<(ActorId, Version), QuarantinedVersion>,
+}
+
+impl ApplyQuarantine {
+    /// Record a failed attempt, returns the quarantined version if this
+    /// failure reached the threshold. A threshold of 0 never quarantines.
+    pub fn record_failure(
+        &mut self,
+        actor_id: ActorId,
+        version: Version,
+        error: &str,
+        threshold: u32,
+    ) -> Option<QuarantinedVersion> {
+        let key = (actor_id, version);
+        if self.failures.len() >= MAX_TRACKED_APPLY_FAILURES && !self.failures.contains_key(&key) {
+            if let Some(evicted) = self
+                .failures
+                .iter()
+                .min_by_key(|(_, failures)| **failures)
+                .map(|(key, _)| *key)
+            {
+                self.failures.remove(&evicted);
+            }
+        }
+
+        let failures = self.failures.entry(key).or_default();
+        *failures += 1;
+        if threshold == 0 || *failures < threshold {
+            return None;
+        }
+
+        let quarantined = QuarantinedVersion {
+            actor_id,
+            version,
+            failures: *failures,
+            last_error: error.to_owned(),
+        };
+        self.failures.remove(&(actor_id, version));
+        self.quarantined
+            .insert((actor_id, version), quarantined.clone());
+
+        Some(quarantined)
+    }
+
+    /// Forget previous failures of a version that applied, or no longer
+    /// needs to
+    pub fn record_success(&mut self, actor_id: ActorId, version: Version) {
+        self.failures.remove(&(actor_id, version));
+    }
+
+    /// Consecutive failures of a version that isn't quarantined
+    pub fn failures(&self, actor_id: ActorId, version: Version) -> u32 {
+        self.failures
+            .get(&(actor_id, version))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    pub fn is_quarantined(&self, actor_id: ActorId, version: Version) -> bool {
+        self.quarantined.contains_key(&(actor_id, version))
+    }
+
+    pub fn list(&self) -> Vec<QuarantinedVersion> {
+        self.quarantined.values().cloned().collect()
+    }
+
+    /// Release quarantined versions, all of them or only an actor's or a
+    /// single version. Released versions start over with no failures.
+    pub fn release(
+        &mut self,
+        actor_id: Option<ActorId>,
+        version: Option<Version>,
+    ) -> Vec<QuarantinedVersion> {
+        let keys: Vec<_> = self
+            .quarantined
+            .keys()
+            .filter(|(a, v)| {
+                actor_id.map_or(true, |actor_id| actor_id == *a)
+                    && version.map_or(true, |version| version == *v)
+            })
+            .copied()
+            .collect();
+
+        keys.into_iter()
+            .filter_map(|key| self.quarantined.remove(&key))
+            .collect()
+    }
+}
+
+/// Changes received from a peer within a window before its redundancy
+/// is judged, so a handful of repeats doesn't get it suppressed
+pub const REDUNDANT_MIN_CHANGES: u64 = 100;
+
+/// Changes remembered per peer, a window ends early past that
+const REDUNDANT_MAX_TRACKED: usize = 10_000;
+
+/// Copies of a change a peer may send us: once to its ring0 members, and
+/// once more as part of a global broadcast
+pub const REDUNDANT_MAX_COPIES: u32 = 2;
+
+type BroadcastChangeKey = (
+    ActorId,
+    RangeInclusive<Version>,
+    Option<RangeInclusive<CrsqlSeq>>,
+);
+
+/// Broadcast changes a peer sent us within the current window
+#[derive(Debug)]
+struct SourceRedundancy {
+    started: Instant,
+    copies: HashMap<BroadcastChangeKey, u32>,
+    received: u64,
+    repeated: u64,
+    suppressed_until: Option<Instant>,
+}
+
+impl SourceRedundancy {
+    fn new(now: Instant) -> Self {
+        Self {
+            started: now,
+            copies: HashMap::new(),
+            received: 0,
+            repeated: 0,
+            suppressed_until: None,
+        }
+    }
+
+    fn reset(&mut self, now: Instant) {
+        self.started = now;
+        self.copies.clear();
+        self.received = 0;
+        self.repeated = 0;
+    }
+
+    fn is_suppressed(&self, now: Instant) -> bool {
+        self.suppressed_until.is_some_and(|until| now < until)
+    }
+}
+
+/// Peer that just got suppressed, with the window's received and
+/// repeated counts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SuppressedSource {
+    pub addr: SocketAddr,
+    pub received: u64,
+    pub repeated: u64,
+}
+
+/// Tracks the broadcast changes each peer sends us, by the address of
+/// its connection.
+///
+/// A member sends a given change to a node at most
+/// `REDUNDANT_MAX_COPIES` times, whether it made it or relays it, further
+/// copies from the same peer are repeats: they're dropped. Relays
+/// legitimately send changes we already have from other members, those
+/// don't count. A peer whose share of repeats within a
+/// window goes over a threshold is suppressed until the end of the next
+/// window: its frames are decoded at a throttled rate and its changes we
+/// already booked are dropped before they're processed.
+#[derive(Debug, Default)]
+pub struct BroadcastRedundancy {
+    sources: Mutex<HashMap<SocketAddr, SourceRedundancy>>,
+}
+
+impl BroadcastRedundancy {
+    /// Drop the changes `addr` already sent `REDUNDANT_MAX_COPIES` times
+    /// within `window` from `changes`, returning how many were dropped and whether this got
+    /// the peer suppressed. A `threshold` of 0 never suppresses.
+    pub fn drop_repeated<T>(
+        &self,
+        addr: SocketAddr,
+        changes: &mut Vec<(ChangeV1, T)>,
+        window: Duration,
+        threshold: f64,
+        now: Instant,
+    ) -> (u64, Option<SuppressedSource>) {
+        let mut sources = self.sources.lock();
+        // forget peers we stopped hearing from
+        sources.retain(|_, state| {
+            now.duration_since(state.started) < window * 2 || state.is_suppressed(now)
+        });
+
+        let state = sources
+            .entry(addr)
+            .or_insert_with(|| SourceRedundancy::new(now));
+
+        let before = changes.len();
+        changes.retain(|(change, _)| {
+            if now.duration_since(state.started) >= window
+                || state.copies.len() >= REDUNDANT_MAX_TRACKED
+            {
+                state.reset(now);
+            }
+
+            let copies = state
+                .copies
+                .entry((change.actor_id, change.versions(), change.seqs().cloned()))
+                .or_default();
+            *copies += 1;
+            state.received += 1;
+            if *copies > REDUNDANT_MAX_COPIES {
+                state.repeated += 1;
+                return false;
+            }
+            true
+        });
+
+        let mut suppressed = None;
+        if threshold > 0.0
+            && !state.is_suppressed(now)
+            && state.received >= REDUNDANT_MIN_CHANGES
+            && state.repeated as f64 >= state.received as f64 * threshold
+        {
+            suppressed = Some(SuppressedSource {
+                addr,
+                received: state.received,
+                repeated: state.repeated,
+            });
+            state.suppressed_until = Some(now + window * 2);
+            state.reset(now);
+        }
+
+        ((before - changes.len()) as u64, suppressed)
+    }
+
+    pub fn is_suppressed(&self, addr: SocketAddr, now: Instant) -> bool {
+        self.sources
+            .lock()
+            .get(&addr)
+            .is_some_and(|state| state.is_suppressed(now))
+    }
+
+    /// Peers currently suppressed
+    pub fn suppressed(&self, now: Instant) -> Vec<SocketAddr> {
+        self.sources
+            .lock()
+            .iter()
+            .filter(|(_, state)| state.is_suppressed(now))
+            .map(|(addr, _)| *addr)
+            .collect()
+    }
+
+    /// Changes `addr` sent within the current window, and how many of
+    /// them were repeats
+    pub fn window_counts(&self, addr: SocketAddr) -> Option<(u64, u64)> {
+        self.sources
+            .lock()
+            .get(&addr)
+            .map(|state| (state.received, state.repeated))
+    }
+}
+
+/// A sync pulling changes from a peer, as listed to operators
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ActiveSync {
+    pub id: u64,
+    pub actor_id: ActorId,
+    pub addr: SocketAddr,
+    #[serde(with = "time::serde::rfc3339")]
+    pub started_at: OffsetDateTime,
+    pub elapsed_secs: f64,
+    /// Changes received so far
+    pub changes: usize,
+}
+
+struct ActiveSyncEntry {
+    actor_id: ActorId,
+    addr: SocketAddr,
+    started_at: OffsetDateTime,
+    started: Instant,
+    changes: Arc<AtomicUsize>,
+    cancel: CancellationToken,
+}
+
+impl ActiveSyncEntry {
+    fn to_active(&self, id: u64) -> ActiveSync {
+        ActiveSync {
+            id,
+            actor_id: self.actor_id,
+            addr: self.addr,
+            started_at: self.started_at,
+            elapsed_secs: self.started.elapsed().as_secs_f64(),
+            changes: self.changes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Syncs currently pulling changes from peers, so operators can see what
+/// a long catch-up is doing and cancel it
+#[derive(Clone, Default)]
+pub struct ActiveSyncs {
+    next_id: Arc<AtomicU64>,
+    syncs: Arc<RwLock<BTreeMap<u64, ActiveSyncEntry>>>,
+}
+
+impl ActiveSyncs {
+    /// Track a sync with a peer until the returned handle is dropped
+    pub fn register(&self, actor_id: ActorId, addr: SocketAddr) -> ActiveSyncHandle {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let changes = Arc::new(AtomicUsize::new(0));
+        let cancel = CancellationToken::new();
+        self.syncs.write().insert(
+            id,
+            ActiveSyncEntry {
+                actor_id,
+                addr,
+                started_at: OffsetDateTime::now_utc(),
+                started: Instant::now(),
+                changes: changes.clone(),
+                cancel: cancel.clone(),
+            },
+        );
+
+        ActiveSyncHandle {
+            id,
+            changes,
+            cancel,
+            syncs: self.clone(),
+        }
+    }
+
+    pub fn list(&self) -> Vec<ActiveSync> {
+        self.syncs
+            .read()
+            .iter()
+            .map(|(id, entry)| entry.to_active(*id))
+            .collect()
+    }
+
+    /// Cancel a sync, returns it if it was running
+    pub fn cancel(&self, id: u64) -> Option<ActiveSync> {
+        let syncs = self.syncs.read();
+        let entry = syncs.get(&id)?;
+        entry.cancel.cancel();
+        Some(entry.to_active(id))
+    }
+}
+
+/// Registration of a running sync, removed from [`ActiveSyncs`] on drop
+pub struct ActiveSyncHandle {
+    id: u64,
+    changes: Arc<AtomicUsize>,
+    cancel: CancellationToken,
+    syncs: ActiveSyncs,
+}
+
+impl ActiveSyncHandle {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn add_changes(&self, count: usize) {
+        self.changes.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Token cancelled when an operator cancels this sync
+    pub fn cancel_token(&self) -> CancellationToken {
+        self.cancel.clone()
+    }
+}
+
+impl Drop for ActiveSyncHandle {
+    fn drop(&mut self) {
+        self.syncs.syncs.write().remove(&self.id);
+    }
+}
+
+/// Maximum number of incoming syncs served concurrently
+pub const MAX_CONCURRENT_SYNCS: usize = 3;
+
+/// Partial version rows read per query when loading bookkeeping
+pub const DEFAULT_LOAD_BATCH_SIZE: usize = 10_000;
+
 #[derive(Debug, Clone)]
 pub struct Limits {
     pub sync: Arc<Semaphore>,
+    /// Incoming broadcast streams being read
+    pub gossip: Arc<Semaphore>,
+    /// Total number of `gossip` permits
+    pub gossip_capacity: usize,
+    /// Buffered versions being applied in the background, up to
+    /// `apply.concurrency`
+    pub apply: Arc<AtomicUsize>,
+}
+
+impl Limits {
+    /// Number of broadcast streams currently being read
+    pub fn gossip_in_flight(&self) -> usize {
+        self.gossip_capacity
+            .saturating_sub(self.gossip.available_permits())
+    }
+
+    /// Number of buffered versions currently being applied
+    pub fn apply_in_flight(&self) -> usize {
+        self.apply.load(Ordering::Acquire)
+    }
 }
 
 impl Agent {
     pub fn new(config: AgentConfig) -> Self {
+        let gossip_concurrency = config.config.load().perf.gossip_handler_concurrency;
         Self(Arc::new(AgentInner {
             actor_id: config.actor_id,
             pool: config.pool,
@@ -138,13 +577,76 @@ impl Agent {
             schema: config.schema,
             cluster_id: ArcSwap::from_pointee(config.cluster_id),
             limits: Limits {
-                sync: Arc::new(Semaphore::new(3)),
+                sync: Arc::new(Semaphore::new(MAX_CONCURRENT_SYNCS)),
+                gossip: Arc::new(Semaphore::new(gossip_concurrency)),
+                gossip_capacity: gossip_concurrency,
+                apply: Arc::new(AtomicUsize::new(0)),
             },
             subs_manager: config.subs_manager,
             updates_manager: config.updates_manager,
+            tripwire: config.tripwire,
+            paused: AtomicBool::new(false),
+            disk_full: AtomicBool::new(false),
+            draining: AtomicBool::new(false),
+            joined: AtomicBool::new(false),
+            synced: AtomicBool::new(false),
+            caught_up_at: RwLock::new(None),
+            missing_versions: AtomicBool::new(true),
+            ready_tx: watch::channel(false).0,
+            apply_hooks: RwLock::new(vec![]),
+            quarantine: RwLock::new(ApplyQuarantine::default()),
+            syncs: ActiveSyncs::default(),
+            redundancy: BroadcastRedundancy::default(),
         }))
     }
 
+    /// Run `hook` for every set of changes committed to the local
+    /// database, whether they were made locally or received from peers.
+    ///
+    /// Up to `capacity` sets of changes are queued for the hook. When it
+    /// falls behind, new changes are dropped for it instead of stalling
+    /// applies.
+    pub fn register_apply_hook<F, Fut>(&self, capacity: usize, hook: F)
+    where
+        F: Fn(AppliedChanges) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let (tx, mut rx) = bounded(capacity, "apply_hook");
+        self.0.apply_hooks.write().push(tx);
+        tokio::spawn(async move {
+            while let Some(applied) = rx.recv().await {
+                hook(applied).await;
+            }
+        });
+    }
+
+    /// Whether any apply hook is registered
+    pub fn has_apply_hooks(&self) -> bool {
+        !self.0.apply_hooks.read().is_empty()
+    }
+
+    /// Hand committed changes over to the apply hooks, never waits
+    pub fn notify_applied(&self, db_version: CrsqlDbVersion, changes: &[Change]) {
+        if changes.is_empty() {
+            return;
+        }
+
+        let hooks = self.0.apply_hooks.read();
+        if hooks.is_empty() {
+            return;
+        }
+
+        let applied = AppliedChanges {
+            db_version,
+            changes: changes.into(),
+        };
+        for tx in hooks.iter() {
+            if let Err(TrySendError::Full(_)) = tx.try_send(applied.clone()) {
+                counter!("corro.agent.apply_hook.dropped").increment(1);
+            }
+        }
+    }
+
     pub fn actor<C: Into<Option<ClusterId>>>(&self, cluster_id: C) -> Actor {
         Actor::new(
             self.0.actor_id,
@@ -195,6 +697,150 @@ impl Agent {
         &self.0.tx_emptyset
     }
 
+    /// Whether syncing and applying remote changes is paused (maintenance mode)
+    pub fn is_paused(&self) -> bool {
+        self.0.paused.load(Ordering::Acquire)
+    }
+
+    /// Pause or resume syncing and applying remote changes, returns the
+    /// previous state
+    pub fn set_paused(&self, paused: bool) -> bool {
+        self.0.paused.swap(paused, Ordering::AcqRel)
+    }
+
+    /// Whether the last write failed because the disk is full, local
+    /// writes and applying remote changes are held off until a probe
+    /// write succeeds
+    pub fn is_disk_full(&self) -> bool {
+        self.0.disk_full.load(Ordering::Acquire)
+    }
+
+    /// Flag or clear a full disk, returns the previous state
+    pub fn set_disk_full(&self, disk_full: bool) -> bool {
+        self.0.disk_full.swap(disk_full, Ordering::AcqRel)
+    }
+
+    /// Whether this node is draining ahead of maintenance: it stays in the
+    /// cluster and keeps applying remote changes, but rejects syncs, new
+    /// transactions and new subscriptions
+    pub fn is_draining(&self) -> bool {
+        self.0.draining.load(Ordering::Acquire)
+    }
+
+    /// Start or stop draining, returns the previous state
+    pub fn set_draining(&self, draining: bool) -> bool {
+        self.0.draining.swap(draining, Ordering::AcqRel)
+    }
+
+    /// Number of members this node still has to see before accepting
+    /// writes, when `api.min_cluster_size_for_writes` is set and not
+    /// reached yet
+    pub fn missing_members_for_writes(&self) -> Option<usize> {
+        let min = self.config().api.min_cluster_size_for_writes?;
+        let seen = self.members().read().states.len();
+        min.checked_sub(seen).filter(|missing| *missing > 0)
+    }
+
+    /// Name of the first internal channel filled above the configured
+    /// high watermark, accepting more writes would only grow memory usage
+    pub fn saturated_channel(&self) -> Option<&'static str> {
+        let pct = self.config().perf.channel_high_watermark_pct;
+        if self.tx_bcast().is_above_watermark(pct) {
+            Some("broadcast")
+        } else if self.tx_changes().is_above_watermark(pct) {
+            Some("changes")
+        } else if self.tx_apply().is_above_watermark(pct) {
+            Some("apply")
+        } else {
+            None
+        }
+    }
+
+    /// Why local writes can't be accepted right now, if they can't. Every
+    /// write path (the HTTP API and PostgreSQL) checks this before writing.
+    pub fn write_rejection(&self) -> Option<WriteRejection> {
+        if self.config().node_role.is_read_only() {
+            Some(WriteRejection::ReadOnly)
+        } else if self.is_draining() {
+            Some(WriteRejection::Draining)
+        } else if self.is_disk_full() {
+            Some(WriteRejection::DiskFull)
+        } else if let Some(missing) = self.missing_members_for_writes() {
+            Some(WriteRejection::TooFewMembers(missing))
+        } else {
+            self.saturated_channel().map(WriteRejection::Saturated)
+        }
+    }
+
+    /// Record that this node joined the cluster, or that it has nobody to join
+    pub fn set_joined(&self) {
+        self.0.joined.store(true, Ordering::Release);
+        self.check_ready();
+    }
+
+    /// Record that a sync completed, or that there's nobody to sync with
+    pub fn set_synced(&self) {
+        self.0.synced.store(true, Ordering::Release);
+        self.check_ready();
+    }
+
+    /// Record how many versions this node was missing when it last
+    /// generated its sync state
+    pub fn record_sync_needs(&self, need_len: usize) {
+        if need_len == 0 {
+            *self.0.caught_up_at.write() = Some(Instant::now());
+        }
+        self.0.missing_versions.store(need_len > 0, Ordering::Release);
+    }
+
+    /// Record that this node is missing versions it knows about, e.g. when
+    /// received changes leave a gap or only part of a version arrived. Only
+    /// the next sync notices when they've all been received.
+    pub fn mark_missing_versions(&self) {
+        self.0.missing_versions.store(true, Ordering::Release);
+    }
+
+    /// Whether this node was missing versions it knows about when it last
+    /// generated its sync state, or has received changes leaving a gap
+    /// since. True until it first generates its sync state.
+    pub fn is_missing_versions(&self) -> bool {
+        self.0.missing_versions.load(Ordering::Acquire)
+    }
+
+    /// Last time this node wasn't missing any version it knows about
+    pub fn caught_up_at(&self) -> Option<Instant> {
+        *self.0.caught_up_at.read()
+    }
+
+    fn check_ready(&self) {
+        if !(self.0.joined.load(Ordering::Acquire) && self.0.synced.load(Ordering::Acquire)) {
+            return;
+        }
+        self.0.ready_tx.send_if_modified(|ready| {
+            if *ready {
+                return false;
+            }
+            *ready = true;
+            info!(actor_id = %self.0.actor_id, "corrosion fully ready");
+            true
+        });
+    }
+
+    /// Whether this node joined the cluster and completed an initial sync
+    pub fn is_ready(&self) -> bool {
+        *self.0.ready_tx.borrow()
+    }
+
+    /// Resolves once this node joined the cluster and completed an
+    /// initial sync (or determined it is alone)
+    pub fn ready(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut ready_rx = self.0.ready_tx.subscribe();
+        async move {
+            // the sender lives as long as the agent
+            _ = ready_rx.wait_for(|ready| *ready).await;
+        }
+    }
+
     pub fn tx_clear_buf(&self) -> &CorroSender<(ActorId, RangeInclusive<Version>)> {
         &self.0.tx_clear_buf
     }
@@ -223,6 +869,20 @@ impl Agent {
         &self.0.members
     }
 
+    /// Fully buffered versions that keep failing to apply
+    pub fn quarantine(&self) -> &RwLock<ApplyQuarantine> {
+        &self.0.quarantine
+    }
+
+    /// Syncs currently pulling changes from peers
+    pub fn syncs(&self) -> &ActiveSyncs {
+        &self.0.syncs
+    }
+
+    pub fn broadcast_redundancy(&self) -> &BroadcastRedundancy {
+        &self.0.redundancy
+    }
+
     pub fn schema(&self) -> &RwLock<Schema> {
         &self.0.schema
     }
@@ -251,6 +911,25 @@ impl Agent {
         &self.0.updates_manager
     }
 
+    pub fn tripwire(&self) -> &Tripwire {
+        &self.0.tripwire
+    }
+
+    /// Subscribe to a query from within the process, the in-process
+    /// equivalent of `POST /v1/subscriptions`.
+    ///
+    /// Every call gets its own matcher, which is cleaned up when the
+    /// returned stream is dropped.
+    pub fn subscribe(&self, sql: &str) -> Result<LocalSubscription, MatcherError> {
+        self.0.subs_manager.insert_local(
+            sql,
+            &self.config().db.subscriptions_path(),
+            &self.0.schema.read(),
+            &self.0.pool,
+            self.0.tripwire.clone(),
+        )
+    }
+
     pub fn set_cluster_id(&self, cluster_id: ClusterId) {
         self.0.cluster_id.store(Arc::new(cluster_id));
     }
@@ -527,6 +1206,21 @@ struct SplitPoolInner {
     low_tx: CorroSender<oneshot::Sender<DropGuard>>,
 }
 
+/// Reason a node rejects local writes, see [`Agent::write_rejection`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum WriteRejection {
+    #[error("node is a read-only replica, send writes to another node")]
+    ReadOnly,
+    #[error("node is draining for maintenance, send writes to another node")]
+    Draining,
+    #[error("disk is full, writes resume once space is freed")]
+    DiskFull,
+    #[error("not accepting writes until {0} more cluster member(s) are seen")]
+    TooFewMembers(usize),
+    #[error("{0} queue is saturated, try again later")]
+    Saturated(&'static str),
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum PoolError {
     #[error(transparent)]
@@ -555,6 +1249,27 @@ pub enum ChangeError {
     },
     #[error("non-contiguous empties range delete")]
     NonContiguousDelete,
+    #[error("transaction produced {changes} changes, more than the maximum of {max} per version, split it into smaller transactions")]
+    TooManyChanges { changes: u64, max: u64 },
+}
+
+impl ChangeError {
+    /// Whether the change failed because the disk is full (or failing)
+    pub fn is_disk_full(&self) -> bool {
+        match self {
+            ChangeError::Rusqlite { source, .. } => is_disk_full_error(source),
+            _ => false,
+        }
+    }
+}
+
+/// `SQLITE_FULL`, or `SQLITE_IOERR` which a full disk also surfaces as
+/// when the WAL can't grow
+pub fn is_disk_full_error(e: &rusqlite::Error) -> bool {
+    matches!(
+        e.sqlite_error_code(),
+        Some(rusqlite::ErrorCode::DiskFull | rusqlite::ErrorCode::SystemIoFailure)
+    )
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -615,10 +1330,24 @@ impl SplitPool {
     pub async fn create<P: AsRef<Path>>(
         path: P,
         write_sema: Arc<Semaphore>,
+    ) -> Result<Self, SplitPoolCreateError> {
+        Self::create_with_synchronous(path, write_sema, Synchronous::default()).await
+    }
+
+    /// Create the pools, using `synchronous` for the write connection. Read
+    /// connections never commit anything, so it doesn't matter for them.
+    pub async fn create_with_synchronous<P: AsRef<Path>>(
+        path: P,
+        write_sema: Arc<Semaphore>,
+        synchronous: Synchronous,
     ) -> Result<Self, SplitPoolCreateError> {
         let rw_pool = sqlite_pool::Config::new(path.as_ref())
             .max_size(1)
-            .create_pool_transform(rusqlite_to_crsqlite)?;
+            .create_pool_transform(move |conn| {
+                let conn = rusqlite_to_crsqlite(conn)?;
+                conn.pragma_update(None, "synchronous", synchronous.as_str())?;
+                Ok(conn)
+            })?;
 
         debug!("built RW pool");
 
@@ -681,6 +1410,18 @@ impl SplitPool {
         gauge!("corro.sqlite.write.permits.available").set(available_permit as f64);
     }
 
+    /// Close both pools, idle connections are closed right away and the
+    /// ones in use once they're returned. Getting a connection fails from
+    /// then on.
+    pub fn close(&self) {
+        self.0.read.close();
+        self.0.write.close();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.0.write.is_closed()
+    }
+
     // get a read-only connection
     #[tracing::instrument(skip(self), level = "debug")]
     pub async fn read(&self) -> Result<sqlite_pool::Connection<CrConn>, SqlitePoolError> {
@@ -1414,7 +2155,19 @@ impl BookedVersions {
     }
 
     pub fn from_conn(conn: &Connection, actor_id: ActorId) -> rusqlite::Result<Self> {
+        Self::from_conn_in_batches(conn, actor_id, DEFAULT_LOAD_BATCH_SIZE)
+    }
+
+    /// Load an actor's bookkeeping, reading its partial versions
+    /// `batch_size` rows at a time so no single read keeps a snapshot
+    /// open (and the WAL from being checkpointed) for the whole scan
+    pub fn from_conn_in_batches(
+        conn: &Connection,
+        actor_id: ActorId,
+        batch_size: usize,
+    ) -> rusqlite::Result<Self> {
         trace!("from_conn");
+        let batch_size = cmp::max(batch_size, 1);
         let mut bv = BookedVersions::new(actor_id);
 
         // fetch the biggest version we know, a partial version might override
@@ -1426,28 +2179,45 @@ impl BookedVersions {
             .query_row([actor_id], |row| row.get(0))?;
 
         {
-            // fetch known partial sequences
+            // fetch known partial sequences, resuming after the last row of
+            // the previous batch
             let mut prepped = conn.prepare_cached(
-            "SELECT version, start_seq, end_seq, last_seq, ts FROM __corro_seq_bookkeeping WHERE site_id = ?",
+                "SELECT version, start_seq, end_seq, last_seq, ts
+                    FROM __corro_seq_bookkeeping
+                    WHERE site_id = :actor_id
+                      AND (:version IS NULL OR (version, start_seq) > (:version, :start_seq))
+                    ORDER BY version ASC, start_seq ASC
+                    LIMIT :limit",
             )?;
-            let mut rows = prepped.query([actor_id])?;
+            let mut last: Option<(Version, CrsqlSeq)> = None;
 
             loop {
-                let row = rows.next()?;
-                match row {
-                    None => break,
-                    Some(row) => {
-                        let version = row.get(0)?;
-                        // NOTE: use normal insert logic to have a consistent behavior
-                        bv.insert_partial(
-                            version,
-                            PartialVersion {
-                                seqs: RangeInclusiveSet::from_iter(vec![row.get(1)?..=row.get(2)?]),
-                                last_seq: row.get(3)?,
-                                ts: row.get(4)?,
-                            },
-                        );
-                    }
+                let mut rows = prepped.query(named_params! {
+                    ":actor_id": actor_id,
+                    ":version": last.map(|(version, _)| version),
+                    ":start_seq": last.map(|(_, start_seq)| start_seq),
+                    ":limit": batch_size as i64,
+                })?;
+
+                let mut count = 0;
+                while let Some(row) = rows.next()? {
+                    count += 1;
+                    let version = row.get(0)?;
+                    let start_seq = row.get(1)?;
+                    // NOTE: use normal insert logic to have a consistent behavior
+                    bv.insert_partial(
+                        version,
+                        PartialVersion {
+                            seqs: RangeInclusiveSet::from_iter(vec![start_seq..=row.get(2)?]),
+                            last_seq: row.get(3)?,
+                            ts: row.get(4)?,
+                        },
+                    );
+                    last = Some((version, start_seq));
+                }
+
+                if count < batch_size {
+                    break;
                 }
             }
         }
@@ -1814,6 +2584,7 @@ pub fn find_overwritten_versions(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::broadcast::Changeset;
     use rangemap::range_inclusive_set;
 
     #[test]
@@ -2028,6 +2799,243 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_apply_quarantine() {
+        let mut quarantine = ApplyQuarantine::default();
+        let a = ActorId(uuid::Uuid::new_v4());
+        let b = ActorId(uuid::Uuid::new_v4());
+
+        // disabled, failures are still counted for retries
+        assert!(quarantine
+            .record_failure(a, Version(1), "boom", 0)
+            .is_none());
+        assert!(!quarantine.is_quarantined(a, Version(1)));
+        assert_eq!(quarantine.failures(a, Version(1)), 1);
+        quarantine.record_success(a, Version(1));
+        assert_eq!(quarantine.failures(a, Version(1)), 0);
+
+        assert!(quarantine
+            .record_failure(a, Version(1), "boom", 3)
+            .is_none());
+        assert!(quarantine
+            .record_failure(a, Version(1), "boom", 3)
+            .is_none());
+        // applying resets the count
+        quarantine.record_success(a, Version(1));
+        assert!(quarantine
+            .record_failure(a, Version(1), "boom", 3)
+            .is_none());
+        assert!(quarantine
+            .record_failure(a, Version(1), "boom", 3)
+            .is_none());
+        assert!(!quarantine.is_quarantined(a, Version(1)));
+
+        let quarantined = quarantine
+            .record_failure(a, Version(1), "no such column", 3)
+            .unwrap();
+        assert_eq!(quarantined.failures, 3);
+        assert_eq!(quarantined.last_error, "no such column");
+        assert!(quarantine.is_quarantined(a, Version(1)));
+        // later versions aren't affected
+        assert!(!quarantine.is_quarantined(a, Version(2)));
+
+        quarantine.record_failure(a, Version(2), "boom", 1).unwrap();
+        quarantine.record_failure(b, Version(1), "boom", 1).unwrap();
+        assert_eq!(quarantine.list().len(), 3);
+
+        let released = quarantine.release(Some(a), Some(Version(2)));
+        assert_eq!(released.len(), 1);
+        assert_eq!(released[0].version, Version(2));
+
+        let released = quarantine.release(Some(a), None);
+        assert_eq!(released.len(), 1);
+        assert!(!quarantine.is_quarantined(a, Version(1)));
+
+        assert_eq!(quarantine.release(None, None).len(), 1);
+        assert!(quarantine.list().is_empty());
+    }
+
+    #[test]
+    fn test_apply_quarantine_bounded() {
+        let mut quarantine = ApplyQuarantine::default();
+        let a = ActorId(uuid::Uuid::new_v4());
+
+        quarantine.record_failure(a, Version(0), "boom", 0);
+        for version in 0..MAX_TRACKED_APPLY_FAILURES as u64 {
+            quarantine.record_failure(a, Version(version), "boom", 0);
+        }
+        assert_eq!(quarantine.failures.len(), MAX_TRACKED_APPLY_FAILURES);
+
+        // a new failing version evicts one that failed the least
+        quarantine.record_failure(a, Version(u64::MAX), "boom", 0);
+        assert_eq!(quarantine.failures.len(), MAX_TRACKED_APPLY_FAILURES);
+        assert_eq!(quarantine.failures(a, Version(0)), 2);
+        assert_eq!(quarantine.failures(a, Version(u64::MAX)), 1);
+    }
+
+    #[test]
+    fn test_broadcast_redundancy() {
+        let redundancy = BroadcastRedundancy::default();
+        let a = ActorId(uuid::Uuid::new_v4());
+        let b = ActorId(uuid::Uuid::new_v4());
+        let peer1: SocketAddr = "127.0.0.1:8787".parse().unwrap();
+        let peer2: SocketAddr = "127.0.0.1:8788".parse().unwrap();
+        let window = Duration::from_secs(10);
+        let change = |actor_id: ActorId, version: u64| {
+            (
+                ChangeV1 {
+                    actor_id,
+                    changeset: Changeset::Full {
+                        version: Version(version),
+                        changes: vec![],
+                        seqs: CrsqlSeq(0)..=CrsqlSeq(0),
+                        last_seq: CrsqlSeq(0),
+                        ts: Default::default(),
+                    },
+                    trace_id: None,
+                },
+                (),
+            )
+        };
+        let changes = |actor_id: ActorId, count: u64| -> Vec<_> {
+            (1..=count)
+                .map(|version| change(actor_id, version))
+                .collect()
+        };
+
+        // made by one peer, sent to its ring0 and globally, and relayed by
+        // another, none of it is a repeat
+        let start = Instant::now();
+        for peer in [peer1, peer1, peer2] {
+            let mut received = changes(a, REDUNDANT_MIN_CHANGES);
+            let res = redundancy.drop_repeated(peer, &mut received, window, 0.3, start);
+            assert_eq!(res, (0, None));
+            assert_eq!(received.len() as u64, REDUNDANT_MIN_CHANGES);
+        }
+        assert_eq!(
+            redundancy.window_counts(peer1),
+            Some((2 * REDUNDANT_MIN_CHANGES, 0))
+        );
+
+        // a third copy from the same peer is a repeat, the same versions
+        // of another actor aren't
+        let mut received = vec![change(a, 1), change(a, 1), change(b, 1)];
+        let res = redundancy.drop_repeated(peer2, &mut received, window, 0.3, start);
+        assert_eq!(res, (1, None));
+        assert_eq!(received.len(), 2);
+        assert_eq!(received[1].0.actor_id, b);
+
+        // enough repeats get the peer suppressed
+        let mut received = changes(a, REDUNDANT_MIN_CHANGES);
+        let (dropped, suppressed) =
+            redundancy.drop_repeated(peer1, &mut received, window, 0.3, start);
+        assert_eq!(dropped, REDUNDANT_MIN_CHANGES);
+        assert_eq!(
+            suppressed,
+            Some(SuppressedSource {
+                addr: peer1,
+                received: 3 * REDUNDANT_MIN_CHANGES,
+                repeated: REDUNDANT_MIN_CHANGES,
+            })
+        );
+        assert!(redundancy.is_suppressed(peer1, start));
+        assert!(!redundancy.is_suppressed(peer2, start));
+
+        // until the end of the next window, it's not reported again
+        let later = start + window;
+        let mut received = changes(a, 1);
+        assert_eq!(
+            redundancy.drop_repeated(peer1, &mut received, window, 0.3, later),
+            (0, None)
+        );
+        assert_eq!(redundancy.suppressed(later), vec![peer1]);
+        assert!(redundancy.suppressed(start + window * 2).is_empty());
+
+        // disabled
+        let mut received = changes(b, REDUNDANT_MIN_CHANGES);
+        for _ in 0..REDUNDANT_MAX_COPIES {
+            received.extend(changes(b, REDUNDANT_MIN_CHANGES));
+        }
+        let (dropped, suppressed) =
+            redundancy.drop_repeated(peer2, &mut received, window, 0.0, later);
+        assert_eq!(dropped, REDUNDANT_MIN_CHANGES);
+        assert!(suppressed.is_none());
+    }
+
+    #[test]
+    fn test_active_syncs() {
+        let syncs = ActiveSyncs::default();
+        let actor_id = ActorId(uuid::Uuid::new_v4());
+        let addr: SocketAddr = "127.0.0.1:8787".parse().unwrap();
+
+        let first = syncs.register(actor_id, addr);
+        let second = syncs.register(actor_id, addr);
+        assert_ne!(first.id(), second.id());
+
+        first.add_changes(3);
+        first.add_changes(2);
+        let listed = syncs.list();
+        assert_eq!(listed.len(), 2);
+        assert_eq!(listed[0].id, first.id());
+        assert_eq!(listed[0].actor_id, actor_id);
+        assert_eq!(listed[0].changes, 5);
+
+        let token = second.cancel_token();
+        assert!(!token.is_cancelled());
+        let cancelled = syncs.cancel(second.id()).unwrap();
+        assert_eq!(cancelled.id, second.id());
+        assert!(token.is_cancelled());
+        assert!(!first.cancel_token().is_cancelled());
+
+        // done syncs are unregistered
+        let id = second.id();
+        drop(second);
+        assert!(syncs.cancel(id).is_none());
+        assert_eq!(syncs.list().len(), 1);
+
+        drop(first);
+        assert!(syncs.list().is_empty());
+    }
+
+    #[test]
+    fn test_booked_from_conn_in_batches() -> rusqlite::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let mut conn = CrConn::init(Connection::open_in_memory()?)?;
+        setup_conn(&conn)?;
+        let clock = Arc::new(uhlc::HLC::default());
+        migrate(clock, &mut conn)?;
+
+        let actor_id = ActorId::default();
+        for version in 1..=3u64 {
+            for (start_seq, end_seq) in [(0u64, 1u64), (3, 4)] {
+                conn.execute(
+                    "INSERT INTO __corro_seq_bookkeeping (site_id, version, start_seq, end_seq, last_seq, ts)
+                        VALUES (?, ?, ?, ?, 9, ?)",
+                    rusqlite::params![actor_id, version, start_seq, end_seq, Timestamp::zero()],
+                )?;
+            }
+        }
+
+        let all_at_once = BookedVersions::from_conn_in_batches(&conn, actor_id, 1000)?;
+        assert_eq!(all_at_once.partials.len(), 3);
+        let partial = all_at_once.get_partial(&Version(2)).unwrap();
+        assert_eq!(
+            partial.seqs.iter().cloned().collect::<Vec<_>>(),
+            vec![CrsqlSeq(0)..=CrsqlSeq(1), CrsqlSeq(3)..=CrsqlSeq(4)]
+        );
+
+        // batch boundaries falling within and between versions
+        for batch_size in [1, 2, 3] {
+            assert_eq!(
+                BookedVersions::from_conn_in_batches(&conn, actor_id, batch_size)?,
+                all_at_once
+            );
+        }
+
+        Ok(())
+    }
+
     fn insert_everywhere(
         conn: &Connection,
         bv: &mut BookedVersions,
@@ -22,6 +22,7 @@ use tokio::{
 };
 use tracing::{debug, error, trace};
 use uhlc::{ParseNTP64Error, NTP64};
+use uuid::Uuid;
 
 use crate::{
     actor::{Actor, ActorId, ClusterId},
@@ -40,9 +41,36 @@ pub enum UniPayload {
         data: UniPayloadV1,
         #[speedy(default_on_eof)]
         cluster_id: ClusterId,
+        #[speedy(default_on_eof)]
+        trace_id: Option<TraceId>,
     },
 }
 
+impl UniPayload {
+    /// Envelope versions this node can decode. The version is written
+    /// first as the enum tag (`V1` is 0), so a new encoding is rolled out
+    /// by adding a variant: nodes decode every known version and only
+    /// start sending the new one once the whole cluster understands it.
+    pub const SUPPORTED_VERSIONS: RangeInclusive<u32> = 0..=0;
+
+    pub fn version(&self) -> u32 {
+        match self {
+            UniPayload::V1 { .. } => 0,
+        }
+    }
+
+    /// Decode a payload, rejecting versions this node doesn't know about
+    /// before attempting to decode the rest of it
+    pub fn decode(buf: &[u8]) -> Result<Self, BroadcastDecodeError> {
+        let version = u32::read_from_buffer(buf)
+            .map_err(|_| BroadcastDecodeError::InsufficientLength(buf.len()))?;
+        if !Self::SUPPORTED_VERSIONS.contains(&version) {
+            return Err(BroadcastDecodeError::UnsupportedVersion(version));
+        }
+        Ok(Self::read_from_buffer(buf)?)
+    }
+}
+
 #[derive(Debug, Clone, Readable, Writable)]
 pub enum UniPayloadV1 {
     Broadcast(BroadcastV1),
@@ -54,6 +82,14 @@ pub enum BiPayload {
         data: BiPayloadV1,
         #[speedy(default_on_eof)]
         cluster_id: ClusterId,
+        /// Hash of the client's `__corro_schema`, see `schema::schema_hash`.
+        /// It goes last so nodes that don't know about it still read the
+        /// cluster id.
+        #[speedy(default_on_eof)]
+        schema_hash: Option<u64>,
+        /// Whether the client decodes `BroadcastV1::Schema`
+        #[speedy(default_on_eof)]
+        schema_broadcasts: bool,
     },
 }
 
@@ -72,6 +108,8 @@ pub enum FocaInput {
     Data(Bytes),
     ClusterSize(NonZeroU32),
     ApplyMany(Vec<Member<Actor>>),
+    /// Mark an alive member as suspect, e.g. when it's been unreachable
+    Suspect(Actor),
     Cmd(FocaCmd),
 }
 
@@ -90,6 +128,33 @@ pub enum AuthzV1 {
 #[derive(Clone, Debug, Readable, Writable)]
 pub enum BroadcastV1 {
     Change(ChangeV1),
+    Schema(SchemaChangeV1),
+}
+
+/// Schema statements merged into a node's schema through its API, sent
+/// to peers so they apply them before data changes that depend on them.
+/// Merging a schema only ever adds tables, columns and indexes.
+#[derive(Debug, Clone, PartialEq, Readable, Writable)]
+pub struct SchemaChangeV1 {
+    pub actor_id: ActorId,
+    pub statements: Vec<String>,
+}
+
+/// Identifies a local transaction so its changes can be followed through
+/// the apply pipeline of every node they're broadcast to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Readable, Writable)]
+pub struct TraceId(pub [u8; 16]);
+
+impl TraceId {
+    pub fn generate() -> Self {
+        Self(*Uuid::new_v4().as_bytes())
+    }
+}
+
+impl fmt::Display for TraceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Uuid::from_bytes(self.0).as_simple().fmt(f)
+    }
 }
 
 #[derive(Debug, Clone, Copy, strum::IntoStaticStr)]
@@ -97,6 +162,9 @@ pub enum BroadcastV1 {
 pub enum ChangeSource {
     Broadcast,
     Sync,
+    /// Pulled again on purpose to repair a diverged node, applied even if
+    /// the version is already booked
+    Resync,
 }
 
 // TODO: shrink this by mapping primary keys to integers instead of repeating them
@@ -104,6 +172,10 @@ pub enum ChangeSource {
 pub struct ChangeV1 {
     pub actor_id: ActorId,
     pub changeset: Changeset,
+    /// Trace id of the originating transaction, carried in the
+    /// `UniPayload` envelope for broadcasts and not sent during syncs
+    #[speedy(skip)]
+    pub trace_id: Option<TraceId>,
 }
 
 impl Deref for ChangeV1 {
@@ -408,12 +480,17 @@ pub enum BroadcastDecodeError {
     Io(#[from] io::Error),
     #[error("insufficient length received to decode message: {0}")]
     InsufficientLength(usize),
+    #[error("unsupported payload version: {0}")]
+    UnsupportedVersion(u32),
 }
 
 #[derive(Debug)]
 pub enum BroadcastInput {
     Rebroadcast(BroadcastV1),
     AddBroadcast(BroadcastV1),
+    /// Rebroadcast that skips the broadcast interval buffer and is sent out
+    /// ahead of anything already queued
+    PriorityRebroadcast(BroadcastV1),
 }
 
 pub struct DispatchRuntime<T> {
@@ -493,6 +570,7 @@ pub async fn broadcast_changes(
     last_seq: CrsqlSeq,
     version: Version,
     ts: Timestamp,
+    trace_id: TraceId,
 ) -> Result<(), BroadcastError> {
     let actor_id = agent.actor_id();
     let conn = agent.pool().read().await?;
@@ -516,11 +594,12 @@ pub async fn broadcast_changes(
                         counter!("corro.changes.committed", "table" => table_name.to_string(), "source" => "local").increment(count as u64);
                     }
 
-                    trace!("broadcasting changes: {changes:?} for seq: {seqs:?}");
+                    trace!(%trace_id, "broadcasting changes: {changes:?} for seq: {seqs:?}");
 
                     debug!("match_changes db_version: {db_version}");
                     match_changes(agent.subs_manager(), &changes, db_version);
                     match_changes(agent.updates_manager(), &changes, db_version);
+                    agent.notify_applied(db_version, &changes);
 
                     let tx_bcast = agent.tx_bcast().clone();
                     tokio::spawn(async move {
@@ -535,6 +614,7 @@ pub async fn broadcast_changes(
                                         last_seq,
                                         ts,
                                     },
+                                    trace_id: Some(trace_id),
                                 },
                             )))
                             .await
@@ -5,6 +5,7 @@ use corro_api_types::{ColumnName, TableName};
 use corro_base_types::{CrsqlDbVersion, Version};
 use rangemap::RangeInclusiveSet;
 use rusqlite::{named_params, params, Connection, Row};
+use serde::{Deserialize, Serialize};
 use speedy::{Readable, Writable};
 use tracing::{debug, trace, warn};
 
@@ -15,7 +16,7 @@ use crate::{
     broadcast::Timestamp,
 };
 
-#[derive(Debug, Default, Clone, Readable, Writable, PartialEq)]
+#[derive(Debug, Default, Clone, Readable, Writable, PartialEq, Serialize, Deserialize)]
 pub struct Change {
     pub table: TableName,
     pub pk: Vec<u8>,
@@ -67,6 +68,7 @@ pub struct ChunkedChanges<I: Iterator> {
     last_start_seq: CrsqlSeq,
     last_seq: CrsqlSeq,
     max_buf_size: usize,
+    max_changes: usize,
     buffered_size: usize,
     done: bool,
 }
@@ -83,11 +85,19 @@ where
             last_start_seq: start_seq,
             last_seq,
             max_buf_size,
+            max_changes: 0,
             buffered_size: 0,
             done: false,
         }
     }
 
+    /// Also cut a chunk once it holds `max_changes` changes, whatever
+    /// their size, 0 only limits chunks by size
+    pub fn with_max_changes(mut self, max_changes: usize) -> Self {
+        self.max_changes = max_changes;
+        self
+    }
+
     pub fn max_buf_size(&self) -> usize {
         self.max_buf_size
     }
@@ -131,7 +141,9 @@ where
                         break;
                     }
 
-                    if self.buffered_size >= self.max_buf_size {
+                    if self.buffered_size >= self.max_buf_size
+                        || (self.max_changes > 0 && self.changes.len() >= self.max_changes)
+                    {
                         // chunking it up
                         let start_seq = self.last_start_seq;
 
@@ -237,6 +249,16 @@ pub fn insert_local_changes(
             version: Some(version),
         })?;
 
+    if let Some(max) = agent.config().db.max_changes_per_version {
+        let changes = last_seq.0 + 1;
+        if changes > max.get() {
+            return Err(ChangeError::TooManyChanges {
+                changes,
+                max: max.get(),
+            });
+        }
+    }
+
     let versions = version..=version;
 
     tx.prepare_cached(
@@ -574,5 +596,28 @@ mod tests {
         );
 
         assert_eq!(chunker.next(), None);
+
+        // limited by count
+        let mut chunker = ChunkedChanges::new(
+            changes[..5].iter().cloned().map(Ok),
+            CrsqlSeq(0),
+            CrsqlSeq(4),
+            100000,
+        )
+        .with_max_changes(2);
+
+        assert_eq!(
+            chunker.next(),
+            Some(Ok((changes[..2].to_vec(), CrsqlSeq(0)..=CrsqlSeq(1))))
+        );
+        assert_eq!(
+            chunker.next(),
+            Some(Ok((changes[2..4].to_vec(), CrsqlSeq(2)..=CrsqlSeq(3))))
+        );
+        assert_eq!(
+            chunker.next(),
+            Some(Ok((vec![changes[4].clone()], CrsqlSeq(4)..=CrsqlSeq(4))))
+        );
+        assert_eq!(chunker.next(), None);
     }
 }
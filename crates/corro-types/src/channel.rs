@@ -95,6 +95,23 @@ pub fn bounded<T: Send + 'static>(
 }
 
 impl<T> CorroSender<T> {
+    /// Number of messages that can currently be sent without waiting
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+
+    /// Total capacity of the channel
+    pub fn max_capacity(&self) -> usize {
+        self.inner.max_capacity()
+    }
+
+    /// Whether more than `pct` percent of the channel's capacity is in use
+    pub fn is_above_watermark(&self, pct: u8) -> bool {
+        let max = self.max_capacity();
+        let used = max - self.capacity();
+        used * 100 > max * pct as usize
+    }
+
     pub async fn send(&self, value: T) -> Result<(), SendError<T>> {
         let before = Instant::now();
         self.inner
@@ -161,6 +178,11 @@ impl<T> CorroSender<T> {
 }
 
 impl<T> CorroReceiver<T> {
+    /// Whether no message is waiting to be received
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
     pub async fn recv(&mut self) -> Option<T> {
         self.inner.recv().await.map(|r| {
             self.recv_count.increment(1);
@@ -1,8 +1,16 @@
-use std::net::{Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    net::{IpAddr, Ipv6Addr, SocketAddr, SocketAddrV6},
+    num::{NonZeroU32, NonZeroU64, NonZeroUsize},
+    str::FromStr,
+};
 
 use camino::Utf8PathBuf;
 use serde::{Deserialize, Serialize};
-use serde_with::{formats::PreferOne, serde_as, OneOrMany};
+use serde_with::{formats::PreferOne, serde_as, DeserializeAs, OneOrMany, SerializeAs};
+
+use crate::actor::ActorId;
 
 pub const DEFAULT_GOSSIP_PORT: u16 = 4001;
 const DEFAULT_GOSSIP_IDLE_TIMEOUT: u32 = 30;
@@ -15,6 +23,10 @@ const fn default_wal_threshold() -> usize {
     10
 }
 
+const fn default_wal_truncate() -> bool {
+    true
+}
+
 const fn default_processing_queue() -> usize {
     20000
 }
@@ -37,6 +49,18 @@ const fn default_small_channel() -> usize {
     256
 }
 
+const fn default_rtt_channel() -> usize {
+    128
+}
+
+const fn default_sync_idle_timeout() -> u64 {
+    30
+}
+
+const fn default_sync_failure_cooldown() -> u64 {
+    10
+}
+
 const fn default_apply_timeout() -> usize {
     10
 }
@@ -45,6 +69,38 @@ fn default_sql_tx_timeout() -> usize {
     60
 }
 
+const fn default_channel_high_watermark() -> u8 {
+    90
+}
+
+const fn default_slow_apply_threshold() -> u64 {
+    1000
+}
+
+const fn default_shutdown_drain_timeout() -> u64 {
+    5
+}
+
+const fn default_persist_identity() -> bool {
+    true
+}
+
+const fn default_periodic_jitter() -> u8 {
+    10
+}
+
+const fn default_bookkeeping_load_batch_size() -> usize {
+    crate::agent::DEFAULT_LOAD_BATCH_SIZE
+}
+
+const fn default_bookkeeping_load_concurrency() -> usize {
+    4
+}
+
+const fn default_gossip_handler_concurrency() -> usize {
+    256
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     pub db: DbConfig,
@@ -54,6 +110,12 @@ pub struct Config {
     #[serde(default)]
     pub perf: PerfConfig,
 
+    #[serde(default)]
+    pub sync: SyncConfig,
+
+    #[serde(default)]
+    pub apply: ApplyConfig,
+
     #[serde(default)]
     pub admin: AdminConfig,
 
@@ -64,6 +126,56 @@ pub struct Config {
     pub log: LogConfig,
     #[serde(default)]
     pub consul: Option<ConsulConfig>,
+
+    /// Actor ID this agent should use, defaults to the site id persisted
+    /// in the database
+    #[serde(default)]
+    pub actor_id: Option<ActorId>,
+    /// How to reconcile `actor_id` with the persisted site id when they differ
+    #[serde(default)]
+    pub actor_id_policy: ActorIdPolicy,
+    /// Whether this node accepts local writes and serves syncs
+    #[serde(default)]
+    pub node_role: NodeRole,
+    /// Maximum number of requests the API servers handle concurrently,
+    /// extra requests are rejected
+    #[serde(default)]
+    pub max_concurrent_tasks: Option<NonZeroUsize>,
+
+    /// File this config was loaded from, reloads read it again
+    #[serde(skip)]
+    pub config_path: Option<Utf8PathBuf>,
+}
+
+/// Policy for reconciling a configured actor id with the database's site id
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ActorIdPolicy {
+    /// Rewrite the persisted site id to the configured actor id
+    #[default]
+    Override,
+    /// Keep the persisted site id and ignore the configured actor id
+    AdoptPersisted,
+    /// Refuse to start
+    FailOnMismatch,
+}
+
+/// Role of this node in the cluster
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum NodeRole {
+    /// Accept local writes and serve syncs to other nodes
+    #[default]
+    ReadWrite,
+    /// Reject local writes and only catch up from other nodes, never acting
+    /// as a sync source
+    ReadOnlyReplica,
+}
+
+impl NodeRole {
+    pub fn is_read_only(&self) -> bool {
+        matches!(self, NodeRole::ReadOnlyReplica)
+    }
 }
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
@@ -71,6 +183,10 @@ pub struct Config {
 pub struct TelemetryConfig {
     pub prometheus: Option<PrometheusConfig>,
     pub open_telemetry: Option<OtelConfig>,
+    /// Labels added to every metric, e.g. the datacenter or region of
+    /// the node
+    #[serde(default, alias = "static_labels")]
+    pub static_labels: BTreeMap<String, String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -100,6 +216,34 @@ impl Default for AdminConfig {
     }
 }
 
+/// SQLite's `PRAGMA synchronous` level for the connection applying changes
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Synchronous {
+    /// Leave syncing to the OS, an OS crash or power loss can corrupt the
+    /// database
+    Off,
+    /// Sync the WAL at checkpoints, an OS crash or power loss can roll back
+    /// the most recent transactions
+    #[default]
+    Normal,
+    /// Sync the WAL on every commit
+    Full,
+    /// Like `Full`, also syncing the directory when files are removed
+    Extra,
+}
+
+impl Synchronous {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Synchronous::Off => "OFF",
+            Synchronous::Normal => "NORMAL",
+            Synchronous::Full => "FULL",
+            Synchronous::Extra => "EXTRA",
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DbConfig {
     pub path: Utf8PathBuf,
@@ -107,6 +251,19 @@ pub struct DbConfig {
     pub schema_paths: Vec<Utf8PathBuf>,
     #[serde(default)]
     pub subscriptions_path: Option<Utf8PathBuf>,
+    /// Maximum number of changes a single local transaction can produce,
+    /// larger transactions are rejected
+    #[serde(default)]
+    pub max_changes_per_version: Option<NonZeroU64>,
+    /// Periodically force a `wal_checkpoint(TRUNCATE)` when the WAL grows
+    /// over `perf.wal_threshold_gb`, when disabled only SQLite's automatic
+    /// checkpoints run
+    #[serde(default = "default_wal_truncate")]
+    pub wal_truncate: bool,
+    /// How eagerly commits, and the bookkeeping written with them, are
+    /// synced to disk
+    #[serde(default)]
+    pub synchronous: Synchronous,
 }
 
 impl DbConfig {
@@ -133,6 +290,79 @@ pub struct ApiConfig {
     pub authorization: Option<AuthzConfig>,
     #[serde(default)]
     pub pg: Option<PgConfig>,
+    #[serde(default)]
+    pub rate_limit: Option<RateLimitConfig>,
+    #[serde(default)]
+    pub listener: ListenerConfig,
+    #[serde(default)]
+    pub overload_policy: OverloadPolicyConfig,
+    /// Reject transactions until this node has seen at least this many
+    /// other cluster members
+    #[serde(default)]
+    pub min_cluster_size_for_writes: Option<usize>,
+    /// Maximum number of subscriptions this node keeps at once, new
+    /// queries are rejected past it
+    #[serde(default)]
+    pub max_subscriptions: Option<usize>,
+}
+
+/// What to do with requests arriving while a route category is at its
+/// concurrency limit
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+pub struct OverloadPolicyConfig {
+    #[serde(default)]
+    pub transactions: OverloadPolicy,
+    #[serde(default)]
+    pub queries: OverloadPolicy,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OverloadPolicy {
+    /// Reject requests right away
+    #[default]
+    Shed,
+    /// Wait up to `max_wait_ms` for the route to have capacity
+    Queue { max_wait_ms: u64 },
+}
+
+/// Socket options applied to the API listeners before binding
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ListenerConfig {
+    /// Set `SO_REUSEADDR`, allows re-binding while old connections are in `TIME_WAIT`
+    #[serde(default = "default_as_true")]
+    pub reuse_addr: bool,
+    /// Set `SO_REUSEPORT` (unix only), allows multiple sockets to bind the same address
+    #[serde(default)]
+    pub reuse_port: bool,
+    /// Maximum number of pending connections waiting to be accepted
+    #[serde(default = "default_listen_backlog")]
+    pub backlog: u32,
+}
+
+impl Default for ListenerConfig {
+    fn default() -> Self {
+        Self {
+            reuse_addr: true,
+            reuse_port: false,
+            backlog: default_listen_backlog(),
+        }
+    }
+}
+
+const fn default_listen_backlog() -> u32 {
+    1024
+}
+
+/// Per-client (by IP address) token bucket applied to the
+/// transactions and queries endpoints
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    /// Sustained number of requests allowed per second
+    pub per_second: NonZeroU32,
+    /// Maximum number of requests allowed in a burst, defaults to `per_second`
+    #[serde(default)]
+    pub burst: Option<NonZeroU32>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -159,10 +389,16 @@ pub enum AuthzConfig {
     BearerToken(String),
 }
 
+#[serde_as]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GossipConfig {
     #[serde(alias = "addr")]
+    #[serde_as(as = "ScopedSocketAddr")]
     pub bind_addr: SocketAddr,
+    /// Address other nodes should use to reach this node, when it differs
+    /// from `bind_addr` (NAT, containers)
+    #[serde(alias = "advertise_addr")]
+    #[serde_as(as = "Option<ScopedSocketAddr>")]
     pub external_addr: Option<SocketAddr>,
     #[serde(default = "default_gossip_client_addr")]
     pub client_addr: SocketAddr,
@@ -174,10 +410,108 @@ pub struct GossipConfig {
     pub plaintext: bool,
     #[serde(default)]
     pub max_mtu: Option<u16>,
+    /// Maximum size of a SWIM payload, in bytes. Each one is sent as a
+    /// single QUIC datagram, so it has to fit in the network's MTU.
+    #[serde(default = "default_gossip_mtu")]
+    pub mtu: u16,
     #[serde(default = "default_gossip_idle_timeout")]
     pub idle_timeout_secs: u32,
     #[serde(default)]
     pub disable_gso: bool,
+    /// Maximum number of random nodes to announce ourselves to when
+    /// bootstrapping
+    #[serde(default = "default_bootstrap_fanout")]
+    pub bootstrap_fanout: NonZeroUsize,
+    /// Number of known members to pick from when no bootstrap node
+    /// could be resolved
+    #[serde(default = "default_bootstrap_fallback_limit")]
+    pub bootstrap_fallback_limit: NonZeroUsize,
+    /// Minimum interval between cluster size updates sent to foca
+    #[serde(default = "default_cluster_size_debounce")]
+    pub cluster_size_debounce_ms: NonZeroU64,
+    /// How bootstrap hostnames are resolved
+    #[serde(default)]
+    pub dns: DnsConfig,
+    /// Persisted states of members not updated for this long are
+    /// removed on startup, 0 disables pruning
+    #[serde(default = "default_member_prune_after")]
+    pub member_prune_after_secs: u64,
+    /// Maximum length of a single broadcast frame, in bytes. Larger
+    /// frames are rejected instead of buffered.
+    #[serde(default = "default_max_broadcast_frame_len")]
+    pub max_broadcast_frame_len: usize,
+    /// Persist this node's SWIM identity and reuse it on restart, so peers
+    /// keep seeing the same member instead of a new one
+    #[serde(default = "default_persist_identity")]
+    pub persist_identity: bool,
+    /// Window over which broadcast changes a peer sends more than once
+    /// are counted
+    #[serde(default = "default_redundant_window")]
+    pub redundant_window_secs: u64,
+    /// Share (0.0 to 1.0) of a window's broadcast changes a peer sent more
+    /// than once above which it's suppressed, 0 never suppresses
+    #[serde(default = "default_redundant_threshold")]
+    pub redundant_threshold: f64,
+}
+
+impl GossipConfig {
+    /// Parses every bootstrap entry, pointing at the first invalid one
+    pub fn bootstrap_entries(&self) -> Result<Vec<BootstrapEntry>, ConfigError> {
+        self.bootstrap
+            .iter()
+            .map(|entry| {
+                entry
+                    .parse()
+                    .map_err(|error| ConfigError::InvalidBootstrap {
+                        entry: entry.clone(),
+                        error,
+                    })
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DnsConfig {
+    /// Timeout of a single DNS query, defaults to the resolver's
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+    /// Number of attempts before giving up on a query, defaults to the
+    /// resolver's
+    #[serde(default)]
+    pub attempts: Option<usize>,
+    /// Read nameservers and options from the system configuration
+    /// (e.g. `/etc/resolv.conf`)
+    #[serde(default = "default_as_true")]
+    pub use_system_conf: bool,
+    /// Cache DNS responses between bootstrap resolutions
+    #[serde(default = "default_as_true")]
+    pub cache: bool,
+    /// Type of record to look up for bootstrap hostnames
+    #[serde(default)]
+    pub record_type: DnsRecordType,
+}
+
+impl Default for DnsConfig {
+    fn default() -> Self {
+        Self {
+            timeout_ms: None,
+            attempts: None,
+            use_system_conf: true,
+            cache: true,
+            record_type: DnsRecordType::default(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DnsRecordType {
+    /// `AAAA` when the gossip address is IPv6, `A` otherwise
+    #[default]
+    Auto,
+    A,
+    Aaaa,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -200,6 +534,8 @@ pub struct PerfConfig {
     pub bcast_channel_len: usize,
     #[serde(default = "default_small_channel")]
     pub foca_channel_len: usize,
+    #[serde(default = "default_rtt_channel")]
+    pub rtt_channel_len: usize,
     #[serde(default = "default_apply_timeout")]
     pub apply_queue_timeout: usize,
     #[serde(default = "default_apply_queue")]
@@ -210,6 +546,35 @@ pub struct PerfConfig {
     pub processing_queue_len: usize,
     #[serde(default = "default_sql_tx_timeout")]
     pub sql_tx_timeout: usize,
+    /// Percentage of the broadcast, changes or apply channel capacity in use
+    /// above which new transactions are rejected
+    #[serde(default = "default_channel_high_watermark")]
+    pub channel_high_watermark_pct: u8,
+    /// Applying a single version for longer than this is logged and counted
+    /// as a slow apply
+    #[serde(default = "default_slow_apply_threshold")]
+    pub slow_apply_threshold_ms: u64,
+    /// Maximum number of incoming broadcast streams read concurrently,
+    /// across all peers
+    #[serde(default = "default_gossip_handler_concurrency")]
+    pub gossip_handler_concurrency: usize,
+    /// On shutdown, how long to keep broadcasting queued changes and
+    /// applying queued buffered versions before giving up, 0 disables
+    /// draining
+    #[serde(default = "default_shutdown_drain_timeout")]
+    pub shutdown_drain_timeout_secs: u64,
+    /// Percentage by which periodic background tasks (bootstrap refresh,
+    /// db maintenance, metrics collection) have their interval spread,
+    /// up to 50. Derived from the actor id so it is stable per node.
+    #[serde(default = "default_periodic_jitter")]
+    pub periodic_jitter_pct: u8,
+    /// Partial version rows read per query when loading bookkeeping at
+    /// startup
+    #[serde(default = "default_bookkeeping_load_batch_size")]
+    pub bookkeeping_load_batch_size: usize,
+    /// Number of actors whose bookkeeping is loaded concurrently at startup
+    #[serde(default = "default_bookkeeping_load_concurrency")]
+    pub bookkeeping_load_concurrency: usize,
 }
 
 impl Default for PerfConfig {
@@ -224,15 +589,175 @@ impl Default for PerfConfig {
             clearbuf_channel_len: default_mid_channel(),
             bcast_channel_len: default_mid_channel(),
             foca_channel_len: default_small_channel(),
+            rtt_channel_len: default_rtt_channel(),
             apply_queue_timeout: default_apply_timeout(),
             apply_queue_len: default_apply_queue(),
             wal_threshold_gb: default_wal_threshold(),
             processing_queue_len: default_processing_queue(),
             sql_tx_timeout: default_sql_tx_timeout(),
+            channel_high_watermark_pct: default_channel_high_watermark(),
+            slow_apply_threshold_ms: default_slow_apply_threshold(),
+            gossip_handler_concurrency: default_gossip_handler_concurrency(),
+            shutdown_drain_timeout_secs: default_shutdown_drain_timeout(),
+            periodic_jitter_pct: default_periodic_jitter(),
+            bookkeeping_load_batch_size: default_bookkeeping_load_batch_size(),
+            bookkeeping_load_concurrency: default_bookkeeping_load_concurrency(),
         }
     }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncConfig {
+    /// How candidate peers are ranked when picking who to sync with
+    #[serde(default)]
+    pub scoring: SyncScoring,
+    /// Abort a sync when the peer hasn't sent anything for this long
+    #[serde(default = "default_sync_idle_timeout")]
+    pub idle_timeout_secs: u64,
+    /// Don't pick a peer as a sync candidate for this long after a sync
+    /// with it failed, 0 disables the cooldown
+    #[serde(default = "default_sync_failure_cooldown")]
+    pub failure_cooldown_secs: u64,
+    /// Fraction (0.0 to 1.0) of versions applied from syncs whose changes
+    /// are read back afterwards to check they took effect
+    #[serde(default)]
+    pub verify_sample_rate: f64,
+    /// Maximum number of changes sent per sync message, on top of the
+    /// 8KiB per message size limit, 0 only limits messages by size
+    #[serde(default)]
+    pub chunk_size: usize,
+    /// Maximum number of versions requested from a peer per sync, oldest
+    /// first, the rest are requested by the following syncs
+    #[serde(default)]
+    pub max_versions_per_request: Option<NonZeroU64>,
+    /// Push the first sync after startup back by up to this many ms, so
+    /// nodes started together don't all sync at once, 0 disables it
+    #[serde(default)]
+    pub initial_jitter_ms: u64,
+    /// Favor sync candidates in our subnet: their need (or score) is
+    /// weighed this much more, and they win ties. 0 disables it
+    #[serde(default)]
+    pub same_subnet_weight: f64,
+    /// Prefix length shared by IPv4 addresses in the same subnet
+    #[serde(default = "default_subnet_prefix_v4")]
+    pub subnet_prefix_v4: u8,
+    /// Prefix length shared by IPv6 addresses in the same subnet
+    #[serde(default = "default_subnet_prefix_v6")]
+    pub subnet_prefix_v6: u8,
+}
+
+impl Default for SyncConfig {
+    fn default() -> Self {
+        Self {
+            scoring: SyncScoring::default(),
+            idle_timeout_secs: default_sync_idle_timeout(),
+            failure_cooldown_secs: default_sync_failure_cooldown(),
+            verify_sample_rate: 0.0,
+            chunk_size: 0,
+            max_versions_per_request: None,
+            initial_jitter_ms: 0,
+            same_subnet_weight: 0.0,
+            subnet_prefix_v4: default_subnet_prefix_v4(),
+            subnet_prefix_v6: default_subnet_prefix_v6(),
+        }
+    }
+}
+
+/// Scoring function used to rank sync candidates
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SyncScoring {
+    /// Only consider how many versions we need from a peer
+    #[default]
+    NeedOnly,
+    /// Weigh needed versions against the peer's recent sync latency and
+    /// penalize peers that recently failed to sync
+    NeedAndLatency,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApplyConfig {
+    /// Maximum number of fully buffered versions applied concurrently in
+    /// the background. Versions of the same actor are always applied one
+    /// at a time.
+    #[serde(default = "default_apply_concurrency")]
+    pub concurrency: usize,
+    /// How long a version that just became fully buffered waits for
+    /// more versions of the same actor before being applied with them in
+    /// one transaction, 0 applies it right away
+    #[serde(default)]
+    pub buffered_debounce_ms: u64,
+    /// Log and count every incoming change that lost its conflict against
+    /// the local state. Costs an extra query per dropped change.
+    #[serde(default)]
+    pub log_lost_conflicts: bool,
+    /// Custom merge strategies for specific columns, checked before
+    /// handing incoming changes to cr-sqlite
+    #[serde(default)]
+    pub column_merge: Vec<ColumnMergeConfig>,
+    /// Stop retrying a fully buffered version after it failed to apply
+    /// this many times in a row, 0 retries forever
+    #[serde(default = "default_apply_quarantine_after")]
+    pub quarantine_after: u32,
+}
+
+impl Default for ApplyConfig {
+    fn default() -> Self {
+        Self {
+            concurrency: default_apply_concurrency(),
+            buffered_debounce_ms: 0,
+            log_lost_conflicts: false,
+            column_merge: vec![],
+            quarantine_after: default_apply_quarantine_after(),
+        }
+    }
+}
+
+impl ApplyConfig {
+    /// Merge strategy configured for a table's column, if any
+    pub fn merge_strategy(&self, table: &str, column: &str) -> Option<MergeStrategy> {
+        self.column_merge
+            .iter()
+            .find(|merge| merge.table == table && merge.column == column)
+            .map(|merge| merge.strategy)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ColumnMergeConfig {
+    pub table: String,
+    pub column: String,
+    pub strategy: MergeStrategy,
+}
+
+/// How an incoming change to a column is merged with the local value,
+/// on top of cr-sqlite's own conflict resolution
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MergeStrategy {
+    /// Only apply changes whose value is greater or equal to the local one
+    Max,
+    /// Only apply changes from transactions with a newer timestamp than
+    /// the one that last wrote the local value
+    NewestTimestamp,
+}
+
+const fn default_subnet_prefix_v4() -> u8 {
+    24
+}
+
+const fn default_subnet_prefix_v6() -> u8 {
+    64
+}
+
+const fn default_apply_concurrency() -> usize {
+    1
+}
+
+const fn default_apply_quarantine_after() -> u32 {
+    5
+}
+
 fn default_gossip_idle_timeout() -> u32 {
     DEFAULT_GOSSIP_IDLE_TIMEOUT
 }
@@ -240,6 +765,49 @@ fn default_gossip_idle_timeout() -> u32 {
 pub const DEFAULT_GOSSIP_CLIENT_ADDR: SocketAddr =
     SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, 0u16, 0, 0));
 
+fn default_bootstrap_fanout() -> NonZeroUsize {
+    NonZeroUsize::new(10).unwrap()
+}
+
+fn default_bootstrap_fallback_limit() -> NonZeroUsize {
+    NonZeroUsize::new(5).unwrap()
+}
+
+fn default_cluster_size_debounce() -> NonZeroU64 {
+    NonZeroU64::new(1000).unwrap()
+}
+
+/// Bytes a QUIC datagram adds on top of its payload: short header,
+/// packet number, authentication tag and datagram frame header
+pub const QUIC_DATAGRAM_OVERHEAD: u16 = 22;
+
+/// Smallest UDP payload every IPv4 host has to accept
+pub const MIN_GOSSIP_MTU: u16 = 508;
+
+/// Largest IPv4 UDP payload, minus the QUIC datagram overhead
+pub const MAX_GOSSIP_MTU: u16 = 65_507 - QUIC_DATAGRAM_OVERHEAD;
+
+/// Fits a datagram in QUIC's minimum 1200 bytes UDP payload
+const fn default_gossip_mtu() -> u16 {
+    1200 - QUIC_DATAGRAM_OVERHEAD
+}
+
+const fn default_max_broadcast_frame_len() -> usize {
+    10 * 1024 * 1024
+}
+
+const fn default_redundant_window() -> u64 {
+    10
+}
+
+const fn default_redundant_threshold() -> f64 {
+    0.5
+}
+
+const fn default_member_prune_after() -> u64 {
+    7 * 24 * 60 * 60
+}
+
 fn default_gossip_client_addr() -> SocketAddr {
     DEFAULT_GOSSIP_CLIENT_ADDR
 }
@@ -287,10 +855,226 @@ fn default_as_true() -> bool {
     true
 }
 
+#[derive(Debug, thiserror::Error)]
+pub enum ScopedAddrError {
+    #[error("invalid socket address '{0}'")]
+    Invalid(String),
+    #[error("unknown network interface '{0}' in IPv6 zone id")]
+    UnknownInterface(String),
+}
+
+/// Parse a socket address, also accepting IPv6 zone ids given as an
+/// interface name (`[fe80::1%eth0]:4001`) on top of numeric ones, which
+/// are all the standard library handles
+pub fn parse_scoped_socket_addr(s: &str) -> Result<SocketAddr, ScopedAddrError> {
+    if let Ok(addr) = s.parse::<SocketAddr>() {
+        return Ok(addr);
+    }
+
+    let (ip_zone, port) = s
+        .strip_prefix('[')
+        .and_then(|s| s.rsplit_once("]:"))
+        .ok_or_else(|| ScopedAddrError::Invalid(s.to_owned()))?;
+    let port = port
+        .parse::<u16>()
+        .map_err(|_| ScopedAddrError::Invalid(s.to_owned()))?;
+
+    parse_scoped_ipv6(ip_zone, port)
+        .ok_or_else(|| ScopedAddrError::Invalid(s.to_owned()))?
+        .map(SocketAddr::V6)
+}
+
+/// Parse an `ip%zone` IPv6 address without brackets or port, `None` if
+/// it isn't one
+fn parse_scoped_ipv6(ip_zone: &str, port: u16) -> Option<Result<SocketAddrV6, ScopedAddrError>> {
+    let (ip, zone) = ip_zone.split_once('%')?;
+    let ip = ip.parse::<Ipv6Addr>().ok()?;
+    Some(scope_id(zone).map(|scope_id| SocketAddrV6::new(ip, port, 0, scope_id)))
+}
+
+/// Index of the network interface a zone id refers to, by number or name
+fn scope_id(zone: &str) -> Result<u32, ScopedAddrError> {
+    if let Ok(index) = zone.parse::<u32>() {
+        return Ok(index);
+    }
+
+    let name = std::ffi::CString::new(zone)
+        .map_err(|_| ScopedAddrError::UnknownInterface(zone.to_owned()))?;
+    // SAFETY: `name` is a valid nul-terminated string for the whole call
+    match unsafe { libc::if_nametoindex(name.as_ptr()) } {
+        0 => Err(ScopedAddrError::UnknownInterface(zone.to_owned())),
+        index => Ok(index),
+    }
+}
+
+/// `serde_as` adapter for socket addresses written with an interface name
+/// as IPv6 zone id, see [`parse_scoped_socket_addr`]
+pub struct ScopedSocketAddr;
+
+impl<'de> DeserializeAs<'de, SocketAddr> for ScopedSocketAddr {
+    fn deserialize_as<D>(deserializer: D) -> Result<SocketAddr, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        parse_scoped_socket_addr(&s).map_err(serde::de::Error::custom)
+    }
+}
+
+impl SerializeAs<SocketAddr> for ScopedSocketAddr {
+    fn serialize_as<S>(addr: &SocketAddr, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        addr.serialize(serializer)
+    }
+}
+
+/// A bootstrap entry, normalized from its `host[:port][@dns_server[:port]]`
+/// string form
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BootstrapEntry {
+    /// Literal address, used as-is
+    Addr(SocketAddr),
+    /// Hostname resolved at runtime, optionally with a specific DNS server
+    Host {
+        hostname: String,
+        port: u16,
+        resolver: Option<SocketAddr>,
+    },
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum BootstrapEntryError {
+    #[error("empty entry")]
+    Empty,
+    #[error("more than one '@' separator")]
+    MultipleResolvers,
+    #[error("invalid dns server '{0}', expected an IP with an optional port")]
+    InvalidResolver(String),
+    #[error("invalid hostname '{0}'")]
+    InvalidHostname(String),
+    #[error("invalid port '{0}'")]
+    InvalidPort(String),
+    #[error(transparent)]
+    InvalidScope(#[from] ScopedAddrError),
+}
+
+const DEFAULT_DNS_PORT: u16 = 53;
+
+impl FromStr for BootstrapEntry {
+    type Err = BootstrapEntryError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err(BootstrapEntryError::Empty);
+        }
+
+        let mut host_port_dns_server = s.split('@');
+        let host_port = host_port_dns_server.next().unwrap_or_default();
+        let resolver = host_port_dns_server
+            .next()
+            .map(|dns_server| {
+                dns_server
+                    .parse::<SocketAddr>()
+                    .or_else(|_| {
+                        // IPv6 resolvers may be bracketed even without a port
+                        dns_server
+                            .strip_prefix('[')
+                            .and_then(|ip| ip.strip_suffix(']'))
+                            .unwrap_or(dns_server)
+                            .parse::<IpAddr>()
+                            .map(|ip| SocketAddr::new(ip, DEFAULT_DNS_PORT))
+                    })
+                    .map_err(|_| BootstrapEntryError::InvalidResolver(dns_server.to_owned()))
+            })
+            .transpose()?;
+        if host_port_dns_server.next().is_some() {
+            return Err(BootstrapEntryError::MultipleResolvers);
+        }
+
+        if resolver.is_none() {
+            match parse_scoped_socket_addr(host_port) {
+                Ok(addr) => return Ok(BootstrapEntry::Addr(addr)),
+                Err(e @ ScopedAddrError::UnknownInterface(_)) => return Err(e.into()),
+                Err(ScopedAddrError::Invalid(_)) => {}
+            }
+            if let Ok(ip) = host_port.parse::<IpAddr>() {
+                return Ok(BootstrapEntry::Addr(SocketAddr::new(
+                    ip,
+                    DEFAULT_GOSSIP_PORT,
+                )));
+            }
+            if let Some(addr) = parse_scoped_ipv6(host_port, DEFAULT_GOSSIP_PORT) {
+                return Ok(BootstrapEntry::Addr(SocketAddr::V6(addr?)));
+            }
+        }
+
+        let (hostname, port) = match host_port.split_once(':') {
+            Some((hostname, port)) => (
+                hostname,
+                port.parse::<u16>()
+                    .map_err(|_| BootstrapEntryError::InvalidPort(port.to_owned()))?,
+            ),
+            None => (host_port, DEFAULT_GOSSIP_PORT),
+        };
+
+        // fully qualified names may end with a dot
+        let valid_hostname = hostname
+            .strip_suffix('.')
+            .unwrap_or(hostname)
+            .split('.')
+            .all(|label| {
+                !label.is_empty()
+                    && label
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            });
+        if !valid_hostname {
+            return Err(BootstrapEntryError::InvalidHostname(hostname.to_owned()));
+        }
+
+        Ok(BootstrapEntry::Host {
+            hostname: hostname.to_owned(),
+            port,
+            resolver,
+        })
+    }
+}
+
+impl fmt::Display for BootstrapEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BootstrapEntry::Addr(addr) => addr.fmt(f),
+            BootstrapEntry::Host {
+                hostname,
+                port,
+                resolver,
+            } => {
+                write!(f, "{hostname}:{port}")?;
+                if let Some(resolver) = resolver {
+                    write!(f, "@{resolver}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum ConfigError {
     #[error(transparent)]
     Config(#[from] config::ConfigError),
+    #[error("invalid gossip.bootstrap entry '{entry}': {error}")]
+    InvalidBootstrap {
+        entry: String,
+        error: BootstrapEntryError,
+    },
+    #[error("gossip.mtu must be between {MIN_GOSSIP_MTU} and {MAX_GOSSIP_MTU}, got {0}")]
+    GossipMtuOutOfRange(u16),
+    #[error("gossip.mtu ({mtu}) plus {QUIC_DATAGRAM_OVERHEAD} bytes of QUIC overhead does not fit in gossip.max_mtu ({max_mtu})")]
+    GossipMtuAboveMaxMtu { mtu: u16, max_mtu: u16 },
 }
 
 impl Config {
@@ -305,8 +1089,100 @@ impl Config {
             .add_source(config::File::new(config_path, config::FileFormat::Toml))
             .add_source(config::Environment::default().separator("__"))
             .build()?;
-        Ok(config.try_deserialize()?)
+        let mut config: Self = config.try_deserialize()?;
+        config.config_path = Some(config_path.into());
+        Ok(config)
+    }
+
+    /// Checks settings that can't be expressed through deserialization
+    /// alone, so mistakes fail at startup instead of at runtime.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        self.gossip.bootstrap_entries()?;
+
+        let mtu = self.gossip.mtu;
+        if !(MIN_GOSSIP_MTU..=MAX_GOSSIP_MTU).contains(&mtu) {
+            return Err(ConfigError::GossipMtuOutOfRange(mtu));
+        }
+        if let Some(max_mtu) = self.gossip.max_mtu {
+            if mtu + QUIC_DATAGRAM_OVERHEAD > max_mtu {
+                return Err(ConfigError::GossipMtuAboveMaxMtu { mtu, max_mtu });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Take the settings that are read whenever they are used from `new`,
+    /// keeping the current value of every other one since those are only
+    /// read when the agent starts. Returns the names of the settings that
+    /// differed and were kept, those need a restart to change.
+    pub fn keep_restart_only(&self, new: &mut Config) -> Vec<String> {
+        let mut merged = self.clone();
+        macro_rules! reload {
+            ($($field:ident).+) => {
+                merged.$($field).+ = new.$($field).+.clone()
+            };
+        }
+
+        reload!(db.max_changes_per_version);
+        reload!(api.authorization);
+        reload!(api.rate_limit);
+        reload!(api.min_cluster_size_for_writes);
+        reload!(api.max_subscriptions);
+        reload!(gossip.bootstrap);
+        reload!(gossip.bootstrap_fanout);
+        reload!(gossip.bootstrap_fallback_limit);
+        reload!(gossip.dns);
+        reload!(gossip.member_prune_after_secs);
+        reload!(gossip.redundant_window_secs);
+        reload!(gossip.redundant_threshold);
+        reload!(perf.channel_high_watermark_pct);
+        reload!(perf.slow_apply_threshold_ms);
+        reload!(sync.scoring);
+        reload!(sync.idle_timeout_secs);
+        reload!(sync.failure_cooldown_secs);
+        reload!(sync.verify_sample_rate);
+        reload!(sync.chunk_size);
+        reload!(sync.max_versions_per_request);
+        reload!(sync.same_subnet_weight);
+        reload!(sync.subnet_prefix_v4);
+        reload!(sync.subnet_prefix_v6);
+        reload!(apply.log_lost_conflicts);
+        reload!(apply.column_merge);
+        reload!(apply.quarantine_after);
+
+        let kept = changed_settings(&merged, new);
+        *new = merged;
+        kept
+    }
+}
+
+/// Names of the settings (`section.setting`, or top-level ones) that
+/// differ between two configs
+fn changed_settings(a: &Config, b: &Config) -> Vec<String> {
+    let (Ok(serde_json::Value::Object(a)), Ok(serde_json::Value::Object(b))) =
+        (serde_json::to_value(a), serde_json::to_value(b))
+    else {
+        return vec![];
+    };
+
+    let mut changed = vec![];
+    for (name, a_value) in a.iter() {
+        let b_value = b.get(name).unwrap_or(&serde_json::Value::Null);
+        match (a_value, b_value) {
+            (serde_json::Value::Object(a_section), serde_json::Value::Object(b_section)) => {
+                let keys: BTreeSet<&String> = a_section.keys().chain(b_section.keys()).collect();
+                changed.extend(
+                    keys.into_iter()
+                        .filter(|key| a_section.get(*key) != b_section.get(*key))
+                        .map(|key| format!("{name}.{key}")),
+                );
+            }
+            (a_value, b_value) if a_value != b_value => changed.push(name.clone()),
+            _ => {}
+        }
     }
+    changed
 }
 
 #[derive(Debug, Default)]
@@ -321,9 +1197,20 @@ pub struct ConfigBuilder {
     log: Option<LogConfig>,
     schema_paths: Vec<Utf8PathBuf>,
     max_change_size: Option<i64>,
+    max_changes_per_version: Option<NonZeroU64>,
+    wal_truncate: Option<bool>,
+    synchronous: Synchronous,
     consul: Option<ConsulConfig>,
     tls: Option<TlsConfig>,
     perf: Option<PerfConfig>,
+    sync: Option<SyncConfig>,
+    apply: Option<ApplyConfig>,
+    min_cluster_size_for_writes: Option<usize>,
+    max_subscriptions: Option<usize>,
+    actor_id: Option<ActorId>,
+    actor_id_policy: ActorIdPolicy,
+    node_role: NodeRole,
+    max_concurrent_tasks: Option<NonZeroUsize>,
 }
 
 impl ConfigBuilder {
@@ -337,6 +1224,11 @@ impl ConfigBuilder {
         self
     }
 
+    pub fn external_addr(mut self, addr: SocketAddr) -> Self {
+        self.external_addr = Some(addr);
+        self
+    }
+
     pub fn api_addr(mut self, addr: SocketAddr) -> Self {
         self.api_addr.push(addr);
         self
@@ -372,16 +1264,76 @@ impl ConfigBuilder {
         self
     }
 
+    pub fn max_changes_per_version(mut self, max: NonZeroU64) -> Self {
+        self.max_changes_per_version = Some(max);
+        self
+    }
+
+    pub fn wal_truncate(mut self, enabled: bool) -> Self {
+        self.wal_truncate = Some(enabled);
+        self
+    }
+
+    pub fn synchronous(mut self, synchronous: Synchronous) -> Self {
+        self.synchronous = synchronous;
+        self
+    }
+
     pub fn consul(mut self, config: ConsulConfig) -> Self {
         self.consul = Some(config);
         self
     }
 
+    pub fn perf(mut self, perf: PerfConfig) -> Self {
+        self.perf = Some(perf);
+        self
+    }
+
+    pub fn sync(mut self, sync: SyncConfig) -> Self {
+        self.sync = Some(sync);
+        self
+    }
+
+    pub fn apply(mut self, apply: ApplyConfig) -> Self {
+        self.apply = Some(apply);
+        self
+    }
+
+    pub fn min_cluster_size_for_writes(mut self, size: usize) -> Self {
+        self.min_cluster_size_for_writes = Some(size);
+        self
+    }
+
+    pub fn max_subscriptions(mut self, max: usize) -> Self {
+        self.max_subscriptions = Some(max);
+        self
+    }
+
     pub fn tls_config(mut self, config: TlsConfig) -> Self {
         self.tls = Some(config);
         self
     }
 
+    pub fn actor_id(mut self, actor_id: ActorId) -> Self {
+        self.actor_id = Some(actor_id);
+        self
+    }
+
+    pub fn actor_id_policy(mut self, policy: ActorIdPolicy) -> Self {
+        self.actor_id_policy = policy;
+        self
+    }
+
+    pub fn node_role(mut self, role: NodeRole) -> Self {
+        self.node_role = role;
+        self
+    }
+
+    pub fn max_concurrent_tasks(mut self, max: NonZeroUsize) -> Self {
+        self.max_concurrent_tasks = Some(max);
+        self
+    }
+
     pub fn build(self) -> Result<Config, ConfigBuilderError> {
         let db_path = self.db_path.ok_or(ConfigBuilderError::DbPathRequired)?;
 
@@ -390,6 +1342,7 @@ impl ConfigBuilder {
                 .prometheus_addr
                 .map(|bind_addr| PrometheusConfig { bind_addr }),
             open_telemetry: None,
+            static_labels: BTreeMap::new(),
         };
 
         if self.api_addr.is_empty() {
@@ -401,11 +1354,19 @@ impl ConfigBuilder {
                 path: db_path,
                 schema_paths: self.schema_paths,
                 subscriptions_path: None,
+                max_changes_per_version: self.max_changes_per_version,
+                wal_truncate: self.wal_truncate.unwrap_or_else(default_wal_truncate),
+                synchronous: self.synchronous,
             },
             api: ApiConfig {
                 bind_addr: self.api_addr,
                 authorization: None,
                 pg: None,
+                rate_limit: None,
+                listener: ListenerConfig::default(),
+                overload_policy: OverloadPolicyConfig::default(),
+                min_cluster_size_for_writes: self.min_cluster_size_for_writes,
+                max_subscriptions: self.max_subscriptions,
             },
             gossip: GossipConfig {
                 bind_addr: self
@@ -418,9 +1379,21 @@ impl ConfigBuilder {
                 tls: self.tls,
                 idle_timeout_secs: default_gossip_idle_timeout(),
                 max_mtu: None, // TODO: add a builder function for it
+                mtu: default_gossip_mtu(),
                 disable_gso: false,
+                bootstrap_fanout: default_bootstrap_fanout(),
+                bootstrap_fallback_limit: default_bootstrap_fallback_limit(),
+                cluster_size_debounce_ms: default_cluster_size_debounce(),
+                dns: DnsConfig::default(),
+                member_prune_after_secs: default_member_prune_after(),
+                max_broadcast_frame_len: default_max_broadcast_frame_len(),
+                persist_identity: default_persist_identity(),
+                redundant_window_secs: default_redundant_window(),
+                redundant_threshold: default_redundant_threshold(),
             },
             perf: self.perf.unwrap_or_default(),
+            sync: self.sync.unwrap_or_default(),
+            apply: self.apply.unwrap_or_default(),
             admin: AdminConfig {
                 uds_path: self.admin_path.unwrap_or_else(default_admin_path),
             },
@@ -428,6 +1401,11 @@ impl ConfigBuilder {
             log: self.log.unwrap_or_default(),
 
             consul: self.consul,
+            actor_id: self.actor_id,
+            actor_id_policy: self.actor_id_policy,
+            node_role: self.node_role,
+            max_concurrent_tasks: self.max_concurrent_tasks,
+            config_path: None,
         })
     }
 }
@@ -457,3 +1435,45 @@ pub enum LogFormat {
 pub struct ConsulConfig {
     pub client: consul_client::Config,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gossip_mtu_is_validated() {
+        let mut config = Config::builder()
+            .db_path("/tmp/corrosion.db")
+            .gossip_addr("127.0.0.1:0".parse().unwrap())
+            .api_addr("127.0.0.1:0".parse().unwrap())
+            .build()
+            .unwrap();
+        assert_eq!(config.gossip.mtu, 1178);
+        config.validate().unwrap();
+
+        config.gossip.mtu = MIN_GOSSIP_MTU - 1;
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::GossipMtuOutOfRange(_))
+        ));
+
+        config.gossip.mtu = MAX_GOSSIP_MTU + 1;
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::GossipMtuOutOfRange(_))
+        ));
+
+        // jumbo frames
+        config.gossip.mtu = 8900;
+        config.validate().unwrap();
+
+        config.gossip.max_mtu = Some(1452);
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::GossipMtuAboveMaxMtu { .. })
+        ));
+
+        config.gossip.mtu = 1430;
+        config.validate().unwrap();
+    }
+}
@@ -1,4 +1,10 @@
-use std::{collections::BTreeMap, net::SocketAddr, ops::Range, time::Duration};
+use std::{
+    cmp,
+    collections::BTreeMap,
+    net::SocketAddr,
+    ops::Range,
+    time::{Duration, Instant},
+};
 
 use circular_buffer::CircularBuffer;
 use serde::{Deserialize, Serialize};
@@ -16,8 +22,31 @@ pub struct MemberState {
     pub cluster_id: ClusterId,
 
     pub ring: Option<u8>,
+    /// When a sync with this member last completed successfully
     pub last_sync_ts: Option<Timestamp>,
     pub last_empty_ts: Option<Timestamp>,
+
+    /// Exponentially weighted moving average of sync handshake latency
+    #[serde(default)]
+    pub sync_latency: Option<Duration>,
+    /// Consecutive failed syncs, reset on success
+    #[serde(default)]
+    pub sync_failures: u32,
+    /// Not a sync candidate until then, set when a sync fails
+    #[serde(skip)]
+    pub sync_cooldown_until: Option<Instant>,
+    /// Whether the member had every version we had, as of our last sync
+    /// with it
+    #[serde(default)]
+    pub caught_up: bool,
+    /// Peer this member suggested syncing with instead, the last time it
+    /// was too busy to sync with us
+    #[serde(skip)]
+    pub sync_alternate: Option<ActorId>,
+    /// Whether the member decodes schema broadcasts, as of our last sync
+    /// with it. Nodes predating them can't decode those payloads.
+    #[serde(default)]
+    pub schema_broadcasts: bool,
 }
 
 impl MemberState {
@@ -29,14 +58,53 @@ impl MemberState {
             ring: None,
             last_sync_ts: None,
             last_empty_ts: None,
+            sync_latency: None,
+            sync_failures: 0,
+            sync_cooldown_until: None,
+            caught_up: false,
+            sync_alternate: None,
+            schema_broadcasts: false,
         }
     }
 
     pub fn is_ring0(&self) -> bool {
         self.ring == Some(0)
     }
+
+    /// Whether a sync with this member failed too recently to try again
+    pub fn in_sync_cooldown(&self, now: Instant) -> bool {
+        self.sync_cooldown_until
+            .map_or(false, |cooldown_until| now < cooldown_until)
+    }
+
+    /// Time since a sync with this member last completed successfully, as
+    /// of `now`. A member that is up but hasn't synced in a long time has
+    /// a sync problem rather than a gossip one.
+    pub fn since_last_sync(&self, now: Timestamp) -> Option<Duration> {
+        self.last_sync_ts
+            .map(|ts| now.to_duration().saturating_sub(ts.to_duration()))
+    }
+
+    /// Score this member as a sync candidate, higher is better.
+    ///
+    /// Needed versions are discounted by the member's sync latency (in
+    /// 100ms increments) and halved for each consecutive failed sync.
+    pub fn sync_score(&self, need_len: u64) -> f64 {
+        let latency = self
+            .sync_latency
+            .map(|latency| latency.as_secs_f64() * 10.0)
+            .unwrap_or_default();
+        let penalty = 2f64.powi(cmp::min(self.sync_failures, MAX_SYNC_FAILURE_PENALTY) as i32);
+
+        (need_len + 1) as f64 / ((1.0 + latency) * penalty)
+    }
 }
 
+/// Weight given to the latest sample in the sync latency moving average
+const SYNC_LATENCY_ALPHA: f64 = 0.2;
+/// Consecutive failures past this point don't lower the score further
+const MAX_SYNC_FAILURE_PENALTY: u32 = 5;
+
 const RING_BUCKETS: [Range<u64>; 6] = [0..6, 6..15, 15..50, 50..100, 100..200, 200..300];
 
 #[derive(Debug, Default, Clone)]
@@ -69,6 +137,54 @@ impl Members {
         }
     }
 
+    pub fn record_sync_success(&mut self, actor_id: &ActorId, latency: Duration) {
+        if let Some(state) = self.states.get_mut(actor_id) {
+            state.sync_latency = Some(match state.sync_latency {
+                Some(avg) => {
+                    avg.mul_f64(1.0 - SYNC_LATENCY_ALPHA) + latency.mul_f64(SYNC_LATENCY_ALPHA)
+                }
+                None => latency,
+            });
+            state.sync_failures = 0;
+            state.sync_cooldown_until = None;
+        }
+    }
+
+    /// Count a failed sync against a member and keep it out of sync
+    /// candidates for `cooldown`
+    pub fn record_sync_failure(&mut self, actor_id: &ActorId, cooldown: Duration) {
+        if let Some(state) = self.states.get_mut(actor_id) {
+            state.sync_failures = state.sync_failures.saturating_add(1);
+            if !cooldown.is_zero() {
+                state.sync_cooldown_until = Some(Instant::now() + cooldown);
+            }
+        }
+    }
+
+    pub fn set_caught_up(&mut self, actor_id: &ActorId, caught_up: bool) {
+        if let Some(state) = self.states.get_mut(actor_id) {
+            state.caught_up = caught_up;
+        }
+    }
+
+    pub fn set_schema_broadcasts(&mut self, actor_id: &ActorId, schema_broadcasts: bool) {
+        if let Some(state) = self.states.get_mut(actor_id) {
+            state.schema_broadcasts = schema_broadcasts;
+        }
+    }
+
+    pub fn set_sync_alternate(&mut self, actor_id: &ActorId, alternate: ActorId) {
+        if let Some(state) = self.states.get_mut(actor_id) {
+            state.sync_alternate = Some(alternate);
+        }
+    }
+
+    pub fn take_sync_alternate(&mut self, actor_id: &ActorId) -> Option<ActorId> {
+        self.states
+            .get_mut(actor_id)
+            .and_then(|state| state.sync_alternate.take())
+    }
+
     pub fn update_last_empty(&mut self, actor_id: &ActorId, ts: Option<Timestamp>) {
         if let Some(state) = self.states.get_mut(actor_id) {
             if ts > state.last_empty_ts {
@@ -187,3 +303,112 @@ impl Members {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use uhlc::NTP64;
+
+    use super::*;
+
+    #[test]
+    fn test_sync_scoring() {
+        let mut members = Members::default();
+
+        let fast = ActorId(uuid::Uuid::new_v4());
+        let slow = ActorId(uuid::Uuid::new_v4());
+
+        for (i, actor_id) in [fast, slow].into_iter().enumerate() {
+            members.add_member(&Actor::new(
+                actor_id,
+                format!("127.0.0.1:{}", 8000 + i).parse().unwrap(),
+                Timestamp::zero(),
+                ClusterId::default(),
+            ));
+        }
+
+        members.record_sync_success(&fast, Duration::from_millis(10));
+        members.record_sync_success(&slow, Duration::from_millis(1000));
+
+        // moves toward the latest sample
+        members.record_sync_success(&slow, Duration::from_millis(500));
+        assert_eq!(
+            members.get(&slow).unwrap().sync_latency,
+            Some(Duration::from_millis(900))
+        );
+
+        // needing a few more versions doesn't make up for the latency
+        assert!(
+            members.get(&fast).unwrap().sync_score(10) > members.get(&slow).unwrap().sync_score(20)
+        );
+
+        // ... but needing a lot more does
+        assert!(
+            members.get(&fast).unwrap().sync_score(10)
+                < members.get(&slow).unwrap().sync_score(1000)
+        );
+
+        let before = members.get(&fast).unwrap().sync_score(10);
+        members.record_sync_failure(&fast, Duration::ZERO);
+        members.record_sync_failure(&fast, Duration::ZERO);
+        assert_eq!(members.get(&fast).unwrap().sync_failures, 2);
+        assert!((members.get(&fast).unwrap().sync_score(10) - before / 4.0).abs() < 1e-9);
+        assert!(!members.get(&fast).unwrap().in_sync_cooldown(Instant::now()));
+
+        // a success resets the penalty
+        members.record_sync_success(&fast, Duration::from_millis(10));
+        assert_eq!(members.get(&fast).unwrap().sync_failures, 0);
+    }
+
+    #[test]
+    fn test_sync_failure_cooldown() {
+        let mut members = Members::default();
+
+        let actor_id = ActorId(uuid::Uuid::new_v4());
+        members.add_member(&Actor::new(
+            actor_id,
+            "127.0.0.1:8000".parse().unwrap(),
+            Timestamp::zero(),
+            ClusterId::default(),
+        ));
+
+        let now = Instant::now();
+        members.record_sync_failure(&actor_id, Duration::from_secs(10));
+        let state = members.get(&actor_id).unwrap();
+        assert!(state.in_sync_cooldown(now));
+        assert!(!state.in_sync_cooldown(now + Duration::from_secs(11)));
+
+        // a success lifts the cooldown right away
+        members.record_sync_success(&actor_id, Duration::from_millis(10));
+        assert!(!members.get(&actor_id).unwrap().in_sync_cooldown(now));
+    }
+
+    #[test]
+    fn test_since_last_sync() {
+        let mut members = Members::default();
+
+        let actor_id = ActorId(uuid::Uuid::new_v4());
+        members.add_member(&Actor::new(
+            actor_id,
+            "127.0.0.1:8000".parse().unwrap(),
+            Timestamp::zero(),
+            ClusterId::default(),
+        ));
+
+        // whole seconds in the upper 32 bits
+        let at = |secs: u64| Timestamp(NTP64(secs << 32));
+
+        assert_eq!(
+            members.get(&actor_id).unwrap().since_last_sync(at(100)),
+            None
+        );
+
+        members.update_sync_ts(&actor_id, at(100));
+        let state = members.get(&actor_id).unwrap();
+        assert_eq!(
+            state.since_last_sync(at(130)),
+            Some(Duration::from_secs(30))
+        );
+        // clocks may disagree, never negative
+        assert_eq!(state.since_last_sync(at(90)), Some(Duration::ZERO));
+    }
+}
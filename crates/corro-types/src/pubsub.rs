@@ -1,7 +1,12 @@
 use std::{
     cmp,
-    collections::{BTreeMap, HashMap, HashSet},
-    sync::Arc,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    task::{ready, Context, Poll},
     time::{Duration, Instant},
 };
 
@@ -10,12 +15,13 @@ use bytes::{Buf, BufMut};
 use camino::{Utf8Path, Utf8PathBuf};
 use compact_str::{format_compact, ToCompactString};
 use corro_api_types::{
-    ChangeId, ColumnName, ColumnType, RowId, SqliteValue, SqliteValueRef, TableName,
+    ChangeId, ChangeOrigin, ColumnName, ColumnType, RowId, SqliteValue, SqliteValueRef, TableName,
 };
 use enquote::unquote;
 use fallible_iterator::FallibleIterator;
+use futures::Stream;
 use indexmap::{IndexMap, IndexSet};
-use metrics::{counter, histogram};
+use metrics::{counter, gauge, histogram};
 use parking_lot::{Condvar, Mutex, RwLock};
 use rusqlite::{
     params_from_iter,
@@ -41,6 +47,7 @@ use tripwire::{Outcome, PreemptibleFutureExt, Tripwire};
 use uuid::Uuid;
 
 use crate::{
+    actor::ActorId,
     agent::SplitPool,
     api::QueryEvent,
     base::CrsqlDbVersion,
@@ -69,6 +76,37 @@ pub struct MatcherCreated {
 
 const SUB_EVENT_CHANNEL_CAP: usize = 512;
 
+/// Set of actors a subscription restricts its changes to, `None` allows all
+pub type SubOrigins = Option<BTreeSet<ActorId>>;
+
+/// Key used to deduplicate subscriptions, the same query with different
+/// origin filters needs its own matcher
+fn sub_query_key(sql: &str, origins: Option<&BTreeSet<ActorId>>) -> String {
+    match origins {
+        None => sql.to_owned(),
+        Some(origins) => format!("{sql}\n-- origins: {}", origins_to_string(origins)),
+    }
+}
+
+fn origins_to_string(origins: &BTreeSet<ActorId>) -> String {
+    origins
+        .iter()
+        .map(|actor_id| actor_id.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn origins_from_str(s: &str) -> Result<BTreeSet<ActorId>, MatcherError> {
+    s.split(',')
+        .map(|id| {
+            id.trim()
+                .parse::<Uuid>()
+                .map(ActorId)
+                .map_err(|_| MatcherError::InvalidOrigins(s.to_owned()))
+        })
+        .collect()
+}
+
 impl Manager<MatcherHandle> for SubsManager {
     fn trait_type(&self) -> String {
         "subs".to_string()
@@ -105,23 +143,35 @@ impl SubsManager {
         self.0.read().handles.clone()
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn get_or_insert(
         &self,
         sql: &str,
+        origins: SubOrigins,
+        max_subs: Option<usize>,
         subs_path: &Utf8Path,
         schema: &Schema,
         pool: &SplitPool,
         tripwire: Tripwire,
     ) -> Result<(MatcherHandle, Option<MatcherCreated>), MatcherError> {
-        if let Some(handle) = self.get_by_query(sql) {
+        let key = sub_query_key(sql, origins.as_ref());
+        if let Some(handle) = self.get_by_query(&key) {
             return Ok((handle, None));
         }
 
         let mut inner = self.0.write();
-        if let Some(handle) = inner.get_by_query(sql) {
+        if let Some(handle) = inner.get_by_query(&key) {
             return Ok((handle, None));
         }
 
+        // checked under the write lock so concurrent requests can't all
+        // squeeze in under the limit
+        if let Some(max) = max_subs {
+            if inner.handles.len() >= max {
+                return Err(MatcherError::TooManySubscriptions(max));
+            }
+        }
+
         let id = Uuid::new_v4();
         let (evt_tx, evt_rx) = mpsc::channel(SUB_EVENT_CHANNEL_CAP);
 
@@ -132,6 +182,7 @@ impl SubsManager {
             pool.client_dedicated()?,
             evt_tx,
             sql,
+            origins,
             tripwire,
         );
 
@@ -147,8 +198,7 @@ impl SubsManager {
             }
         };
 
-        inner.handles.insert(id, handle.clone());
-        inner.queries.insert(sql.to_owned(), id);
+        inner.insert(key, handle.clone());
 
         Ok((handle, Some(MatcherCreated { evt_rx })))
     }
@@ -179,8 +229,7 @@ impl SubsManager {
             tripwire,
         )?;
 
-        inner.handles.insert(id, handle.clone());
-        inner.queries.insert(handle.inner.sql.clone(), id);
+        inner.insert(handle.query_key(), handle.clone());
 
         Ok((handle, MatcherCreated { evt_rx }))
     }
@@ -189,6 +238,99 @@ impl SubsManager {
         let mut inner = self.0.write();
         inner.remove(id)
     }
+
+    /// Create a matcher for an in-process subscriber. Unlike
+    /// `get_or_insert`, the matcher is never shared: its events only go
+    /// to the returned subscription.
+    pub fn insert_local(
+        &self,
+        sql: &str,
+        subs_path: &Utf8Path,
+        schema: &Schema,
+        pool: &SplitPool,
+        tripwire: Tripwire,
+    ) -> Result<LocalSubscription, MatcherError> {
+        let id = Uuid::new_v4();
+        let (evt_tx, evt_rx) = mpsc::channel(SUB_EVENT_CHANNEL_CAP);
+
+        let handle = match Matcher::create(
+            id,
+            subs_path.to_path_buf(),
+            schema,
+            pool.client_dedicated()?,
+            evt_tx,
+            sql,
+            None,
+            tripwire,
+        ) {
+            Ok(handle) => handle,
+            Err(e) => {
+                error!(sub_id = %id, "could not create local subscription: {e}");
+                if let Err(e) = Matcher::cleanup(id, Matcher::sub_path(subs_path, id)) {
+                    error!("could not cleanup subscription: {e}");
+                }
+
+                return Err(e);
+            }
+        };
+
+        self.0.write().insert_handle(handle.clone());
+
+        Ok(LocalSubscription {
+            handle,
+            subs: self.clone(),
+            evt_rx,
+        })
+    }
+}
+
+/// Subscription to a query made from within the process, without going
+/// through the HTTP API.
+///
+/// Yields batches of the events that were ready when polled: the columns
+/// and current rows first, then changes as they're matched. Dropping it
+/// removes and cancels the matcher.
+pub struct LocalSubscription {
+    handle: MatcherHandle,
+    subs: SubsManager,
+    evt_rx: mpsc::Receiver<QueryEvent>,
+}
+
+impl LocalSubscription {
+    pub fn id(&self) -> Uuid {
+        self.handle.id()
+    }
+
+    pub fn handle(&self) -> &MatcherHandle {
+        &self.handle
+    }
+}
+
+impl Stream for LocalSubscription {
+    type Item = Vec<QueryEvent>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let first = match ready!(self.evt_rx.poll_recv(cx)) {
+            Some(evt) => evt,
+            None => return Poll::Ready(None),
+        };
+
+        let mut batch = vec![first];
+        while let Ok(evt) = self.evt_rx.try_recv() {
+            batch.push(evt);
+        }
+
+        Poll::Ready(Some(batch))
+    }
+}
+
+impl Drop for LocalSubscription {
+    fn drop(&mut self) {
+        let id = self.handle.id();
+        self.subs.remove(&id);
+        self.handle.inner.cancel.cancel();
+        info!(sub_id = %id, "Dropped local subscription");
+    }
 }
 
 #[derive(Debug)]
@@ -197,6 +339,7 @@ pub struct MatchableChange<'a> {
     pub pk: &'a [u8],
     pub column: &'a ColumnName,
     pub cl: i64,
+    pub site_id: ActorId,
 }
 
 impl<'a> From<&'a Change> for MatchableChange<'a> {
@@ -206,6 +349,7 @@ impl<'a> From<&'a Change> for MatchableChange<'a> {
             pk: &value.pk,
             column: &value.cid,
             cl: value.cl,
+            site_id: ActorId::from_bytes(value.site_id),
         }
     }
 }
@@ -228,9 +372,26 @@ impl InnerSubsManager {
             .cloned()
     }
 
+    fn insert(&mut self, key: String, handle: MatcherHandle) {
+        self.queries.insert(key, handle.id());
+        self.insert_handle(handle);
+    }
+
+    /// Track a matcher without making it available for reuse by query
+    fn insert_handle(&mut self, handle: MatcherHandle) {
+        self.handles.insert(handle.id(), handle);
+        gauge!("corro.subs.active").set(self.handles.len() as f64);
+    }
+
     fn remove(&mut self, id: &Uuid) -> Option<MatcherHandle> {
         let handle = self.handles.remove(id)?;
-        self.queries.remove(&handle.inner.sql);
+        // local subscriptions aren't keyed, don't drop a shared matcher's
+        // entry for the same query
+        let key = handle.query_key();
+        if self.queries.get(&key) == Some(id) {
+            self.queries.remove(&key);
+        }
+        gauge!("corro.subs.active").set(self.handles.len() as f64);
         Some(handle)
     }
 }
@@ -261,6 +422,8 @@ struct InnerMatcherHandle {
     pool: sqlite_pool::RusqlitePool,
     parsed: ParsedSelect,
     col_names: Vec<ColumnName>,
+    origins: SubOrigins,
+    origins_wanted: Arc<AtomicBool>,
     cancel: CancellationToken,
     changes_tx: mpsc::Sender<(MatchCandidates, CrsqlDbVersion)>,
     last_change_rx: watch::Receiver<ChangeId>,
@@ -270,7 +433,25 @@ struct InnerMatcherHandle {
     metrics: HashMap<String, HandleMetrics>,
 }
 
-pub type MatchCandidates = IndexMap<TableName, IndexMap<Vec<u8>, i64>>;
+/// Latest change matched for a primary key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MatchedPk {
+    /// Causal length of the row, even when it was deleted
+    pub cl: i64,
+    /// Actor the change originated from
+    pub site_id: ActorId,
+}
+
+impl From<&MatchableChange<'_>> for MatchedPk {
+    fn from(change: &MatchableChange<'_>) -> Self {
+        MatchedPk {
+            cl: change.cl,
+            site_id: change.site_id,
+        }
+    }
+}
+
+pub type MatchCandidates = IndexMap<TableName, IndexMap<Vec<u8>, MatchedPk>>;
 
 #[async_trait]
 impl Handle for MatcherHandle {
@@ -307,6 +488,11 @@ impl Handle for MatcherHandle {
             return false;
         }
 
+        if !self.allows_origin(&change.site_id) {
+            trace!("change origin {} is filtered out", change.site_id);
+            return false;
+        }
+
         // don't consider changes that don't have both the table + col in the matcher query
         if !self
             .inner
@@ -321,11 +507,11 @@ impl Handle for MatcherHandle {
         }
 
         if let Some(v) = candidates.get_mut(change.table) {
-            v.insert(change.pk.to_vec(), change.cl).is_none()
+            v.insert(change.pk.to_vec(), (&change).into()).is_none()
         } else {
             candidates.insert(
                 change.table.clone(),
-                [(change.pk.to_vec(), change.cl)].into(),
+                [(change.pk.to_vec(), (&change).into())].into(),
             );
             true
         }
@@ -358,6 +544,28 @@ impl MatcherHandle {
         &self.inner.col_names
     }
 
+    pub fn origins(&self) -> Option<&BTreeSet<ActorId>> {
+        self.inner.origins.as_ref()
+    }
+
+    /// Precede changes with the origin they came from, from now on, for
+    /// subscribers asking for it
+    pub fn want_origins(&self) {
+        self.inner.origins_wanted.store(true, Ordering::Relaxed);
+    }
+
+    fn allows_origin(&self, site_id: &ActorId) -> bool {
+        self.inner
+            .origins
+            .as_ref()
+            .map(|origins| origins.contains(site_id))
+            .unwrap_or(true)
+    }
+
+    fn query_key(&self) -> String {
+        sub_query_key(&self.inner.sql, self.inner.origins.as_ref())
+    }
+
     pub fn subs_path(&self) -> &String {
         &self.inner.subs_path
     }
@@ -513,7 +721,11 @@ pub struct Matcher {
     pub parsed: ParsedSelect,
     pub evt_tx: mpsc::Sender<QueryEvent>,
     pub col_names: Vec<ColumnName>,
+    pub origins: SubOrigins,
     pub last_rowid: u64,
+    /// This node's actor id, to tell local changes apart
+    actor_id: ActorId,
+    origins_wanted: Arc<AtomicBool>,
     conn: Connection,
     base_path: Utf8PathBuf,
     cancel: CancellationToken,
@@ -549,9 +761,12 @@ impl Matcher {
         state_conn: &Connection,
         evt_tx: mpsc::Sender<QueryEvent>,
         sql: &str,
+        origins: SubOrigins,
     ) -> Result<(Matcher, MatcherHandle), MatcherError> {
         let sub_path = Self::sub_path(subs_path.as_path(), id);
-        let sql_hash = hex::encode(seahash::hash(sql.as_bytes()).to_be_bytes());
+        let sql_hash = hex::encode(
+            seahash::hash(sub_query_key(sql, origins.as_ref()).as_bytes()).to_be_bytes(),
+        );
 
         info!(%sql_hash, sub_id = %id, "Initializing subscription at {sub_path}");
 
@@ -559,6 +774,9 @@ impl Matcher {
 
         let sub_db_path = sub_path.join(SUB_DB_PATH);
 
+        let actor_id: ActorId =
+            state_conn.query_row("SELECT crsql_site_id()", [], |row| row.get(0))?;
+
         let col_names: Vec<ColumnName> = {
             state_conn
                 .prepare(sql)?
@@ -745,6 +963,8 @@ impl Matcher {
         // big channel to not miss anything
         let (changes_tx, changes_rx) = mpsc::channel(20480);
 
+        let origins_wanted = Arc::new(AtomicBool::new(false));
+
         // metrics counters
         let mut counter_map = HashMap::new();
         for table in parsed.table_columns.keys() {
@@ -765,6 +985,8 @@ impl Matcher {
                     .expect("could not build pool, this can't fail because we specified a runtime"),
                 parsed: parsed.clone(),
                 col_names: col_names.clone(),
+                origins: origins.clone(),
+                origins_wanted: origins_wanted.clone(),
                 cancel: cancel.clone(),
                 last_change_rx,
                 changes_tx,
@@ -784,7 +1006,10 @@ impl Matcher {
             parsed,
             evt_tx,
             col_names,
+            origins,
             last_rowid: 0,
+            actor_id,
+            origins_wanted,
             conn,
             base_path: sub_path,
             cancel,
@@ -825,7 +1050,7 @@ impl Matcher {
         evt_tx: mpsc::Sender<QueryEvent>,
         tripwire: Tripwire,
     ) -> Result<MatcherHandle, MatcherError> {
-        let sql: String = block_in_place(|| {
+        let (sql, origins) = block_in_place(|| {
             let conn = Connection::open(Matcher::sub_db_path(&subs_path, id))?;
             let state: Option<String> = conn
                 .query_row("SELECT value FROM meta WHERE key = 'state'", [], |row| {
@@ -842,13 +1067,19 @@ impl Matcher {
                 })
                 .optional()?;
 
-            match sql {
-                Some(sql) => Ok(sql),
-                None => Err(MatcherError::MissingSql),
-            }
+            let sql = sql.ok_or(MatcherError::MissingSql)?;
+
+            let origins: Option<String> = conn
+                .query_row("SELECT value FROM meta WHERE key = 'origins'", [], |row| {
+                    row.get(0)
+                })
+                .optional()?;
+
+            Ok::<_, MatcherError>((sql, origins.as_deref().map(origins_from_str).transpose()?))
         })?;
 
-        let (matcher, handle) = Self::new(id, subs_path, schema, &state_conn, evt_tx, &sql)?;
+        let (matcher, handle) =
+            Self::new(id, subs_path, schema, &state_conn, evt_tx, &sql, origins)?;
 
         spawn_counted(matcher.run_restore(state_conn, tripwire));
 
@@ -863,9 +1094,11 @@ impl Matcher {
         state_conn: CrConn,
         evt_tx: mpsc::Sender<QueryEvent>,
         sql: &str,
+        origins: SubOrigins,
         tripwire: Tripwire,
     ) -> Result<MatcherHandle, MatcherError> {
-        let (mut matcher, handle) = Self::new(id, subs_path, schema, &state_conn, evt_tx, sql)?;
+        let (mut matcher, handle) =
+            Self::new(id, subs_path, schema, &state_conn, evt_tx, sql, origins)?;
 
         let pk_cols = matcher
             .pks
@@ -955,6 +1188,12 @@ impl Matcher {
             trace!("inserted sub columns");
 
             tx.execute("INSERT INTO meta (key, value) VALUES ('sql', ?)", [sql])?;
+            if let Some(origins) = matcher.origins.as_ref() {
+                tx.execute(
+                    "INSERT INTO meta (key, value) VALUES ('origins', ?)",
+                    [origins_to_string(origins)],
+                )?;
+            }
             tx.execute(
                 "INSERT INTO meta (key, value) VALUES ('state', 'created')",
                 [],
@@ -1107,8 +1346,8 @@ impl Matcher {
                 Some((candidates, db_version)) = self.changes_rx.recv() => {
                     for (table, pks) in  candidates {
                         let buffed = buf.entry(table).or_default();
-                        for (pk, cl) in pks {
-                            if buffed.insert(pk, cl).is_none() {
+                        for (pk, matched) in pks {
+                            if buffed.insert(pk, matched).is_none() {
                                 buf_count += 1;
                             }
                         }
@@ -1157,6 +1396,11 @@ impl Matcher {
                     }) {
                         if !matches!(e, MatcherError::EventReceiverClosed) {
                             error!(sub_id = %self.id, "could not handle change: {e}");
+                            // let subscribers know why the subscription is ending
+                            _ = self
+                                .evt_tx
+                                .send(QueryEvent::Error(e.to_compact_string()))
+                                .await;
                         }
                         break;
                     }
@@ -1448,7 +1692,7 @@ impl Matcher {
         );
 
         let tx = self.conn.transaction()?;
-        for (table, pks) in candidates {
+        for (table, pks) in candidates.iter() {
             let pks = pks
                 .iter()
                 .map(|(pk, _)| unpack_columns(pk))
@@ -1514,6 +1758,7 @@ impl Matcher {
         ))?;
 
         let mut new_last_rowid = self.last_rowid;
+        let with_origins = self.origins_wanted.load(Ordering::Relaxed);
 
         {
             // read-only!
@@ -1553,6 +1798,11 @@ impl Matcher {
                     tmp_insert_prepped.raw_execute()?;
                 }
 
+                let table_pks = self
+                    .pks
+                    .get(table.as_str())
+                    .ok_or(MatcherError::MissingPrimaryKeys)?;
+
                 let coalesced_pks = pk_cols
                     .iter()
                     .map(|pk| format!("coalesce({pk},\"\")"))
@@ -1569,7 +1819,7 @@ impl Matcher {
                             DO UPDATE SET
                                 {excluded}
                             WHERE {excluded_not_same}
-                        RETURNING __corro_rowid,{return_cols}",
+                        RETURNING __corro_rowid,{return_cols},{table_pks}",
                     // insert into
                     insert_cols = all_cols.join(","),
                     query_query = stmt.temp_query,
@@ -1582,7 +1832,8 @@ impl Matcher {
                         .map(|i| format!("col_{i} IS NOT excluded.col_{i}"))
                         .collect::<Vec<_>>()
                         .join(" OR "),
-                    return_cols = query_cols.join(",")
+                    return_cols = query_cols.join(","),
+                    table_pks = table_pks.join(","),
                 );
 
                 trace!("INSERT SQL: {sql}");
@@ -1595,13 +1846,14 @@ impl Matcher {
                         {query_query}
                         EXCEPT
                         SELECT * FROM state_results
-                    )) RETURNING __corro_rowid,{return_cols}
+                    )) RETURNING __corro_rowid,{return_cols},{table_pks}
                 ",
                     // delete from
                     pks = coalesced_pks,
                     select_pks = coalesced_pks,
                     query_query = stmt.temp_query,
-                    return_cols = query_cols.join(",")
+                    return_cols = query_cols.join(","),
+                    table_pks = table_pks.join(","),
                 );
 
                 trace!("DELETE SQL: {sql}");
@@ -1622,6 +1874,7 @@ impl Matcher {
                     (Some(ChangeType::Delete), delete_prepped),
                 ] {
                     let col_count = prepped.column_count();
+                    let cells_end = query_cols.len() + 1;
 
                     let mut rows = prepped.raw_query();
 
@@ -1638,7 +1891,23 @@ impl Matcher {
 
                         new_last_rowid = cmp::max(new_last_rowid, rowid.0);
 
-                        match (1..col_count)
+                        // the change of this table's row that got it there
+                        let origin = if with_origins {
+                            (cells_end..col_count)
+                                .map(|i| row.get::<_, SqliteValue>(i))
+                                .collect::<rusqlite::Result<Vec<_>>>()
+                                .ok()
+                                .and_then(|pk| pack_columns(&pk).ok())
+                                .and_then(|pk| candidates.get(table)?.get(&pk))
+                                .map(|matched| ChangeOrigin {
+                                    actor_id: matched.site_id.0,
+                                    local: matched.site_id == self.actor_id,
+                                })
+                        } else {
+                            None
+                        };
+
+                        match (1..cells_end)
                             .map(|i| row.get::<_, SqliteValue>(i))
                             .collect::<rusqlite::Result<Vec<_>>>()
                         {
@@ -1659,6 +1928,17 @@ impl Matcher {
 
                                 trace!("got change id: {change_id}");
 
+                                if let Some(origin) = origin {
+                                    if let Err(e) =
+                                        self.evt_tx.blocking_send(QueryEvent::Origin(origin))
+                                    {
+                                        debug!(
+                                            "could not send back origin to matcher sub sender: {e}"
+                                        );
+                                        return Err(MatcherError::EventReceiverClosed);
+                                    }
+                                }
+
                                 if let Err(e) = self.evt_tx.blocking_send(QueryEvent::Change(
                                     change_type,
                                     rowid,
@@ -1714,32 +1994,67 @@ impl Matcher {
     ) -> Result<(), MatcherError> {
         debug!(sub_id = %self.id, "handling change from version {start_db_version} to {end_db_version}");
 
-        let mut candidates = MatchCandidates::new();
-        {
-            let mut changes_prepped = state_conn.prepare_cached(
-                r#"
-            SELECT DISTINCT "table", pk, cl
-                FROM crsql_changes
-                    WHERE db_version > ?
-                      AND db_version <= ? -- TODO: allow going over?
-                      AND ("table", cid) IN __corro_sub.columns -- only care about table/columns touched by the query
-                    GROUP BY "table", pk
-        "#,
-            )?;
-
-            let mut rows = changes_prepped.query([start_db_version, end_db_version])?;
-            while let Ok(Some(row)) = rows.next() {
-                candidates
-                    .entry(row.get(0)?)
-                    .or_default()
-                    .insert(row.get(1)?, row.get(2)?);
-            }
-        }
+        let candidates = changed_candidates(
+            state_conn,
+            start_db_version,
+            end_db_version,
+            self.origins.as_ref(),
+        )?;
 
         self.handle_candidates(state_conn, candidates, end_db_version)
     }
 }
 
+/// Rows touched by the changes between `start_db_version` and
+/// `end_db_version`, in tables and columns the query selects and, if
+/// given, only from `origins`
+fn changed_candidates(
+    state_conn: &Connection,
+    start_db_version: CrsqlDbVersion,
+    end_db_version: CrsqlDbVersion,
+    origins: Option<&BTreeSet<ActorId>>,
+) -> Result<MatchCandidates, MatcherError> {
+    // filtered before grouping, a row's latest change coming from
+    // another origin must not hide the allowed ones
+    let origins_filter = match origins {
+        Some(origins) => format!(
+            "AND site_id IN ({})",
+            origins
+                .iter()
+                .map(|site_id| format!("x'{}'", site_id.as_simple()))
+                .collect::<Vec<_>>()
+                .join(",")
+        ),
+        None => String::new(),
+    };
+
+    let mut changes_prepped = state_conn.prepare_cached(&format!(
+        r#"
+        SELECT DISTINCT "table", pk, cl, site_id
+            FROM crsql_changes
+                WHERE db_version > ?
+                  AND db_version <= ? -- TODO: allow going over?
+                  AND ("table", cid) IN __corro_sub.columns -- only care about table/columns touched by the query
+                  {origins_filter}
+                GROUP BY "table", pk
+    "#
+    ))?;
+
+    let mut candidates = MatchCandidates::new();
+    let mut rows = changes_prepped.query([start_db_version, end_db_version])?;
+    while let Ok(Some(row)) = rows.next() {
+        candidates.entry(row.get(0)?).or_default().insert(
+            row.get(1)?,
+            MatchedPk {
+                cl: row.get(2)?,
+                site_id: row.get(3)?,
+            },
+        );
+    }
+
+    Ok(candidates)
+}
+
 fn dump_query_plan(
     conn: &mut Connection,
     query: &str,
@@ -2237,6 +2552,8 @@ pub enum MatcherError {
     Unpack(#[from] UnpackError),
     #[error("did not insert subscription")]
     InsertSub,
+    #[error("invalid subscription origins: {0}")]
+    InvalidOrigins(String),
     #[error(transparent)]
     FromSql(#[from] FromSqlError),
     #[error(transparent)]
@@ -2253,6 +2570,8 @@ pub enum MatcherError {
     NotRunning,
     #[error("subscription restore is missing SQL query")]
     MissingSql,
+    #[error("too many subscriptions, at most {0} are allowed")]
+    TooManySubscriptions(usize),
 }
 
 impl MatcherError {
@@ -2497,6 +2816,8 @@ mod tests {
 
         let (handle, maybe_created) = subs.get_or_insert(
             sql,
+            None,
+            None,
             subscriptions_path.as_path(),
             &schema,
             &pool,
@@ -2514,6 +2835,162 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_matcher_origins() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>>
+    {
+        _ = tracing_subscriber::fmt::try_init();
+        let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+        let schema_sql = "CREATE TABLE sw (pk TEXT NOT NULL PRIMARY KEY, sandwich TEXT);";
+        let mut schema = parse_sql(schema_sql)?;
+
+        let sql = "SELECT sandwich FROM sw";
+
+        let subs = SubsManager::default();
+
+        let tmpdir = tempfile::tempdir()?;
+        let db_path = tmpdir.path().join("test.db");
+        let subscriptions_path: Utf8PathBuf =
+            tmpdir.path().join("subs").display().to_string().into();
+
+        let pool = SplitPool::create(db_path, Arc::new(Semaphore::new(1))).await?;
+        let clock = Arc::new(uhlc::HLC::default());
+
+        {
+            let mut conn = pool.write_priority().await?;
+            setup_conn(&conn)?;
+            migrate(clock, &mut conn)?;
+            let tx = conn.transaction()?;
+            apply_schema(&tx, &Schema::default(), &mut schema)?;
+            tx.commit()?;
+        }
+
+        let local = ActorId(Uuid::new_v4());
+        let remote = ActorId(Uuid::new_v4());
+
+        let (all_handle, _) = subs.get_or_insert(
+            sql,
+            None,
+            None,
+            subscriptions_path.as_path(),
+            &schema,
+            &pool,
+            tripwire.clone(),
+        )?;
+
+        let (local_handle, maybe_created) = subs.get_or_insert(
+            sql,
+            Some([local].into()),
+            None,
+            subscriptions_path.as_path(),
+            &schema,
+            &pool,
+            tripwire.clone(),
+        )?;
+
+        // same query, different origins: needs its own matcher
+        assert!(maybe_created.is_some());
+        assert_ne!(all_handle.id(), local_handle.id());
+        assert_ne!(all_handle.hash(), local_handle.hash());
+
+        let (same_handle, maybe_created) = subs.get_or_insert(
+            sql,
+            Some([local].into()),
+            None,
+            subscriptions_path.as_path(),
+            &schema,
+            &pool,
+            tripwire.clone(),
+        )?;
+        assert!(maybe_created.is_none());
+        assert_eq!(same_handle.id(), local_handle.id());
+
+        let table = TableName("sw".into());
+        let column = ColumnName("sandwich".into());
+
+        for (handle, site_id, expected) in [
+            (&local_handle, remote, false),
+            (&local_handle, local, true),
+            (&all_handle, remote, true),
+        ] {
+            let mut candidates = MatchCandidates::new();
+            let matched = handle.filter_matchable_change(
+                &mut candidates,
+                MatchableChange {
+                    table: &table,
+                    pk: b"mad",
+                    column: &column,
+                    cl: 1,
+                    site_id,
+                },
+            );
+            assert_eq!(matched, expected);
+        }
+
+        all_handle.cleanup().await;
+        local_handle.cleanup().await;
+
+        tripwire_tx.send(()).await.ok();
+        tripwire_worker.await;
+        wait_for_all_pending_handles().await;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_changed_candidates_filters_origins_before_grouping(
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let mut conn = CrConn::init(rusqlite::Connection::open_in_memory()?)?;
+        setup_conn(&conn)?;
+        migrate(Arc::new(uhlc::HLC::default()), &mut conn)?;
+
+        let mut schema =
+            parse_sql("CREATE TABLE sw (pk TEXT NOT NULL PRIMARY KEY, sandwich TEXT);")?;
+        {
+            let tx = conn.transaction()?;
+            apply_schema(&tx, &Schema::default(), &mut schema)?;
+            tx.commit()?;
+        }
+
+        conn.execute_batch(
+            r#"
+            ATTACH DATABASE ':memory:' AS __corro_sub;
+            CREATE TABLE __corro_sub.columns ("table" TEXT NOT NULL, cid TEXT NOT NULL, PRIMARY KEY ("table", cid)) WITHOUT ROWID;
+            INSERT INTO __corro_sub.columns VALUES ('sw', '-1'), ('sw', 'sandwich');
+            "#,
+        )?;
+
+        let local: ActorId = conn.query_row("SELECT crsql_site_id()", [], |row| row.get(0))?;
+        let remote = ActorId(Uuid::new_v4());
+
+        // local insert, then a remote update of the same row
+        conn.execute("INSERT INTO sw (pk, sandwich) VALUES ('mad', 'cheese')", [])?;
+        let pk: Vec<u8> =
+            conn.query_row("SELECT pk FROM crsql_changes LIMIT 1", [], |row| row.get(0))?;
+        conn.execute(
+            r#"INSERT INTO crsql_changes ("table", pk, cid, val, col_version, db_version, site_id, cl, seq)
+                VALUES ('sw', ?, 'sandwich', 'ham', 2, 1, ?, 1, 0)"#,
+            params![pk, remote],
+        )?;
+        let db_version: CrsqlDbVersion =
+            conn.query_row("SELECT crsql_db_version()", [], |row| row.get(0))?;
+
+        let candidates = |origins: Option<BTreeSet<ActorId>>| {
+            changed_candidates(&conn, CrsqlDbVersion(0), db_version, origins.as_ref())
+        };
+        let table = TableName("sw".into());
+
+        for origin in [local, remote] {
+            let matched = candidates(Some([origin].into()))?;
+            assert_eq!(matched[&table][&pk].site_id, origin);
+        }
+        assert_eq!(candidates(None)?[&table].len(), 1);
+        assert!(candidates(Some([ActorId(Uuid::new_v4())].into()))?.is_empty());
+
+        Ok(())
+    }
+
     #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
     async fn test_diff() {
         _ = tracing_subscriber::fmt::try_init();
@@ -2710,6 +3187,8 @@ mod tests {
             let (matcher, maybe_created) = subs
                 .get_or_insert(
                     sql,
+                    None,
+                    None,
                     subscriptions_path.as_path(),
                     &schema,
                     &pool,
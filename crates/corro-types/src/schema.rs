@@ -1,6 +1,7 @@
 use std::{
     collections::{HashMap, HashSet},
     fmt,
+    hash::Hasher,
     time::{Instant, SystemTime},
 };
 
@@ -15,7 +16,11 @@ use sqlite3_parser::ast::{
 };
 use tracing::{debug, info, trace};
 
-use crate::agent::create_clock_change_trigger;
+use crate::{
+    agent::create_clock_change_trigger,
+    api::{SqliteValue, SqliteValueRef},
+    pubsub::{pack_columns, unpack_columns, PackError, UnpackError},
+};
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct Column {
@@ -85,6 +90,44 @@ pub struct Table {
     pub raw: CreateTableBody,
 }
 
+impl Table {
+    /// Pack primary key values, looked up by column name, the way
+    /// cr-sqlite encodes `pk` in `crsql_changes`: one value per primary
+    /// key column, in the order they're declared in the primary key.
+    pub fn pack_pk(&self, values: &HashMap<String, SqliteValue>) -> Result<Vec<u8>, PkError> {
+        let pk = self
+            .pk
+            .iter()
+            .map(|col| {
+                values
+                    .get(col)
+                    .cloned()
+                    .ok_or_else(|| PkError::MissingColumn(col.clone()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(pack_columns(&pk)?)
+    }
+
+    /// Unpack a `crsql_changes` pk into its values, along with the
+    /// primary key column each belongs to
+    pub fn unpack_pk<'a>(
+        &'a self,
+        pk: &'a [u8],
+    ) -> Result<Vec<(&'a str, SqliteValueRef<'a>)>, PkError> {
+        let values = unpack_columns(pk)?;
+        if values.len() != self.pk.len() {
+            return Err(PkError::ColumnCount {
+                tbl_name: self.name.clone(),
+                expected: self.pk.len(),
+                got: values.len(),
+            });
+        }
+
+        Ok(self.pk.iter().map(String::as_str).zip(values).collect())
+    }
+}
+
 impl fmt::Display for Table {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         Cmd::Stmt(Stmt::CreateTable {
@@ -182,12 +225,30 @@ pub enum SchemaError {
     Parse(#[from] sqlite3_parser::lexer::sql::Error),
     #[error("nothing to parse")]
     NothingParsed,
-    #[error("unsupported command: {0}")]
+    #[error("unsupported statement, schemas may only contain CREATE TABLE and CREATE INDEX statements: {0}")]
     UnsupportedCmd(Cmd),
     #[error("missing table for index (table: '{tbl_name}', index: '{name}')")]
     IndexWithoutTable { tbl_name: String, name: String },
     #[error("temporary tables are not supported: {0}")]
     TemporaryTable(Cmd),
+    #[error("tables created from a SELECT are not supported: {0}")]
+    TableAsSelect(Cmd),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PkError {
+    #[error("missing value for primary key column '{0}'")]
+    MissingColumn(String),
+    #[error("packed primary key has {got} columns, table '{tbl_name}' has {expected}")]
+    ColumnCount {
+        tbl_name: String,
+        expected: usize,
+        got: usize,
+    },
+    #[error(transparent)]
+    Pack(#[from] PackError),
+    #[error(transparent)]
+    Unpack(#[from] UnpackError),
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -235,6 +296,27 @@ pub fn init_schema(conn: &Connection) -> Result<Schema, SchemaError> {
     parse_sql(dump.as_str())
 }
 
+/// Hash of the definitions in `__corro_schema`, stable across nodes so
+/// peers can tell when their schemas diverged
+pub fn schema_hash(conn: &Connection) -> rusqlite::Result<u64> {
+    let mut hasher = seahash::SeaHasher::new();
+
+    let mut prepped = conn.prepare_cached(
+        "SELECT tbl_name, type, name, sql FROM __corro_schema ORDER BY tbl_name, type, name",
+    )?;
+    let mut rows = prepped.query(())?;
+
+    while let Some(row) = rows.next()? {
+        for i in 0..4 {
+            hasher.write(row.get::<_, String>(i)?.as_bytes());
+            // separate fields so ("ab", "c") and ("a", "bc") differ
+            hasher.write_u8(0);
+        }
+    }
+
+    Ok(hasher.finish())
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum ApplySchemaError {
     #[error(transparent)]
@@ -674,7 +756,7 @@ pub fn parse_sql_to_schema(schema: &mut Schema, sql: &str) -> Result<(), SchemaE
                 Stmt::CreateTable {
                     body: CreateTableBody::AsSelect(_),
                     ..
-                } => return Err(SchemaError::TemporaryTable(cmd.clone())),
+                } => return Err(SchemaError::TableAsSelect(cmd.clone())),
                 Stmt::CreateTable {
                     temporary: false,
                     if_not_exists: _,
@@ -802,10 +884,12 @@ fn prepare_table(
                 });
                 let nullable = !not_nullable;
 
-                let primary_key = pk.contains(&def.col_name.0);
-
                 let col_name = unquote(&def.col_name.0).unwrap_or_else(|_| def.col_name.0.clone());
 
+                // `pk` holds unquoted names, quoted columns of a composite
+                // primary key would otherwise not be flagged
+                let primary_key = pk.contains(&col_name);
+
                 (
                     col_name.clone(),
                     Column {
@@ -1,10 +1,10 @@
-use std::{cmp, collections::HashMap, io, ops::RangeInclusive};
+use std::{cmp, collections::HashMap, fmt, io, ops::RangeInclusive};
 
 use bytes::BytesMut;
 use opentelemetry::propagation::{Extractor, Injector};
 use rangemap::RangeInclusiveSet;
 use serde::{Deserialize, Serialize};
-use speedy::{Readable, Writable};
+use speedy::{Context, Readable, Reader, Writable, Writer};
 use tokio_util::codec::{Decoder, LengthDelimitedCodec};
 use tracing::warn;
 
@@ -68,12 +68,106 @@ impl Extractor for SyncTraceContextV1 {
 
 pub type SyncRequestV1 = Vec<(ActorId, Vec<SyncNeedV1>)>;
 
-#[derive(Debug, thiserror::Error, Clone, PartialEq, Readable, Writable)]
+#[derive(Debug, thiserror::Error, Clone, PartialEq)]
 pub enum SyncRejectionV1 {
     #[error("max concurrency reached")]
     MaxConcurrencyReached,
     #[error("different cluster")]
     DifferentCluster,
+    #[error("overloaded: {0}")]
+    Overloaded(SyncLoadV1),
+    #[error("read-only replica")]
+    ReadOnlyReplica,
+    #[error("draining")]
+    Draining,
+}
+
+/// How a `SyncRejectionV1` goes over the wire. Nodes predating the newer
+/// rejection reasons only decode the first two variants, so those reasons
+/// are carried in a trailing field of `MaxConcurrencyReached`: older
+/// nodes ignore it and back off as if the peer was busy.
+#[derive(Readable, Writable)]
+enum SyncRejectionWireV1 {
+    MaxConcurrencyReached {
+        #[speedy(default_on_eof)]
+        reason: Option<SyncRejectionReasonV1>,
+    },
+    DifferentCluster,
+}
+
+#[derive(Readable, Writable)]
+enum SyncRejectionReasonV1 {
+    Overloaded(SyncLoadV1),
+    ReadOnlyReplica,
+    Draining,
+}
+
+impl<'a, C> Readable<'a, C> for SyncRejectionV1
+where
+    C: Context,
+{
+    #[inline]
+    fn read_from<R: Reader<'a, C>>(reader: &mut R) -> Result<Self, C::Error> {
+        Ok(match SyncRejectionWireV1::read_from(reader)? {
+            SyncRejectionWireV1::MaxConcurrencyReached { reason } => match reason {
+                None => SyncRejectionV1::MaxConcurrencyReached,
+                Some(SyncRejectionReasonV1::Overloaded(load)) => SyncRejectionV1::Overloaded(load),
+                Some(SyncRejectionReasonV1::ReadOnlyReplica) => SyncRejectionV1::ReadOnlyReplica,
+                Some(SyncRejectionReasonV1::Draining) => SyncRejectionV1::Draining,
+            },
+            SyncRejectionWireV1::DifferentCluster => SyncRejectionV1::DifferentCluster,
+        })
+    }
+}
+
+impl<C> Writable<C> for SyncRejectionV1
+where
+    C: Context,
+{
+    #[inline]
+    fn write_to<T: ?Sized + Writer<C>>(&self, writer: &mut T) -> Result<(), C::Error> {
+        let busy = |reason| SyncRejectionWireV1::MaxConcurrencyReached { reason };
+        let wire = match self {
+            SyncRejectionV1::MaxConcurrencyReached => busy(None),
+            SyncRejectionV1::DifferentCluster => SyncRejectionWireV1::DifferentCluster,
+            SyncRejectionV1::Overloaded(load) => {
+                busy(Some(SyncRejectionReasonV1::Overloaded(*load)))
+            }
+            SyncRejectionV1::ReadOnlyReplica => busy(Some(SyncRejectionReasonV1::ReadOnlyReplica)),
+            SyncRejectionV1::Draining => busy(Some(SyncRejectionReasonV1::Draining)),
+        };
+        wire.write_to(writer)
+    }
+}
+
+/// Sync load of a peer rejecting a sync, to tell a busy peer apart
+/// from a cluster where every peer is busy
+#[derive(Debug, Clone, Copy, PartialEq, Readable, Writable)]
+pub struct SyncLoadV1 {
+    /// Syncs currently being served
+    pub in_flight: u32,
+    /// Maximum number of syncs served concurrently
+    pub max: u32,
+    /// Suggested delay before syncing with this peer again
+    pub retry_after_ms: u64,
+    /// Another peer the rejecting peer knows to be caught up with it, to
+    /// sync with instead
+    #[speedy(default_on_eof)]
+    pub alternate: Option<ActorId>,
+}
+
+impl fmt::Display for SyncLoadV1 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}/{} syncs in progress, retry after {}ms",
+            self.in_flight, self.max, self.retry_after_ms
+        )?;
+        if let Some(alternate) = self.alternate {
+            write!(f, ", try {alternate} instead")?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Default, Clone, PartialEq, Readable, Writable, Serialize, Deserialize)]
@@ -84,6 +178,13 @@ pub struct SyncStateV1 {
     pub partial_need: HashMap<ActorId, HashMap<Version, Vec<RangeInclusive<CrsqlSeq>>>>,
     #[speedy(default_on_eof)]
     pub last_cleared_ts: Option<Timestamp>,
+    /// Hash of the sender's `__corro_schema`, see `schema::schema_hash`
+    #[speedy(default_on_eof)]
+    pub schema_hash: Option<u64>,
+    /// Whether the sender decodes `BroadcastV1::Schema`
+    #[speedy(default_on_eof)]
+    #[serde(default)]
+    pub schema_broadcasts: bool,
 }
 
 impl SyncStateV1 {
@@ -124,6 +225,21 @@ impl SyncStateV1 {
                 .unwrap_or(0)
     }
 
+    /// A state claiming to need every version of the given actors, so
+    /// that computing needs against a peer's state requests everything
+    /// that peer has, regardless of our own bookkeeping.
+    pub fn need_everything(actor_id: ActorId, actors: impl IntoIterator<Item = ActorId>) -> Self {
+        SyncStateV1 {
+            actor_id,
+            need: actors
+                .into_iter()
+                .filter(|id| *id != actor_id)
+                .map(|id| (id, vec![Version(1)..=Version(u64::MAX)]))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
     pub fn compute_available_needs(
         &self,
         other: &SyncStateV1,
@@ -248,6 +364,74 @@ impl SyncStateV1 {
     }
 }
 
+/// Trim needs down to at most `max_versions` versions, oldest first for
+/// each actor so that successive syncs make steady progress. The budget is
+/// shared fairly between actors, leftovers of actors needing little go to
+/// the others. Partial versions count as one version, empty needs aren't
+/// counted.
+pub fn cap_needs(
+    needs: HashMap<ActorId, Vec<SyncNeedV1>>,
+    max_versions: u64,
+) -> HashMap<ActorId, Vec<SyncNeedV1>> {
+    let mut by_actor: Vec<(ActorId, Vec<SyncNeedV1>, u64)> = needs
+        .into_iter()
+        .map(|(actor_id, mut needs)| {
+            needs.sort_by_key(|need| match need {
+                SyncNeedV1::Full { versions } => *versions.start(),
+                SyncNeedV1::Partial { version, .. } => *version,
+                SyncNeedV1::Empty { .. } => Version(0),
+            });
+            let count = needs
+                .iter()
+                .filter(|need| !matches!(need, SyncNeedV1::Empty { .. }))
+                .map(|need| need.count() as u64)
+                .sum();
+            (actor_id, needs, count)
+        })
+        .collect();
+
+    // actors needing the least go first, so what they don't use of their
+    // share is left for the others
+    by_actor.sort_by_key(|(actor_id, _, count)| (*count, *actor_id));
+
+    let mut remaining = max_versions;
+    let mut actors_left = by_actor.len() as u64;
+    let mut capped = HashMap::with_capacity(by_actor.len());
+
+    for (actor_id, needs, _) in by_actor {
+        let mut budget = cmp::min(remaining, cmp::max(1, remaining / actors_left));
+        actors_left -= 1;
+        remaining -= budget;
+
+        let mut kept = Vec::with_capacity(needs.len());
+        for need in needs {
+            match need {
+                SyncNeedV1::Empty { .. } => kept.push(need),
+                _ if budget == 0 => {}
+                SyncNeedV1::Full { versions } => {
+                    let count = cmp::min(budget, versions.end().0 - versions.start().0 + 1);
+                    budget -= count;
+                    kept.push(SyncNeedV1::Full {
+                        versions: *versions.start()..=*versions.start() + (count - 1),
+                    });
+                }
+                need @ SyncNeedV1::Partial { .. } => {
+                    budget -= 1;
+                    kept.push(need);
+                }
+            }
+        }
+        // hand back what this actor didn't need
+        remaining += budget;
+
+        if !kept.is_empty() {
+            capped.insert(actor_id, kept);
+        }
+    }
+
+    capped
+}
+
 #[derive(Debug, Clone, PartialEq, Readable, Writable)]
 pub enum SyncNeedV1 {
     Full {
@@ -506,4 +690,140 @@ mod tests {
             .into()
         );
     }
+
+    #[test]
+    fn test_cap_needs() {
+        let actor1 = ActorId(Uuid::new_v4());
+        let actor2 = ActorId(Uuid::new_v4());
+
+        let needs: HashMap<ActorId, Vec<SyncNeedV1>> = [
+            (
+                actor1,
+                vec![
+                    SyncNeedV1::Full {
+                        versions: Version(100)..=Version(1000),
+                    },
+                    SyncNeedV1::Empty { ts: None },
+                    SyncNeedV1::Partial {
+                        version: Version(50),
+                        seqs: vec![CrsqlSeq(0)..=CrsqlSeq(10)],
+                    },
+                    SyncNeedV1::Full {
+                        versions: Version(1)..=Version(10),
+                    },
+                ],
+            ),
+            (
+                actor2,
+                vec![SyncNeedV1::Full {
+                    versions: Version(1)..=Version(3),
+                }],
+            ),
+        ]
+        .into();
+
+        // plenty of room, nothing changes but the order
+        let capped = cap_needs(needs.clone(), 10_000);
+        assert_eq!(capped[&actor2], needs[&actor2]);
+        assert_eq!(capped[&actor1].len(), 4);
+
+        // actor2 only uses 3 of its 10, actor1 gets the rest, oldest first
+        let capped = cap_needs(needs, 20);
+        assert_eq!(
+            capped[&actor2],
+            vec![SyncNeedV1::Full {
+                versions: Version(1)..=Version(3)
+            }]
+        );
+        assert_eq!(
+            capped[&actor1],
+            vec![
+                SyncNeedV1::Empty { ts: None },
+                SyncNeedV1::Full {
+                    versions: Version(1)..=Version(10)
+                },
+                SyncNeedV1::Partial {
+                    version: Version(50),
+                    seqs: vec![CrsqlSeq(0)..=CrsqlSeq(10)],
+                },
+                SyncNeedV1::Full {
+                    versions: Version(100)..=Version(105)
+                },
+            ]
+        );
+        assert_eq!(
+            capped
+                .values()
+                .flatten()
+                .filter(|need| !matches!(need, SyncNeedV1::Empty { .. }))
+                .map(|need| need.count())
+                .sum::<usize>(),
+            20
+        );
+    }
+
+    #[test]
+    fn test_sync_rejection_wire_compat() {
+        // what nodes predating the newer rejection reasons decode
+        #[derive(Debug, PartialEq, Readable, Writable)]
+        enum LegacySyncRejectionV1 {
+            MaxConcurrencyReached,
+            DifferentCluster,
+        }
+
+        let load = SyncLoadV1 {
+            in_flight: 3,
+            max: 3,
+            retry_after_ms: 500,
+            alternate: Some(ActorId(Uuid::new_v4())),
+        };
+
+        for (rejection, legacy) in [
+            (
+                SyncRejectionV1::MaxConcurrencyReached,
+                LegacySyncRejectionV1::MaxConcurrencyReached,
+            ),
+            (
+                SyncRejectionV1::DifferentCluster,
+                LegacySyncRejectionV1::DifferentCluster,
+            ),
+            (
+                SyncRejectionV1::Overloaded(load),
+                LegacySyncRejectionV1::MaxConcurrencyReached,
+            ),
+            (
+                SyncRejectionV1::ReadOnlyReplica,
+                LegacySyncRejectionV1::MaxConcurrencyReached,
+            ),
+            (
+                SyncRejectionV1::Draining,
+                LegacySyncRejectionV1::MaxConcurrencyReached,
+            ),
+        ] {
+            let bytes = rejection.write_to_vec().unwrap();
+            assert_eq!(
+                SyncRejectionV1::read_from_buffer(&bytes).unwrap(),
+                rejection
+            );
+            assert_eq!(
+                LegacySyncRejectionV1::read_from_buffer(&bytes).unwrap(),
+                legacy
+            );
+
+            // and what they send is still understood
+            let bytes = legacy.write_to_vec().unwrap();
+            let expected = match legacy {
+                LegacySyncRejectionV1::MaxConcurrencyReached => {
+                    SyncRejectionV1::MaxConcurrencyReached
+                }
+                LegacySyncRejectionV1::DifferentCluster => SyncRejectionV1::DifferentCluster,
+            };
+            assert_eq!(SyncRejectionV1::read_from_buffer(&bytes).unwrap(), expected);
+        }
+
+        // the reason is read from the end of the sync message it comes in
+        let msg = SyncMessage::V1(SyncMessageV1::Rejection(SyncRejectionV1::Overloaded(load)));
+        let bytes = msg.write_to_vec().unwrap();
+        assert_eq!(SyncMessage::from_slice(&bytes).unwrap(), msg);
+    }
 }
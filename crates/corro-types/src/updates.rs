@@ -1,6 +1,7 @@
+use crate::actor::ActorId;
 use crate::agent::SplitPool;
 use crate::change::Change;
-use crate::pubsub::{unpack_columns, MatchCandidates, MatchableChange, MatcherError};
+use crate::pubsub::{unpack_columns, MatchCandidates, MatchableChange, MatchedPk, MatcherError};
 use crate::schema::Schema;
 use async_trait::async_trait;
 use corro_api_types::sqlite::ChangeType;
@@ -8,7 +9,9 @@ use corro_api_types::{ColumnName, NotifyEvent, SqliteValueRef, TableName};
 use corro_base_types::CrsqlDbVersion;
 use metrics::{counter, histogram, Counter};
 use indexmap::{IndexMap, map::Entry};
+use once_cell::sync::Lazy;
 use parking_lot::RwLock;
+use rayon::prelude::*;
 use rusqlite::Connection;
 use spawn::spawn_counted;
 use std::collections::BTreeMap;
@@ -108,11 +111,11 @@ impl Handle for UpdateHandle {
         }
 
         if let Some(v) = candidates.get_mut(change.table) {
-            v.insert(change.pk.into(), change.cl).is_none()
+            v.insert(change.pk.into(), (&change).into()).is_none()
         } else {
             candidates.insert(
                 change.table.clone(),
-                [(change.pk.to_vec(), change.cl)].into(),
+                [(change.pk.to_vec(), (&change).into())].into(),
             );
             true
         }
@@ -281,12 +284,12 @@ fn handle_candidates(
     for (_, pks) in candidates {
         let pks = pks
             .iter()
-            .map(|(pk, cl)| unpack_columns(pk).map(|x| (x, *cl)))
-            .collect::<Result<Vec<(Vec<SqliteValueRef>, i64)>, _>>()?;
+            .map(|(pk, matched)| unpack_columns(pk).map(|x| (x, *matched)))
+            .collect::<Result<Vec<(Vec<SqliteValueRef>, MatchedPk)>, _>>()?;
 
-        for (pk, cl) in pks {
+        for (pk, matched) in pks {
             let mut change_type = ChangeType::Update;
-            if cl % 2 == 0 {
+            if matched.cl % 2 == 0 {
                 change_type = ChangeType::Delete
             }
             if let Err(e) = evt_tx.blocking_send(NotifyEvent::Notify(
@@ -344,21 +347,21 @@ async fn batch_candidates(
                 for (table, pk_map) in  candidates {
                     let buffed = buf.entry(table.clone()).or_default();
         
-                    for (pk, cl) in pk_map {
+                    for (pk, matched) in pk_map {
                         let e = cl_cache.entry((table.clone(), pk.clone()));
                         match e {
                             Entry::Occupied(mut o) => {
-                                if *o.get() > cl {
+                                if *o.get() > matched.cl {
                                     continue;
                                 }
-                                o.insert(cl);
+                                o.insert(matched.cl);
                             }
                             Entry::Vacant(v) => {
-                                v.insert(cl);
+                                v.insert(matched.cl);
                             }
                         }
 
-                        buffed.insert(pk, cl);
+                        buffed.insert(pk, matched);
                         buf_count += 1;
                     }
                 }
@@ -415,9 +418,37 @@ async fn batch_candidates(
     debug!(id = %id, "update loop is done");
 }
 
+/// Above this many handles, changes are matched against handles in parallel
+const PARALLEL_MATCH_THRESHOLD: usize = 64;
+
+/// Persistent pool matching changes against many handles at once, so the
+/// latency of the apply path doesn't grow linearly with the number of handles
+static MATCH_POOL: Lazy<rayon::ThreadPool> = Lazy::new(|| {
+    let threads = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+        .max(2);
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .thread_name(|i| format!("corro-match-{i}"))
+        .build()
+        .expect("could not build change matching pool")
+});
+
+fn filter_changes<H: Handle>(handle: &H, changes: &[Change]) -> (MatchCandidates, usize) {
+    let mut candidates = MatchCandidates::new();
+    let mut match_count = 0;
+    for change in changes.iter().map(MatchableChange::from) {
+        if handle.filter_matchable_change(&mut candidates, change) {
+            match_count += 1;
+        }
+    }
+    (candidates, match_count)
+}
+
 pub fn match_changes<H>(manager: &impl Manager<H>, changes: &[Change], db_version: CrsqlDbVersion)
 where
-    H: Handle + Send + 'static,
+    H: Handle + Send + Sync + 'static,
 {
     let trait_type = manager.trait_type();
     trace!(
@@ -434,15 +465,23 @@ where
         return;
     }
 
-    for (id, handle) in handles.iter() {
-        trace!(sub_id = %id, %db_version, "attempting to match changes to a subscription");
-        let mut candidates = MatchCandidates::new();
-        let mut match_count = 0;
-        for change in changes.iter().map(MatchableChange::from) {
-            if handle.filter_matchable_change(&mut candidates, change) {
-                match_count += 1;
-            }
-        }
+    let matched: Vec<_> = if handles.len() < PARALLEL_MATCH_THRESHOLD {
+        handles
+            .iter()
+            .map(|(id, handle)| (id, handle, filter_changes(handle, changes)))
+            .collect()
+    } else {
+        MATCH_POOL.install(|| {
+            handles
+                .par_iter()
+                .map(|(id, handle)| (id, handle, filter_changes(handle, changes)))
+                .collect()
+        })
+    };
+
+    // sending (and spawning) needs to happen from the runtime's thread
+    for (id, handle, (candidates, match_count)) in matched {
+        trace!(sub_id = %id, %db_version, "matched changes to a subscription");
 
         // metrics...
         for (table, pks) in candidates.iter() {
@@ -499,7 +538,7 @@ where
     {
         let mut prepped = conn.prepare_cached(
             r#"
-        SELECT "table", pk, cid, cl
+        SELECT "table", pk, cid, cl, site_id
             FROM crsql_changes
             WHERE db_version = ?
             ORDER BY seq ASC
@@ -512,11 +551,12 @@ where
                 row.get::<_, Vec<u8>>(1)?,
                 row.get::<_, ColumnName>(2)?,
                 row.get::<_, i64>(3)?,
+                row.get::<_, ActorId>(4)?,
             ))
         })?;
 
         for change_res in rows {
-            let (table, pk, column, cl) = change_res?;
+            let (table, pk, column, cl, site_id) = change_res?;
 
             for (_id, (candidates, handle)) in candidates.iter_mut() {
                 let change = MatchableChange {
@@ -524,6 +564,7 @@ where
                     pk: &pk,
                     column: &column,
                     cl,
+                    site_id,
                 };
                 handle.filter_matchable_change(candidates, change);
             }
@@ -567,3 +608,212 @@ where
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+    use std::thread::ThreadId;
+
+    use parking_lot::Mutex;
+
+    use super::*;
+
+    #[derive(Clone)]
+    struct TestHandle {
+        id: Uuid,
+        table: TableName,
+        cancel: CancellationToken,
+        changes_tx: mpsc::Sender<(MatchCandidates, CrsqlDbVersion)>,
+        counters: HandleMetrics,
+        // how long filtering a change takes
+        cost: Duration,
+        // threads that filtered changes
+        threads: Arc<Mutex<HashSet<ThreadId>>>,
+    }
+
+    #[async_trait]
+    impl Handle for TestHandle {
+        fn id(&self) -> Uuid {
+            self.id
+        }
+
+        fn cancelled(&self) -> WaitForCancellationFuture {
+            self.cancel.cancelled()
+        }
+
+        fn filter_matchable_change(
+            &self,
+            candidates: &mut MatchCandidates,
+            change: MatchableChange,
+        ) -> bool {
+            self.threads.lock().insert(std::thread::current().id());
+            std::thread::sleep(self.cost);
+            if *change.table != self.table {
+                return false;
+            }
+            candidates
+                .entry(change.table.clone())
+                .or_default()
+                .insert(change.pk.to_vec(), (&change).into())
+                .is_none()
+        }
+
+        fn changes_tx(&self) -> mpsc::Sender<(MatchCandidates, CrsqlDbVersion)> {
+            self.changes_tx.clone()
+        }
+
+        async fn cleanup(&self) {
+            self.cancel.cancel();
+        }
+
+        fn get_counter(&self, _table: &str) -> &HandleMetrics {
+            &self.counters
+        }
+    }
+
+    #[derive(Default)]
+    struct TestManager(Mutex<BTreeMap<Uuid, TestHandle>>);
+
+    impl Manager<TestHandle> for TestManager {
+        fn trait_type(&self) -> String {
+            "test".to_string()
+        }
+
+        fn get(&self, id: &Uuid) -> Option<TestHandle> {
+            self.0.lock().get(id).cloned()
+        }
+
+        fn remove(&self, id: &Uuid) -> Option<TestHandle> {
+            self.0.lock().remove(id)
+        }
+
+        fn get_handles(&self) -> BTreeMap<Uuid, TestHandle> {
+            self.0.lock().clone()
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_match_changes_many_handles() {
+        let manager = TestManager::default();
+        let mut receivers = HashMap::new();
+
+        for i in 0..1000 {
+            let id = Uuid::new_v4();
+            let (changes_tx, changes_rx) = mpsc::channel(1);
+            let table = if i % 2 == 0 { "tests" } else { "tests2" };
+            manager.0.lock().insert(
+                id,
+                TestHandle {
+                    id,
+                    table: TableName(table.into()),
+                    cancel: CancellationToken::new(),
+                    changes_tx,
+                    counters: HandleMetrics {
+                        matched_count: Counter::noop(),
+                    },
+                    cost: Duration::ZERO,
+                    threads: Default::default(),
+                },
+            );
+            // every 10th handle is gone and should be removed
+            if i % 10 != 0 {
+                receivers.insert(id, (i, changes_rx));
+            }
+        }
+
+        let changes = (0..100)
+            .map(|i| Change {
+                table: TableName("tests".into()),
+                pk: vec![i],
+                cid: ColumnName("text".into()),
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+
+        match_changes(&manager, &changes, CrsqlDbVersion(1));
+
+        assert_eq!(manager.0.lock().len(), receivers.len());
+
+        for (_id, (i, mut changes_rx)) in receivers {
+            let (candidates, db_version) = changes_rx.try_recv().unwrap();
+            assert_eq!(db_version, CrsqlDbVersion(1));
+            let matched = candidates.get("tests").map(|pks| pks.len()).unwrap_or(0);
+            assert_eq!(matched, if i % 2 == 0 { 100 } else { 0 });
+        }
+    }
+
+    fn slow_handles(
+        count: usize,
+        cost: Duration,
+    ) -> (
+        TestManager,
+        Arc<Mutex<HashSet<ThreadId>>>,
+        Vec<mpsc::Receiver<(MatchCandidates, CrsqlDbVersion)>>,
+    ) {
+        let manager = TestManager::default();
+        let threads: Arc<Mutex<HashSet<ThreadId>>> = Default::default();
+        let mut receivers = vec![];
+
+        for _ in 0..count {
+            let id = Uuid::new_v4();
+            let (changes_tx, changes_rx) = mpsc::channel(1);
+            manager.0.lock().insert(
+                id,
+                TestHandle {
+                    id,
+                    table: TableName("tests".into()),
+                    cancel: CancellationToken::new(),
+                    changes_tx,
+                    counters: HandleMetrics {
+                        matched_count: Counter::noop(),
+                    },
+                    cost,
+                    threads: threads.clone(),
+                },
+            );
+            receivers.push(changes_rx);
+        }
+
+        (manager, threads, receivers)
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_match_changes_parallel_above_threshold() {
+        let changes = vec![Change {
+            table: TableName("tests".into()),
+            pk: vec![1],
+            cid: ColumnName("text".into()),
+            ..Default::default()
+        }];
+        let cost = Duration::from_millis(2);
+
+        // few handles are matched right away on the calling thread
+        let (manager, threads, _receivers) = slow_handles(PARALLEL_MATCH_THRESHOLD - 1, cost);
+        match_changes(&manager, &changes, CrsqlDbVersion(1));
+        assert_eq!(
+            *threads.lock(),
+            HashSet::from([std::thread::current().id()])
+        );
+
+        // many handles are spread over the matching pool, which takes a
+        // fraction of the time matching them one after the other would
+        let handles = PARALLEL_MATCH_THRESHOLD * 4;
+        let (manager, threads, receivers) = slow_handles(handles, cost);
+        let start = std::time::Instant::now();
+        match_changes(&manager, &changes, CrsqlDbVersion(1));
+        let elapsed = start.elapsed();
+
+        let threads = threads.lock();
+        assert!(threads.len() > 1, "matched on {} thread(s)", threads.len());
+        assert!(!threads.contains(&std::thread::current().id()));
+        assert!(
+            elapsed < cost * handles as u32,
+            "matching {handles} handles took {elapsed:?}"
+        );
+
+        for mut changes_rx in receivers {
+            let (candidates, _) = changes_rx.try_recv().unwrap();
+            assert_eq!(candidates.get("tests").map(|pks| pks.len()), Some(1));
+        }
+    }
+}
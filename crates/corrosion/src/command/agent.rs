@@ -1,12 +1,10 @@
-use std::{net::SocketAddr, time::Duration};
+use std::time::Duration;
 
 use build_info::VersionControl;
 use camino::Utf8PathBuf;
 use corro_admin::AdminConfig;
-use corro_types::config::{Config, PrometheusConfig};
+use corro_types::config::Config;
 use metrics::gauge;
-use metrics_exporter_prometheus::{Matcher, PrometheusBuilder};
-use metrics_util::MetricKindMask;
 use spawn::wait_for_all_pending_handles;
 use tokio_metrics::RuntimeMonitor;
 use tracing::{error, info};
@@ -16,8 +14,8 @@ use crate::VERSION;
 pub async fn run(config: Config, config_path: &Utf8PathBuf) -> eyre::Result<()> {
     info!("Starting Corrosion Agent v{VERSION}");
 
-    if let Some(PrometheusConfig { bind_addr }) = config.telemetry.prometheus {
-        setup_prometheus(bind_addr).expect("could not setup prometheus");
+    // the exporter itself is installed by the agent
+    if config.telemetry.prometheus.is_some() {
         let info = crate::version().clone();
 
         // I know this is cloned a lot, but I don't care since it's called once
@@ -92,33 +90,6 @@ pub async fn run(config: Config, config_path: &Utf8PathBuf) -> eyre::Result<()>
     Ok(())
 }
 
-fn setup_prometheus(addr: SocketAddr) -> eyre::Result<()> {
-    PrometheusBuilder::new()
-        .with_http_listener(addr)
-        .idle_timeout(MetricKindMask::GAUGE, Some(Duration::from_secs(120)))
-        .set_buckets_for_metric(
-            Matcher::Suffix("chunk_size".into()),
-            &[1.0, 10.0, 75.0, 250.0, 375.0, 500.0, 650.0],
-        )?
-        .set_buckets(&[
-            0.001, // 1ms
-            0.005, // 5ms
-            0.025, // 25ms
-            0.050, // 50ms
-            0.100, // 100ms
-            0.200, // 200ms
-            1.0,   // 1s
-            2.0,   // 2s
-            3.0,   // 3s
-            4.0,   // 4s
-            5.0,   // 5s
-            10.0,  // 10s :screaming:
-            30.0, 60.0,
-        ])?
-        .install()?;
-    Ok(())
-}
-
 fn start_tokio_runtime_reporter() {
     let handle = tokio::runtime::Handle::current();
 
@@ -310,7 +310,8 @@ async fn update_hashes(
             .results
             .into_iter()
             .find_map(|res| match res {
-                corro_api_types::ExecResult::Execute { .. } => None,
+                corro_api_types::ExecResult::Execute { .. }
+                | corro_api_types::ExecResult::CompareAndSet { .. } => None,
                 corro_api_types::ExecResult::Error { error } => Some(error),
             })
         {
@@ -696,7 +697,8 @@ async fn execute(
             .results
             .into_iter()
             .find_map(|res| match res {
-                corro_api_types::ExecResult::Execute { .. } => None,
+                corro_api_types::ExecResult::Execute { .. }
+                | corro_api_types::ExecResult::CompareAndSet { .. } => None,
                 corro_api_types::ExecResult::Error { error } => Some(error),
             })
         {
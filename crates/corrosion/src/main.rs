@@ -401,6 +401,7 @@ async fn process_cli(cli: Cli) -> eyre::Result<()> {
                     Ok(QueryEvent::Change(_, _, _, _)) => {
                         break;
                     }
+                    Ok(QueryEvent::Origin(_)) => {}
                     Ok(QueryEvent::Error(e)) => {
                         eyre::bail!("{e}");
                     }
@@ -432,12 +433,19 @@ async fn process_cli(cli: Cli) -> eyre::Result<()> {
                     ExecResult::Execute {
                         rows_affected,
                         time,
+                        ..
                     } => {
                         info!("Rows affected: {rows_affected}");
                         if *timer {
                             println!("Run Time: real {time}");
                         }
                     }
+                    ExecResult::CompareAndSet { applied, time } => {
+                        info!("Applied: {applied}");
+                        if *timer {
+                            println!("Run Time: real {time}");
+                        }
+                    }
                     ExecResult::Error { error } => {
                         error!("{error}");
                     }